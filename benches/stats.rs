@@ -0,0 +1,78 @@
+//! `cargo bench --bench stats`: the per-request statistics update under
+//! contention. Threads count requests into one shared `ServerStats`, against
+//! the same counters behind a single `RwLock` (as they used to be), and the
+//! table shows nanoseconds per update for each thread count.
+//!
+//! WOLFSERVE_BENCH_UPDATES (default 1000000) sets the updates per thread.
+
+use parking_lot::RwLock;
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+use wolfserve::{ServerStats, StatsSnapshot};
+
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// How the old code counted a request: all counters under one write lock
+fn record_locked(stats: &RwLock<StatsSnapshot>, status: u16, duration_ms: u64, php_duration_ms: Option<u64>) {
+    let mut stats = stats.write();
+    stats.total_requests += 1;
+    stats.total_response_time_ms += duration_ms;
+    match status {
+        200..=299 => stats.requests_2xx += 1,
+        300..=399 => stats.requests_3xx += 1,
+        400..=499 => stats.requests_4xx += 1,
+        500..=599 => stats.requests_5xx += 1,
+        _ => {}
+    }
+    if let Some(php_ms) = php_duration_ms {
+        stats.php_requests += 1;
+        stats.total_php_time_ms += php_ms;
+    }
+}
+
+/// Time for `threads` threads to make `updates` calls of `record` each,
+/// started together
+fn run<F: Fn(u16, u64, Option<u64>) + Sync>(threads: usize, updates: u64, record: F) -> Duration {
+    let barrier = Barrier::new(threads + 1);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                barrier.wait();
+                for i in 0..updates {
+                    let status = if i % 10 == 0 { 404 } else { 200 };
+                    record(status, i % 50, (i % 3 == 0).then_some(i % 20));
+                }
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        // The scope joins every thread before returning
+        start
+    })
+    .elapsed()
+}
+
+fn main() {
+    let updates = std::env::var("WOLFSERVE_BENCH_UPDATES").ok().and_then(|v| v.parse().ok()).unwrap_or(1_000_000u64);
+
+    println!("{} updates per thread; ns per update", updates);
+    println!("{:<8} {:>10} {:>10}", "threads", "rwlock", "atomic");
+    for threads in THREADS {
+        let total = (threads as u64 * updates) as f64;
+
+        let locked = RwLock::new(StatsSnapshot::default());
+        let locked_time = run(threads, updates, |status, ms, php| record_locked(&locked, status, ms, php));
+        assert_eq!(locked.read().total_requests, threads as u64 * updates);
+
+        let atomic = ServerStats::new();
+        let atomic_time = run(threads, updates, |status, ms, php| atomic.record(status, ms, php));
+        assert_eq!(atomic.snapshot().total_requests, threads as u64 * updates);
+
+        println!(
+            "{:<8} {:>10.1} {:>10.1}",
+            threads,
+            locked_time.as_nanos() as f64 / total,
+            atomic_time.as_nanos() as f64 / total
+        );
+    }
+}
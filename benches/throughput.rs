@@ -0,0 +1,110 @@
+//! `cargo bench` harness for the request hot path: static files of several
+//! sizes and a PHP request answered by a mock PHP-FPM, all through the same
+//! router the listeners use. Each case runs concurrent clients for a few
+//! seconds and reports requests/sec and latency percentiles.
+//!
+//!     cargo bench                  # every case
+//!     cargo bench -- php           # cases whose name contains "php"
+//!
+//! WOLFSERVE_BENCH_SECS (default 3) and WOLFSERVE_BENCH_CONCURRENCY
+//! (default 8) tune the runs.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use common::{MockFpm, Site, HOST, PORT};
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+const STATIC_FILES: [(&str, usize); 3] = [("1k.bin", 1024), ("64k.bin", 64 * 1024), ("1m.bin", 1024 * 1024)];
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+async fn request(router: &Router, uri: &str) {
+    let request = Request::get(uri).header(header::HOST, HOST).body(Body::empty()).unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+    to_bytes(response.into_body(), usize::MAX).await.unwrap();
+}
+
+/// Requests/sec and sorted latencies of `concurrency` clients fetching `uri`
+/// back to back for `duration`
+async fn run(router: &Router, uri: &str, duration: Duration, concurrency: u64) -> (f64, Vec<Duration>) {
+    // Warm up the page cache and the FPM connection path
+    for _ in 0..10 {
+        request(router, uri).await;
+    }
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let router = router.clone();
+            let uri = uri.to_string();
+            tokio::spawn(async move {
+                let mut latencies = Vec::new();
+                while start.elapsed() < duration {
+                    let sent = Instant::now();
+                    request(&router, &uri).await;
+                    latencies.push(sent.elapsed());
+                }
+                latencies
+            })
+        })
+        .collect();
+
+    let mut latencies = Vec::new();
+    for worker in workers {
+        latencies.extend(worker.await.unwrap());
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    latencies.sort();
+    (latencies.len() as f64 / elapsed, latencies)
+}
+
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    sorted[(sorted.len() * pct / 100).min(sorted.len() - 1)]
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let duration = Duration::from_secs(env_or("WOLFSERVE_BENCH_SECS", 3));
+    let concurrency = env_or("WOLFSERVE_BENCH_CONCURRENCY", 8).max(1);
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let site = Site::new("");
+        for (name, size) in STATIC_FILES {
+            site.write(name, vec![b'x'; size]);
+        }
+        site.write("index.php", "<?php");
+        let fpm = MockFpm::start_unrecorded(format!("Content-Type: text/html\r\n\r\n{}", "x".repeat(2048))).await;
+        let router = wolfserve::site_router(&site.state(&format!("fpm_address = \"{}\"", fpm.addr)), PORT, false);
+
+        let mut cases: Vec<(String, String)> = STATIC_FILES
+            .iter()
+            .map(|(name, _)| (format!("static {}", name), format!("/{}", name)))
+            .collect();
+        cases.push(("php 2k".to_string(), "/index.php".to_string()));
+
+        println!("{} concurrent clients, {}s per case", concurrency, duration.as_secs());
+        println!("{:<18} {:>10} {:>10} {:>10} {:>10}", "case", "req/s", "p50", "p99", "max");
+        for (name, uri) in cases {
+            if filter.as_ref().is_some_and(|f| !name.contains(f.as_str())) {
+                continue;
+            }
+            let (rate, latencies) = run(&router, &uri, duration, concurrency).await;
+            println!(
+                "{:<18} {:>10.0} {:>10.2?} {:>10.2?} {:>10.2?}",
+                name,
+                rate,
+                percentile(&latencies, 50),
+                percentile(&latencies, 99),
+                latencies.last().unwrap()
+            );
+        }
+    });
+}
@@ -177,6 +177,8 @@ if [ ! -f "$INSTALL_DIR/wolfserve.toml" ]; then
 [server]
 host = "0.0.0.0"
 port = 3000
+# Server header: "full" (name and version), "prod" (name only) or "off"
+# server_tokens = "full"
 
 [php]
 fpm_address = "127.0.0.1:$PHP_FPM_PORT"
@@ -153,6 +153,8 @@ cat > "$INSTALL_DIR/wolfserve.toml" <<EOF
 [server]
 host = "$SERVER_HOST"
 port = $SERVER_PORT
+# Server header: "full" (name and version), "prod" (name only) or "off"
+# server_tokens = "full"
 
 [php]
 fpm_address = "127.0.0.1:$PHP_FPM_PORT"
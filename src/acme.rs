@@ -0,0 +1,743 @@
+//! Built-in ACME (RFC 8555) client for vhosts with `acme = true`.
+//! Certificates are ordered with HTTP-01 challenges, answered from
+//! `/.well-known/acme-challenge/` on every listener, or with TLS-ALPN-01
+//! (RFC 8737) challenges, answered by the certificate resolver with a
+//! throwaway certificate for `acme-tls/1` handshakes. They are written under
+//! `[acme] state_dir` as the vhost's certificate and key files, which the
+//! certificate resolver then loads like any other.
+
+use crate::admin::AcmeStatus;
+use crate::apache::VirtualHost;
+use crate::{certs, tls, AcmeChallenge, AppState};
+use anyhow::{bail, Context};
+use aws_lc_rs::digest;
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use bytes::Bytes;
+use chrono::Utc;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{HeaderMap, Method, StatusCode};
+use hyper_util::rt::TokioIo;
+use parking_lot::RwLock;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::sign::CertifiedKey;
+use serde_json::{json, Value};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// Seconds to wait between checks when every certificate is in order
+const CHECK_INTERVAL: u64 = 12 * 3600;
+/// Seconds before retrying after a failed order
+const RETRY_INTERVAL: u64 = 3600;
+/// Status polls before an authorization or order is given up on
+const MAX_POLLS: usize = 60;
+/// Largest reply body read from the ACME server or an OCSP responder; a
+/// certificate chain or OCSP response is a few kilobytes
+const MAX_REPLY_BYTES: usize = 1024 * 1024;
+
+/// Key authorizations of the HTTP-01 challenges in progress, by token
+#[derive(Default)]
+pub struct Challenges(RwLock<HashMap<String, String>>);
+
+impl Challenges {
+    pub fn get(&self, token: &str) -> Option<String> {
+        self.0.read().get(token).cloned()
+    }
+}
+
+/// Check ACME certificates now and then, and order those that are missing
+/// or due for renewal
+pub fn spawn(state: Arc<AppState>) {
+    println!("ACME: certificates from {} kept in {}", state.config.acme.directory_url, state.config.acme.state_dir.display());
+    tokio::spawn(async move {
+        // Let the listeners come up before the CA calls back
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        loop {
+            let all_ok = renew_due(&state).await;
+            tokio::time::sleep(Duration::from_secs(if all_ok { CHECK_INTERVAL } else { RETRY_INTERVAL })).await;
+        }
+    });
+}
+
+/// Names to put on a vhost's certificate. HTTP-01 cannot validate wildcards.
+pub fn certificate_names(vhost: &VirtualHost) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in vhost.server_name.iter().chain(&vhost.server_aliases) {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if !name.contains('*') && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Order certificates for the ACME vhosts that need one. Returns false if
+/// any order failed.
+async fn renew_due(state: &Arc<AppState>) -> bool {
+    let vhosts: Vec<Arc<VirtualHost>> = state.sites.read().list.iter().filter(|v| v.acme).cloned().collect();
+    let renew_before = chrono::Duration::days(state.config.acme.renew_days);
+    let mut clients: HashMap<String, Client> = HashMap::new();
+    // Vhosts sharing a ServerName on several ports share one certificate
+    let mut seen = HashSet::new();
+    let mut all_ok = true;
+    let mut issued = false;
+
+    for vhost in vhosts {
+        let (Some(name), Some(cert_file), Some(key_file)) = (&vhost.server_name, &vhost.ssl_cert_file, &vhost.ssl_key_file) else {
+            continue;
+        };
+        if !seen.insert(cert_file.clone()) {
+            continue;
+        }
+        let expires = tls::certificate_expiry(cert_file).ok();
+        if let Some(expires) = expires.filter(|t| *t - Utc::now() > renew_before) {
+            record(state, name, true, format!("valid until {}", expires.format("%Y-%m-%d")), Some(expires));
+            continue;
+        }
+
+        println!("ACME: ordering a certificate for {}", name);
+        let result = async {
+            let email = state
+                .config
+                .vhost_overrides(name)
+                .and_then(|o| o.acme_email.clone())
+                .or_else(|| state.config.acme.email.clone())
+                .context("no acme_email or [acme] email set")?;
+            let client = match clients.entry(email) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let client = Client::connect(state, entry.key()).await?;
+                    entry.insert(client)
+                }
+            };
+            let (key_pem, cert_pem) = client.order(&certificate_names(&vhost), state).await?;
+            write_private(key_file, key_pem.as_bytes())?;
+            write_private(cert_file, cert_pem.as_bytes())?;
+            tls::certificate_expiry(cert_file).map_err(anyhow::Error::msg)
+        }
+        .await;
+
+        match result {
+            Ok(expires) => {
+                println!("ACME: certificate for {} issued, expires {}", name, expires.format("%Y-%m-%d %H:%M UTC"));
+                record(state, name, true, format!("issued, valid until {}", expires.format("%Y-%m-%d")), Some(expires));
+                issued = true;
+            }
+            Err(e) => {
+                eprintln!("ERROR: ACME order for {} failed: {:#}", name, e);
+                record(state, name, false, format!("order failed: {:#}", e), expires);
+                all_ok = false;
+            }
+        }
+    }
+
+    if issued {
+        let state = state.clone();
+        let _ = tokio::task::spawn_blocking(move || certs::reload(&state, "ACME certificates issued")).await;
+    }
+    all_ok
+}
+
+fn record(state: &AppState, name: &str, ok: bool, message: String, expires: Option<chrono::DateTime<Utc>>) {
+    state.admin_state.acme.write().insert(
+        name.to_string(),
+        AcmeStatus { time: Utc::now(), ok, message, expires },
+    );
+}
+
+/// Write a key or certificate readable only by us, replacing the old file
+/// in one step
+fn write_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("writing {}", tmp.display()))?;
+    file.write_all(contents)?;
+    fs::rename(&tmp, path).with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+pub fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        out.push_str(std::str::from_utf8(line).unwrap_or_default());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+/// Load the account key for a contact, creating it on first use
+fn account_key(state_dir: &Path, email: &str) -> anyhow::Result<EcdsaKeyPair> {
+    let path = state_dir.join("accounts").join(format!("{}.key", email));
+    if !path.exists() {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("generating an account key failed"))?;
+        write_private(&path, pem("PRIVATE KEY", pkcs8.as_ref()).as_bytes())?;
+    }
+    let mut reader = std::io::BufReader::new(fs::File::open(&path).with_context(|| format!("reading {}", path.display()))?);
+    let key = rustls_pemfile::private_key(&mut reader)?.with_context(|| format!("no key in {}", path.display()))?;
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, key.secret_der())
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+/// A response from the ACME server or an OCSP responder
+pub struct Reply {
+    pub status: StatusCode,
+    headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Reply {
+    fn json(&self) -> anyhow::Result<Value> {
+        serde_json::from_slice(&self.body).context("invalid JSON from the ACME server")
+    }
+
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+    }
+}
+
+/// TLS settings for talking to the ACME server
+pub fn client_tls(ca_file: Option<&Path>) -> anyhow::Result<Arc<rustls::ClientConfig>> {
+    const SYSTEM_BUNDLES: [&str; 3] = [
+        "/etc/ssl/certs/ca-certificates.crt",
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        "/etc/ssl/cert.pem",
+    ];
+    let bundle = match ca_file {
+        Some(file) => file.to_path_buf(),
+        None => SYSTEM_BUNDLES
+            .iter()
+            .map(PathBuf::from)
+            .find(|p| p.is_file())
+            .context("no CA bundle found; set [acme] ca_file")?,
+    };
+    let mut reader = std::io::BufReader::new(fs::File::open(&bundle).with_context(|| format!("reading {}", bundle.display()))?);
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_pemfile::certs(&mut reader).flatten());
+    if roots.is_empty() {
+        bail!("no CA certificates in {}", bundle.display());
+    }
+    Ok(Arc::new(
+        rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    ))
+}
+
+/// One HTTP/1.1 request; `body` is its content type and bytes. `tls` is only
+/// needed for https URLs.
+pub async fn request(tls: Option<&Arc<rustls::ClientConfig>>, method: Method, url: &str, body: Option<(&str, Vec<u8>)>) -> anyhow::Result<Reply> {
+    let uri: hyper::Uri = url.parse().with_context(|| format!("bad URL {}", url))?;
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => bail!("unsupported URL {}", url),
+    };
+    let host = uri.host().with_context(|| format!("no host in {}", url))?.to_string();
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let mut builder = hyper::Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map(|p| p.as_str()).unwrap_or("/"))
+        .header(hyper::header::HOST, uri.authority().map(|a| a.as_str()).unwrap_or(&host))
+        .header(hyper::header::USER_AGENT, concat!("wolfserve/", env!("CARGO_PKG_VERSION")));
+    let body = match body {
+        Some((content_type, bytes)) => {
+            builder = builder.header(hyper::header::CONTENT_TYPE, content_type);
+            bytes
+        }
+        None => Vec::new(),
+    };
+    let request = builder.body(Full::new(Bytes::from(body)))?;
+
+    let exchange = async {
+        let stream = TcpStream::connect((host.as_str(), port)).await.with_context(|| format!("connecting to {}:{}", host, port))?;
+        if https {
+            let tls = tls.with_context(|| format!("no TLS settings for {}", url))?;
+            let name = rustls::pki_types::ServerName::try_from(host.clone())?;
+            let stream = tokio_rustls::TlsConnector::from(tls.clone()).connect(name, stream).await?;
+            send(stream, request).await
+        } else {
+            send(stream, request).await
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(30), exchange)
+        .await
+        .with_context(|| format!("{} timed out", url))?
+}
+
+async fn send<S>(stream: S, request: hyper::Request<Full<Bytes>>) -> anyhow::Result<Reply>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+    let (parts, body) = sender.send_request(request).await?.into_parts();
+    let body = match Limited::new(body, MAX_REPLY_BYTES).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => bail!("reply larger than {} bytes", MAX_REPLY_BYTES),
+        Err(e) => return Err(anyhow::anyhow!(e)),
+    };
+    Ok(Reply { status: parts.status, headers: parts.headers, body })
+}
+
+/// The `detail` of an ACME problem document, else the raw body
+fn problem(reply: &Reply) -> String {
+    let text = String::from_utf8_lossy(&reply.body).into_owned();
+    match serde_json::from_slice::<Value>(&reply.body) {
+        Ok(doc) => doc["detail"].as_str().map(str::to_string).unwrap_or(text),
+        Err(_) => text,
+    }
+}
+
+/// The flattened JWS (RFC 7515) of a request signed with `key`; no payload
+/// makes it a POST-as-GET
+fn jws(key: &EcdsaKeyPair, protected: &Value, payload: Option<&Value>) -> anyhow::Result<Value> {
+    let protected = b64(protected.to_string());
+    let payload = payload.map(|p| b64(p.to_string())).unwrap_or_default();
+    let signature = key
+        .sign(&SystemRandom::new(), format!("{}.{}", protected, payload).as_bytes())
+        .map_err(|_| anyhow::anyhow!("signing the request failed"))?;
+    Ok(json!({ "protected": protected, "payload": payload, "signature": b64(signature) }))
+}
+
+/// An ACME account session
+struct Client {
+    tls: Arc<rustls::ClientConfig>,
+    new_nonce: String,
+    new_order: String,
+    key: EcdsaKeyPair,
+    jwk: Value,
+    /// Account URL, sent as `kid` once the account exists
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Client {
+    /// Fetch the directory and register (or look up) the account for `email`
+    async fn connect(state: &AppState, email: &str) -> anyhow::Result<Client> {
+        let config = &state.config.acme;
+        let tls = client_tls(config.ca_file.as_deref())?;
+        let reply = request(Some(&tls), Method::GET, &config.directory_url, None).await?;
+        if !reply.status.is_success() {
+            bail!("{}: {} {}", config.directory_url, reply.status, problem(&reply));
+        }
+        let directory = reply.json()?;
+        let url = |key: &str| -> anyhow::Result<String> {
+            directory[key].as_str().map(str::to_string).with_context(|| format!("directory has no {}", key))
+        };
+
+        let key = account_key(&config.state_dir, email)?;
+        let point = key.public_key().as_ref();
+        if point.len() != 65 {
+            bail!("unexpected account public key length");
+        }
+        let jwk = json!({ "crv": "P-256", "kty": "EC", "x": b64(&point[1..33]), "y": b64(&point[33..]) });
+
+        let mut client = Client {
+            tls,
+            new_nonce: url("newNonce")?,
+            new_order: url("newOrder")?,
+            key,
+            jwk,
+            kid: None,
+            nonce: None,
+        };
+        let new_account = url("newAccount")?;
+        let reply = client
+            .post(&new_account, Some(json!({ "termsOfServiceAgreed": true, "contact": [format!("mailto:{}", email)] })))
+            .await?;
+        client.kid = Some(reply.header("location").context("no account URL in the newAccount reply")?);
+        Ok(client)
+    }
+
+    /// RFC 7638 thumbprint of the account key, the second half of key authorizations
+    fn thumbprint(&self) -> String {
+        // Members in lexicographic order, no whitespace
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            self.jwk["x"].as_str().unwrap_or_default(),
+            self.jwk["y"].as_str().unwrap_or_default()
+        );
+        b64(digest::digest(&digest::SHA256, canonical.as_bytes()))
+    }
+
+    /// Signed POST; `None` makes it a POST-as-GET. A rejected nonce is retried once.
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> anyhow::Result<Reply> {
+        for attempt in 0..2 {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => request(Some(&self.tls), Method::HEAD, &self.new_nonce, None)
+                    .await?
+                    .header("replay-nonce")
+                    .context("no Replay-Nonce from newNonce")?,
+            };
+
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk.clone(),
+            }
+            let body = jws(&self.key, &protected, payload.as_ref())?;
+
+            let reply = request(Some(&self.tls), Method::POST, url, Some(("application/jose+json", body.to_string().into_bytes()))).await?;
+            self.nonce = reply.header("replay-nonce");
+            if reply.status.is_success() {
+                return Ok(reply);
+            }
+            let bad_nonce = serde_json::from_slice::<Value>(&reply.body)
+                .is_ok_and(|doc| doc["type"] == "urn:ietf:params:acme:error:badNonce");
+            if !(bad_nonce && attempt == 0) {
+                bail!("{}: {} {}", url, reply.status, problem(&reply));
+            }
+        }
+        unreachable!()
+    }
+
+    /// POST-as-GET `url` until its status is `valid`
+    async fn poll(&mut self, url: &str) -> anyhow::Result<Value> {
+        for _ in 0..MAX_POLLS {
+            let doc = self.post(url, None).await?.json()?;
+            match doc["status"].as_str() {
+                Some("valid") => return Ok(doc),
+                Some("invalid") => {
+                    // An order carries its error; an authorization carries it on the challenge
+                    let detail = doc["error"]["detail"]
+                        .as_str()
+                        .or_else(|| {
+                            doc["challenges"]
+                                .as_array()?
+                                .iter()
+                                .find_map(|c| c["error"]["detail"].as_str())
+                        })
+                        .unwrap_or("no detail given");
+                    bail!("{} is invalid: {}", url, detail);
+                }
+                _ => tokio::time::sleep(Duration::from_secs(2)).await,
+            }
+        }
+        bail!("{} did not become valid in time", url)
+    }
+
+    /// Order a certificate for `names`, answering the `[acme] challenge`
+    /// type of challenge. Returns the new private key and the certificate
+    /// chain, both PEM.
+    async fn order(&mut self, names: &[String], state: &AppState) -> anyhow::Result<(String, String)> {
+        if names.is_empty() {
+            bail!("no names to certify");
+        }
+        let identifiers: Vec<Value> = names.iter().map(|name| json!({ "type": "dns", "value": name })).collect();
+        let new_order = self.new_order.clone();
+        let reply = self.post(&new_order, Some(json!({ "identifiers": identifiers }))).await?;
+        let order_url = reply.header("location").context("no order URL in the newOrder reply")?;
+        let order = reply.json()?;
+
+        let authorizations: Vec<String> = order["authorizations"]
+            .as_array()
+            .context("order has no authorizations")?
+            .iter()
+            .filter_map(|a| a.as_str().map(str::to_string))
+            .collect();
+        for authorization in authorizations {
+            let doc = self.post(&authorization, None).await?.json()?;
+            if doc["status"] == "valid" {
+                continue;
+            }
+            let kind = state.config.acme.challenge;
+            let kind_name = match kind {
+                AcmeChallenge::Http01 => "http-01",
+                AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+            };
+            let identifier = doc["identifier"]["value"].as_str().unwrap_or_default().to_string();
+            let challenge = doc["challenges"]
+                .as_array()
+                .and_then(|list| list.iter().find(|c| c["type"] == kind_name))
+                .with_context(|| format!("no {} challenge offered for {}", kind_name, identifier))?;
+            let (Some(token), Some(challenge_url)) = (challenge["token"].as_str(), challenge["url"].as_str()) else {
+                bail!("malformed {} challenge for {}", kind_name, identifier);
+            };
+            let token = token.to_string();
+            let challenge_url = challenge_url.to_string();
+
+            let key_authorization = format!("{}.{}", token, self.thumbprint());
+            match kind {
+                AcmeChallenge::Http01 => {
+                    state.acme_challenges.0.write().insert(token.clone(), key_authorization);
+                }
+                AcmeChallenge::TlsAlpn01 => publish_tls_alpn_challenge(state, &identifier, &key_authorization)?,
+            }
+            let result = async {
+                self.post(&challenge_url, Some(json!({}))).await?;
+                self.poll(&authorization).await
+            }
+            .await;
+            match kind {
+                AcmeChallenge::Http01 => {
+                    state.acme_challenges.0.write().remove(&token);
+                }
+                AcmeChallenge::TlsAlpn01 => withdraw_tls_alpn_challenge(state, &identifier),
+            }
+            result?;
+        }
+
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("generating a certificate key failed"))?;
+        let cert_key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+            .map_err(|e| anyhow::anyhow!("certificate key: {}", e))?;
+        let finalize = order["finalize"].as_str().context("order has no finalize URL")?;
+        self.post(finalize, Some(json!({ "csr": b64(csr(names, &cert_key)?) }))).await?;
+
+        let order = self.poll(&order_url).await?;
+        let certificate = order["certificate"].as_str().context("valid order has no certificate URL")?;
+        let chain = self.post(certificate, None).await?;
+        let chain = String::from_utf8(chain.body.to_vec()).context("certificate is not PEM")?;
+        Ok((pem("PRIVATE KEY", pkcs8.as_ref()), chain))
+    }
+}
+
+// DER for the certificate signing request
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+const OID_EXTENSION_REQUEST: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+/// id-pe-acmeIdentifier (RFC 8737)
+const OID_ACME_IDENTIFIER: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f];
+
+pub fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let len = content.len().to_be_bytes();
+        let skip = len.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub fn sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &parts.concat())
+}
+
+/// Name with `name` as its CN; empty past the 64 characters a CN may hold,
+/// leaving the name to the SAN
+fn subject(name: Option<&str>) -> Vec<u8> {
+    match name.filter(|name| name.len() <= 64) {
+        Some(name) => sequence(&[der(0x31, &sequence(&[OID_COMMON_NAME.to_vec(), der(0x0c, name.as_bytes())]))]),
+        None => sequence(&[]),
+    }
+}
+
+fn public_key_info(key: &EcdsaKeyPair) -> Vec<u8> {
+    sequence(&[
+        sequence(&[OID_EC_PUBLIC_KEY.to_vec(), OID_P256.to_vec()]),
+        der(0x03, &[&[0u8][..], key.public_key().as_ref()].concat()),
+    ])
+}
+
+/// PKCS#10 request for `names` (the first also as CN), signed with `key`
+fn csr(names: &[String], key: &EcdsaKeyPair) -> anyhow::Result<Vec<u8>> {
+    let alt_names: Vec<Vec<u8>> = names.iter().map(|name| der(0x82, name.as_bytes())).collect();
+    let extensions = sequence(&[sequence(&[OID_SUBJECT_ALT_NAME.to_vec(), der(0x04, &sequence(&alt_names))])]);
+    let info = sequence(&[
+        der(0x02, &[0]),
+        subject(names.first().map(String::as_str)),
+        public_key_info(key),
+        der(0xa0, &sequence(&[OID_EXTENSION_REQUEST.to_vec(), der(0x31, &extensions)])),
+    ]);
+    let signature = key
+        .sign(&SystemRandom::new(), &info)
+        .map_err(|_| anyhow::anyhow!("signing the CSR failed"))?;
+    Ok(sequence(&[
+        info,
+        sequence(&[OID_ECDSA_SHA256.to_vec()]),
+        der(0x03, &[&[0u8][..], signature.as_ref()].concat()),
+    ]))
+}
+
+/// Answer `acme-tls/1` handshakes for `name` with a challenge certificate
+/// for `key_authorization`
+pub fn publish_tls_alpn_challenge(state: &AppState, name: &str, key_authorization: &str) -> anyhow::Result<()> {
+    let key = challenge_certificate(name, key_authorization)?;
+    state.certs.add_challenge(name, Arc::new(key));
+    Ok(())
+}
+
+/// Drop the challenge certificate for `name` once the CA is done with it
+pub fn withdraw_tls_alpn_challenge(state: &AppState, name: &str) {
+    state.certs.remove_challenge(name);
+}
+
+/// Self-signed TLS-ALPN-01 certificate for `name`: the name as its only SAN
+/// and the SHA-256 of `key_authorization` in a critical acmeIdentifier
+/// extension, with a fresh key that is thrown away with the challenge
+fn challenge_certificate(name: &str, key_authorization: &str) -> anyhow::Result<CertifiedKey> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(|_| anyhow::anyhow!("generating a challenge key failed"))?;
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+        .map_err(|e| anyhow::anyhow!("challenge key: {}", e))?;
+
+    let mut serial = [0u8; 16];
+    aws_lc_rs::rand::SecureRandom::fill(&rng, &mut serial).map_err(|_| anyhow::anyhow!("no random serial"))?;
+    // Positive, and without a leading zero byte to strip
+    serial[0] = serial[0] & 0x7f | 0x40;
+    let utc_time = |t: chrono::DateTime<Utc>| der(0x17, t.format("%y%m%d%H%M%SZ").to_string().as_bytes());
+    let now = Utc::now();
+    let digest = digest::digest(&digest::SHA256, key_authorization.as_bytes());
+    let extensions = sequence(&[
+        sequence(&[OID_SUBJECT_ALT_NAME.to_vec(), der(0x04, &sequence(&[der(0x82, name.as_bytes())]))]),
+        sequence(&[OID_ACME_IDENTIFIER.to_vec(), der(0x01, &[0xff]), der(0x04, &der(0x04, digest.as_ref()))]),
+    ]);
+    let tbs = sequence(&[
+        der(0xa0, &der(0x02, &[2])),
+        der(0x02, &serial),
+        sequence(&[OID_ECDSA_SHA256.to_vec()]),
+        subject(Some(name)),
+        sequence(&[utc_time(now - chrono::Duration::days(1)), utc_time(now + chrono::Duration::days(7))]),
+        subject(Some(name)),
+        public_key_info(&key),
+        der(0xa3, &extensions),
+    ]);
+    let signature = key.sign(&rng, &tbs).map_err(|_| anyhow::anyhow!("signing the challenge certificate failed"))?;
+    let cert = sequence(&[
+        tbs,
+        sequence(&[OID_ECDSA_SHA256.to_vec()]),
+        der(0x03, &[&[0u8][..], signature.as_ref()].concat()),
+    ]);
+
+    let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec()));
+    let signing_key = rustls::crypto::aws_lc_rs::sign::any_ecdsa_type(&private_key)
+        .map_err(|e| anyhow::anyhow!("challenge key: {}", e))?;
+    Ok(CertifiedKey::new(vec![CertificateDer::from(cert)], signing_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lc_rs::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use axum::body::Body;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+
+    #[test]
+    fn der_lengths_use_the_shortest_form() {
+        assert_eq!(der(0x04, &[]), [0x04, 0x00]);
+        assert_eq!(der(0x04, &[7; 0x7f])[..2], [0x04, 0x7f]);
+        assert_eq!(der(0x04, &[7; 0x80])[..3], [0x04, 0x81, 0x80]);
+        assert_eq!(der(0x04, &[7; 0x100])[..4], [0x04, 0x82, 0x01, 0x00]);
+        assert_eq!(der(0x04, &[7; 0x100]).len(), 4 + 0x100);
+
+        let nested = sequence(&[der(0x02, &[1]), sequence(&[]), der(0x04, &[7; 0x80])]);
+        let (tag, content, rest) = tls::der_element(&nested).unwrap();
+        assert_eq!((tag, content.len(), rest.len()), (0x30, 3 + 2 + 3 + 0x80, 0));
+        assert_eq!(content[..5], [0x02, 0x01, 0x01, 0x30, 0x00]);
+    }
+
+    fn account_key() -> EcdsaKeyPair {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap()
+    }
+
+    fn decode(field: &Value) -> Vec<u8> {
+        URL_SAFE_NO_PAD.decode(field.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn jws_is_signed_with_the_account_key() {
+        let key = account_key();
+        let protected = json!({ "alg": "ES256", "nonce": "n0nce", "url": "https://ca.test/new-order", "kid": "https://ca.test/acct/1" });
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": "example.test" }] });
+        let body = jws(&key, &protected, Some(&payload)).unwrap();
+
+        assert_eq!(serde_json::from_slice::<Value>(&decode(&body["protected"])).unwrap(), protected);
+        assert_eq!(serde_json::from_slice::<Value>(&decode(&body["payload"])).unwrap(), payload);
+        let signed = format!("{}.{}", body["protected"].as_str().unwrap(), body["payload"].as_str().unwrap());
+        let signature = decode(&body["signature"]);
+        // ES256 signatures are r and s side by side, not DER
+        assert_eq!(signature.len(), 64);
+        let public_key = UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.public_key().as_ref());
+        assert!(public_key.verify(signed.as_bytes(), &signature).is_ok());
+        assert!(public_key.verify(format!("{}x", signed).as_bytes(), &signature).is_err());
+    }
+
+    #[test]
+    fn post_as_get_has_an_empty_payload() {
+        let key = account_key();
+        let body = jws(&key, &json!({ "alg": "ES256" }), None).unwrap();
+        assert_eq!(body["payload"], "");
+        let signed = format!("{}.", body["protected"].as_str().unwrap());
+        let public_key = UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.public_key().as_ref());
+        assert!(public_key.verify(signed.as_bytes(), &decode(&body["signature"])).is_ok());
+    }
+
+    #[tokio::test]
+    async fn http_01_challenges_are_served_on_any_host() {
+        let config = toml::from_str("[server]\nhost = \"127.0.0.1\"\nport = 8080\n\n[php]\n\n[apache]\nconfig_dir = \"/nonexistent\"\n").unwrap();
+        let state = crate::load_state(config);
+        state.acme_challenges.0.write().insert("t0ken".to_string(), "t0ken.thumbprint".to_string());
+
+        let get = |path: &str| axum::http::Request::get(path).header("host", "unknown.test").body(Body::empty()).unwrap();
+        let response = crate::site_router(&state, 8080, false).oneshot(get("/.well-known/acme-challenge/t0ken")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "t0ken.thumbprint");
+
+        let response = crate::site_router(&state, 8080, false).oneshot(get("/.well-known/acme-challenge/other")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// A server on a local port answering one request with `body`
+    async fn answer_with(body: Vec<u8>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            let _ = stream.write_all(head.as_bytes()).await;
+            let _ = stream.write_all(&body).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn replies_are_read_up_to_the_limit() {
+        let url = answer_with(vec![b'x'; MAX_REPLY_BYTES]).await;
+        let reply = request(None, Method::GET, &url, None).await.unwrap();
+        assert_eq!(reply.body.len(), MAX_REPLY_BYTES);
+
+        let url = answer_with(vec![b'x'; MAX_REPLY_BYTES + 1]).await;
+        let error = request(None, Method::GET, &url, None).await.err().unwrap();
+        assert_eq!(error.to_string(), format!("reply larger than {} bytes", MAX_REPLY_BYTES));
+    }
+}
@@ -1,25 +1,56 @@
 //! Admin Dashboard Module for WolfServe
-//! Provides authentication, statistics, and monitoring on port 5000
+//! Provides authentication, statistics, and monitoring on `[admin] port` (5000)
 
 use axum::{
-    extract::{State, Form},
-    http::{StatusCode, HeaderMap, header},
-    response::{Response, IntoResponse, Html, Redirect},
-    routing::get,
+    extract::{ConnectInfo, FromRequestParts, State, Form, Json, Query},
+    http::{StatusCode, HeaderMap, Method, header, request::Parts},
+    response::{Response, IntoResponse, Html, Redirect, sse::{Event, KeepAlive, Sse}},
+    routing::{get, post},
     Router,
     body::Body,
 };
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
 use std::fs;
-use std::collections::VecDeque;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use parking_lot::RwLock;
 use chrono::{DateTime, Utc, Duration};
+use futures_util::future::BoxFuture;
 use uuid::Uuid;
+use tokio::sync::broadcast;
+use crate::dump::VhostChanges;
+use crate::listeners::{self, PortListener};
+use crate::log_ring::LogRing;
+use crate::login_throttle::LoginThrottle;
+use crate::tap::DebugTap;
 
 const CREDENTIALS_FILE: &str = "wolfserve_admin.dat";
-const MAX_LOG_ENTRIES: usize = 50;
-const SESSION_TIMEOUT_HOURS: i64 = 24;
+/// Signed-in sessions, kept across restarts by the server (not by tests)
+pub const SESSIONS_FILE: &str = "wolfserve_sessions.json";
+/// Requests kept in memory unless `[admin] log_entries` says otherwise
+pub const DEFAULT_LOG_ENTRIES: usize = 50;
+/// Log rows rendered into the dashboard page; the rest are paged in by the
+/// browser
+const DASHBOARD_LOG_ROWS: usize = 25;
+/// Entries a `/api/logs/stream` subscriber may fall behind before it skips
+/// ahead past the ones it missed
+const LOG_STREAM_CAPACITY: usize = 256;
+/// Comment sent on an idle log stream so proxies don't time it out
+const LOG_STREAM_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15);
+/// Number of recent PHP timings kept for percentile calculations
+const PHP_LATENCY_SAMPLES: usize = 1000;
+/// Sign-in events kept for `/api/audit`
+const AUDIT_EVENTS: usize = 100;
+/// Added to every failed sign-in, so failures cannot be timed or rushed
+const LOGIN_FAILURE_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+/// An API token's use is audited at most this often, so a monitoring script
+/// polling every few seconds doesn't push everything else out
+const TOKEN_AUDIT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
 
 /// Request log entry
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -32,11 +63,182 @@ pub struct RequestLogEntry {
     pub client_ip: String,
     pub host: String,
     pub user_agent: String,
+    /// `HTTP/1.1`, `HTTP/2.0`, ...
+    pub protocol: String,
+    /// Time spent in the PHP backend (connect + execute); None for non-PHP requests
+    pub php_duration_ms: Option<u64>,
 }
 
-/// Server statistics
-#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+/// Server statistics. The counters are bumped by every request, so they are
+/// atomics rather than fields behind a lock; read them with `snapshot`.
+#[derive(Debug, Default)]
 pub struct ServerStats {
+    pub total_requests: AtomicU64,
+    pub requests_2xx: AtomicU64,
+    pub requests_3xx: AtomicU64,
+    pub requests_4xx: AtomicU64,
+    pub requests_5xx: AtomicU64,
+    pub total_response_time_ms: AtomicU64,
+    pub start_time: RwLock<Option<DateTime<Utc>>>,
+    pub bytes_sent: AtomicU64,
+    pub php_requests: AtomicU64,
+    pub total_php_time_ms: AtomicU64,
+    /// TLS handshakes that failed or ran past `[tls] handshake_timeout`
+    pub tls_handshake_failures: AtomicU64,
+    pub tls_handshakes: AtomicU64,
+    /// Completed handshakes by negotiated protocol (`TLSv1.3`) and by cipher
+    /// suite; a handshake costs far more than taking these locks
+    pub tls_protocols: RwLock<BTreeMap<String, u64>>,
+    pub tls_ciphers: RwLock<BTreeMap<String, u64>>,
+    /// Seconds served by earlier runs whose counters were restored, so the
+    /// request rate leaves out the time the server was down
+    pub previous_uptime_secs: AtomicU64,
+    /// Requests by the vhost that served them, keyed `name:port`; the write
+    /// lock is only taken for a vhost's first request
+    vhosts: RwLock<HashMap<String, VhostCounters>>,
+}
+
+#[derive(Debug, Default)]
+struct VhostCounters {
+    requests: AtomicU64,
+    requests_4xx: AtomicU64,
+    requests_5xx: AtomicU64,
+    total_response_time_ms: AtomicU64,
+}
+
+impl VhostCounters {
+    fn of(stats: &VhostStats) -> Self {
+        VhostCounters {
+            requests: AtomicU64::new(stats.requests),
+            requests_4xx: AtomicU64::new(stats.requests_4xx),
+            requests_5xx: AtomicU64::new(stats.requests_5xx),
+            total_response_time_ms: AtomicU64::new(stats.total_response_time_ms),
+        }
+    }
+
+    fn snapshot(&self) -> VhostStats {
+        VhostStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            requests_4xx: self.requests_4xx.load(Ordering::Relaxed),
+            requests_5xx: self.requests_5xx.load(Ordering::Relaxed),
+            total_response_time_ms: self.total_response_time_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// One vhost's share of the requests
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct VhostStats {
+    pub requests: u64,
+    pub requests_4xx: u64,
+    pub requests_5xx: u64,
+    pub total_response_time_ms: u64,
+}
+
+impl ServerStats {
+    /// Counters at zero, started now
+    pub fn new() -> Self {
+        Self {
+            start_time: RwLock::new(Some(Utc::now())),
+            ..Default::default()
+        }
+    }
+
+    /// Count a finished request
+    pub fn record(&self, status: u16, duration_ms: u64, php_duration_ms: Option<u64>) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.total_response_time_ms.fetch_add(duration_ms, Ordering::Relaxed);
+        let class = match status {
+            200..=299 => Some(&self.requests_2xx),
+            300..=399 => Some(&self.requests_3xx),
+            400..=499 => Some(&self.requests_4xx),
+            500..=599 => Some(&self.requests_5xx),
+            _ => None,
+        };
+        if let Some(class) = class {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(php_ms) = php_duration_ms {
+            self.php_requests.fetch_add(1, Ordering::Relaxed);
+            self.total_php_time_ms.fetch_add(php_ms, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a finished request against the vhost that served it
+    pub fn record_vhost(&self, key: &str, status: u16, duration_ms: u64) {
+        let count = |counters: &VhostCounters| {
+            counters.requests.fetch_add(1, Ordering::Relaxed);
+            counters.total_response_time_ms.fetch_add(duration_ms, Ordering::Relaxed);
+            match status {
+                400..=499 => counters.requests_4xx.fetch_add(1, Ordering::Relaxed),
+                500..=599 => counters.requests_5xx.fetch_add(1, Ordering::Relaxed),
+                _ => 0,
+            };
+        };
+        if let Some(counters) = self.vhosts.read().get(key) {
+            return count(counters);
+        }
+        count(self.vhosts.write().entry(key.to_string()).or_default());
+    }
+
+    pub fn record_tls_handshake_failure(&self) {
+        self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a completed handshake and what it negotiated
+    pub fn record_tls_handshake(&self, protocol: &str, cipher: &str) {
+        self.tls_handshakes.fetch_add(1, Ordering::Relaxed);
+        *self.tls_protocols.write().entry(protocol.to_string()).or_default() += 1;
+        *self.tls_ciphers.write().entry(cipher.to_string()).or_default() += 1;
+    }
+
+    /// Carry on from the counters of an earlier run, saved with its serving
+    /// time in `previous_uptime_secs`; the start time stays this run's
+    pub fn restore(&self, saved: &StatsSnapshot) {
+        self.total_requests.store(saved.total_requests, Ordering::Relaxed);
+        self.requests_2xx.store(saved.requests_2xx, Ordering::Relaxed);
+        self.requests_3xx.store(saved.requests_3xx, Ordering::Relaxed);
+        self.requests_4xx.store(saved.requests_4xx, Ordering::Relaxed);
+        self.requests_5xx.store(saved.requests_5xx, Ordering::Relaxed);
+        self.total_response_time_ms.store(saved.total_response_time_ms, Ordering::Relaxed);
+        self.bytes_sent.store(saved.bytes_sent, Ordering::Relaxed);
+        self.php_requests.store(saved.php_requests, Ordering::Relaxed);
+        self.total_php_time_ms.store(saved.total_php_time_ms, Ordering::Relaxed);
+        self.tls_handshake_failures.store(saved.tls_handshake_failures, Ordering::Relaxed);
+        self.tls_handshakes.store(saved.tls_handshakes, Ordering::Relaxed);
+        *self.tls_protocols.write() = saved.tls_protocols.clone();
+        *self.tls_ciphers.write() = saved.tls_ciphers.clone();
+        *self.vhosts.write() = saved.vhosts.iter().map(|(key, stats)| (key.clone(), VhostCounters::of(stats))).collect();
+        self.previous_uptime_secs.store(saved.previous_uptime_secs, Ordering::Relaxed);
+    }
+
+    /// The current values. Each counter is read on its own, so a snapshot
+    /// taken mid-request may be one request apart between counters.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            total_requests: self.total_requests.load(Ordering::Relaxed),
+            requests_2xx: self.requests_2xx.load(Ordering::Relaxed),
+            requests_3xx: self.requests_3xx.load(Ordering::Relaxed),
+            requests_4xx: self.requests_4xx.load(Ordering::Relaxed),
+            requests_5xx: self.requests_5xx.load(Ordering::Relaxed),
+            total_response_time_ms: self.total_response_time_ms.load(Ordering::Relaxed),
+            start_time: *self.start_time.read(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            php_requests: self.php_requests.load(Ordering::Relaxed),
+            total_php_time_ms: self.total_php_time_ms.load(Ordering::Relaxed),
+            tls_handshake_failures: self.tls_handshake_failures.load(Ordering::Relaxed),
+            tls_handshakes: self.tls_handshakes.load(Ordering::Relaxed),
+            tls_protocols: self.tls_protocols.read().clone(),
+            tls_ciphers: self.tls_ciphers.read().clone(),
+            vhosts: self.vhosts.read().iter().map(|(key, counters)| (key.clone(), counters.snapshot())).collect(),
+            previous_uptime_secs: self.previous_uptime_secs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Server statistics at one moment, for the API and the dashboard
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct StatsSnapshot {
     pub total_requests: u64,
     pub requests_2xx: u64,
     pub requests_3xx: u64,
@@ -45,9 +247,31 @@ pub struct ServerStats {
     pub total_response_time_ms: u64,
     pub start_time: Option<DateTime<Utc>>,
     pub bytes_sent: u64,
+    pub php_requests: u64,
+    pub total_php_time_ms: u64,
+    pub tls_handshake_failures: u64,
+    /// Completed TLS handshakes, in all and by protocol and cipher suite
+    #[serde(default)]
+    pub tls_handshakes: u64,
+    #[serde(default)]
+    pub tls_protocols: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub tls_ciphers: BTreeMap<String, u64>,
+    /// Requests by vhost, keyed `name:port`
+    #[serde(default)]
+    pub vhosts: BTreeMap<String, VhostStats>,
+    /// Serving time of earlier runs the counters include
+    #[serde(default)]
+    pub previous_uptime_secs: u64,
 }
 
-impl ServerStats {
+impl StatsSnapshot {
+    /// Seconds the counters cover: this run's uptime plus the earlier runs'
+    pub fn served_secs(&self) -> u64 {
+        let uptime = self.start_time.map_or(0, |start| Utc::now().signed_duration_since(start).num_seconds().max(0) as u64);
+        self.previous_uptime_secs + uptime
+    }
+
     pub fn avg_response_time_ms(&self) -> f64 {
         if self.total_requests == 0 {
             0.0
@@ -56,16 +280,37 @@ impl ServerStats {
         }
     }
     
+    pub fn avg_php_time_ms(&self) -> f64 {
+        if self.php_requests == 0 {
+            0.0
+        } else {
+            self.total_php_time_ms as f64 / self.php_requests as f64
+        }
+    }
+    
     pub fn requests_per_second(&self) -> f64 {
-        if let Some(start) = self.start_time {
-            let elapsed = Utc::now().signed_duration_since(start);
-            let secs = elapsed.num_seconds().max(1) as f64;
-            self.total_requests as f64 / secs
+        if self.start_time.is_some() {
+            self.total_requests as f64 / self.served_secs().max(1) as f64
         } else {
             0.0
         }
     }
     
+    /// Completed and failed TLS handshakes, for the dashboard card
+    pub fn tls_summary(&self) -> String {
+        format!("{} ok / {} failed", self.tls_handshakes, self.tls_handshake_failures)
+    }
+
+    /// The protocols and cipher suites negotiated, one per line with counts
+    pub fn tls_details(&self) -> String {
+        self.tls_protocols
+            .iter()
+            .chain(&self.tls_ciphers)
+            .map(|(name, count)| format!("{}: {}", name, count))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     pub fn uptime_string(&self) -> String {
         if let Some(start) = self.start_time {
             let elapsed = Utc::now().signed_duration_since(start);
@@ -80,131 +325,1124 @@ impl ServerStats {
     }
 }
 
-/// Session for authenticated users
-#[derive(Clone, Debug)]
+/// Session for authenticated users. Only the SHA-256 of its token is kept,
+/// so the sessions file cannot be used to sign in.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Session {
-    token: String,
+    token_hash: String,
+    /// Names the session in the dashboard without giving away the token
+    id: String,
     created_at: DateTime<Utc>,
     username: String,
+    /// The user's role at sign-in; sessions saved before roles were admins'
+    #[serde(default)]
+    role: Role,
+    /// Signed in with "remember me", so it lasts `remember_me` instead
+    remember: bool,
+    /// Fingerprint of the user's password hash at sign-in: the session ends
+    /// when the password changes, however it is changed. Empty in sessions
+    /// saved before it was kept.
+    #[serde(default)]
+    password: String,
+}
+
+/// A signed-in session, as listed on the dashboard
+#[derive(Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub username: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub remember: bool,
+    /// The session making the request
+    pub current: bool,
+}
+
+/// How long admin sessions last, from `[admin]`
+#[derive(Clone, Copy, Debug)]
+pub struct SessionTimeouts {
+    pub session: Duration,
+    /// Lifetime of "remember me" sessions; None hides the checkbox
+    pub remember_me: Option<Duration>,
+}
+
+impl Default for SessionTimeouts {
+    fn default() -> Self {
+        Self {
+            session: Duration::hours(24),
+            remember_me: Some(Duration::days(30)),
+        }
+    }
+}
+
+impl SessionTimeouts {
+    fn lifetime(&self, remember: bool) -> Duration {
+        match self.remember_me {
+            Some(remember_me) if remember => remember_me,
+            _ => self.session,
+        }
+    }
+}
+
+/// What a dashboard user may do
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including managing the users
+    #[default]
+    Admin,
+    /// The dashboard and the read-only APIs
+    Viewer,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Viewer => "viewer",
+        }
+    }
 }
 
-/// Stored credentials (encrypted)
-#[derive(Serialize, Deserialize)]
-struct StoredCredentials {
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(format!("unknown role {:?}; use admin or viewer", s)),
+        }
+    }
+}
+
+/// A dashboard user in the credentials file
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredUser {
     username: String,
     password_hash: String,
+    /// Files from before roles hold their one user without it
+    #[serde(default)]
+    role: Role,
+}
+
+/// An API token in the credentials file; the token itself is only shown
+/// when it is created
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredToken {
+    /// Short public id, for listing and revoking it
+    id: String,
+    name: String,
+    /// SHA-256 of the token
+    token_hash: String,
+    /// Viewer for read-only tokens
+    role: Role,
+    created_by: String,
+    created_at: DateTime<Utc>,
+}
+
+/// What tells one version of a file from another without reading it: it
+/// is saved by renaming a new file over it
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    inode: u64,
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl FileStamp {
+    fn of(meta: &fs::Metadata) -> Self {
+        Self { inode: meta.ino(), len: meta.len(), modified: meta.modified().ok() }
+    }
+}
+
+/// What the credentials file holds
+#[derive(Default, Serialize, Deserialize)]
+struct Credentials {
+    users: Vec<StoredUser>,
+    #[serde(default)]
+    tokens: Vec<StoredToken>,
+}
+
+/// The credentials file as written now, as a bare list of users, or as the
+/// single admin of older files
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredCredentials {
+    Current(Credentials),
+    Users(Vec<StoredUser>),
+    Single(StoredUser),
+}
+
+/// A user as listed by `/api/users`, without the password hash
+#[derive(Clone, Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+}
+
+/// An API token as listed by `/api/tokens`, without its hash
+#[derive(Clone, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Maintenance mode switches, seeded from config and toggled from the dashboard
+#[derive(Clone, Default, Serialize)]
+pub struct MaintenanceState {
+    /// Every site is in maintenance
+    pub global: bool,
+    /// Individual sites (ServerName) in maintenance
+    pub sites: BTreeSet<String>,
+}
+
+/// Outcome of the last reload of the Apache configuration
+#[derive(Clone, Serialize)]
+pub struct ReloadStatus {
+    pub time: DateTime<Utc>,
+    pub ok: bool,
+    pub message: String,
+    /// What asked for it: changed files, or an admin
+    pub trigger: String,
+    /// Vhosts added, removed and changed; empty when it failed
+    pub vhosts: VhostChanges,
+    /// The new errors that rejected it, each with its vhost's `file:line`
+    pub errors: Vec<String>,
+    /// How reloading the TLS certificates went, when there are any
+    pub certificates: Option<serde_json::Value>,
+}
+
+impl ReloadStatus {
+    fn summary(status: Option<&ReloadStatus>) -> String {
+        match status {
+            Some(s) => format!("{} {}", if s.ok { "OK" } else { "Failed" }, s.time.format("%H:%M:%S")),
+            None => "Never".to_string(),
+        }
+    }
+}
+
+/// Last certificate check for a vhost using the built-in ACME client
+#[derive(Clone, Serialize)]
+pub struct AcmeStatus {
+    pub time: DateTime<Utc>,
+    pub ok: bool,
+    pub message: String,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl AcmeStatus {
+    fn summary(statuses: &BTreeMap<String, AcmeStatus>) -> String {
+        let failed = statuses.values().filter(|s| !s.ok).count();
+        match (statuses.len(), failed) {
+            (0, _) => "Off".to_string(),
+            (total, 0) => format!("{} OK", total),
+            (_, failed) => format!("{} failed", failed),
+        }
+    }
+
+    /// One "name: message" line per vhost, for the dashboard tooltip
+    fn details(statuses: &BTreeMap<String, AcmeStatus>) -> String {
+        statuses
+            .iter()
+            .map(|(name, s)| format!("{}: {}", name, s.message))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// OCSP staple of a certificate
+#[derive(Clone, Serialize)]
+pub struct OcspStatus {
+    /// Last fetch attempt
+    pub time: DateTime<Utc>,
+    /// Whether a staple is being served
+    pub stapled: bool,
+    pub message: String,
+    /// thisUpdate of the stapled response
+    pub produced: Option<DateTime<Utc>>,
+    pub next_update: Option<DateTime<Utc>>,
+}
+
+impl OcspStatus {
+    fn summary(statuses: &BTreeMap<String, OcspStatus>) -> String {
+        let stapled = statuses.values().filter(|s| s.stapled).count();
+        match (statuses.len(), stapled) {
+            (0, _) => "Off".to_string(),
+            (total, stapled) if stapled == total => format!("{} stapled", total),
+            (total, stapled) => format!("{}/{} stapled", stapled, total),
+        }
+    }
+
+    /// One "name: message" line per certificate with the staple's age, for
+    /// the dashboard tooltip
+    fn details(statuses: &BTreeMap<String, OcspStatus>) -> String {
+        statuses
+            .iter()
+            .map(|(name, s)| match s.produced {
+                Some(produced) => format!("{}: {} ({}m old)", name, s.message, (Utc::now() - produced).num_minutes()),
+                None => format!("{}: {}", name, s.message),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Health of a PHP-FPM pool, from its last ping
+#[derive(Clone, Serialize)]
+pub struct FpmPoolStatus {
+    /// Last ping
+    pub time: DateTime<Utc>,
+    pub reachable: bool,
+    /// Last ping the pool answered
+    pub last_ok: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    pub message: String,
+    /// `name:port` of the loaded vhosts whose PHP runs in the pool
+    pub vhosts: Vec<String>,
+    /// From the pool's status page, when it serves one
+    pub workers: Option<FpmWorkers>,
+}
+
+/// Worker counts from a pool's status page
+#[derive(Clone, Serialize)]
+pub struct FpmWorkers {
+    pub active: u64,
+    pub idle: u64,
+    pub listen_queue: u64,
+}
+
+impl FpmPoolStatus {
+    fn summary(pools: &BTreeMap<String, FpmPoolStatus>) -> String {
+        let up = pools.values().filter(|p| p.reachable).count();
+        match (pools.len(), up) {
+            (0, _) => "Off".to_string(),
+            (total, up) if up == total => format!("{} up", total),
+            (total, up) => format!("{}/{} up", up, total),
+        }
+    }
+
+    /// One "address: message" line per pool with its workers, for the
+    /// dashboard tooltip
+    fn details(pools: &BTreeMap<String, FpmPoolStatus>) -> String {
+        pools
+            .iter()
+            .map(|(address, p)| match &p.workers {
+                Some(w) => format!("{}: {} ({} active, {} idle, {} queued)", address, p.message, w.active, w.idle, w.listen_queue),
+                None => format!("{}: {}", address, p.message),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The pools a loaded vhost uses that are down, with those vhosts, for
+    /// the dashboard's banner
+    fn down(pools: &BTreeMap<String, FpmPoolStatus>) -> Vec<String> {
+        pools
+            .iter()
+            .filter(|(_, p)| !p.reachable && !p.vhosts.is_empty())
+            .map(|(address, p)| format!("{} ({})", address, p.vhosts.join(", ")))
+            .collect()
+    }
 }
 
+/// How close a certificate is to its notAfter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryState {
+    Ok,
+    /// Within `[tls] expiry_warning_days`
+    Expiring,
+    Expired,
+}
+
+/// Expiry of a loaded certificate
+#[derive(Clone, Serialize)]
+pub struct CertExpiry {
+    pub names: Vec<String>,
+    pub not_after: DateTime<Utc>,
+    pub days_left: i64,
+    pub state: ExpiryState,
+    #[serde(skip)]
+    warning_days: u64,
+}
+
+impl CertExpiry {
+    pub fn new(names: Vec<String>, not_after: DateTime<Utc>, warning_days: u64) -> Self {
+        let left = not_after - Utc::now();
+        let state = if left <= Duration::zero() {
+            ExpiryState::Expired
+        } else if left < Duration::days(warning_days as i64) {
+            ExpiryState::Expiring
+        } else {
+            ExpiryState::Ok
+        };
+        CertExpiry { names, not_after, days_left: left.num_days(), state, warning_days }
+    }
+
+    /// The same certificate judged as of now
+    pub fn refreshed(&self) -> Self {
+        CertExpiry::new(self.names.clone(), self.not_after, self.warning_days)
+    }
+
+    fn count(expiries: &BTreeMap<String, CertExpiry>, state: ExpiryState) -> usize {
+        expiries.values().filter(|e| e.state == state).count()
+    }
+
+    fn summary(expiries: &BTreeMap<String, CertExpiry>) -> String {
+        match (Self::count(expiries, ExpiryState::Expired), Self::count(expiries, ExpiryState::Expiring)) {
+            _ if expiries.is_empty() => "None".to_string(),
+            (0, 0) => format!("{} OK", expiries.len()),
+            (0, expiring) => format!("{} expiring", expiring),
+            (expired, _) => format!("{} expired", expired),
+        }
+    }
+
+    /// One "name: expiry" line per certificate, for the dashboard tooltip
+    fn details(expiries: &BTreeMap<String, CertExpiry>) -> String {
+        expiries
+            .iter()
+            .map(|(name, e)| match e.state {
+                ExpiryState::Expired => format!("{}: expired {}", name, e.not_after.format("%Y-%m-%d")),
+                _ => format!("{}: expires {} ({} days)", name, e.not_after.format("%Y-%m-%d"), e.days_left),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Dashboard card highlight: red for expired, amber for expiring
+    fn card_class(expiries: &BTreeMap<String, CertExpiry>) -> &'static str {
+        if Self::count(expiries, ExpiryState::Expired) > 0 {
+            "error"
+        } else if Self::count(expiries, ExpiryState::Expiring) > 0 {
+            "warning"
+        } else {
+            ""
+        }
+    }
+
+    /// Every certificate judged as of now
+    fn current(expiries: &RwLock<BTreeMap<String, CertExpiry>>) -> BTreeMap<String, CertExpiry> {
+        expiries.read().iter().map(|(name, e)| (name.clone(), e.refreshed())).collect()
+    }
+}
+
+/// A sign-in attempt, lockout or configuration reload, for the dashboard's
+/// audit trail
+#[derive(Clone, Serialize, Debug)]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    /// `login`, `login_failed`, `lockout`, `token_used`, `token_rejected`,
+    /// `token_created`, `token_revoked` or `config_reload`
+    pub event: &'static str,
+    /// As typed on the login form, or the API token's name
+    pub username: String,
+    pub client_ip: String,
+    pub message: String,
+}
+
+/// Callback into the server for an admin API endpoint, answering with JSON
+pub type AdminHook = Box<dyn Fn() -> serde_json::Value + Send + Sync>;
+
+/// What importing a configuration snapshot would change, or why it can't be
+pub type ImportPreview = Box<dyn Fn(serde_json::Value) -> Result<serde_json::Value, String> + Send + Sync>;
+
+/// Reloads the Apache configuration, giving what asked for it; None while
+/// another reload is under way
+pub type ReloadHook = Box<dyn Fn(String) -> BoxFuture<'static, Option<ReloadStatus>> + Send + Sync>;
+
+/// The client behind a request, from its headers and the connection's peer
+pub type ClientAddrHook = Box<dyn Fn(&HeaderMap, Option<IpAddr>) -> Option<IpAddr> + Send + Sync>;
+
 /// Admin state
 pub struct AdminState {
     pub logs: RwLock<VecDeque<RequestLogEntry>>,
-    pub stats: RwLock<ServerStats>,
+    /// Entries `logs` keeps, from `[admin] log_entries`
+    log_capacity: AtomicUsize,
+    /// The log on disk, from `[admin] log_file`
+    pub log_ring: RwLock<Option<LogRing>>,
+    /// Each logged entry, for the `/api/logs/stream` subscribers
+    log_events: broadcast::Sender<RequestLogEntry>,
+    pub stats: ServerStats,
+    /// Most recent PHP backend timings, used for percentiles
+    php_latencies: RwLock<VecDeque<u64>>,
     sessions: RwLock<Vec<Session>>,
+    /// Failed sign-ins by client and username
+    login_throttle: LoginThrottle,
+    /// Recent sign-in events, oldest first
+    audit: RwLock<VecDeque<AuditEvent>>,
+    /// When each API token's use was last audited, by token id
+    token_audited: parking_lot::Mutex<HashMap<String, std::time::Instant>>,
+    /// The credentials file as last read, for checking API tokens
+    credentials: parking_lot::Mutex<Option<(FileStamp, Arc<Credentials>)>>,
+    /// Where sessions are saved on every change; None keeps them in memory
+    sessions_file: RwLock<Option<PathBuf>>,
+    /// Session lifetimes, fixed at startup
+    pub session_timeouts: RwLock<SessionTimeouts>,
+    pub maintenance: RwLock<MaintenanceState>,
+    /// Requests logged in full, seeded from `[debug_tap]`
+    pub debug_tap: RwLock<Option<DebugTap>>,
+    pub last_reload: RwLock<Option<ReloadStatus>>,
+    /// ACME certificate state by ServerName
+    pub acme: RwLock<BTreeMap<String, AcmeStatus>>,
+    /// OCSP stapling state by certificate label
+    pub ocsp: RwLock<BTreeMap<String, OcspStatus>>,
+    /// PHP-FPM pool health by address
+    pub fpm_pools: RwLock<BTreeMap<String, FpmPoolStatus>>,
+    /// Expiry of the loaded certificates by label
+    pub certificates: RwLock<BTreeMap<String, CertExpiry>>,
+    /// What each port serves, fixed at startup
+    pub listeners: RwLock<Vec<PortListener>>,
+    /// Builds the `/api/vhosts` document from the live site table
+    pub vhost_dump: RwLock<Option<AdminHook>>,
+    /// Reloads the TLS certificates for `/api/certificates/reload`
+    pub cert_reload: RwLock<Option<AdminHook>>,
+    /// Builds the `/api/config/export` snapshot of the running configuration
+    pub config_export: RwLock<Option<AdminHook>>,
+    /// Checks a snapshot posted to `/api/config/import`
+    pub config_import: RwLock<Option<ImportPreview>>,
+    /// Reloads the Apache configuration for `/api/reload`
+    pub config_reload: RwLock<Option<ReloadHook>>,
+    /// Finds the client of a request behind `[server] trusted_proxies`; the
+    /// peer's address is the client's until it is set
+    pub client_addr: RwLock<Option<ClientAddrHook>>,
 }
 
 impl AdminState {
     pub fn new() -> Self {
-        let mut stats = ServerStats::default();
-        stats.start_time = Some(Utc::now());
-        
         Self {
-            logs: RwLock::new(VecDeque::with_capacity(MAX_LOG_ENTRIES)),
-            stats: RwLock::new(stats),
+            logs: RwLock::new(VecDeque::with_capacity(DEFAULT_LOG_ENTRIES)),
+            log_capacity: AtomicUsize::new(DEFAULT_LOG_ENTRIES),
+            log_ring: RwLock::new(None),
+            log_events: broadcast::channel(LOG_STREAM_CAPACITY).0,
+            stats: ServerStats::new(),
+            php_latencies: RwLock::new(VecDeque::with_capacity(PHP_LATENCY_SAMPLES)),
             sessions: RwLock::new(Vec::new()),
+            login_throttle: LoginThrottle::default(),
+            audit: RwLock::new(VecDeque::with_capacity(AUDIT_EVENTS)),
+            token_audited: parking_lot::Mutex::new(HashMap::new()),
+            credentials: parking_lot::Mutex::new(None),
+            sessions_file: RwLock::new(None),
+            session_timeouts: RwLock::new(SessionTimeouts::default()),
+            maintenance: RwLock::new(MaintenanceState::default()),
+            debug_tap: RwLock::new(None),
+            last_reload: RwLock::new(None),
+            acme: RwLock::new(BTreeMap::new()),
+            ocsp: RwLock::new(BTreeMap::new()),
+            fpm_pools: RwLock::new(BTreeMap::new()),
+            certificates: RwLock::new(BTreeMap::new()),
+            listeners: RwLock::new(Vec::new()),
+            vhost_dump: RwLock::new(None),
+            config_export: RwLock::new(None),
+            config_import: RwLock::new(None),
+            config_reload: RwLock::new(None),
+            client_addr: RwLock::new(None),
+            cert_reload: RwLock::new(None),
         }
     }
     
+    /// Keep the newest `capacity` entries in memory from now on
+    pub fn set_log_capacity(&self, capacity: usize) {
+        self.log_capacity.store(capacity, Ordering::Relaxed);
+        let mut logs = self.logs.write();
+        let excess = logs.len().saturating_sub(capacity);
+        logs.drain(..excess);
+    }
+
+    /// Put back the request log of an earlier run, oldest first
+    pub fn restore_logs(&self, entries: Vec<RequestLogEntry>) {
+        let skip = entries.len().saturating_sub(self.log_capacity.load(Ordering::Relaxed));
+        *self.logs.write() = entries.into_iter().skip(skip).collect();
+    }
+    
     /// Log a request
     pub fn log_request(&self, entry: RequestLogEntry) {
-        // Update stats
-        {
-            let mut stats = self.stats.write();
-            stats.total_requests += 1;
-            stats.total_response_time_ms += entry.duration_ms;
-            
-            match entry.status {
-                200..=299 => stats.requests_2xx += 1,
-                300..=399 => stats.requests_3xx += 1,
-                400..=499 => stats.requests_4xx += 1,
-                500..=599 => stats.requests_5xx += 1,
-                _ => {}
+        self.stats.record(entry.status, entry.duration_ms, entry.php_duration_ms);
+
+        if let Some(php_ms) = entry.php_duration_ms {
+            let mut samples = self.php_latencies.write();
+            if samples.len() >= PHP_LATENCY_SAMPLES {
+                samples.pop_front();
             }
+            samples.push_back(php_ms);
         }
         
+        // Only cloned while a dashboard is streaming
+        if self.log_events.receiver_count() > 0 {
+            let _ = self.log_events.send(entry.clone());
+        }
+
+        if let Some(ring) = &*self.log_ring.read() {
+            ring.push(&entry);
+        }
+
         // Add log entry
         {
+            let capacity = self.log_capacity.load(Ordering::Relaxed);
             let mut logs = self.logs.write();
-            if logs.len() >= MAX_LOG_ENTRIES {
+            if logs.len() >= capacity {
                 logs.pop_front();
             }
             logs.push_back(entry);
         }
     }
     
+    /// PHP latency percentiles (p50, p95, p99) over the recent samples
+    pub fn php_latency_percentiles(&self) -> (u64, u64, u64) {
+        let mut samples: Vec<u64> = self.php_latencies.read().iter().copied().collect();
+        if samples.is_empty() {
+            return (0, 0, 0);
+        }
+        samples.sort_unstable();
+        let pick = |pct: usize| samples[((samples.len() - 1) * pct) / 100];
+        (pick(50), pick(95), pick(99))
+    }
+    
+    /// Whether a site (by ServerName) is currently in maintenance
+    pub fn in_maintenance(&self, site: Option<&str>) -> bool {
+        let maintenance = self.maintenance.read();
+        maintenance.global || site.is_some_and(|name| maintenance.sites.contains(&name.to_lowercase()))
+    }
+    
     /// Create a new session
-    fn create_session(&self, username: &str) -> String {
+    fn create_session(&self, username: &str, role: Role, remember: bool, password_hash: &str) -> String {
         let token = Uuid::new_v4().to_string();
+        let timeouts = *self.session_timeouts.read();
         let session = Session {
-            token: token.clone(),
+            token_hash: hash_token(&token),
+            id: Uuid::new_v4().simple().to_string()[..12].to_string(),
             created_at: Utc::now(),
             username: username.to_string(),
+            role,
+            remember: remember && timeouts.remember_me.is_some(),
+            password: hash_token(password_hash),
         };
         
         // Clean up expired sessions and add new one
         let mut sessions = self.sessions.write();
-        let cutoff = Utc::now() - Duration::hours(SESSION_TIMEOUT_HOURS);
-        sessions.retain(|s| s.created_at > cutoff);
+        let now = Utc::now();
+        sessions.retain(|s| s.created_at + timeouts.lifetime(s.remember) > now);
         sessions.push(session);
+        self.save_sessions(&sessions);
         
         token
     }
     
-    /// Validate a session token
-    fn validate_session(&self, token: &str) -> Option<String> {
-        let sessions = self.sessions.read();
-        let cutoff = Utc::now() - Duration::hours(SESSION_TIMEOUT_HOURS);
+    /// Validate a session token against the cutoff of its kind and the
+    /// user's current password; the user and role it belongs to
+    fn validate_session(&self, token: &str) -> Option<(String, Role)> {
+        let timeouts = *self.session_timeouts.read();
+        let now = Utc::now();
+        let token_hash = hash_token(token);
         
-        sessions.iter()
-            .find(|s| s.token == token && s.created_at > cutoff)
-            .map(|s| s.username.clone())
+        let session = self.sessions.read()
+            .iter()
+            .find(|s| s.token_hash == token_hash && s.created_at + timeouts.lifetime(s.remember) > now)
+            .cloned()?;
+        if !session.password.is_empty() && self.password_fingerprint(&session.username)? != session.password {
+            return None;
+        }
+        Some((session.username, session.role))
+    }
+
+    /// Fingerprint of `username`'s password hash in the credentials file;
+    /// None if there is no such user or the file cannot be read
+    fn password_fingerprint(&self, username: &str) -> Option<String> {
+        let credentials = self.cached_credentials().ok()??;
+        let user = credentials.users.iter().find(|user| user.username == username)?;
+        Some(hash_token(&user.password_hash))
     }
     
     /// Remove a session
     fn remove_session(&self, token: &str) {
+        let token_hash = hash_token(token);
         let mut sessions = self.sessions.write();
-        sessions.retain(|s| s.token != token);
+        sessions.retain(|s| s.token_hash != token_hash);
+        self.save_sessions(&sessions);
+    }
+
+    /// The unexpired sessions, oldest first; `current` is the caller's token
+    fn active_sessions(&self, current: &str) -> Vec<SessionInfo> {
+        let timeouts = *self.session_timeouts.read();
+        let now = Utc::now();
+        let current = hash_token(current);
+        self.sessions
+            .read()
+            .iter()
+            .map(|s| SessionInfo {
+                id: s.id.clone(),
+                username: s.username.clone(),
+                role: s.role,
+                created_at: s.created_at,
+                expires_at: s.created_at + timeouts.lifetime(s.remember),
+                remember: s.remember,
+                current: s.token_hash == current,
+            })
+            .filter(|s| s.expires_at > now)
+            .collect()
+    }
+
+    /// Sign out the session `id`, or every session but `current` when None;
+    /// the number of sessions removed
+    fn revoke_sessions(&self, id: Option<&str>, current: &str) -> usize {
+        let current = hash_token(current);
+        let mut sessions = self.sessions.write();
+        let before = sessions.len();
+        match id {
+            Some(id) => sessions.retain(|s| s.id != id),
+            None => sessions.retain(|s| s.token_hash == current),
+        }
+        self.save_sessions(&sessions);
+        before - sessions.len()
+    }
+
+    /// The client of a request from `peer`: see `client_addr`
+    fn client(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        match self.client_addr.read().as_ref() {
+            Some(client_addr) => client_addr(headers, peer),
+            None => peer,
+        }
+    }
+
+    /// Add a sign-in event to the audit trail
+    fn audit(&self, event: &'static str, username: &str, client: Option<IpAddr>, message: String) {
+        let mut audit = self.audit.write();
+        if audit.len() >= AUDIT_EVENTS {
+            audit.pop_front();
+        }
+        audit.push_back(AuditEvent {
+            timestamp: Utc::now(),
+            event,
+            username: username.chars().take(64).collect(),
+            client_ip: client.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+            message,
+        });
+    }
+
+    /// The API token `token`, noting its use in the audit trail at most once
+    /// per TOKEN_AUDIT_INTERVAL, and every unknown token
+    /// The credentials file, read again only once it has changed
+    fn cached_credentials(&self) -> Result<Option<Arc<Credentials>>, String> {
+        let stamp = match fs::metadata(CREDENTIALS_FILE) {
+            Ok(meta) => FileStamp::of(&meta),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("cannot read {}: {}", CREDENTIALS_FILE, e)),
+        };
+        let mut cached = self.credentials.lock();
+        if let Some((cached_stamp, credentials)) = cached.as_ref() {
+            if *cached_stamp == stamp {
+                return Ok(Some(credentials.clone()));
+            }
+        }
+        let credentials = read_credentials()?.map(Arc::new);
+        *cached = credentials.clone().map(|credentials| (stamp, credentials));
+        Ok(credentials)
+    }
+
+    fn authenticate_token(&self, token: &str, client: Option<IpAddr>, used_for: String) -> Option<Auth> {
+        let token_hash = hash_token(token);
+        // Read only: a bad credentials file refuses the token rather than
+        // being replaced
+        let credentials = match self.cached_credentials() {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                eprintln!("ERROR: cannot check API token for {}: {}", used_for, e);
+                return None;
+            }
+        };
+        let tokens = credentials.as_deref().map_or(&[][..], |credentials| &credentials.tokens);
+        let Some(stored) = tokens.iter().find(|stored| stored.token_hash == token_hash).cloned() else {
+            self.audit("token_rejected", "", client, format!("Unknown or revoked API token for {}", used_for));
+            return None;
+        };
+        let now = std::time::Instant::now();
+        let due = {
+            let mut audited = self.token_audited.lock();
+            let due = audited.get(&stored.id).is_none_or(|last| now.duration_since(*last) >= TOKEN_AUDIT_INTERVAL);
+            if due {
+                audited.insert(stored.id.clone(), now);
+            }
+            due
+        };
+        if due {
+            self.audit("token_used", &stored.name, client, format!("API token {} used for {}", stored.id, used_for));
+        }
+        Some(Auth { principal: stored.name, role: stored.role, method: AuthMethod::Token(stored.id), client, csrf: None })
     }
-}
 
-/// Load or create default credentials
-fn load_credentials() -> StoredCredentials {
-    if let Ok(data) = fs::read_to_string(CREDENTIALS_FILE) {
-        // Decode from base64
-        if let Ok(decoded) = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data) {
-            if let Ok(json) = String::from_utf8(decoded) {
-                if let Ok(creds) = serde_json::from_str::<StoredCredentials>(&json) {
-                    return creds;
+    /// Sign out every session of `username` but `current`, after the user is
+    /// removed or their password is changed; `current` goes on with the new
+    /// password
+    fn revoke_user_sessions(&self, username: &str, current: Option<&str>) {
+        let current = current.map(hash_token);
+        let password = self.password_fingerprint(username).unwrap_or_default();
+        let mut sessions = self.sessions.write();
+        sessions.retain(|s| s.username != username || Some(&s.token_hash) == current.as_ref());
+        for session in sessions.iter_mut().filter(|s| s.username == username) {
+            session.password = password.clone();
+        }
+        self.save_sessions(&sessions);
+    }
+
+    /// Keep sessions in `path` from now on, starting with the unexpired ones
+    /// saved there by the last run. An unreadable file is ignored and
+    /// replaced.
+    pub fn load_sessions(&self, path: &Path) {
+        let saved = match fs::read_to_string(path) {
+            Ok(data) => match serde_json::from_str::<Vec<Session>>(&data) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    eprintln!("Warning: ignoring corrupt sessions file {}: {}; everyone signs in again", path.display(), e);
+                    Vec::new()
                 }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                eprintln!("Warning: cannot read sessions file {}: {}; everyone signs in again", path.display(), e);
+                Vec::new()
             }
+        };
+        let timeouts = *self.session_timeouts.read();
+        let now = Utc::now();
+        *self.sessions_file.write() = Some(path.to_path_buf());
+        let mut sessions = self.sessions.write();
+        *sessions = saved.into_iter().filter(|s| s.created_at + timeouts.lifetime(s.remember) > now).collect();
+        self.save_sessions(&sessions);
+    }
+
+    /// Write the sessions to the sessions file, if there is one; called with
+    /// the sessions locked so saves land in the order of the changes
+    fn save_sessions(&self, sessions: &[Session]) {
+        let Some(path) = self.sessions_file.read().clone() else {
+            return;
+        };
+        if let Err(e) = write_private(&path, &serde_json::to_vec(sessions).unwrap_or_default()) {
+            eprintln!("Warning: cannot save admin sessions to {}: {}", path.display(), e);
         }
     }
-    
-    // Create default credentials
-    let default_hash = bcrypt::hash("admin", bcrypt::DEFAULT_COST).unwrap();
-    let creds = StoredCredentials {
-        username: "admin".to_string(),
-        password_hash: default_hash,
+}
+
+impl Default for AdminState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hex SHA-256 of a session token, which is all that is kept of it
+fn hash_token(token: &str) -> String {
+    let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, token.as_bytes());
+    digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The CSRF token of a session: derived from the session token, which a
+/// page on another site can neither read nor guess
+fn csrf_token(session_token: &str) -> String {
+    hash_token(&format!("csrf:{}", session_token))
+}
+
+/// Replace `path` with a file only we can read
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp)?;
+    file.write_all(contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Held while the credentials are read, changed and saved, so two changes
+/// at once don't lose one of them
+static CREDENTIALS_LOCK: parking_lot::Mutex<()> = parking_lot::const_mutex(());
+
+/// Read the credentials file without changing it: None if it does not
+/// exist yet, an error if it cannot be read or decoded or has no users
+fn read_credentials() -> Result<Option<Credentials>, String> {
+    let data = match fs::read_to_string(CREDENTIALS_FILE) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("cannot read {}: {}", CREDENTIALS_FILE, e)),
     };
-    
-    save_credentials(&creds);
-    creds
+    let corrupt = |e: String| format!("{} is corrupt: {}", CREDENTIALS_FILE, e);
+    // Decode from base64
+    let decoded = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data).map_err(|e| corrupt(e.to_string()))?;
+    let json = String::from_utf8(decoded).map_err(|e| corrupt(e.to_string()))?;
+    let credentials = match serde_json::from_str::<StoredCredentials>(&json).map_err(|e| corrupt(e.to_string()))? {
+        StoredCredentials::Current(credentials) => credentials,
+        StoredCredentials::Users(users) => Credentials { users, tokens: Vec::new() },
+        StoredCredentials::Single(user) => Credentials { users: vec![user], tokens: Vec::new() },
+    };
+    if credentials.users.is_empty() {
+        return Err(corrupt("no users".to_string()));
+    }
+    Ok(Some(credentials))
 }
 
-/// Save credentials to encrypted file
-fn save_credentials(creds: &StoredCredentials) {
-    let json = serde_json::to_string(creds).unwrap();
+/// Whether the login is still admin/admin, including when the credentials
+/// file does not exist yet; never creates the file
+pub fn has_default_credentials() -> bool {
+    read_credentials().ok().flatten().is_none_or(|credentials| {
+        credentials.users.iter().any(|user| user.username == "admin" && bcrypt::verify("admin", &user.password_hash).unwrap_or(false))
+    })
+}
+
+/// Create the credentials file with the default admin/admin login on the
+/// first run. A file that is already there is left alone, even one that
+/// cannot be read, so nothing a request does can reset the logins.
+pub fn init_credentials() {
+    let _lock = CREDENTIALS_LOCK.lock();
+    if !matches!(Path::new(CREDENTIALS_FILE).try_exists(), Ok(false)) {
+        return;
+    }
+    let credentials = Credentials {
+        users: vec![StoredUser {
+            username: "admin".to_string(),
+            password_hash: bcrypt::hash("admin", bcrypt::DEFAULT_COST).unwrap(),
+            role: Role::Admin,
+        }],
+        tokens: Vec::new(),
+    };
+    if let Err(e) = save_credentials(&credentials) {
+        eprintln!("Warning: cannot save admin credentials to {}: {}", CREDENTIALS_FILE, e);
+    }
+}
+
+/// Load the credentials; an error if the file is missing or bad
+fn load_credentials() -> Result<Credentials, String> {
+    read_credentials()?.ok_or_else(|| format!("{} does not exist; create it with `wolfserve admin init`", CREDENTIALS_FILE))
+}
+
+fn load_users() -> Result<Vec<StoredUser>, String> {
+    load_credentials().map(|credentials| credentials.users)
+}
+
+/// Save the credentials file, readable only by us
+fn save_credentials(credentials: &Credentials) -> std::io::Result<()> {
+    let json = serde_json::to_string(credentials).unwrap();
     let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, json.as_bytes());
-    let _ = fs::write(CREDENTIALS_FILE, encoded);
+    write_private(Path::new(CREDENTIALS_FILE), encoded.as_bytes())
+}
+
+/// Apply `change` to the credentials and save them, unless it refuses
+fn update_credentials<T>(change: impl FnOnce(&mut Credentials) -> Result<T, String>) -> Result<T, String> {
+    let _lock = CREDENTIALS_LOCK.lock();
+    let mut credentials = load_credentials()?;
+    let result = change(&mut credentials)?;
+    save_credentials(&credentials).map_err(|e| format!("cannot save {}: {}", CREDENTIALS_FILE, e))?;
+    Ok(result)
+}
+
+/// Apply `change` to the users and save them, unless it refuses
+fn update_users<T>(change: impl FnOnce(&mut Vec<StoredUser>) -> Result<T, String>) -> Result<T, String> {
+    update_credentials(|credentials| change(&mut credentials.users))
+}
+
+/// The users, without their password hashes
+fn list_users() -> Result<Vec<UserInfo>, String> {
+    Ok(load_users()?.into_iter().map(|user| UserInfo { username: user.username, role: user.role }).collect())
+}
+
+/// The API tokens, without their hashes
+fn list_tokens() -> Result<Vec<TokenInfo>, String> {
+    let tokens = load_credentials()?.tokens.into_iter().map(|token| TokenInfo {
+        id: token.id,
+        name: token.name,
+        role: token.role,
+        created_by: token.created_by,
+        created_at: token.created_at,
+    });
+    Ok(tokens.collect())
+}
+
+/// A listing of users or tokens as JSON, 500 when the credentials file
+/// cannot be read
+fn listing_response<T: Serialize>(listing: Result<T, String>) -> Response {
+    match listing {
+        Ok(listing) => Json(listing).into_response(),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot read the admin credentials").into_response()
+        }
+    }
+}
+
+/// Create an API token; the token, which is not kept, and its listing
+fn create_token(name: &str, role: Role, created_by: &str) -> Result<(String, TokenInfo), String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err("Token names are 1 to 64 characters".to_string());
+    }
+    let token = format!("wst_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let stored = StoredToken {
+        id: Uuid::new_v4().simple().to_string()[..12].to_string(),
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        role,
+        created_by: created_by.to_string(),
+        created_at: Utc::now(),
+    };
+    let info = TokenInfo {
+        id: stored.id.clone(),
+        name: stored.name.clone(),
+        role,
+        created_by: stored.created_by.clone(),
+        created_at: stored.created_at,
+    };
+    update_credentials(|credentials| {
+        credentials.tokens.push(stored);
+        Ok(())
+    })?;
+    Ok((token, info))
+}
+
+fn revoke_token(id: &str) -> Result<StoredToken, String> {
+    update_credentials(|credentials| match credentials.tokens.iter().position(|token| token.id == id) {
+        Some(index) => Ok(credentials.tokens.remove(index)),
+        None => Err(format!("No token {}", id)),
+    })
+}
+
+/// The rule for new passwords, from the dashboard or the command line
+fn check_password(password: &str) -> Result<(), String> {
+    if password.len() < 4 {
+        return Err("Password must be at least 4 characters".to_string());
+    }
+    Ok(())
+}
+
+/// The rule for usernames
+fn check_username(username: &str) -> Result<(), String> {
+    if username.is_empty() || username.chars().count() > 64 || username.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Usernames are 1 to 64 characters without spaces".to_string());
+    }
+    Ok(())
+}
+
+/// Create the credentials file with one admin; an error if it exists
+fn create_credentials(username: &str, password: &str) -> Result<(), String> {
+    check_username(username)?;
+    check_password(password)?;
+    let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| e.to_string())?;
+    let _lock = CREDENTIALS_LOCK.lock();
+    if !matches!(Path::new(CREDENTIALS_FILE).try_exists(), Ok(false)) {
+        return Err(format!("{} already exists", CREDENTIALS_FILE));
+    }
+    let credentials = Credentials {
+        users: vec![StoredUser { username: username.to_string(), password_hash, role: Role::Admin }],
+        tokens: Vec::new(),
+    };
+    save_credentials(&credentials).map_err(|e| format!("cannot save {}: {}", CREDENTIALS_FILE, e))
+}
+
+fn add_user(username: &str, password: &str, role: Role) -> Result<(), String> {
+    check_username(username)?;
+    check_password(password)?;
+    let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| e.to_string())?;
+    update_users(|users| {
+        if users.iter().any(|user| user.username == username) {
+            return Err(format!("User {} already exists", username));
+        }
+        users.push(StoredUser { username: username.to_string(), password_hash, role });
+        Ok(())
+    })
+}
+
+/// Remove a user, unless they are the last admin
+fn remove_user(username: &str) -> Result<(), String> {
+    update_users(|users| {
+        let Some(index) = users.iter().position(|user| user.username == username) else {
+            return Err(format!("No user {}", username));
+        };
+        if users[index].role == Role::Admin && users.iter().filter(|user| user.role == Role::Admin).count() == 1 {
+            return Err(format!("{} is the last admin; add another admin first", username));
+        }
+        users.remove(index);
+        Ok(())
+    })
+}
+
+fn set_password(username: &str, password: &str) -> Result<(), String> {
+    check_password(password)?;
+    let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| e.to_string())?;
+    update_users(|users| match users.iter_mut().find(|user| user.username == username) {
+        Some(user) => {
+            user.password_hash = password_hash;
+            Ok(())
+        }
+        None => Err(format!("No user {}", username)),
+    })
+}
+
+/// `wolfserve admin <command>`: manage the dashboard users in the current
+/// directory's credentials file; returns the exit status. Only `init`
+/// creates the file, the others need it to be there.
+pub fn run_cli(args: &[String]) -> i32 {
+    const USAGE: &str = "usage: wolfserve admin init [<name>]\n       wolfserve admin add-user <name> [--role admin|viewer]\n       wolfserve admin set-password <name>\n       wolfserve admin remove-user <name>\n       wolfserve admin list-users";
+    let result = match args {
+        [command, rest @ ..] if command == "init" && rest.len() <= 1 => {
+            let name = rest.first().map_or("admin", String::as_str);
+            read_password()
+                .and_then(|password| create_credentials(name, &password))
+                .map(|()| println!("Created {} with the admin {}", CREDENTIALS_FILE, name))
+        }
+        [command, name, rest @ ..] if command == "add-user" => {
+            let role = match rest {
+                [] => Ok(Role::Admin),
+                [flag, role] if flag == "--role" => role.parse(),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            };
+            role.and_then(|role| {
+                // Before asking for a password it could not store
+                load_credentials()?;
+                let password = read_password()?;
+                add_user(name, &password, role)?;
+                println!("Added {} ({})", name, role.as_str());
+                Ok(())
+            })
+        }
+        [command, name] if command == "set-password" => {
+            load_credentials()
+                .and_then(|_| read_password())
+                .and_then(|password| set_password(name, &password))
+                .map(|()| println!("Changed the password of {}", name))
+        }
+        [command, name] if command == "remove-user" => remove_user(name).map(|()| println!("Removed {}", name)),
+        [command] if command == "list-users" => {
+            list_users().map(|users| {
+                for user in users {
+                    println!("{}\t{}", user.username, user.role.as_str());
+                }
+            })
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            1
+        }
+    }
+}
+
+/// The password for a user: the first line of stdin, asked for when that
+/// is a terminal
+fn read_password() -> Result<String, String> {
+    use std::io::{BufRead, IsTerminal};
+    if std::io::stdin().is_terminal() {
+        eprint!("Password: ");
+    }
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line).map_err(|e| format!("cannot read the password: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
 }
 
 /// Get session token from cookie
@@ -222,16 +1460,159 @@ fn get_session_token(headers: &HeaderMap) -> Option<String> {
         })
 }
 
-/// Check if request is authenticated
-fn is_authenticated(headers: &HeaderMap, state: &AdminState) -> Option<String> {
-    let token = get_session_token(headers)?;
-    state.validate_session(&token)
+/// The token of an `Authorization: Bearer` header
+fn get_bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim()).filter(|token| !token.is_empty())
+}
+
+/// How a request was authenticated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The dashboard's session cookie
+    Session,
+    /// An API token, by its id
+    Token(String),
+}
+
+/// Who made an authenticated request: a signed-in user, or on `/api/`
+/// routes an API token. Handlers that take it answer 401 to anyone else.
+#[derive(Clone, Debug)]
+pub struct Auth {
+    /// The username, or the token's name
+    pub principal: String,
+    pub role: Role,
+    pub method: AuthMethod,
+    /// The connection's address
+    pub client: Option<IpAddr>,
+    /// The session's CSRF token; None for API tokens, which a browser never
+    /// sends on its own
+    pub csrf: Option<String>,
+}
+
+impl Auth {
+    /// 403 unless the request may change things
+    #[allow(clippy::result_large_err)]
+    fn require_admin(&self) -> Result<(), Response> {
+        match self.role {
+            Role::Admin => Ok(()),
+            Role::Viewer => Err((StatusCode::FORBIDDEN, "Admins only").into_response()),
+        }
+    }
+
+    /// 403 unless an admin signed in to the dashboard made the request; API
+    /// tokens cannot mint or revoke tokens
+    #[allow(clippy::result_large_err)]
+    fn require_signed_in_admin(&self) -> Result<(), Response> {
+        self.require_admin()?;
+        match self.method {
+            AuthMethod::Session => Ok(()),
+            AuthMethod::Token(_) => Err((StatusCode::FORBIDDEN, "API tokens are managed from the dashboard").into_response()),
+        }
+    }
+}
+
+/// Names the principal in log lines: the username, or the token
+impl std::fmt::Display for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.method {
+            AuthMethod::Session => f.write_str(&self.principal),
+            AuthMethod::Token(id) => write!(f, "API token {} ({})", self.principal, id),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<Arc<AdminState>> for Auth {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AdminState>) -> Result<Self, Self::Rejection> {
+        let unauthorized = || (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        let client = state.client(&parts.headers, peer);
+        if parts.uri.path().starts_with("/api/") {
+            if let Some(token) = get_bearer_token(&parts.headers) {
+                let used_for = format!("{} {}", parts.method, parts.uri.path());
+                return state.authenticate_token(token, client, used_for).ok_or_else(unauthorized);
+            }
+        }
+        let token = get_session_token(&parts.headers).ok_or_else(unauthorized)?;
+        let (username, role) = state.validate_session(&token).ok_or_else(unauthorized)?;
+        let csrf = csrf_token(&token);
+        // A browser sends the cookie with any site's request, so a session's
+        // changes through the API must also carry the token the dashboard's
+        // scripts got with the page. The change-password form posts it as a field.
+        let safe = matches!(parts.method, Method::GET | Method::HEAD | Method::OPTIONS);
+        if !safe && parts.uri.path().starts_with("/api/") {
+            let sent = parts.headers.get("x-csrf-token").and_then(|v| v.to_str().ok());
+            if sent != Some(csrf.as_str()) {
+                return Err((StatusCode::FORBIDDEN, "Missing or wrong CSRF token").into_response());
+            }
+        }
+        Ok(Auth { principal: username, role, method: AuthMethod::Session, client, csrf: Some(csrf) })
+    }
 }
 
 #[derive(Deserialize)]
 struct LoginForm {
     username: String,
     password: String,
+    /// The "remember me" checkbox, present when ticked
+    remember: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MaintenanceToggle {
+    enabled: bool,
+    /// Site to toggle; the global switch when omitted
+    site: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SessionRevoke {
+    /// Session to sign out; every other session when omitted
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    #[serde(default)]
+    role: Role,
+}
+
+#[derive(Deserialize)]
+struct NewToken {
+    name: String,
+    /// Only the read APIs, as a viewer
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenTarget {
+    id: String,
+}
+
+/// A new API token: the only time the token itself is sent
+#[derive(Serialize)]
+struct CreatedToken {
+    token: String,
+    #[serde(flatten)]
+    info: TokenInfo,
+}
+
+#[derive(Deserialize)]
+struct UserTarget {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct UserPassword {
+    username: String,
+    password: String,
 }
 
 #[derive(Deserialize)]
@@ -239,6 +1620,9 @@ struct ChangePasswordForm {
     current_password: String,
     new_password: String,
     confirm_password: String,
+    /// The session's CSRF token, from a hidden field
+    #[serde(default)]
+    csrf_token: String,
 }
 
 /// Create the admin router
@@ -248,39 +1632,124 @@ pub fn admin_router(state: Arc<AdminState>) -> Router {
         .route("/login", get(login_page).post(login_handler))
         .route("/logout", get(logout_handler))
         .route("/change-password", get(change_password_page).post(change_password_handler))
+        .route("/vhosts", get(vhosts_page))
         .route("/api/stats", get(api_stats))
         .route("/api/logs", get(api_logs))
+        .route("/api/logs/stream", get(api_logs_stream))
+        .route("/api/logs/export", get(api_logs_export))
+        .route("/api/vhosts", get(api_vhosts))
+        .route("/api/php", get(api_php))
+        .route("/api/reload", post(api_reload_config))
+        .route("/api/certificates/reload", post(api_reload_certificates))
+        .route("/api/config/export", get(api_export_config))
+        .route("/api/config/import", post(api_import_config))
+        .route("/api/maintenance", get(api_maintenance).post(api_set_maintenance))
+        .route("/api/debug-tap", get(api_debug_tap).post(api_set_debug_tap).delete(api_clear_debug_tap))
+        .route("/api/sessions", get(api_sessions))
+        .route("/api/sessions/revoke", post(api_revoke_sessions))
+        .route("/api/audit", get(api_audit))
+        .route("/api/users", get(api_users).post(api_add_user))
+        .route("/api/users/remove", post(api_remove_user))
+        .route("/api/users/password", post(api_set_user_password))
+        .route("/api/tokens", get(api_tokens).post(api_create_token))
+        .route("/api/tokens/revoke", post(api_revoke_token))
         .with_state(state)
 }
 
-async fn login_page() -> Html<String> {
-    Html(LOGIN_HTML.to_string())
+async fn login_page(State(state): State<Arc<AdminState>>) -> Html<String> {
+    Html(login_html(&state, ""))
+}
+
+/// The login form, with the "remember me" checkbox when it is enabled
+fn login_html(state: &AdminState, error: &str) -> String {
+    let remember = match state.session_timeouts.read().remember_me {
+        Some(lifetime) => format!(
+            r#"<div class="form-group remember">
+                <label><input type="checkbox" name="remember" value="on"> Remember me for {} days</label>
+            </div>"#,
+            lifetime.num_days()
+        ),
+        None => String::new(),
+    };
+    LOGIN_HTML.replace("<!-- ERROR -->", error).replace("<!-- REMEMBER -->", &remember)
 }
 
 async fn login_handler(
     State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     Form(form): Form<LoginForm>,
 ) -> Response {
-    let creds = load_credentials();
-    
-    if form.username == creds.username {
-        if let Ok(true) = bcrypt::verify(&form.password, &creds.password_hash) {
-            let token = state.create_session(&form.username);
-            
-            return Response::builder()
-                .status(StatusCode::SEE_OTHER)
-                .header(header::LOCATION, "/")
-                .header(
-                    header::SET_COOKIE,
-                    format!("wolfserve_session={}; Path=/; HttpOnly; SameSite=Strict", token)
-                )
-                .body(Body::empty())
-                .unwrap();
+    let client = state.client(&headers, connect_info.map(|ConnectInfo(addr)| addr.ip()));
+    if let Some(left) = state.login_throttle.locked_for(client) {
+        return too_many_sign_ins(&state, left);
+    }
+    // A username guessed from many addresses is slowed rather than locked
+    if let Some(delay) = state.login_throttle.delay_for(&form.username) {
+        tokio::time::sleep(delay).await;
+    }
+    let users = match load_users() {
+        Ok(users) => users,
+        Err(e) => {
+            eprintln!("ERROR: cannot check sign-in of {}: {}", form.username.chars().take(64).collect::<String>(), e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Cannot read the admin credentials").into_response();
         }
+    };
+    let user = users.iter().find(|user| user.username == form.username);
+    
+    // A hash is checked whatever the username, so a wrong username takes
+    // as long to refuse as a wrong password
+    let password_hash = user.unwrap_or(&users[0]).password_hash.as_str();
+    let password_ok = bcrypt::verify(&form.password, password_hash).unwrap_or(false);
+    if let Some(user) = user.filter(|_| password_ok) {
+        state.login_throttle.record_success(client, &form.username);
+        state.audit("login", &form.username, client, "Signed in".to_string());
+        let remember = form.remember.is_some();
+        let token = state.create_session(&user.username, user.role, remember, &user.password_hash);
+        // A remembered session outlives the browser; others end with it
+        let max_age = match state.session_timeouts.read().remember_me {
+            Some(lifetime) if remember => format!("; Max-Age={}", lifetime.num_seconds()),
+            _ => String::new(),
+        };
+        
+        return Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(header::LOCATION, "/")
+            .header(
+                header::SET_COOKIE,
+                format!("wolfserve_session={}; Path=/; HttpOnly; SameSite=Strict{}", token, max_age)
+            )
+            .body(Body::empty())
+            .unwrap();
     }
     
-    Html(LOGIN_HTML.replace("<!-- ERROR -->", 
-        r#"<div class="error">Invalid username or password</div>"#)).into_response()
+    let lockout = state.login_throttle.record_failure(client, &form.username);
+    state.audit("login_failed", &form.username, client, "Invalid username or password".to_string());
+    if let Some(duration) = lockout {
+        let message = format!("Sign-in locked for {}s after repeated failures", duration.as_secs());
+        eprintln!(
+            "Admin sign-ins from {} locked for {}s after repeated failures, the last as {:?}",
+            client.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+            duration.as_secs(),
+            form.username
+        );
+        state.audit("lockout", &form.username, client, message);
+    }
+    tokio::time::sleep(LOGIN_FAILURE_DELAY).await;
+    match lockout {
+        Some(duration) => too_many_sign_ins(&state, duration),
+        None => Html(login_html(&state, r#"<div class="error">Invalid username or password</div>"#)).into_response(),
+    }
+}
+
+/// 429 with the login form while sign-ins are locked for `left`
+fn too_many_sign_ins(state: &AdminState, left: std::time::Duration) -> Response {
+    // Rounded up, so a client that waits as told is let in
+    let secs = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    let error = format!(r#"<div class="error">Too many failed sign-ins; try again in {} seconds</div>"#, secs);
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, Html(login_html(state, &error))).into_response();
+    response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+    response
 }
 
 async fn logout_handler(
@@ -304,14 +1773,11 @@ async fn logout_handler(
 
 async fn dashboard_handler(
     State(state): State<Arc<AdminState>>,
-    headers: HeaderMap,
+    auth: Option<Auth>,
 ) -> Response {
-    match is_authenticated(&headers, &state) {
-        Some(username) => {
-            let stats = state.stats.read().clone();
-            let logs = state.logs.read().clone();
-            
-            let html = generate_dashboard_html(&username, &stats, &logs);
+    match auth {
+        Some(auth) => {
+            let html = dashboard_html(&auth.principal, auth.role, auth.csrf.as_deref().unwrap_or_default(), &state);
             Html(html).into_response()
         }
         None => {
@@ -320,63 +1786,85 @@ async fn dashboard_handler(
     }
 }
 
-async fn change_password_page(
-    State(state): State<Arc<AdminState>>,
-    headers: HeaderMap,
-) -> Response {
-    match is_authenticated(&headers, &state) {
-        Some(_) => Html(CHANGE_PASSWORD_HTML.to_string()).into_response(),
+async fn vhosts_page(auth: Option<Auth>) -> Response {
+    match auth {
+        Some(auth) => Html(
+            VHOSTS_HTML.replace("{{USERNAME}}", &html_escape(&auth.principal)).replace("{{ROLE}}", auth.role.as_str()),
+        )
+        .into_response(),
+        None => Redirect::to("/login").into_response(),
+    }
+}
+
+async fn change_password_page(auth: Option<Auth>) -> Response {
+    match auth {
+        Some(auth) => Html(change_password_html(&auth, "")).into_response(),
         None => Redirect::to("/login").into_response(),
     }
 }
 
 async fn change_password_handler(
     State(state): State<Arc<AdminState>>,
+    auth: Option<Auth>,
     headers: HeaderMap,
     Form(form): Form<ChangePasswordForm>,
 ) -> Response {
-    if is_authenticated(&headers, &state).is_none() {
+    let Some(auth) = auth else {
         return Redirect::to("/login").into_response();
+    };
+    if auth.csrf.as_deref() != Some(form.csrf_token.as_str()) {
+        return (StatusCode::FORBIDDEN, "Missing or wrong CSRF token").into_response();
     }
+    let username = auth.principal.as_str();
     
-    let creds = load_credentials();
+    let current_hash = match load_users() {
+        Ok(users) => users.into_iter().find(|user| user.username == username).map(|user| user.password_hash),
+        Err(e) => {
+            eprintln!("ERROR: cannot change the password of {}: {}", username, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Cannot read the admin credentials").into_response();
+        }
+    };
     
     // Verify current password
-    if bcrypt::verify(&form.current_password, &creds.password_hash).unwrap_or(false) {
+    if current_hash.is_some_and(|hash| bcrypt::verify(&form.current_password, &hash).unwrap_or(false)) {
         if form.new_password == form.confirm_password {
-            if form.new_password.len() >= 4 {
-                let new_hash = bcrypt::hash(&form.new_password, bcrypt::DEFAULT_COST).unwrap();
-                let new_creds = StoredCredentials {
-                    username: creds.username,
-                    password_hash: new_hash,
-                };
-                save_credentials(&new_creds);
-                
-                return Html(CHANGE_PASSWORD_HTML.replace("<!-- MESSAGE -->",
-                    r#"<div class="success">Password changed successfully!</div>"#)).into_response();
-            } else {
-                return Html(CHANGE_PASSWORD_HTML.replace("<!-- MESSAGE -->",
-                    r#"<div class="error">Password must be at least 4 characters</div>"#)).into_response();
-            }
+            return match set_password(username, &form.new_password) {
+                Ok(()) => {
+                    state.revoke_user_sessions(username, get_session_token(&headers).as_deref());
+                    Html(change_password_html(&auth,
+                        r#"<div class="success">Password changed successfully! Your other sessions are signed out.</div>"#)).into_response()
+                }
+                Err(e) => Html(change_password_html(&auth,
+                    &format!(r#"<div class="error">{}</div>"#, html_escape(&e)))).into_response(),
+            };
         } else {
-            return Html(CHANGE_PASSWORD_HTML.replace("<!-- MESSAGE -->",
+            return Html(change_password_html(&auth,
                 r#"<div class="error">New passwords do not match</div>"#)).into_response();
         }
     }
     
-    Html(CHANGE_PASSWORD_HTML.replace("<!-- MESSAGE -->",
+    Html(change_password_html(&auth,
         r#"<div class="error">Current password is incorrect</div>"#)).into_response()
 }
 
+/// The change-password form for `auth`'s session, with `message` above it
+fn change_password_html(auth: &Auth, message: &str) -> String {
+    CHANGE_PASSWORD_HTML
+        .replace("<!-- MESSAGE -->", message)
+        .replace("{{CSRF_TOKEN}}", auth.csrf.as_deref().unwrap_or_default())
+}
+
 async fn api_stats(
     State(state): State<Arc<AdminState>>,
-    headers: HeaderMap,
+    _auth: Auth,
 ) -> Response {
-    if is_authenticated(&headers, &state).is_none() {
-        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
-    }
-    
-    let stats = state.stats.read();
+    let (php_p50, php_p95, php_p99) = state.php_latency_percentiles();
+    let last_reload = state.last_reload.read().clone();
+    let acme = state.acme.read().clone();
+    let ocsp = state.ocsp.read().clone();
+    let fpm_pools = state.fpm_pools.read().clone();
+    let certificates = CertExpiry::current(&state.certificates);
+    let stats = state.stats.snapshot();
     let json = serde_json::json!({
         "total_requests": stats.total_requests,
         "requests_2xx": stats.requests_2xx,
@@ -384,8 +1872,35 @@ async fn api_stats(
         "requests_4xx": stats.requests_4xx,
         "requests_5xx": stats.requests_5xx,
         "avg_response_time_ms": stats.avg_response_time_ms(),
+        "php_requests": stats.php_requests,
+        "avg_php_time_ms": stats.avg_php_time_ms(),
+        "tls_handshake_failures": stats.tls_handshake_failures,
+        "tls_handshakes": stats.tls_handshakes,
+        "tls_protocols": stats.tls_protocols,
+        "tls_ciphers": stats.tls_ciphers,
+        "tls_summary": stats.tls_summary(),
+        "tls_details": stats.tls_details(),
+        "php_p50_ms": php_p50,
+        "php_p95_ms": php_p95,
+        "php_p99_ms": php_p99,
         "requests_per_second": stats.requests_per_second(),
         "uptime": stats.uptime_string(),
+        "last_reload": last_reload,
+        "last_reload_summary": ReloadStatus::summary(last_reload.as_ref()),
+        "acme": acme,
+        "acme_summary": AcmeStatus::summary(&acme),
+        "acme_details": AcmeStatus::details(&acme),
+        "ocsp": ocsp,
+        "ocsp_summary": OcspStatus::summary(&ocsp),
+        "ocsp_details": OcspStatus::details(&ocsp),
+        "php_pools": fpm_pools,
+        "php_pools_summary": FpmPoolStatus::summary(&fpm_pools),
+        "php_pools_details": FpmPoolStatus::details(&fpm_pools),
+        "php_pools_down": FpmPoolStatus::down(&fpm_pools),
+        "certificates": certificates,
+        "certificates_summary": CertExpiry::summary(&certificates),
+        "certificates_details": CertExpiry::details(&certificates),
+        "certificates_class": CertExpiry::card_class(&certificates),
     });
     
     Response::builder()
@@ -395,16 +1910,120 @@ async fn api_stats(
         .unwrap()
 }
 
+/// `/api/logs` query string: filters over the log, all optional, and the
+/// page of the newest-first matches to return
+#[derive(Deserialize, Default)]
+struct LogQuery {
+    /// A class (`5xx`) or one code (`404`)
+    status: Option<String>,
+    method: Option<String>,
+    /// Substring of the path
+    path: Option<String>,
+    /// Regex over the path
+    path_regex: Option<String>,
+    /// Host header, with or without the port
+    host: Option<String>,
+    client_ip: Option<String>,
+    /// Only entries at or after this RFC 3339 time
+    since: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+/// A LogQuery's filters, checked and compiled
+struct LogFilter {
+    status: Option<std::ops::RangeInclusive<u16>>,
+    method: Option<String>,
+    path: Option<String>,
+    path_regex: Option<regex::Regex>,
+    host: Option<String>,
+    client_ip: Option<String>,
+    since: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    fn new(query: &LogQuery) -> Result<Self, String> {
+        let status = match query.status.as_deref().map(str::to_ascii_lowercase) {
+            None => None,
+            Some(status) => Some(match status.strip_suffix("xx").map(str::parse::<u16>) {
+                Some(Ok(class @ 1..=5)) => class * 100..=class * 100 + 99,
+                Some(_) => return Err(format!("invalid status class {}", status)),
+                None => match status.parse::<u16>() {
+                    Ok(code @ 100..=599) => code..=code,
+                    _ => return Err(format!("invalid status {}", status)),
+                },
+            }),
+        };
+        let path_regex = match &query.path_regex {
+            Some(pattern) => Some(regex::Regex::new(pattern).map_err(|e| format!("invalid path_regex: {}", e))?),
+            None => None,
+        };
+        Ok(LogFilter {
+            status,
+            method: query.method.clone(),
+            path: query.path.clone(),
+            path_regex,
+            host: query.host.clone(),
+            client_ip: query.client_ip.clone(),
+            since: query.since,
+        })
+    }
+
+    fn matches(&self, log: &RequestLogEntry) -> bool {
+        let host_without_port = log.host.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map_or(log.host.as_str(), |(host, _)| host);
+        self.status.as_ref().is_none_or(|range| range.contains(&log.status))
+            && self.method.as_ref().is_none_or(|method| method.eq_ignore_ascii_case(&log.method))
+            && self.path.as_ref().is_none_or(|path| log.path.contains(path.as_str()))
+            && self.path_regex.as_ref().is_none_or(|regex| regex.is_match(&log.path))
+            && self.host.as_ref().is_none_or(|host| host.eq_ignore_ascii_case(&log.host) || host.eq_ignore_ascii_case(host_without_port))
+            && self.client_ip.as_ref().is_none_or(|ip| *ip == log.client_ip)
+            && self.since.is_none_or(|since| log.timestamp >= since)
+    }
+}
+
+/// One page of the matching log entries, newest first, with how many match
+/// in all
+#[derive(Serialize)]
+struct LogPage<'a> {
+    total: usize,
+    offset: usize,
+    entries: Vec<&'a RequestLogEntry>,
+}
+
+/// The entries of the on-disk log older than any still in memory, oldest
+/// first; None without a log file or when it cannot be read
+async fn older_log_entries(state: &AdminState) -> Option<Vec<RequestLogEntry>> {
+    let ring = state.log_ring.read().clone()?;
+    let mut entries = ring.read().await?;
+    if let Some(oldest) = state.logs.read().front().map(|log| log.timestamp) {
+        entries.retain(|log| log.timestamp < oldest);
+    }
+    Some(entries)
+}
+
 async fn api_logs(
     State(state): State<Arc<AdminState>>,
-    headers: HeaderMap,
+    _auth: Auth,
+    Query(query): Query<LogQuery>,
 ) -> Response {
-    if is_authenticated(&headers, &state).is_none() {
-        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
-    }
+    let filter = match LogFilter::new(&query) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
     
-    let logs: Vec<_> = state.logs.read().iter().rev().cloned().collect();
-    let json = serde_json::to_string(&logs).unwrap();
+    // Filtered and serialized under the read guard, which is held only for
+    // this statement; log_request waits for it, other readers don't
+    let older = older_log_entries(&state).await.unwrap_or_default();
+    let json = {
+        let logs = state.logs.read();
+        let mut entries: Vec<&RequestLogEntry> =
+            logs.iter().rev().chain(older.iter().rev()).filter(|log| filter.matches(log)).collect();
+        let total = entries.len();
+        entries.drain(..query.offset.min(total));
+        entries.truncate(query.limit.unwrap_or(total));
+        serde_json::to_string(&LogPage { total, offset: query.offset, entries }).unwrap()
+    };
     
     Response::builder()
         .status(StatusCode::OK)
@@ -413,8 +2032,541 @@ async fn api_logs(
         .unwrap()
 }
 
-fn generate_dashboard_html(username: &str, stats: &ServerStats, logs: &VecDeque<RequestLogEntry>) -> String {
-    let logs_html: String = logs.iter().rev().map(|log| {
+/// `/api/logs/export` download formats
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// RequestLogEntry's fields, in order
+const CSV_HEADER: &str = "timestamp,method,path,status,duration_ms,client_ip,host,user_agent,protocol,php_duration_ms\r\n";
+
+/// A CSV field, quoted when it holds a comma, quote or line break
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+impl ExportFormat {
+    fn line(self, log: &RequestLogEntry) -> String {
+        match self {
+            ExportFormat::Csv => format!(
+                "{},{},{},{},{},{},{},{},{},{}\r\n",
+                log.timestamp.to_rfc3339(),
+                csv_field(&log.method),
+                csv_field(&log.path),
+                log.status,
+                log.duration_ms,
+                csv_field(&log.client_ip),
+                csv_field(&log.host),
+                csv_field(&log.user_agent),
+                csv_field(&log.protocol),
+                log.php_duration_ms.map_or(String::new(), |ms| ms.to_string()),
+            ),
+            ExportFormat::Ndjson => serde_json::to_string(log).unwrap() + "\n",
+        }
+    }
+}
+
+/// The entries `/api/logs` would list, as a CSV or NDJSON download written
+/// one line at a time
+async fn api_logs_export(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+    Query(query): Query<LogQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Response {
+    let filter = match LogFilter::new(&query) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    // Copied out, so log_request doesn't wait on a slow download
+    let older = older_log_entries(&state).await.unwrap_or_default();
+    let entries: Vec<RequestLogEntry> = state
+        .logs
+        .read()
+        .iter()
+        .rev()
+        .chain(older.iter().rev())
+        .filter(|log| filter.matches(log))
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    let format = export.format;
+    let (content_type, extension, header_row) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv", Some(CSV_HEADER.to_string())),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson", None),
+    };
+    let lines = header_row.into_iter().chain(entries.into_iter().map(move |log| format.line(&log)));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"wolfserve-requests-{}.{}\"", Utc::now().format("%Y%m%d-%H%M%S"), extension),
+        )
+        .body(Body::from_stream(futures_util::stream::iter(lines.map(Ok::<_, std::convert::Infallible>))))
+        .unwrap()
+}
+
+/// Server-Sent Events: each entry matching the `/api/logs` filters as it is
+/// logged. A subscriber too slow to keep up misses entries rather than
+/// queueing them, and gets a `lagged` event saying how many.
+async fn api_logs_stream(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+    Query(query): Query<LogQuery>,
+) -> Response {
+    let filter = match LogFilter::new(&query) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let events = futures_util::stream::unfold((state.log_events.subscribe(), filter), |(mut receiver, filter)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(entry) if filter.matches(&entry) => Event::default().json_data(&entry),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => Ok(Event::default().event("lagged").data(missed.to_string())),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            return Some((event, (receiver, filter)));
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::new().interval(LOG_STREAM_HEARTBEAT)).into_response()
+}
+
+async fn api_php(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+) -> Response {
+    let pools = state.fpm_pools.read().clone();
+    Json(pools).into_response()
+}
+
+async fn api_vhosts(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+) -> Response {
+    // The dump reads certificates and checks document roots
+    let dumping = state.clone();
+    let dump = tokio::task::spawn_blocking(move || dumping.vhost_dump.read().as_ref().map(|dump| dump())).await;
+    let Ok(Some(mut dump)) = dump else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Vhosts not loaded yet").into_response();
+    };
+    add_vhost_status(&mut dump, &state.stats.snapshot().vhosts).await;
+    Json(dump).into_response()
+}
+
+/// Longest a PHP backend gets to accept a connection for `/api/vhosts`
+const PHP_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether the PHP handler can be reached: PHP-FPM accepts a connection, or
+/// the php-cgi binary exists
+async fn probe_php(mode: &str, target: &str) -> Result<(), String> {
+    if mode == "cgi" {
+        return match tokio::fs::metadata(target).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}: {}", target, e)),
+        };
+    }
+    let connect = async {
+        match target.strip_prefix("unix:") {
+            Some(path) => tokio::net::UnixStream::connect(path).await.map(drop),
+            None => tokio::net::TcpStream::connect(target).await.map(drop),
+        }
+    };
+    match tokio::time::timeout(PHP_PROBE_TIMEOUT, connect).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("{}: {}", target, e)),
+        Err(_) => Err(format!("{}: no answer within {}s", target, PHP_PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+/// Give each vhost of a `/api/vhosts` dump its request counters, whether its
+/// PHP handler answers, and `problems`: what the Virtual Hosts page
+/// highlights. Each PHP handler is probed once, however many vhosts share it.
+async fn add_vhost_status(dump: &mut serde_json::Value, stats: &BTreeMap<String, VhostStats>) {
+    let Some(vhosts) = dump.get_mut("vhosts").and_then(|v| v.as_array_mut()) else {
+        return;
+    };
+    let handler = |vhost: &serde_json::Value| {
+        let php = &vhost["php"];
+        Some((php["mode"].as_str()?.to_string(), php["target"].as_str()?.to_string()))
+    };
+    let handlers: BTreeSet<(String, String)> = vhosts.iter().filter_map(handler).collect();
+    let probes = futures_util::future::join_all(handlers.iter().map(|(mode, target)| probe_php(mode, target))).await;
+    let reachable: BTreeMap<&(String, String), Result<(), String>> = handlers.iter().zip(probes).collect();
+
+    for vhost in vhosts.iter_mut() {
+        let mut problems = Vec::new();
+        if let (Some(false), Some(root)) = (vhost["document_root_exists"].as_bool(), vhost["document_root"].as_str()) {
+            problems.push(format!("document root {} is missing", root));
+        }
+        let certificate = &vhost["certificate"];
+        if let Some(error) = certificate["error"].as_str() {
+            problems.push(format!("certificate: {}", error));
+        } else if certificate["days_left"].as_i64().is_some_and(|days| days < 0) {
+            problems.push("certificate has expired".to_string());
+        }
+        if let Some(result) = handler(&*vhost).and_then(|key| reachable.get(&key)) {
+            vhost["php"]["reachable"] = serde_json::json!(result.is_ok());
+            if let Err(e) = result {
+                problems.push(format!("PHP handler unreachable: {}", e));
+            }
+        }
+        let requests = vhost["id"].as_str().and_then(|id| stats.get(id)).cloned().unwrap_or_default();
+        vhost["requests"] = serde_json::json!(requests);
+        vhost["problems"] = serde_json::json!(problems);
+    }
+}
+
+async fn api_export_config(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+
+    let Some(snapshot) = state.config_export.read().as_ref().map(|export| export()) else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Server not started yet").into_response();
+    };
+    let filename = format!("wolfserve-config-{}.json", Utc::now().format("%Y%m%d-%H%M%S"));
+    (
+        [(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))],
+        Json(snapshot),
+    )
+        .into_response()
+}
+
+async fn api_import_config(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(snapshot): Json<serde_json::Value>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+
+    // Checks every file the snapshot names
+    let previewing = state.clone();
+    let preview = tokio::task::spawn_blocking(move || previewing.config_import.read().as_ref().map(|preview| preview(snapshot))).await;
+    match preview {
+        Ok(Some(Ok(preview))) => Json(preview).into_response(),
+        Ok(Some(Err(e))) => (StatusCode::BAD_REQUEST, e).into_response(),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "Server not started yet").into_response(),
+    }
+}
+
+async fn api_reload_config(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+
+    let Some(reload) = state.config_reload.read().as_ref().map(|reload| reload(format!("admin {}", auth))) else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Server not started yet").into_response();
+    };
+    let Some(status) = reload.await else {
+        return (StatusCode::CONFLICT, "A reload is already in progress").into_response();
+    };
+    let message = format!("Configuration reload by {}: {}", auth, if status.ok { "OK" } else { "failed" });
+    state.audit("config_reload", &auth.principal, auth.client, format!("{}; {}", message, status.message));
+    Json(status).into_response()
+}
+
+async fn api_reload_certificates(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+
+    match state.cert_reload.read().as_ref() {
+        Some(reload) => Json(reload()).into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "Server not started yet").into_response(),
+    }
+}
+
+async fn api_maintenance(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+) -> Response {
+    let maintenance = state.maintenance.read().clone();
+    Json(maintenance).into_response()
+}
+
+async fn api_set_maintenance(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(toggle): Json<MaintenanceToggle>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    let maintenance = {
+        let mut maintenance = state.maintenance.write();
+        match &toggle.site {
+            Some(site) if toggle.enabled => { maintenance.sites.insert(site.to_lowercase()); }
+            Some(site) => { maintenance.sites.remove(&site.to_lowercase()); }
+            None => maintenance.global = toggle.enabled,
+        }
+        maintenance.clone()
+    };
+    
+    println!("Maintenance mode {} for {} by {}",
+        if toggle.enabled { "enabled" } else { "disabled" },
+        toggle.site.as_deref().unwrap_or("all sites"),
+        auth);
+    Json(maintenance).into_response()
+}
+
+async fn api_debug_tap(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+) -> Response {
+    let tap = state.debug_tap.read().clone();
+    Json(tap).into_response()
+}
+
+async fn api_set_debug_tap(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(tap): Json<DebugTap>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    if tap.host.is_empty() || tap.path.is_empty() {
+        return (StatusCode::BAD_REQUEST, "host and path must not be empty").into_response();
+    }
+    
+    println!("Debug tap set to {}{} by {}", tap.host, tap.path, auth);
+    *state.debug_tap.write() = Some(tap.clone());
+    Json(Some(tap)).into_response()
+}
+
+async fn api_clear_debug_tap(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    if state.debug_tap.write().take().is_some() {
+        println!("Debug tap cleared by {}", auth);
+    }
+    Json(None::<DebugTap>).into_response()
+}
+
+async fn api_sessions(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+    headers: HeaderMap,
+) -> Response {
+    let token = get_session_token(&headers).unwrap_or_default();
+    Json(state.active_sessions(&token)).into_response()
+}
+
+async fn api_revoke_sessions(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    headers: HeaderMap,
+    Json(revoke): Json<SessionRevoke>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    let token = get_session_token(&headers).unwrap_or_default();
+    let revoked = state.revoke_sessions(revoke.id.as_deref(), &token);
+    if revoke.id.is_some() && revoked == 0 {
+        return (StatusCode::NOT_FOUND, "No such session").into_response();
+    }
+    
+    println!("{} admin session(s) signed out ({}) by {}",
+        revoked,
+        revoke.id.as_deref().unwrap_or("all others"),
+        auth);
+    Json(state.active_sessions(&token)).into_response()
+}
+
+/// Recent sign-ins, failures and lockouts, newest first
+async fn api_audit(
+    State(state): State<Arc<AdminState>>,
+    _auth: Auth,
+) -> Response {
+    let events: Vec<AuditEvent> = state.audit.read().iter().rev().cloned().collect();
+    Json(events).into_response()
+}
+
+async fn api_users(
+    auth: Auth,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    listing_response(list_users())
+}
+
+async fn api_add_user(
+    auth: Auth,
+    Json(new_user): Json<NewUser>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    if let Err(e) = add_user(&new_user.username, &new_user.password, new_user.role) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    println!("Admin user {} ({}) added by {}", new_user.username, new_user.role.as_str(), auth);
+    listing_response(list_users())
+}
+
+async fn api_remove_user(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(target): Json<UserTarget>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    if let Err(e) = remove_user(&target.username) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    state.revoke_user_sessions(&target.username, None);
+    println!("Admin user {} removed by {}", target.username, auth);
+    listing_response(list_users())
+}
+
+/// Set another user's password, signing out their sessions
+async fn api_set_user_password(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    headers: HeaderMap,
+    Json(target): Json<UserPassword>,
+) -> Response {
+    if let Err(refused) = auth.require_admin() {
+        return refused;
+    }
+    
+    if let Err(e) = set_password(&target.username, &target.password) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let current = get_session_token(&headers);
+    state.revoke_user_sessions(&target.username, current.as_deref());
+    println!("Password of admin user {} reset by {}", target.username, auth);
+    listing_response(list_users())
+}
+
+async fn api_tokens(auth: Auth) -> Response {
+    if let Err(refused) = auth.require_signed_in_admin() {
+        return refused;
+    }
+    
+    listing_response(list_tokens())
+}
+
+async fn api_create_token(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(new_token): Json<NewToken>,
+) -> Response {
+    if let Err(refused) = auth.require_signed_in_admin() {
+        return refused;
+    }
+    
+    let role = if new_token.read_only { Role::Viewer } else { Role::Admin };
+    let (token, info) = match create_token(&new_token.name, role, &auth.principal) {
+        Ok(created) => created,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let message = format!("API token {} ({}, {}) created by {}", info.id, info.name, role.as_str(), auth);
+    println!("{}", message);
+    state.audit("token_created", &info.name, auth.client, message);
+    Json(CreatedToken { token, info }).into_response()
+}
+
+async fn api_revoke_token(
+    State(state): State<Arc<AdminState>>,
+    auth: Auth,
+    Json(target): Json<TokenTarget>,
+) -> Response {
+    if let Err(refused) = auth.require_signed_in_admin() {
+        return refused;
+    }
+    
+    let revoked = match revoke_token(&target.id) {
+        Ok(revoked) => revoked,
+        Err(e) => return (StatusCode::NOT_FOUND, e).into_response(),
+    };
+    let message = format!("API token {} ({}) revoked by {}", revoked.id, revoked.name, auth);
+    println!("{}", message);
+    state.audit("token_revoked", &revoked.name, auth.client, message);
+    listing_response(list_tokens())
+}
+
+/// Escape text for an HTML attribute or element
+pub(crate) fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The dashboard page for `username`, an admin. Everything taken from
+/// requests (path, method, Host, ...) is escaped: it is attacker-controlled
+/// and would otherwise run as script in the admin's browser.
+pub fn generate_dashboard_html(username: &str, state: &AdminState) -> String {
+    dashboard_html(username, Role::Admin, "", state)
+}
+
+/// The dashboard page for `username`, with the controls `role` may use and
+/// the session's `csrf` token for them
+fn dashboard_html(username: &str, role: Role, csrf: &str, state: &AdminState) -> String {
+    let stats = state.stats.snapshot();
+    let php_percentiles = state.php_latency_percentiles();
+    let maintenance = state.maintenance.read().global;
+    let debug_tap = state.debug_tap.read().as_ref().map_or("Off".to_string(), |tap| format!("{}{}", tap.host, tap.path));
+    let last_reload = state.last_reload.read().clone();
+    let last_reload = last_reload.as_ref();
+    let acme = state.acme.read().clone();
+    let ocsp = state.ocsp.read().clone();
+    let fpm_pools = state.fpm_pools.read().clone();
+    let pools_down = FpmPoolStatus::down(&fpm_pools);
+    let certificates = CertExpiry::current(&state.certificates);
+    let ports = state.listeners.read().clone();
+
+    // The rows are formatted under the read lock instead of from a copy of
+    // the log; for DASHBOARD_LOG_ROWS rows that takes well under a
+    // millisecond. The guard is dropped at the end of this statement, so
+    // log_request never waits for the rest of the page.
+    let logs_html: String = state.logs.read().iter().rev().take(DASHBOARD_LOG_ROWS).map(|log| {
         let status_class = match log.status {
             200..=299 => "status-2xx",
             300..=399 => "status-3xx",
@@ -426,25 +2578,32 @@ fn generate_dashboard_html(username: &str, stats: &ServerStats, logs: &VecDeque<
                 <td>{}</td>
                 <td><span class="method {}">{}</span></td>
                 <td class="path">{}</td>
+                <td>{}</td>
                 <td><span class="status {}">{}</span></td>
                 <td>{}ms</td>
                 <td>{}</td>
                 <td>{}</td>
+                <td class="user-agent" title="{}">{}</td>
             </tr>"#,
             log.timestamp.format("%Y-%m-%d %H:%M:%S"),
-            log.method.to_lowercase(),
-            log.method,
-            log.path,
+            html_escape(&log.method.to_lowercase()),
+            html_escape(&log.method),
+            html_escape(&log.path),
+            html_escape(&log.protocol),
             status_class,
             log.status,
             log.duration_ms,
-            log.client_ip,
-            log.host,
+            html_escape(&log.client_ip),
+            html_escape(&log.host),
+            html_escape(&log.user_agent),
+            html_escape(&log.user_agent),
         )
     }).collect();
     
     DASHBOARD_HTML
-        .replace("{{USERNAME}}", username)
+        .replace("{{USERNAME}}", &html_escape(username))
+        .replace("{{ROLE}}", role.as_str())
+        .replace("{{CSRF_TOKEN}}", csrf)
         .replace("{{UPTIME}}", &stats.uptime_string())
         .replace("{{TOTAL_REQUESTS}}", &stats.total_requests.to_string())
         .replace("{{REQUESTS_2XX}}", &stats.requests_2xx.to_string())
@@ -452,7 +2611,29 @@ fn generate_dashboard_html(username: &str, stats: &ServerStats, logs: &VecDeque<
         .replace("{{REQUESTS_4XX}}", &stats.requests_4xx.to_string())
         .replace("{{REQUESTS_5XX}}", &stats.requests_5xx.to_string())
         .replace("{{AVG_RESPONSE_TIME}}", &format!("{:.2}", stats.avg_response_time_ms()))
+        .replace("{{AVG_PHP_TIME}}", &format!("{:.2}", stats.avg_php_time_ms()))
+        .replace("{{PHP_P95}}", &php_percentiles.1.to_string())
         .replace("{{REQUESTS_PER_SEC}}", &format!("{:.2}", stats.requests_per_second()))
+        .replace("{{TLS_HANDSHAKES}}", &stats.tls_summary())
+        .replace("{{TLS_DETAILS}}", &html_escape(&stats.tls_details()))
+        .replace("{{MAINTENANCE}}", if maintenance { "On" } else { "Off" })
+        .replace("{{DEBUG_TAP}}", &html_escape(&debug_tap))
+        .replace("{{LAST_RELOAD}}", &ReloadStatus::summary(last_reload))
+        .replace("{{LAST_RELOAD_MESSAGE}}", &last_reload.map(|s| html_escape(&s.message)).unwrap_or_default())
+        .replace("{{LISTENERS}}", &listeners::summary(&ports))
+        .replace("{{LISTENERS_DETAILS}}", &html_escape(&listeners::details(&ports)))
+        .replace("{{ACME}}", &AcmeStatus::summary(&acme))
+        .replace("{{ACME_DETAILS}}", &html_escape(&AcmeStatus::details(&acme)))
+        .replace("{{OCSP}}", &OcspStatus::summary(&ocsp))
+        .replace("{{OCSP_DETAILS}}", &html_escape(&OcspStatus::details(&ocsp)))
+        .replace("{{PHP_POOLS}}", &FpmPoolStatus::summary(&fpm_pools))
+        .replace("{{PHP_POOLS_DETAILS}}", &html_escape(&FpmPoolStatus::details(&fpm_pools)))
+        .replace("{{PHP_POOLS_CLASS}}", if pools_down.is_empty() { "" } else { "error" })
+        .replace("{{PHP_DOWN}}", &html_escape(&pools_down.join("; ")))
+        .replace("{{PHP_BANNER_HIDDEN}}", if pools_down.is_empty() { " hidden" } else { "" })
+        .replace("{{CERTS}}", &CertExpiry::summary(&certificates))
+        .replace("{{CERTS_DETAILS}}", &html_escape(&CertExpiry::details(&certificates)))
+        .replace("{{CERTS_CLASS}}", CertExpiry::card_class(&certificates))
         .replace("{{LOGS_TABLE}}", &logs_html)
 }
 
@@ -495,6 +2676,7 @@ const LOGIN_HTML: &str = r#"<!DOCTYPE html>
             margin-bottom: 8px;
             font-size: 14px;
         }
+        .remember label { display: flex; align-items: center; gap: 8px; cursor: pointer; }
         input[type="text"], input[type="password"] {
             width: 100%;
             padding: 12px 16px;
@@ -552,6 +2734,7 @@ const LOGIN_HTML: &str = r#"<!DOCTYPE html>
                 <label for="password">Password</label>
                 <input type="password" id="password" name="password" required autocomplete="current-password">
             </div>
+            <!-- REMEMBER -->
             <button type="submit">Sign In</button>
         </form>
     </div>
@@ -649,6 +2832,7 @@ const CHANGE_PASSWORD_HTML: &str = r#"<!DOCTYPE html>
         <h1>🔐 Change Password</h1>
         <!-- MESSAGE -->
         <form method="POST" action="/change-password">
+            <input type="hidden" name="csrf_token" value="{{CSRF_TOKEN}}">
             <div class="form-group">
                 <label for="current_password">Current Password</label>
                 <input type="password" id="current_password" name="current_password" required>
@@ -673,6 +2857,7 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <meta name="csrf-token" content="{{CSRF_TOKEN}}">
     <title>WolfServe Admin Dashboard</title>
     <style>
         * { margin: 0; padding: 0; box-sizing: border-box; }
@@ -742,6 +2927,16 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
         .stat-card.success .value { background: linear-gradient(135deg, #4caf50 0%, #8bc34a 100%); -webkit-background-clip: text; background-clip: text; }
         .stat-card.warning .value { background: linear-gradient(135deg, #ff9800 0%, #ffc107 100%); -webkit-background-clip: text; background-clip: text; }
         .stat-card.error .value { background: linear-gradient(135deg, #f44336 0%, #ff5252 100%); -webkit-background-clip: text; background-clip: text; }
+
+        .alert-banner {
+            background: rgba(244,67,54,0.85);
+            color: #fff;
+            padding: 15px 20px;
+            border-radius: 8px;
+            margin-bottom: 20px;
+            font-weight: 600;
+        }
+        .alert-banner[hidden] { display: none; }
         
         .logs-section {
             background: rgba(255,255,255,0.05);
@@ -768,6 +2963,26 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
             transition: all 0.3s;
         }
         .refresh-btn:hover { background: #4facfe; color: #fff; }
+        .refresh-btn:disabled { opacity: 0.4; cursor: default; background: rgba(79,172,254,0.2); color: #4facfe; }
+        .role-viewer .admin-only { display: none; }
+        
+        .log-filters, .log-pager {
+            padding: 12px 20px;
+            display: flex;
+            flex-wrap: wrap;
+            gap: 10px;
+            align-items: center;
+        }
+        .log-filters { border-bottom: 1px solid rgba(255,255,255,0.1); }
+        .log-pager { justify-content: flex-end; color: #888; font-size: 14px; }
+        .log-filters input, .log-filters select {
+            background: rgba(0,0,0,0.2);
+            color: #fff;
+            border: 1px solid rgba(255,255,255,0.2);
+            border-radius: 6px;
+            padding: 6px 10px;
+            font-size: 13px;
+        }
         
         table {
             width: 100%;
@@ -820,6 +3035,18 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
             font-size: 13px;
         }
         
+        .sessions-section { margin-top: 30px; }
+        
+        .user-agent {
+            max-width: 240px;
+            overflow: hidden;
+            text-overflow: ellipsis;
+            white-space: nowrap;
+            font-size: 13px;
+            color: #aaa;
+        }
+        table.hide-user-agent .user-agent { display: none; }
+        
         .empty-state {
             padding: 60px 20px;
             text-align: center;
@@ -841,20 +3068,22 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
         }
     </style>
 </head>
-<body>
+<body class="role-{{ROLE}}">
     <div class="header">
         <div class="logo">
             <h1>🐺 WolfServe</h1>
             <span>Admin Dashboard</span>
         </div>
         <div class="user-info">
-            <span>👤 {{USERNAME}}</span>
+            <span>👤 {{USERNAME}} ({{ROLE}})</span>
+            <a href="/vhosts">Virtual Hosts</a>
             <a href="/change-password">Change Password</a>
             <a href="/logout" class="logout">Logout</a>
         </div>
     </div>
     
     <div class="container">
+        <div class="alert-banner" id="php-banner"{{PHP_BANNER_HIDDEN}}>PHP-FPM is down: <span id="php-down">{{PHP_DOWN}}</span></div>
         <div class="stats-grid">
             <div class="stat-card">
                 <h3>Uptime</h3>
@@ -884,27 +3113,95 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
                 <h3>Avg Response Time</h3>
                 <div class="value" id="avg-response">{{AVG_RESPONSE_TIME}}ms</div>
             </div>
+            <div class="stat-card">
+                <h3>Avg PHP Time</h3>
+                <div class="value" id="avg-php">{{AVG_PHP_TIME}}ms</div>
+            </div>
+            <div class="stat-card">
+                <h3>PHP p95</h3>
+                <div class="value" id="php-p95">{{PHP_P95}}ms</div>
+            </div>
             <div class="stat-card">
                 <h3>Requests/sec</h3>
                 <div class="value" id="req-per-sec">{{REQUESTS_PER_SEC}}</div>
             </div>
+            <div class="stat-card">
+                <h3>TLS Handshakes</h3>
+                <div class="value" id="tls-handshakes" title="{{TLS_DETAILS}}">{{TLS_HANDSHAKES}}</div>
+            </div>
+            <div class="stat-card">
+                <h3>Maintenance Mode</h3>
+                <div class="value" id="maintenance">{{MAINTENANCE}}</div>
+                <button class="refresh-btn admin-only" onclick="toggleMaintenance()">Toggle</button>
+            </div>
+            <div class="stat-card">
+                <h3>Debug Tap</h3>
+                <div class="value" id="debug-tap" title="Requests logged in full at debug level">{{DEBUG_TAP}}</div>
+                <button class="refresh-btn admin-only" onclick="setDebugTap()">Set</button>
+                <button class="refresh-btn admin-only" onclick="clearDebugTap()">Clear</button>
+            </div>
+            <div class="stat-card">
+                <h3>Config Reload</h3>
+                <div class="value" id="last-reload" title="{{LAST_RELOAD_MESSAGE}}">{{LAST_RELOAD}}</div>
+                <button class="refresh-btn admin-only" id="reload-btn" onclick="reloadConfig()">Reload</button>
+            </div>
+            <div class="stat-card">
+                <h3>Listeners</h3>
+                <div class="value" title="{{LISTENERS_DETAILS}}">{{LISTENERS}}</div>
+            </div>
+            <div class="stat-card">
+                <h3>ACME Certificates</h3>
+                <div class="value" id="acme" title="{{ACME_DETAILS}}">{{ACME}}</div>
+            </div>
+            <div class="stat-card">
+                <h3>OCSP Stapling</h3>
+                <div class="value" id="ocsp" title="{{OCSP_DETAILS}}">{{OCSP}}</div>
+            </div>
+            <div class="stat-card {{PHP_POOLS_CLASS}}" id="php-card">
+                <h3>PHP-FPM Pools</h3>
+                <div class="value" id="php-pools" title="{{PHP_POOLS_DETAILS}}">{{PHP_POOLS}}</div>
+            </div>
+            <div class="stat-card {{CERTS_CLASS}}" id="certs-card">
+                <h3>Certificate Expiry</h3>
+                <div class="value" id="certs" title="{{CERTS_DETAILS}}">{{CERTS}}</div>
+            </div>
         </div>
         
         <div class="logs-section">
             <div class="logs-header">
-                <h2><span class="live-indicator"></span>Recent Requests (Last 50)</h2>
-                <button class="refresh-btn" onclick="refreshData()">↻ Refresh</button>
+                <h2><span class="live-indicator"></span>Recent Requests</h2>
+                <div>
+                    <button class="refresh-btn" id="user-agent-btn" onclick="toggleUserAgent()">Hide User-Agent</button>
+                    <button class="refresh-btn" onclick="exportLogs('csv')">⤓ CSV</button>
+                    <button class="refresh-btn" onclick="exportLogs('ndjson')">⤓ NDJSON</button>
+                    <button class="refresh-btn" onclick="refreshData()">↻ Refresh</button>
+                </div>
             </div>
-            <table>
+            <div class="log-filters">
+                <select id="filter-status" onchange="filterLogs()">
+                    <option value="">Any status</option>
+                    <option value="2xx">2xx</option>
+                    <option value="3xx">3xx</option>
+                    <option value="4xx">4xx</option>
+                    <option value="5xx">5xx</option>
+                </select>
+                <input type="text" id="filter-method" placeholder="Method" size="8" onchange="filterLogs()">
+                <input type="text" id="filter-path" placeholder="Path contains" onchange="filterLogs()">
+                <input type="text" id="filter-host" placeholder="Host" onchange="filterLogs()">
+                <input type="text" id="filter-client-ip" placeholder="Client IP" size="15" onchange="filterLogs()">
+            </div>
+            <table id="logs">
                 <thead>
                     <tr>
                         <th>Time</th>
                         <th>Method</th>
                         <th>Path</th>
+                        <th>Protocol</th>
                         <th>Status</th>
                         <th>Duration</th>
                         <th>Client IP</th>
                         <th>Host</th>
+                        <th class="user-agent">User-Agent</th>
                     </tr>
                 </thead>
                 <tbody id="logs-table">
@@ -914,10 +3211,98 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
             <div class="empty-state" id="empty-state" style="display: none;">
                 No requests logged yet. Start making requests to see them here.
             </div>
+            <div class="log-pager">
+                <span id="log-range"></span>
+                <button class="refresh-btn" id="log-newer" onclick="pageLogs(-1)">← Newer</button>
+                <button class="refresh-btn" id="log-older" onclick="pageLogs(1)">Older →</button>
+            </div>
+        </div>
+        
+        <div class="logs-section sessions-section">
+            <div class="logs-header">
+                <h2>Active Sessions</h2>
+                <button class="refresh-btn admin-only" onclick="revokeSessions(null)">Sign Out Other Sessions</button>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>Session</th>
+                        <th>User</th>
+                        <th>Signed In</th>
+                        <th>Expires</th>
+                        <th></th>
+                    </tr>
+                </thead>
+                <tbody id="sessions-table"></tbody>
+            </table>
+        </div>
+        
+        <div class="logs-section sessions-section">
+            <div class="logs-header">
+                <h2>Sign-in Activity</h2>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>Time</th>
+                        <th>Event</th>
+                        <th>User</th>
+                        <th>Client IP</th>
+                        <th>Details</th>
+                    </tr>
+                </thead>
+                <tbody id="audit-table"></tbody>
+            </table>
+        </div>
+        
+        <div class="logs-section sessions-section admin-only">
+            <div class="logs-header">
+                <h2>Users</h2>
+                <button class="refresh-btn" onclick="addUser()">Add User</button>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>User</th>
+                        <th>Role</th>
+                        <th></th>
+                    </tr>
+                </thead>
+                <tbody id="users-table"></tbody>
+            </table>
+        </div>
+        
+        <div class="logs-section sessions-section admin-only">
+            <div class="logs-header">
+                <h2>API Tokens</h2>
+                <button class="refresh-btn" onclick="createToken()">New Token</button>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>Token</th>
+                        <th>Access</th>
+                        <th>Created By</th>
+                        <th>Created</th>
+                        <th></th>
+                    </tr>
+                </thead>
+                <tbody id="tokens-table"></tbody>
+            </table>
         </div>
     </div>
     
     <script>
+        // Request fields are attacker-controlled; never put them in HTML raw
+        function escapeHtml(text) {
+            return String(text)
+                .replace(/&/g, '&amp;')
+                .replace(/</g, '&lt;')
+                .replace(/>/g, '&gt;')
+                .replace(/"/g, '&quot;')
+                .replace(/'/g, '&#39;');
+        }
+
         function refreshData() {
             fetch('/api/stats')
                 .then(r => r.json())
@@ -929,41 +3314,565 @@ const DASHBOARD_HTML: &str = r##"<!DOCTYPE html>
                     document.getElementById('requests-4xx').textContent = data.requests_4xx;
                     document.getElementById('requests-5xx').textContent = data.requests_5xx;
                     document.getElementById('avg-response').textContent = data.avg_response_time_ms.toFixed(2) + 'ms';
+                    document.getElementById('avg-php').textContent = data.avg_php_time_ms.toFixed(2) + 'ms';
+                    document.getElementById('php-p95').textContent = data.php_p95_ms + 'ms';
                     document.getElementById('req-per-sec').textContent = data.requests_per_second.toFixed(2);
+                    const tls = document.getElementById('tls-handshakes');
+                    tls.textContent = data.tls_summary;
+                    tls.title = data.tls_details;
+                    const reload = document.getElementById('last-reload');
+                    reload.textContent = data.last_reload_summary;
+                    reload.title = data.last_reload ? data.last_reload.message : '';
+                    const acme = document.getElementById('acme');
+                    acme.textContent = data.acme_summary;
+                    acme.title = data.acme_details;
+                    const ocsp = document.getElementById('ocsp');
+                    ocsp.textContent = data.ocsp_summary;
+                    ocsp.title = data.ocsp_details;
+                    const php = document.getElementById('php-pools');
+                    php.textContent = data.php_pools_summary;
+                    php.title = data.php_pools_details;
+                    document.getElementById('php-card').className = 'stat-card ' + (data.php_pools_down.length ? 'error' : '');
+                    document.getElementById('php-down').textContent = data.php_pools_down.join('; ');
+                    document.getElementById('php-banner').hidden = !data.php_pools_down.length;
+                    const certs = document.getElementById('certs');
+                    certs.textContent = data.certificates_summary;
+                    certs.title = data.certificates_details;
+                    document.getElementById('certs-card').className = 'stat-card ' + data.certificates_class;
                 });
             
-            fetch('/api/logs')
+            refreshSessions();
+            refreshAudit();
+            // Polled only while the live stream is down
+            if (!logStream || logStream.readyState !== EventSource.OPEN) {
+                refreshLogs();
+            }
+        }
+        
+        // The log table shows one page of the entries matching the filters,
+        // all filtered and paged by /api/logs. The first page is kept up to
+        // date by /api/logs/stream, with the same filters.
+        const LOG_PAGE_SIZE = 25;
+        let logOffset = 0;
+        let logPage = null;
+        let logStream = null;
+        
+        function filterLogs() {
+            logOffset = 0;
+            refreshLogs();
+            streamLogs();
+        }
+        
+        function pageLogs(direction) {
+            logOffset = Math.max(0, logOffset + direction * LOG_PAGE_SIZE);
+            refreshLogs();
+            streamLogs();
+        }
+        
+        function logFilters() {
+            const params = new URLSearchParams();
+            for (const [name, id] of [['status', 'filter-status'], ['method', 'filter-method'], ['path', 'filter-path'],
+                                      ['host', 'filter-host'], ['client_ip', 'filter-client-ip']]) {
+                const value = document.getElementById(id).value.trim();
+                if (value) params.set(name, value);
+            }
+            return params;
+        }
+        
+        function refreshLogs() {
+            const params = logFilters();
+            params.set('limit', LOG_PAGE_SIZE);
+            params.set('offset', logOffset);
+            fetch('/api/logs?' + params)
                 .then(r => r.json())
-                .then(logs => {
-                    const tbody = document.getElementById('logs-table');
-                    const empty = document.getElementById('empty-state');
-                    
-                    if (logs.length === 0) {
-                        tbody.innerHTML = '';
-                        empty.style.display = 'block';
-                        return;
-                    }
-                    
-                    empty.style.display = 'none';
-                    tbody.innerHTML = logs.map(log => {
-                        const statusClass = log.status >= 500 ? 'status-5xx' : 
-                                           log.status >= 400 ? 'status-4xx' :
-                                           log.status >= 300 ? 'status-3xx' : 'status-2xx';
-                        return `<tr>
-                            <td>${new Date(log.timestamp).toLocaleString()}</td>
-                            <td><span class="method ${log.method.toLowerCase()}">${log.method}</span></td>
-                            <td class="path">${log.path}</td>
-                            <td><span class="status ${statusClass}">${log.status}</span></td>
-                            <td>${log.duration_ms}ms</td>
-                            <td>${log.client_ip}</td>
-                            <td>${log.host}</td>
-                        </tr>`;
-                    }).join('');
+                .then(page => {
+                    logPage = page;
+                    renderLogs();
+                });
+        }
+        
+        // Without EventSource, or once the stream is refused, the table
+        // falls back to the five-second poll
+        function streamLogs() {
+            if (logStream) logStream.close();
+            logStream = null;
+            if (!window.EventSource || logOffset !== 0) return;
+            const source = new EventSource('/api/logs/stream?' + logFilters());
+            source.onmessage = event => {
+                if (!logPage || logOffset !== 0) return;
+                logPage.entries.unshift(JSON.parse(event.data));
+                logPage.entries.length = Math.min(logPage.entries.length, LOG_PAGE_SIZE);
+                logPage.total++;
+                renderLogs();
+            };
+            // Entries were skipped, or the stream reconnected after missing some
+            source.addEventListener('lagged', refreshLogs);
+            source.onopen = refreshLogs;
+            source.onerror = () => {
+                if (source.readyState === EventSource.CLOSED && logStream === source) logStream = null;
+            };
+            logStream = source;
+        }
+        
+        // Downloads every entry matching the filters, not just this page
+        function exportLogs(format) {
+            const params = logFilters();
+            params.set('format', format);
+            window.location = '/api/logs/export?' + params;
+        }
+        
+        function renderLogs() {
+            const tbody = document.getElementById('logs-table');
+            const empty = document.getElementById('empty-state');
+            const page = logPage;
+            const logs = page.entries;
+            
+            document.getElementById('log-range').textContent = logs.length === 0 ? page.total + ' matching' :
+                (page.offset + 1) + '–' + (page.offset + logs.length) + ' of ' + page.total;
+            document.getElementById('log-newer').disabled = page.offset === 0;
+            document.getElementById('log-older').disabled = page.offset + logs.length >= page.total;
+            
+            if (logs.length === 0) {
+                tbody.innerHTML = '';
+                empty.textContent = logFilters().toString() ? 'No logged requests match these filters.' :
+                    'No requests logged yet. Start making requests to see them here.';
+                empty.style.display = 'block';
+                return;
+            }
+            
+            empty.style.display = 'none';
+            tbody.innerHTML = logs.map(log => {
+                const statusClass = log.status >= 500 ? 'status-5xx' : 
+                                   log.status >= 400 ? 'status-4xx' :
+                                   log.status >= 300 ? 'status-3xx' : 'status-2xx';
+                return `<tr>
+                    <td>${new Date(log.timestamp).toLocaleString()}</td>
+                    <td><span class="method ${escapeHtml(log.method.toLowerCase())}">${escapeHtml(log.method)}</span></td>
+                    <td class="path">${escapeHtml(log.path)}</td>
+                    <td>${escapeHtml(log.protocol)}</td>
+                    <td><span class="status ${statusClass}">${log.status}</span></td>
+                    <td>${log.duration_ms}ms</td>
+                    <td>${escapeHtml(log.client_ip)}</td>
+                    <td>${escapeHtml(log.host)}</td>
+                    <td class="user-agent" title="${escapeHtml(log.user_agent)}">${escapeHtml(log.user_agent)}</td>
+                </tr>`;
+            }).join('');
+        }
+        
+        function loadSessions(sessions) {
+            document.getElementById('sessions-table').innerHTML = sessions.map(session => `<tr>
+                <td class="path">${escapeHtml(session.id)}${session.remember ? ' (remembered)' : ''}</td>
+                <td>${escapeHtml(session.username)}</td>
+                <td>${new Date(session.created_at).toLocaleString()}</td>
+                <td>${new Date(session.expires_at).toLocaleString()}</td>
+                <td>${session.current
+                    ? 'This session'
+                    : `<button class="refresh-btn admin-only" onclick="revokeSessions('${escapeHtml(session.id)}')">Sign Out</button>`}</td>
+            </tr>`).join('');
+        }
+
+        function refreshSessions() {
+            fetch('/api/sessions').then(r => r.json()).then(loadSessions);
+        }
+
+        const isAdmin = document.body.classList.contains('role-admin');
+        // Every change the API accepts from a session carries the page's token
+        const csrfToken = document.querySelector('meta[name="csrf-token"]').content;
+
+        function loadUsers(users) {
+            document.getElementById('users-table').innerHTML = users.map(user => `<tr>
+                <td>${escapeHtml(user.username)}</td>
+                <td>${escapeHtml(user.role)}</td>
+                <td>
+                    <button class="refresh-btn" data-username="${escapeHtml(user.username)}" onclick="resetPassword(this.dataset.username)">Reset Password</button>
+                    <button class="refresh-btn" data-username="${escapeHtml(user.username)}" onclick="removeUser(this.dataset.username)">Remove</button>
+                </td>
+            </tr>`).join('');
+        }
+
+        function refreshUsers() {
+            if (isAdmin) fetch('/api/users').then(r => r.json()).then(loadUsers);
+        }
+
+        // Refusals, such as removing the last admin, come back as text
+        function changeUsers(url, body) {
+            fetch(url, {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify(body)
+            })
+                .then(r => r.ok ? r.json() : r.text().then(message => { alert(message); return fetch('/api/users').then(r => r.json()); }))
+                .then(loadUsers);
+        }
+
+        function addUser() {
+            const username = prompt('Username:');
+            if (!username) return;
+            const role = prompt('Role (admin or viewer):', 'viewer');
+            if (!role) return;
+            const password = prompt('Password:');
+            if (!password) return;
+            changeUsers('/api/users', { username, password, role });
+        }
+
+        function resetPassword(username) {
+            const password = prompt('New password for ' + username + ':');
+            if (!password) return;
+            changeUsers('/api/users/password', { username, password });
+        }
+
+        function removeUser(username) {
+            if (!confirm('Remove ' + username + '? Their sessions are signed out.')) return;
+            changeUsers('/api/users/remove', { username });
+        }
+
+        function loadTokens(tokens) {
+            document.getElementById('tokens-table').innerHTML = tokens.map(token => `<tr>
+                <td>${escapeHtml(token.name)} <span class="path">${escapeHtml(token.id)}</span></td>
+                <td>${token.role === 'viewer' ? 'Read-only' : 'Full'}</td>
+                <td>${escapeHtml(token.created_by)}</td>
+                <td>${new Date(token.created_at).toLocaleString()}</td>
+                <td><button class="refresh-btn" onclick="revokeToken('${escapeHtml(token.id)}')">Revoke</button></td>
+            </tr>`).join('');
+        }
+
+        function refreshTokens() {
+            if (isAdmin) fetch('/api/tokens').then(r => r.json()).then(loadTokens);
+        }
+
+        // The token is only ever shown here; the server keeps a hash
+        function createToken() {
+            const name = prompt('Token name (what uses it):');
+            if (!name) return;
+            const read_only = confirm('Read-only token? Cancel for a token that can change settings too.');
+            fetch('/api/tokens', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify({ name, read_only })
+            })
+                .then(r => r.ok ? r.json() : r.text().then(message => { throw new Error(message); }))
+                .then(created => prompt('Copy the token now; it is not shown again:', created.token))
+                .catch(e => alert(e.message))
+                .then(refreshTokens);
+        }
+
+        function revokeToken(id) {
+            if (!confirm('Revoke this token? Scripts using it are refused from now on.')) return;
+            fetch('/api/tokens/revoke', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify({ id })
+            }).then(refreshTokens);
+        }
+
+        // Usernames are whatever was typed on the login form
+        function refreshAudit() {
+            fetch('/api/audit').then(r => r.json()).then(events => {
+                document.getElementById('audit-table').innerHTML = events.map(event => `<tr>
+                    <td>${new Date(event.timestamp).toLocaleString()}</td>
+                    <td>${escapeHtml(event.event)}</td>
+                    <td>${escapeHtml(event.username)}</td>
+                    <td>${escapeHtml(event.client_ip)}</td>
+                    <td>${escapeHtml(event.message)}</td>
+                </tr>`).join('');
+            });
+        }
+
+        // id null signs out every session but this one
+        function revokeSessions(id) {
+            if (id === null && !confirm('Sign out every other session?')) return;
+            fetch('/api/sessions/revoke', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify(id === null ? {} : { id })
+            })
+                .then(r => r.ok ? r.json() : fetch('/api/sessions').then(r => r.json()))
+                .then(loadSessions);
+        }
+
+        // The User-Agent column is wide; whether it is shown sticks per browser
+        function showUserAgent(show) {
+            document.getElementById('logs').classList.toggle('hide-user-agent', !show);
+            document.getElementById('user-agent-btn').textContent = show ? 'Hide User-Agent' : 'Show User-Agent';
+            localStorage.setItem('wolfserve-user-agent', show ? 'shown' : 'hidden');
+        }
+
+        function toggleUserAgent() {
+            showUserAgent(document.getElementById('logs').classList.contains('hide-user-agent'));
+        }
+
+        function toggleMaintenance() {
+            const enabled = document.getElementById('maintenance').textContent !== 'On';
+            if (enabled && !confirm('Put all sites into maintenance mode?')) return;
+            fetch('/api/maintenance', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify({ enabled })
+            })
+                .then(r => r.json())
+                .then(data => {
+                    document.getElementById('maintenance').textContent = data.global ? 'On' : 'Off';
                 });
         }
+
+        function reloadConfig() {
+            const button = document.getElementById('reload-btn');
+            button.disabled = true;
+            fetch('/api/reload', {
+                method: 'POST',
+                headers: { 'X-CSRF-Token': csrfToken }
+            })
+                .then(r => r.ok ? r.json() : r.text().then(text => { throw new Error(text); }))
+                .then(status => {
+                    const reload = document.getElementById('last-reload');
+                    reload.textContent = (status.ok ? 'OK ' : 'Failed ') + status.time.slice(11, 19);
+                    reload.title = status.message;
+                    if (status.ok) {
+                        const changes = ['added', 'removed', 'changed']
+                            .filter(kind => status.vhosts[kind].length)
+                            .map(kind => kind + ': ' + status.vhosts[kind].join(', '));
+                        alert('Configuration reloaded.\n' + (changes.length ? changes.join('\n') : 'No vhosts changed.'));
+                    } else {
+                        alert('Reload failed; the running configuration was kept.\n' + status.errors.join('\n'));
+                    }
+                })
+                .catch(e => alert('Reload failed: ' + e.message))
+                .finally(() => { button.disabled = false; });
+        }
+
+
+        function showDebugTap(tap) {
+            document.getElementById('debug-tap').textContent = tap ? tap.host + tap.path : 'Off';
+        }
+
+        function setDebugTap() {
+            const host = prompt('Host to tap (* and ? match any characters):');
+            if (!host) return;
+            const path = prompt('Path to tap:', '/*');
+            if (!path) return;
+            fetch('/api/debug-tap', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken },
+                body: JSON.stringify({ host, path })
+            })
+                .then(r => r.json())
+                .then(showDebugTap);
+        }
+
+        function clearDebugTap() {
+            fetch('/api/debug-tap', { method: 'DELETE', headers: { 'X-CSRF-Token': csrfToken } }).then(r => r.json()).then(showDebugTap);
+        }
         
+        showUserAgent(localStorage.getItem('wolfserve-user-agent') !== 'hidden');
+        refreshSessions();
+        refreshUsers();
+        refreshTokens();
+        refreshLogs();
+        streamLogs();
+
         // Auto-refresh every 5 seconds
         setInterval(refreshData, 5000);
     </script>
 </body>
 </html>"##;
+
+const VHOSTS_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>WolfServe Admin - Virtual Hosts</title>
+    <style>
+        * { margin: 0; padding: 0; box-sizing: border-box; }
+        body {
+            font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;
+            background: #0f0f1a;
+            color: #fff;
+            min-height: 100vh;
+        }
+        .header {
+            background: linear-gradient(135deg, #1a1a2e 0%, #16213e 100%);
+            padding: 20px 30px;
+            display: flex;
+            justify-content: space-between;
+            align-items: center;
+            border-bottom: 1px solid rgba(255,255,255,0.1);
+        }
+        .logo { display: flex; align-items: center; gap: 15px; }
+        .logo h1 { font-size: 24px; }
+        .logo span { color: #4facfe; }
+        .user-info { display: flex; align-items: center; gap: 20px; }
+        .user-info a {
+            color: #888;
+            text-decoration: none;
+            padding: 8px 16px;
+            border-radius: 6px;
+            transition: all 0.3s;
+        }
+        .user-info a:hover { background: rgba(255,255,255,0.1); color: #fff; }
+        .user-info .logout { color: #ff5252; }
+        .container { padding: 30px; max-width: 1600px; margin: 0 auto; }
+        .vhosts-section {
+            background: rgba(255,255,255,0.05);
+            border-radius: 12px;
+            border: 1px solid rgba(255,255,255,0.1);
+            overflow: hidden;
+        }
+        .vhosts-header {
+            padding: 20px;
+            border-bottom: 1px solid rgba(255,255,255,0.1);
+            display: flex;
+            justify-content: space-between;
+            align-items: center;
+        }
+        .vhosts-header h2 { font-size: 18px; }
+        .refresh-btn {
+            background: rgba(79,172,254,0.2);
+            color: #4facfe;
+            border: 1px solid #4facfe;
+            padding: 8px 16px;
+            border-radius: 6px;
+            cursor: pointer;
+            font-size: 14px;
+        }
+        .refresh-btn:hover { background: #4facfe; color: #fff; }
+        table { width: 100%; border-collapse: collapse; }
+        th, td {
+            padding: 14px 16px;
+            text-align: left;
+            vertical-align: top;
+            border-bottom: 1px solid rgba(255,255,255,0.05);
+            font-size: 14px;
+        }
+        th {
+            background: rgba(0,0,0,0.2);
+            font-size: 12px;
+            text-transform: uppercase;
+            letter-spacing: 1px;
+            color: #888;
+        }
+        tr.unhealthy { background: rgba(244,67,54,0.08); }
+        .mono { font-family: 'Monaco', 'Menlo', monospace; font-size: 13px; }
+        .muted { color: #888; font-size: 13px; }
+        .bad { color: #f44336; }
+        .warn { color: #ff9800; }
+        .ok { color: #4caf50; }
+        .empty-state { padding: 60px 20px; text-align: center; color: #666; }
+    </style>
+</head>
+<body class="role-{{ROLE}}">
+    <div class="header">
+        <div class="logo">
+            <h1>🐺 WolfServe</h1>
+            <span>Virtual Hosts</span>
+        </div>
+        <div class="user-info">
+            <span>👤 {{USERNAME}} ({{ROLE}})</span>
+            <a href="/">Dashboard</a>
+            <a href="/logout" class="logout">Logout</a>
+        </div>
+    </div>
+
+    <div class="container">
+        <div class="vhosts-section">
+            <div class="vhosts-header">
+                <h2 id="summary">Loading…</h2>
+                <button class="refresh-btn" onclick="refreshVhosts()">↻ Check again</button>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>Names</th>
+                        <th>Port</th>
+                        <th>Document Root</th>
+                        <th>Certificate</th>
+                        <th>PHP</th>
+                        <th>Rules</th>
+                        <th>Requests</th>
+                        <th>Problems</th>
+                    </tr>
+                </thead>
+                <tbody id="vhosts-body">
+                    <tr><td colspan="8" class="empty-state">Loading…</td></tr>
+                </tbody>
+            </table>
+        </div>
+    </div>
+
+    <script>
+        // Names and paths come from the Apache config; never put them in HTML raw
+        function escapeHtml(text) {
+            return String(text)
+                .replace(/&/g, '&amp;')
+                .replace(/</g, '&lt;')
+                .replace(/>/g, '&gt;')
+                .replace(/"/g, '&quot;')
+                .replace(/'/g, '&#39;');
+        }
+
+        function certificateCell(cert) {
+            if (!cert) return '<span class="muted">none</span>';
+            if (cert.error) return '<span class="bad">' + escapeHtml(cert.error) + '</span>';
+            const days = cert.days_left;
+            const cls = days < 0 ? 'bad' : days < 14 ? 'warn' : 'ok';
+            return '<span class="' + cls + '">' + (days < 0 ? 'expired' : days + ' days left') + '</span>'
+                + '<div class="muted">' + escapeHtml(cert.not_after.slice(0, 10)) + '</div>';
+        }
+
+        function phpCell(php) {
+            const state = php.reachable === undefined ? '' : php.reachable
+                ? ' <span class="ok">●</span>' : ' <span class="bad">● unreachable</span>';
+            return escapeHtml(php.mode) + state + '<div class="mono muted">' + escapeHtml(php.target) + '</div>';
+        }
+
+        function rulesCell(vhost) {
+            const htaccess = vhost.htaccess || { rewrite_rules: 0, redirects: 0 };
+            const redirects = vhost.redirects.length + htaccess.redirects;
+            return htaccess.rewrite_rules + ' rewrite / ' + redirects + ' redirect';
+        }
+
+        function requestsCell(requests) {
+            const errors = requests.requests_5xx
+                ? ' <span class="bad">' + requests.requests_5xx + ' 5xx</span>' : '';
+            return requests.requests + errors + '<div class="muted">' + requests.requests_4xx + ' 4xx</div>';
+        }
+
+        function showVhosts(dump) {
+            const vhosts = dump.vhosts;
+            const unhealthy = vhosts.filter(v => v.problems.length).length;
+            document.getElementById('summary').textContent = vhosts.length + ' virtual hosts, '
+                + (unhealthy ? unhealthy + ' with problems' : 'all healthy');
+            const body = document.getElementById('vhosts-body');
+            if (!vhosts.length) {
+                body.innerHTML = '<tr><td colspan="8" class="empty-state">No virtual hosts loaded</td></tr>';
+                return;
+            }
+            body.innerHTML = vhosts.map(v => {
+                const root = v.document_root === null ? '<span class="muted">none</span>'
+                    : '<span class="mono ' + (v.document_root_exists ? '' : 'bad') + '">' + escapeHtml(v.document_root) + '</span>';
+                const names = v.names.length ? v.names.map(escapeHtml).join('<br>') : '<span class="muted">(default)</span>';
+                return '<tr class="' + (v.problems.length ? 'unhealthy' : '') + '">'
+                    + '<td>' + names + '</td>'
+                    + '<td>' + v.port + (v.tls ? ' 🔒' : '') + (v.port_default ? '<div class="muted">default</div>' : '') + '</td>'
+                    + '<td>' + root + '</td>'
+                    + '<td>' + certificateCell(v.certificate) + '</td>'
+                    + '<td>' + phpCell(v.php) + '</td>'
+                    + '<td>' + rulesCell(v) + '</td>'
+                    + '<td>' + requestsCell(v.requests) + '</td>'
+                    + '<td>' + (v.problems.length ? v.problems.map(p => '<div class="bad">' + escapeHtml(p) + '</div>').join('') : '<span class="ok">OK</span>') + '</td>'
+                    + '</tr>';
+            }).join('');
+        }
+
+        function refreshVhosts() {
+            fetch('/api/vhosts')
+                .then(r => r.ok ? r.json() : r.text().then(message => { throw new Error(message); }))
+                .then(showVhosts)
+                .catch(e => { document.getElementById('summary').textContent = e.message; });
+        }
+
+        refreshVhosts();
+        setInterval(refreshVhosts, 30000);
+    </script>
+</body>
+</html>"##;
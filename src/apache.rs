@@ -1,8 +1,9 @@
 use std::path::{Path, PathBuf};
 use std::fs;
 use serde::{Deserialize, Serialize};
-use regex::Regex;
-use std::collections::HashMap;
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 
 /// Represents a redirect rule parsed from Apache config
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +47,8 @@ pub struct RewriteRule {
     pub redirect: Option<u16>, // [R], [R=301], [R=302]
     pub nocase: bool,        // [NC]
     pub qsappend: bool,      // [QSA] - query string append
+    pub qsdiscard: bool,     // [QSD] - drop the request's query string
+    pub noescape: bool,      // [NE] - don't percent-encode redirect targets
     pub passthrough: bool,   // [PT] - pass through
     pub skip: bool,          // Used internally for "-" substitution
 }
@@ -57,6 +60,11 @@ pub struct HtaccessConfig {
     pub rewrite_base: String,
     pub rewrite_rules: Vec<RewriteRule>,
     pub redirects: Vec<RedirectRule>,
+    /// LimitRequestBody for this directory and below
+    pub limit_request_body: Option<u64>,
+    /// FallbackResource for this directory and below; `disabled` turns off
+    /// an inherited one
+    pub fallback_resource: Option<String>,
 }
 
 /// Request context for evaluating rewrite conditions
@@ -66,11 +74,33 @@ pub struct RewriteContext<'a> {
     pub query_string: &'a str,
     pub http_host: &'a str,
     pub request_method: &'a str,
+    /// Request arrived over TLS, directly or via a proxy (X-Forwarded-Proto)
     pub https: bool,
     pub document_root: &'a Path,
 }
 
 impl HtaccessConfig {
+    /// Patterns that fail to compile, with the regex error. Broken patterns
+    /// are silently skipped at request time, so this is how they surface.
+    pub fn invalid_patterns(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for rule in &self.rewrite_rules {
+            if let Some(e) = regex_error(&rule.pattern) {
+                errors.push(format!("RewriteRule {}: {}", rule.pattern, e));
+            }
+            for cond in &rule.conditions {
+                if cond.pattern.starts_with('-') && cond.pattern.len() == 2 {
+                    continue; // file tests such as -f / -d
+                }
+                if let Some(e) = regex_error(&cond.pattern) {
+                    errors.push(format!("RewriteCond {}: {}", cond.pattern, e));
+                }
+            }
+        }
+        errors.extend(self.redirects.iter().filter_map(RedirectRule::pattern_error));
+        errors
+    }
+
     /// Apply rewrite rules and return the rewritten path (or None if no rewrite)
     pub fn apply_rewrites(&self, ctx: &RewriteContext) -> Option<RewriteResult> {
         if !self.rewrite_engine {
@@ -78,6 +108,7 @@ impl HtaccessConfig {
         }
 
         let mut current_uri = ctx.request_uri.to_string();
+        let mut current_query = ctx.query_string.to_string();
         
         // Strip rewrite base from the beginning for matching
         let match_path = if !self.rewrite_base.is_empty() && self.rewrite_base != "/" {
@@ -90,10 +121,10 @@ impl HtaccessConfig {
         };
 
         for rule in &self.rewrite_rules {
-            // Check conditions
-            if !self.evaluate_conditions(&rule.conditions, ctx, &current_uri) {
+            // Check conditions, keeping the groups %N refers to
+            let Some(cond_groups) = self.evaluate_conditions(&rule.conditions, ctx, &current_uri) else {
                 continue;
-            }
+            };
 
             // Try to match the pattern
             let pattern = if rule.nocase {
@@ -117,45 +148,34 @@ impl HtaccessConfig {
                 }
 
                 // Build substitution with backreferences
-                let mut new_uri = rule.substitution.clone();
-                for i in 0..=9 {
-                    if let Some(m) = caps.get(i) {
-                        new_uri = new_uri.replace(&format!("${}", i), m.as_str());
-                    }
-                }
+                let new_uri = expand_backrefs(&rule.substitution, &caps, Some(&cond_groups));
+                // Server variables, e.g. https://%{HTTP_HOST}%{REQUEST_URI}
+                let new_uri = self.expand_variables(&new_uri, ctx, &current_uri);
 
-                // Handle absolute URLs (external redirects)
-                if new_uri.starts_with("http://") || new_uri.starts_with("https://") {
-                    let status = rule.redirect.unwrap_or(302);
-                    return Some(RewriteResult::Redirect { 
-                        url: new_uri, 
-                        status 
-                    });
-                }
+                // A `?` in the substitution sets a new query string
+                let (mut new_path, new_query) = match new_uri.split_once('?') {
+                    Some((path, query)) => (path.to_string(), Some(query)),
+                    None => (new_uri.clone(), None),
+                };
+                let query = rule.rewritten_query(new_query, &current_query);
 
-                // Prepend rewrite base if not absolute path
-                if !new_uri.starts_with('/') {
-                    new_uri = format!("{}{}", self.rewrite_base, new_uri);
-                }
+                let is_absolute = new_path.starts_with("http://") || new_path.starts_with("https://");
 
-                // Handle query string
-                if rule.qsappend && !ctx.query_string.is_empty() {
-                    if new_uri.contains('?') {
-                        new_uri = format!("{}&{}", new_uri, ctx.query_string);
-                    } else {
-                        new_uri = format!("{}?{}", new_uri, ctx.query_string);
-                    }
+                // Prepend rewrite base if not absolute path
+                if !is_absolute && !new_path.starts_with('/') {
+                    new_path = format!("{}{}", self.rewrite_base, new_path);
                 }
 
-                // Check if this is a redirect
-                if let Some(status) = rule.redirect {
-                    return Some(RewriteResult::Redirect { 
-                        url: new_uri, 
-                        status 
+                // Absolute URLs are always external redirects
+                if is_absolute || rule.redirect.is_some() {
+                    return Some(RewriteResult::Redirect {
+                        url: rule.redirect_target(&new_path, &query),
+                        status: rule.redirect.unwrap_or(302),
                     });
                 }
 
-                current_uri = new_uri;
+                current_uri = new_path;
+                current_query = query;
 
                 if rule.last {
                     break;
@@ -163,25 +183,27 @@ impl HtaccessConfig {
             }
         }
 
-        if current_uri != ctx.request_uri {
-            Some(RewriteResult::InternalRewrite { path: current_uri })
+        if current_uri != ctx.request_uri || current_query != ctx.query_string {
+            Some(RewriteResult::InternalRewrite { path: current_uri, query: current_query })
         } else {
             None
         }
     }
 
-    fn evaluate_conditions(&self, conditions: &[RewriteCond], ctx: &RewriteContext, current_uri: &str) -> bool {
-        if conditions.is_empty() {
-            return true;
-        }
-
+    /// Whether the conditions hold, with the groups captured by the last
+    /// condition that matched (what `%0`..`%9` refer to)
+    fn evaluate_conditions(&self, conditions: &[RewriteCond], ctx: &RewriteContext, current_uri: &str) -> Option<Vec<Option<String>>> {
         let mut result = true;
         let mut or_chain = false;
+        let mut groups = Vec::new();
 
         for cond in conditions {
             let test_value = self.expand_variables(&cond.test_string, ctx, current_uri);
-            let matched = self.test_condition(&test_value, &cond.pattern, cond.nocase);
-            let matched = if cond.negate { !matched } else { matched };
+            let captured = self.test_condition(&test_value, &cond.pattern, cond.nocase);
+            let matched = captured.is_some() != cond.negate;
+            if let Some(captured) = captured.filter(|_| matched) {
+                groups = captured;
+            }
 
             if or_chain {
                 result = result || matched;
@@ -192,7 +214,7 @@ impl HtaccessConfig {
             or_chain = cond.or_next;
         }
 
-        result
+        result.then_some(groups)
     }
 
     fn expand_variables(&self, s: &str, ctx: &RewriteContext, current_uri: &str) -> String {
@@ -206,18 +228,21 @@ impl HtaccessConfig {
         result = result.replace("%{REQUEST_METHOD}", ctx.request_method);
         result = result.replace("%{DOCUMENT_ROOT}", &ctx.document_root.to_string_lossy());
         result = result.replace("%{HTTPS}", if ctx.https { "on" } else { "off" });
+        result = result.replace("%{REQUEST_SCHEME}", if ctx.https { "https" } else { "http" });
         
         result
     }
 
-    fn test_condition(&self, test_value: &str, pattern: &str, nocase: bool) -> bool {
+    /// The groups `pattern` captures in `test_value`, or None if it does
+    /// not match. File tests capture nothing.
+    fn test_condition(&self, test_value: &str, pattern: &str, nocase: bool) -> Option<Vec<Option<String>>> {
         // Special file/directory tests
         match pattern {
-            "-f" => return Path::new(test_value).is_file(),
-            "-d" => return Path::new(test_value).is_dir(),
-            "-s" => return Path::new(test_value).metadata().map(|m| m.len() > 0).unwrap_or(false),
-            "-l" => return Path::new(test_value).is_symlink(),
-            "-F" => return Path::new(test_value).exists(),
+            "-f" => return Path::new(test_value).is_file().then(Vec::new),
+            "-d" => return Path::new(test_value).is_dir().then(Vec::new),
+            "-s" => return Path::new(test_value).metadata().is_ok_and(|m| m.len() > 0).then(Vec::new),
+            "-l" => return Path::new(test_value).is_symlink().then(Vec::new),
+            "-F" => return Path::new(test_value).exists().then(Vec::new),
             _ => {}
         }
 
@@ -228,17 +253,72 @@ impl HtaccessConfig {
             pattern.to_string()
         };
 
-        Regex::new(&pattern)
-            .map(|re| re.is_match(test_value))
-            .unwrap_or(false)
+        let caps = Regex::new(&pattern).ok()?.captures(test_value)?;
+        Some(caps.iter().map(|m| m.map(|m| m.as_str().to_string())).collect())
+    }
+}
+
+impl RewriteRule {
+    /// Query string after this rule: the one the substitution sets (plus the
+    /// incoming one with QSA), else the incoming one unchanged. QSD drops the
+    /// incoming query string in both cases.
+    fn rewritten_query(&self, substituted: Option<&str>, incoming: &str) -> String {
+        let incoming = if self.qsdiscard { "" } else { incoming };
+        match substituted {
+            Some(query) if self.qsappend && !incoming.is_empty() && !query.is_empty() => format!("{}&{}", query, incoming),
+            Some(query) if self.qsappend && query.is_empty() => incoming.to_string(),
+            Some(query) => query.to_string(),
+            None => incoming.to_string(),
+        }
+    }
+
+    /// Location for a redirect. Unless NE is set, characters that are not
+    /// allowed in a URL path (including `#`) are percent-encoded.
+    fn redirect_target(&self, path: &str, query: &str) -> String {
+        let (path, fragment) = match path.split_once('#') {
+            Some((path, fragment)) if self.noescape => (path.to_string(), Some(fragment)),
+            _ if self.noescape => (path.to_string(), None),
+            _ => (escape_url_path(path), None),
+        };
+
+        let mut url = path;
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(query);
+        }
+        if let Some(fragment) = fragment {
+            url.push('#');
+            url.push_str(fragment);
+        }
+        url
+    }
+}
+
+/// Percent-encode bytes that may not appear in a URL path. Existing `%XX`
+/// escapes are kept: request paths arrive encoded, so backreferences already are.
+fn escape_url_path(path: &str) -> String {
+    const ALLOWED: &[u8] = b"-._~!$&'()*+,;=:@/";
+    let bytes = path.as_bytes();
+    let mut escaped = String::with_capacity(path.len());
+
+    for (i, &b) in bytes.iter().enumerate() {
+        let is_escape = b == b'%'
+            && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+        if b.is_ascii_alphanumeric() || ALLOWED.contains(&b) || is_escape {
+            escaped.push(b as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", b));
+        }
     }
+    escaped
 }
 
 /// Result of applying rewrite rules
 #[derive(Debug, Clone)]
 pub enum RewriteResult {
-    /// Internal rewrite - serve different path
-    InternalRewrite { path: String },
+    /// Internal rewrite - serve a different path with this query string
+    InternalRewrite { path: String, query: String },
     /// External redirect
     Redirect { url: String, status: u16 },
 }
@@ -253,6 +333,90 @@ pub fn parse_htaccess(path: &Path) -> Option<HtaccessConfig> {
     Some(parse_htaccess_content(&content))
 }
 
+/// Join physical lines ending in a backslash into single logical lines,
+/// the way Apache reads continued directives, each with the number of the
+/// physical line it starts on.
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending = String::new();
+    let mut start = 1;
+
+    for (index, raw) in content.lines().enumerate() {
+        if pending.is_empty() {
+            start = index + 1;
+        }
+        let line = raw.trim_end();
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            continue;
+        }
+        pending.push_str(line);
+        lines.push((start, std::mem::take(&mut pending)));
+    }
+
+    // A continuation on the last line of the file still forms a directive
+    if !pending.is_empty() {
+        lines.push((start, pending));
+    }
+
+    lines
+}
+
+/// Split a directive line into its lower-cased name and its arguments,
+/// tolerating any run of spaces or tabs between tokens. Section tags keep
+/// their angle bracket in the name (`<virtualhost`, `</virtualhost`).
+/// Returns None for blank lines and comments.
+fn split_directive(line: &str) -> Option<(String, Vec<String>)> {
+    let mut line = line.trim();
+    if line.starts_with('<') {
+        line = line.trim_end_matches('>').trim_end();
+    }
+    let mut tokens = tokenize_args(line).into_iter();
+    let name = tokens.next()?;
+    if name.starts_with('#') {
+        return None;
+    }
+    Some((name.to_ascii_lowercase(), tokens.collect()))
+}
+
+/// Quote-aware tokenizer following Apache's argument rules: a token that
+/// starts with `"` or `'` runs to the matching quote (so paths may contain
+/// spaces) and `\"` inside it is an escaped quote. Unquoted tokens end at
+/// whitespace and keep backslashes untouched, which matters for regexes.
+fn tokenize_args(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            break;
+        };
+
+        let mut token = String::new();
+        if first == '"' || first == '\'' {
+            while let Some(c) = chars.next() {
+                if c == '\\' && chars.peek() == Some(&first) {
+                    token.push(first);
+                    chars.next();
+                } else if c == first {
+                    break;
+                } else {
+                    token.push(c);
+                }
+            }
+        } else {
+            token.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+
+    tokens
+}
+
 /// Parse .htaccess content
 pub fn parse_htaccess_content(content: &str) -> HtaccessConfig {
     let mut config = HtaccessConfig {
@@ -260,61 +424,50 @@ pub fn parse_htaccess_content(content: &str) -> HtaccessConfig {
         rewrite_base: "/".to_string(),
         rewrite_rules: Vec::new(),
         redirects: Vec::new(),
+        limit_request_body: None,
+        fallback_resource: None,
     };
 
     let mut pending_conditions: Vec<RewriteCond> = Vec::new();
 
-    for line in content.lines() {
-        let line = line.trim();
-        
-        // Skip comments and empty lines
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-
-        // Skip IfModule directives (assume modules are available)
-        if line.starts_with("<IfModule") || line.starts_with("</IfModule") {
+    for (_, line) in logical_lines(content) {
+        let Some((directive, args)) = split_directive(&line) else {
             continue;
-        }
+        };
 
-        if line.eq_ignore_ascii_case("RewriteEngine On") {
-            config.rewrite_engine = true;
-        } else if line.eq_ignore_ascii_case("RewriteEngine Off") {
-            config.rewrite_engine = false;
-        } else if line.starts_with("RewriteBase") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                config.rewrite_base = parts[1].to_string();
-            }
-        } else if line.starts_with("RewriteCond") {
-            if let Some(cond) = parse_rewrite_cond(line) {
-                pending_conditions.push(cond);
-            }
-        } else if line.starts_with("RewriteRule") {
-            if let Some(mut rule) = parse_rewrite_rule(line) {
-                rule.conditions = std::mem::take(&mut pending_conditions);
-                config.rewrite_rules.push(rule);
-            }
-        } else if line.starts_with("Redirect") {
-            // Handle Redirect directives in .htaccess
-            if line.starts_with("RedirectMatch") {
-                if let Some(rule) = parse_redirect_directive(line, true) {
-                    config.redirects.push(rule);
+        match directive.as_str() {
+            // Skip IfModule directives (assume modules are available)
+            "<ifmodule" | "</ifmodule" => {}
+            "rewriteengine" => {
+                if let Some(value) = args.first() {
+                    config.rewrite_engine = value.eq_ignore_ascii_case("on");
                 }
-            } else if line.starts_with("RedirectPermanent") {
-                let parts: Vec<&str> = line.splitn(3, char::is_whitespace)
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                if parts.len() >= 3 {
-                    config.redirects.push(RedirectRule {
-                        status: 301,
-                        from: parts[1].to_string(),
-                        to: Some(parts[2].to_string()),
-                        is_regex: false,
-                    });
+            }
+            "rewritebase" => {
+                if let Some(base) = args.first() {
+                    config.rewrite_base = base.to_string();
+                }
+            }
+            "rewritecond" => {
+                if let Some(cond) = parse_rewrite_cond(&args) {
+                    pending_conditions.push(cond);
+                }
+            }
+            "rewriterule" => {
+                if let Some(mut rule) = parse_rewrite_rule(&args) {
+                    rule.conditions = std::mem::take(&mut pending_conditions);
+                    config.rewrite_rules.push(rule);
                 }
-            } else if line.starts_with("Redirect ") {
-                if let Some(rule) = parse_redirect_directive(line, false) {
+            }
+            "limitrequestbody" => {
+                config.limit_request_body = parse_limit_request_body(&args);
+            }
+            "fallbackresource" => {
+                config.fallback_resource = args.first().cloned();
+            }
+            _ => {
+                // Handle Redirect directives in .htaccess
+                if let Some(rule) = parse_redirect_family(&directive, &args) {
                     config.redirects.push(rule);
                 }
             }
@@ -324,18 +477,14 @@ pub fn parse_htaccess_content(content: &str) -> HtaccessConfig {
     config
 }
 
-fn parse_rewrite_cond(line: &str) -> Option<RewriteCond> {
+fn parse_rewrite_cond(args: &[String]) -> Option<RewriteCond> {
     // RewriteCond TestString CondPattern [flags]
-    let parts: Vec<&str> = line.splitn(4, char::is_whitespace)
-        .filter(|s| !s.is_empty())
-        .collect();
-    
-    if parts.len() < 3 {
+    if args.len() < 2 {
         return None;
     }
 
-    let test_string = parts[1].to_string();
-    let mut pattern = parts[2].to_string();
+    let test_string = args[0].to_string();
+    let mut pattern = args[1].to_string();
     let negate = pattern.starts_with('!');
     if negate {
         pattern = pattern[1..].to_string();
@@ -344,10 +493,12 @@ fn parse_rewrite_cond(line: &str) -> Option<RewriteCond> {
     let mut nocase = false;
     let mut or_next = false;
 
-    if parts.len() >= 4 {
-        let flags = parts[3].to_uppercase();
-        nocase = flags.contains("NC");
-        or_next = flags.contains("OR");
+    for (flag, _) in parse_flags(args.get(2)) {
+        match flag.as_str() {
+            "NC" | "NOCASE" => nocase = true,
+            "OR" | "ORNEXT" => or_next = true,
+            _ => {}
+        }
     }
 
     Some(RewriteCond {
@@ -359,43 +510,43 @@ fn parse_rewrite_cond(line: &str) -> Option<RewriteCond> {
     })
 }
 
-fn parse_rewrite_rule(line: &str) -> Option<RewriteRule> {
+fn parse_rewrite_rule(args: &[String]) -> Option<RewriteRule> {
     // RewriteRule Pattern Substitution [flags]
-    let parts: Vec<&str> = line.splitn(4, char::is_whitespace)
-        .filter(|s| !s.is_empty())
-        .collect();
-    
-    if parts.len() < 3 {
+    if args.len() < 2 {
         return None;
     }
 
-    let pattern = parts[1].to_string();
-    let substitution = parts[2].to_string();
+    let pattern = args[0].to_string();
+    let substitution = args[1].to_string();
     let skip = substitution == "-";
 
     let mut last = false;
     let mut redirect = None;
     let mut nocase = false;
     let mut qsappend = false;
+    let mut qsdiscard = false;
+    let mut noescape = false;
     let mut passthrough = false;
 
-    if parts.len() >= 4 {
-        let flags = parts[3].to_uppercase();
-        last = flags.contains('L') || flags.contains("[L]") || flags.contains("L,") || flags.contains(",L");
-        nocase = flags.contains("NC");
-        qsappend = flags.contains("QSA");
-        passthrough = flags.contains("PT");
-        
-        // Parse redirect flag [R] or [R=301]
-        if flags.contains('R') {
-            if let Some(start) = flags.find("R=") {
-                let rest = &flags[start + 2..];
-                let code_str: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
-                redirect = code_str.parse().ok();
-            }
-            if redirect.is_none() {
-                redirect = Some(302); // Default redirect status
+    for (flag, value) in parse_flags(args.get(2)) {
+        match flag.as_str() {
+            "L" | "LAST" => last = true,
+            "NC" | "NOCASE" => nocase = true,
+            "QSA" | "QSAPPEND" => qsappend = true,
+            "QSD" | "QSDISCARD" => qsdiscard = true,
+            "NE" | "NOESCAPE" => noescape = true,
+            "PT" | "PASSTHROUGH" => passthrough = true,
+            // [R] alone is a 302; [R=301] or [R=permanent] pick the status
+            "R" | "REDIRECT" => {
+                redirect = Some(match value.map(|v| v.to_ascii_lowercase()).as_deref() {
+                    None => 302,
+                    Some("permanent") => 301,
+                    Some("temp") => 302,
+                    Some("seeother") => 303,
+                    Some(code) => code.parse().unwrap_or(302),
+                });
             }
+            _ => {}
         }
     }
 
@@ -407,222 +558,1676 @@ fn parse_rewrite_rule(line: &str) -> Option<RewriteRule> {
         redirect,
         nocase,
         qsappend,
+        qsdiscard,
+        noescape,
         passthrough,
         skip,
     })
 }
 
+/// Split a `[flag,flag=value,...]` argument into upper-cased flag names and
+/// their raw values. Flags are matched as whole tokens, never as substrings.
+fn parse_flags(arg: Option<&String>) -> Vec<(String, Option<String>)> {
+    let Some(arg) = arg else {
+        return Vec::new();
+    };
+    arg.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(|flag| match flag.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_uppercase(), Some(value.trim().to_string())),
+            None => (flag.to_ascii_uppercase(), None),
+        })
+        .collect()
+}
+
+/// One-line compile error for a pattern, if it does not compile
+fn regex_error(pattern: &str) -> Option<String> {
+    Regex::new(pattern).err().map(|e| {
+        let message = e.to_string();
+        // Syntax errors span several lines with a caret diagram; keep the last
+        message.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string()
+    })
+}
+
 impl RedirectRule {
-    /// Check if this rule matches the given path and return the redirect target
-    pub fn matches(&self, path: &str) -> Option<(u16, Option<String>)> {
-        if self.is_regex {
-            if let Ok(re) = Regex::new(&self.from) {
-                if let Some(caps) = re.captures(path) {
-                    if let Some(ref to) = self.to {
-                        // Replace backreferences $1, $2, etc.
-                        let mut target = to.clone();
-                        for i in 1..=9 {
-                            if let Some(m) = caps.get(i) {
-                                target = target.replace(&format!("${}", i), m.as_str());
-                            }
-                        }
-                        return Some((self.status, Some(target)));
-                    } else {
-                        // Gone or similar - no target
-                        return Some((self.status, None));
-                    }
-                }
-            }
+    /// Compile error for a RedirectMatch pattern, if any
+    pub fn pattern_error(&self) -> Option<String> {
+        if !self.is_regex {
+            return None;
+        }
+        regex_error(&self.from).map(|e| format!("RedirectMatch {}: {}", self.from, e))
+    }
+
+    /// Check if this rule matches the given path and return the redirect target.
+    /// The request's query string is carried over unless the target has its own.
+    pub fn matches(&self, path: &str, query: &str) -> Option<(u16, Option<String>)> {
+        let target = if self.is_regex {
+            let caps = Regex::new(&self.from).ok()?.captures(path)?;
+            self.to.as_ref().map(|to| expand_backrefs(to, &caps, None))
         } else {
             // Exact prefix match for regular Redirect
-            if path == self.from || path.starts_with(&format!("{}/", self.from)) {
-                if let Some(ref to) = self.to {
-                    // Append the remainder of the path
-                    let remainder = &path[self.from.len()..];
-                    let target = format!("{}{}", to, remainder);
-                    return Some((self.status, Some(target)));
-                } else {
-                    return Some((self.status, None));
+            if path != self.from && !path.starts_with(&format!("{}/", self.from)) {
+                return None;
+            }
+            // Append the remainder of the path, ahead of the target's own
+            // query string or #fragment
+            let remainder = &path[self.from.len()..];
+            self.to.as_ref().map(|to| {
+                let (base, suffix) = to.split_at(to.find(['?', '#']).unwrap_or(to.len()));
+                format!("{}{}{}", base, remainder, suffix)
+            })
+        };
+        // Gone or similar - no target
+        Some((self.status, target.map(|target| with_query(target, query))))
+    }
+}
+
+/// Substitute regex backreferences: `$0` is the whole match, `$1`..`$9` a
+/// single-digit group and `${nn}` any group, so `$10` is `$1` followed by
+/// `0`. Groups that did not take part in the match expand to nothing, and
+/// `\$` is a literal dollar sign. With `cond_groups` (for a RewriteRule),
+/// `%0`..`%9` are the last matched RewriteCond's groups and `\%` is a
+/// literal percent sign; a `%` not followed by a digit is kept as is.
+fn expand_backrefs(template: &str, caps: &Captures, cond_groups: Option<&[Option<String>]>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    let markers: &[char] = if cond_groups.is_some() { &['$', '%', '\\'] } else { &['$', '\\'] };
+
+    while let Some(pos) = rest.find(markers) {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if rest[pos..].starts_with("\\$") || (cond_groups.is_some() && rest[pos..].starts_with("\\%")) {
+            result.push_str(&after[..1]);
+            rest = &after[1..];
+            continue;
+        }
+        if rest[pos..].starts_with('\\') {
+            result.push('\\');
+            rest = after;
+            continue;
+        }
+        if let (Some(groups), true) = (cond_groups, rest[pos..].starts_with('%')) {
+            match after.chars().next().and_then(|c| c.to_digit(10)) {
+                Some(digit) => {
+                    result.push_str(groups.get(digit as usize).and_then(Option::as_deref).unwrap_or(""));
+                    rest = &after[1..];
+                }
+                None => {
+                    result.push('%');
+                    rest = after;
                 }
             }
+            continue;
         }
-        None
+
+        let braced = after
+            .strip_prefix('{')
+            .and_then(|inner| inner.split_once('}'))
+            .and_then(|(digits, tail)| Some((digits.parse::<usize>().ok()?, tail)));
+        let (group, tail) = match braced {
+            Some(found) => found,
+            None => match after.chars().next().and_then(|c| c.to_digit(10)) {
+                Some(digit) => (digit as usize, &after[1..]),
+                None => {
+                    result.push('$');
+                    rest = after;
+                    continue;
+                }
+            },
+        };
+        result.push_str(caps.get(group).map_or("", |m| m.as_str()));
+        rest = tail;
     }
+    result.push_str(rest);
+    result
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Attach `query` to a redirect target that has no query string of its own,
+/// keeping a trailing #fragment last. Other targets are returned verbatim.
+fn with_query(target: String, query: &str) -> String {
+    let (base, fragment) = match target.split_once('#') {
+        Some((base, fragment)) => (base, Some(fragment)),
+        None => (target.as_str(), None),
+    };
+    if query.is_empty() || base.contains('?') {
+        return target;
+    }
+    match fragment {
+        Some(fragment) => format!("{}?{}#{}", base, query, fragment),
+        None => format!("{}?{}", base, query),
+    }
+}
+
+/// Percent-decode a URL path for matching against config paths. Invalid
+/// escapes are kept as-is and invalid UTF-8 is replaced.
+pub fn decode_url_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Lookup table keyed by host name. Exact names are stored in a map, while
+/// wildcard names such as `*.example.com` or `www*.example.com` are kept in
+/// declaration order and tried only when no exact name matches.
+#[derive(Debug, Clone)]
+pub struct HostTable<T> {
+    exact: HashMap<String, T>,
+    wildcards: Vec<(String, T)>,
+}
+
+impl<T> Default for HostTable<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            wildcards: Vec::new(),
+        }
+    }
+}
+
+impl<T> HostTable<T> {
+    /// Register a name (exact or wildcard). Later exact entries replace earlier ones.
+    pub fn insert(&mut self, name: &str, value: T) {
+        let name = normalize_host_name(name);
+        if name.contains('*') || name.contains('?') {
+            self.wildcards.push((name, value));
+        } else {
+            self.exact.insert(name, value);
+        }
+    }
+
+    /// Find the entry for a host. Exact names win; among matching wildcards the
+    /// one with the most literal characters (the longest fixed suffix) is chosen,
+    /// and ties go to the first declared.
+    pub fn get(&self, host: &str) -> Option<&T> {
+        let host = normalize_host_name(host);
+        if let Some(value) = self.exact.get(&host) {
+            return Some(value);
+        }
+
+        let mut best: Option<(usize, &T)> = None;
+        for (pattern, value) in &self.wildcards {
+            if !wildcard_match(pattern, &host) {
+                continue;
+            }
+            let literal_len = pattern.chars().filter(|c| *c != '*' && *c != '?').count();
+            if best.is_none_or(|(len, _)| literal_len > len) {
+                best = Some((literal_len, value));
+            }
+        }
+        best.map(|(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.wildcards.is_empty()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.exact.values_mut().chain(self.wildcards.iter_mut().map(|(_, value)| value))
+    }
+}
+
+/// Split an authority such as `example.com:8080` or `[::1]:443` into the
+/// lower-cased host and its optional port
+pub fn split_host_port(authority: &str) -> (String, Option<u16>) {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        // IPv6 literal keeps its brackets so it never looks like host:port
+        match rest.split_once(']') {
+            Some((addr, tail)) => (format!("[{}]", addr), tail.strip_prefix(':')),
+            None => (authority.to_string(), None),
+        }
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host.to_string(), Some(port)),
+            _ => (authority.to_string(), None),
+        }
+    };
+    (host.to_ascii_lowercase(), port.and_then(|p| p.parse().ok()))
+}
+
+/// Normalize a ServerName/ServerAlias value. Apache accepts forms such as
+/// `https://www.example.com:443`; only the bare host is used for lookups,
+/// while the declared scheme and port are returned separately.
+fn parse_server_name(value: &str) -> (String, Option<String>, Option<u16>) {
+    let (scheme, rest) = match value.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+        None => (None, value),
+    };
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let (host, port) = split_host_port(authority);
+    (host, scheme, port)
+}
+
+/// Host names compare case-insensitively and ignore a trailing root dot
+fn normalize_host_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Glob-style match where `*` matches any run of characters and `?` matches one
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// fnmatch with FNM_PATHNAME, the way `<Location>` wildcards match a URL
+/// path: `*` and `?` never match a `/`, and `[a-z]` or `[!.]` match one
+/// character of a set. The whole path must match.
+pub fn url_wildcard_match(pattern: &str, url_path: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| matches(rest, &text[i..])),
+            Some(('?', rest)) => text.first().is_some_and(|&c| c != '/') && matches(rest, &text[1..]),
+            Some(('[', rest)) => match text.first() {
+                Some(&c) if c != '/' => match class_match(rest, c) {
+                    Some((found, after)) => found && matches(after, &text[1..]),
+                    // An unclosed `[` is literal
+                    None => c == '[' && matches(rest, &text[1..]),
+                },
+                _ => false,
+            },
+            Some((c, rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = url_path.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Whether `c` is in the character class at the start of `pattern` (just
+/// past its `[`), and the pattern after the closing `]`; None if unclosed
+fn class_match(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut i) = match pattern.first() {
+        Some('!' | '^') => (true, 1),
+        _ => (false, 0),
+    };
+    let start = i;
+    let mut found = false;
+    while let Some(&first) = pattern.get(i) {
+        // A `]` right after the `[` is a member, not the end
+        if first == ']' && i > start {
+            return Some((found != negated, &pattern[i + 1..]));
+        }
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&last)) if last != ']' => {
+                found |= (first..=last).contains(&c);
+                i += 3;
+            }
+            _ => {
+                found |= first == c;
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct VirtualHost {
     pub port: u16,
+    /// Declared as `<VirtualHost _default_:port>`
+    pub is_default: bool,
+    /// `file:line` of the `<VirtualHost>` line
+    #[serde(default)]
+    pub defined_at: Option<String>,
     pub server_name: Option<String>,
+    /// Scheme given on the ServerName line (`https://...`), if any
+    pub server_name_scheme: Option<String>,
+    /// Port given on the ServerName line (`example.com:8080`), if any
+    pub server_name_port: Option<u16>,
     pub server_aliases: Vec<String>,
+    /// ServerAdmin: the contact (email or URL) shown on error pages
+    pub server_admin: Option<String>,
     pub document_root: Option<PathBuf>,
     pub ssl_cert_file: Option<PathBuf>,
     pub ssl_key_file: Option<PathBuf>,
     pub ssl_chain_file: Option<PathBuf>,
+    /// Further SSLCertificateFile/SSLCertificateKeyFile pairs, in order
+    pub ssl_extra_cert_files: Vec<PathBuf>,
+    pub ssl_extra_key_files: Vec<PathBuf>,
     pub redirects: Vec<RedirectRule>,
+    pub aliases: Vec<Alias>,
+    /// ErrorLog file for request-scoped errors
+    pub error_log: Option<PathBuf>,
+    /// CustomLog access logs (several are allowed per vhost)
+    pub custom_logs: Vec<CustomLog>,
+    /// Explicit SSLEngine on/off; when absent TLS is inferred from the cert paths
+    pub ssl_engine: Option<bool>,
+    /// SSLProtocol arguments, e.g. `-all +TLSv1.3`
+    pub ssl_protocol: Option<Vec<String>>,
+    /// SSLCipherSuite for TLS 1.2 and below (OpenSSL cipher string)
+    pub ssl_cipher_suite: Option<String>,
+    /// TLS 1.3 suites from `SSLCipherSuite TLSv1.3 ...` or `SSLOpenSSLConfCmd Ciphersuites ...`
+    pub ssl_tls13_cipher_suites: Option<String>,
+    /// SSLVerifyClient: whether clients must present a certificate
+    pub ssl_verify_client: Option<VerifyClient>,
+    /// SSLCACertificateFile: CAs that client certificates must chain to
+    pub ssl_ca_certificate_file: Option<PathBuf>,
+    /// PHP mode for this vhost (`fpm` or `cgi`), overriding `[php] mode`
+    pub php_mode: Option<String>,
+    /// PHP-FPM pool for this vhost (`host:port` or `unix:/path`), from
+    /// `SetHandler "proxy:..."` or wolfserve.toml
+    pub fpm_address: Option<String>,
+    /// Extra extensions run as PHP, from `AddHandler`/`AddType application/x-httpd-php`
+    pub php_extensions: Vec<String>,
+    /// FastCGI upstreams of other extensions (`py` → `127.0.0.1:9100`), from
+    /// `AddHandler "proxy:fcgi://127.0.0.1:9100" .py` or wolfserve.toml
+    pub fastcgi_handlers: BTreeMap<String, String>,
+    /// UseCanonicalName On/Off (`DNS` counts as Off)
+    pub use_canonical_name: Option<bool>,
+    /// Host that aliases are 301-redirected to (set from wolfserve.toml)
+    pub canonical_host: Option<String>,
+    /// Redirect `/dir/index.html` to `/dir/` (set from wolfserve.toml)
+    pub strip_index: Option<bool>,
+    /// 301 plain HTTP requests for this TLS vhost's names to HTTPS (set from wolfserve.toml)
+    pub redirect_to_https: Option<bool>,
+    /// Extensions never served, replacing `[server] deny_extensions` (set from wolfserve.toml)
+    pub deny_extensions: Option<Vec<String>>,
+    /// Cross-Origin-Opener-Policy for every response (set from wolfserve.toml)
+    pub cross_origin_opener_policy: Option<String>,
+    /// Cross-Origin-Embedder-Policy for every response (set from wolfserve.toml)
+    pub cross_origin_embedder_policy: Option<String>,
+    /// SERVER_NAME sent to PHP and FastCGI scripts instead of the Host's name
+    /// (set from wolfserve.toml)
+    pub php_server_name: Option<String>,
+    /// HTTP_HOST sent to PHP and FastCGI scripts instead of the Host header
+    /// (set from wolfserve.toml)
+    pub php_http_host: Option<String>,
+    /// LimitRequestBody at vhost level
+    pub limit_request_body: Option<u64>,
+    /// FallbackResource at vhost level: the URL-path served for URLs that
+    /// match no file
+    pub fallback_resource: Option<String>,
+    /// `<Directory>` blocks inside the vhost
+    pub directories: Vec<DirectoryConfig>,
+    /// `<Location>` and `<LocationMatch>` blocks, in config order
+    pub locations: Vec<LocationConfig>,
+    /// Certificate issued by the built-in ACME client (set from wolfserve.toml)
+    pub acme: bool,
+    /// Symlink handling from `Options`; unset follows them, as Apache does
+    pub symlinks: Option<SymlinkPolicy>,
 }
 
-pub fn load_apache_config(config_dir: &Path) -> Vec<VirtualHost> {
+/// SSLVerifyClient level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyClient {
+    #[default]
+    None,
+    /// Ask for a certificate but accept clients without one
+    Optional,
+    Require,
+}
 
-    let mut vhosts = Vec::new();
-    let sites_enabled = config_dir.join("sites-enabled");
+/// Which symlinks may be followed when serving files, from
+/// `Options [+|-]FollowSymLinks [+|-]SymLinksIfOwnerMatch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// FollowSymLinks
+    Follow,
+    /// SymLinksIfOwnerMatch: only links owned by their target's owner
+    IfOwnerMatch,
+    /// Neither option: no path through a symlink is served
+    Refuse,
+}
 
-    if !sites_enabled.exists() {
-        return vhosts;
-    }
+/// Settings from a `<Directory>` block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryConfig {
+    /// Directory path; may contain `*` and `?` wildcards
+    pub path: String,
+    pub limit_request_body: Option<u64>,
+    pub symlinks: Option<SymlinkPolicy>,
+    pub fallback_resource: Option<String>,
+}
 
-    if let Ok(entries) = fs::read_dir(sites_enabled) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().map_or(false, |ext| ext == "conf") {
-                vhosts.extend(parse_apache_file(&path, config_dir));
-            }
-        }
-    }
-    vhosts
+/// Settings from a `<Location>` or `<LocationMatch>` block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationConfig {
+    /// URL-path prefix, or a regex for `<LocationMatch>` and `<Location ~ ...>`
+    pub path: String,
+    pub is_regex: bool,
+    pub limit_request_body: Option<u64>,
+    /// `Require all granted` (true) or `Require all denied` (false)
+    pub require_all: Option<bool>,
+    /// `Redirect [status] URL` for the whole block
+    pub redirect: Option<RedirectRule>,
+    /// `SetHandler` for the whole block
+    pub handler: Option<LocationHandler>,
 }
 
-fn parse_apache_file(path: &Path, base_dir: &Path) -> Vec<VirtualHost> {
-    let content = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(_) => return Vec::new(),
-    };
+/// What `SetHandler` in a `<Location>` does with the files it matches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocationHandler {
+    /// `proxy:fcgi://host:port` or `proxy:unix:/path|fcgi://...`: every file
+    /// runs through this FastCGI upstream, whatever its extension
+    FastCgi(String),
+    /// `none` or `default-handler`: files are handled by extension again,
+    /// undoing the SetHandler of an earlier block
+    Default,
+}
 
-    let mut vhosts = Vec::new();
-    let mut current_vhost: Option<VirtualHost> = None;
+impl LocationConfig {
+    /// Whether the block applies to a decoded URL path. As in Apache,
+    /// `/api` matches `/api` and `/api/...` but not `/apix`, while a path
+    /// with wildcards must match whole, so `/api/*` matches `/api/users`
+    /// but not `/api/users/1`.
+    pub fn matches(&self, url_path: &str) -> bool {
+        if self.is_regex {
+            return Regex::new(&self.path).is_ok_and(|re| re.is_match(url_path));
+        }
+        if self.is_wildcard() {
+            return url_wildcard_match(&self.path, url_path);
+        }
+        if self.path.ends_with('/') {
+            url_path.starts_with(&self.path)
+        } else {
+            url_path == self.path || url_path.starts_with(&format!("{}/", self.path))
+        }
+    }
 
-    for line in content.lines() {
-        let line = line.trim();
-        
-        if line.starts_with("<VirtualHost") {
-            // Parse port from <VirtualHost *:8080>
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if let Some(addr_port) = parts.get(1) {
-                let port_str = addr_port.split(':').last().unwrap_or("80");
-                let port = port_str.trim_end_matches('>').parse().unwrap_or(80);
-                
-                current_vhost = Some(VirtualHost {
-                    port,
-                    server_name: None,
-                    server_aliases: Vec::new(),
-                    document_root: None,
-                    ssl_cert_file: None,
-                    ssl_key_file: None,
-                    ssl_chain_file: None,
-                    redirects: Vec::new(),
-                });
-            }
-        } else if line.starts_with("</VirtualHost>") {
-            if let Some(vhost) = current_vhost.take() {
-                vhosts.push(vhost);
-            }
-        } else if let Some(vhost) = &mut current_vhost {
-            if line.starts_with("ServerName") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    vhost.server_name = Some(parts[1].to_string());
-                }
-            } else if line.starts_with("ServerAlias") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                for part in parts.iter().skip(1) {
-                    vhost.server_aliases.push(part.to_string());
-                }
-            } else if line.starts_with("DocumentRoot") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    vhost.document_root = Some(PathBuf::from(parts[1].trim_matches('"')));
-                }
-            } else if line.starts_with("SSLCertificateFile") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let p = PathBuf::from(parts[1].trim_matches('"'));
-                    vhost.ssl_cert_file = Some(if p.is_absolute() { p } else { base_dir.join(p) });
-                }
-            } else if line.starts_with("SSLCertificateKeyFile") {
-                 let parts: Vec<&str> = line.split_whitespace().collect();
-                 if parts.len() >= 2 {
-                     let p = PathBuf::from(parts[1].trim_matches('"'));
-                     vhost.ssl_key_file = Some(if p.is_absolute() { p } else { base_dir.join(p) });
-                 }
-            } else if line.starts_with("SSLCertificateChainFile") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let p = PathBuf::from(parts[1].trim_matches('"'));
-                    vhost.ssl_chain_file = Some(if p.is_absolute() { p } else { base_dir.join(p) });
-                }
-            } else if line.starts_with("RedirectMatch") {
-                // RedirectMatch [status] regex-pattern target-URL
-                if let Some(rule) = parse_redirect_directive(line, true) {
-                    vhost.redirects.push(rule);
-                }
-            } else if line.starts_with("RedirectPermanent") {
-                // RedirectPermanent URL-path URL (shorthand for 301)
-                let parts: Vec<&str> = line.splitn(3, char::is_whitespace)
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                if parts.len() >= 3 {
-                    vhost.redirects.push(RedirectRule {
-                        status: 301,
-                        from: parts[1].to_string(),
-                        to: Some(parts[2].to_string()),
-                        is_regex: false,
-                    });
-                }
-            } else if line.starts_with("RedirectTemp") {
-                // RedirectTemp URL-path URL (shorthand for 302)
-                let parts: Vec<&str> = line.splitn(3, char::is_whitespace)
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                if parts.len() >= 3 {
-                    vhost.redirects.push(RedirectRule {
-                        status: 302,
-                        from: parts[1].to_string(),
-                        to: Some(parts[2].to_string()),
-                        is_regex: false,
-                    });
-                }
-            } else if line.starts_with("Redirect") && !line.starts_with("Redirect ") {
-                // Other Redirect variants we don't recognize - skip
-            } else if line.starts_with("Redirect ") {
-                // Redirect [status] URL-path URL
-                if let Some(rule) = parse_redirect_directive(line, false) {
-                    vhost.redirects.push(rule);
-                }
-            }
+    /// Whether a `<Location>` path is an fnmatch pattern
+    pub fn is_wildcard(&self) -> bool {
+        !self.is_regex && self.path.contains(['*', '?', '['])
+    }
+
+    /// Compile error for a regex block, if any
+    pub fn pattern_error(&self) -> Option<String> {
+        if !self.is_regex {
+            return None;
         }
+        regex_error(&self.path).map(|e| format!("<LocationMatch {}>: {}", self.path, e))
     }
+}
 
+/// The merged `<Location>` settings for one request; later blocks win
+#[derive(Debug, Default)]
+pub struct LocationSettings {
+    pub limit_request_body: Option<BodyLimit>,
+    pub require_all: Option<bool>,
+    pub redirect: Option<RedirectRule>,
+    /// FastCGI upstream that runs every file, from `SetHandler`
+    pub handler: Option<String>,
+}
 
-    vhosts
+/// The LimitRequestBody that applies to a request, and where it was set
+#[derive(Debug, Clone)]
+pub struct BodyLimit {
+    pub bytes: u64,
+    pub scope: String,
 }
 
-/// Parse Apache Redirect or RedirectMatch directive
-fn parse_redirect_directive(line: &str, is_regex: bool) -> Option<RedirectRule> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    
-    // Minimum: Redirect /path URL or RedirectMatch pattern URL
-    if parts.len() < 3 {
-        return None;
+/// An access log declared with CustomLog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomLog {
+    pub path: PathBuf,
+    /// Format nickname (`combined`, `common`, ...) or a literal format string
+    pub format: String,
+}
+
+/// URL-path to filesystem mapping from an Alias directive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alias {
+    pub url_path: String,
+    pub path: PathBuf,
+}
+
+impl VirtualHost {
+    /// Whether this vhost is served over TLS. SSLEngine decides when present;
+    /// older configs without it are TLS when both cert and key are given.
+    pub fn is_ssl(&self) -> bool {
+        self.ssl_engine
+            .unwrap_or(self.ssl_cert_file.is_some() && self.ssl_key_file.is_some())
     }
-    
-    // Check if second token is a status code or keyword
-    let (status, from_idx) = match parts[1] {
-        "permanent" | "301" => (301, 2),
-        "temp" | "302" => (302, 2),
-        "seeother" | "303" => (303, 2),
-        "gone" | "410" => (410, 2),
-        s if s.parse::<u16>().is_ok() => (s.parse().unwrap(), 2),
-        _ => (302, 1), // Default to temporary redirect
-    };
-    
-    if parts.len() <= from_idx {
-        return None;
+
+    /// Every certificate and its key file. A repeated SSLCertificateFile
+    /// without a key file of its own holds its key, as Apache allows.
+    pub fn ssl_cert_pairs(&self) -> Vec<(&Path, &Path)> {
+        let (Some(cert), Some(key)) = (&self.ssl_cert_file, &self.ssl_key_file) else {
+            return Vec::new();
+        };
+        let extra = self.ssl_extra_cert_files.iter().enumerate().map(|(i, cert)| {
+            (cert.as_path(), self.ssl_extra_key_files.get(i).unwrap_or(cert).as_path())
+        });
+        std::iter::once((cert.as_path(), key.as_path())).chain(extra).collect()
     }
-    
+
+    /// Fill in what this vhost leaves unset from the main server config, the
+    /// way Apache merges server configs. Redirects, aliases and `<Directory>`
+    /// blocks are combined, with the vhost's own taking precedence.
+    pub fn inherit(&mut self, server: &VirtualHost) {
+        // Inherited certificate paths alone don't make a vhost TLS
+        if self.ssl_engine.is_none() {
+            self.ssl_engine = server.ssl_engine.or(Some(self.is_ssl()));
+        }
+        if self.server_name.is_none() {
+            self.server_name = server.server_name.clone();
+            self.server_name_scheme = server.server_name_scheme.clone();
+            self.server_name_port = server.server_name_port;
+        }
+        fn fill<T: Clone>(own: &mut Option<T>, inherited: &Option<T>) {
+            if own.is_none() {
+                own.clone_from(inherited);
+            }
+        }
+        fill(&mut self.document_root, &server.document_root);
+        fill(&mut self.server_admin, &server.server_admin);
+        if self.ssl_cert_file.is_none() {
+            self.ssl_extra_cert_files.clone_from(&server.ssl_extra_cert_files);
+        }
+        if self.ssl_key_file.is_none() {
+            self.ssl_extra_key_files.clone_from(&server.ssl_extra_key_files);
+        }
+        fill(&mut self.ssl_cert_file, &server.ssl_cert_file);
+        fill(&mut self.ssl_key_file, &server.ssl_key_file);
+        fill(&mut self.ssl_chain_file, &server.ssl_chain_file);
+        fill(&mut self.ssl_protocol, &server.ssl_protocol);
+        fill(&mut self.ssl_cipher_suite, &server.ssl_cipher_suite);
+        fill(&mut self.ssl_tls13_cipher_suites, &server.ssl_tls13_cipher_suites);
+        fill(&mut self.ssl_verify_client, &server.ssl_verify_client);
+        fill(&mut self.ssl_ca_certificate_file, &server.ssl_ca_certificate_file);
+        fill(&mut self.error_log, &server.error_log);
+        fill(&mut self.limit_request_body, &server.limit_request_body);
+        fill(&mut self.fallback_resource, &server.fallback_resource);
+        fill(&mut self.use_canonical_name, &server.use_canonical_name);
+        fill(&mut self.symlinks, &server.symlinks);
+        if self.fpm_address.is_none() && self.php_mode.is_none() {
+            self.php_mode.clone_from(&server.php_mode);
+            self.fpm_address.clone_from(&server.fpm_address);
+        }
+        if self.custom_logs.is_empty() {
+            self.custom_logs.clone_from(&server.custom_logs);
+        }
+        for extension in &server.php_extensions {
+            if !self.php_extensions.contains(extension) {
+                self.php_extensions.push(extension.clone());
+            }
+        }
+        for (extension, address) in &server.fastcgi_handlers {
+            self.fastcgi_handlers.entry(extension.clone()).or_insert_with(|| address.clone());
+        }
+        self.redirects.extend(server.redirects.iter().cloned());
+        self.aliases.extend(server.aliases.iter().cloned());
+        // Later blocks win in resolve_body_limit, so the vhost's go last
+        self.directories.splice(0..0, server.directories.iter().cloned());
+    }
+
+    /// Merge the `<Location>` blocks that match a decoded URL path, in config order
+    pub fn location_settings(&self, url_path: &str) -> LocationSettings {
+        let mut settings = LocationSettings::default();
+        for location in self.locations.iter().filter(|l| l.matches(url_path)) {
+            if let Some(bytes) = location.limit_request_body {
+                let kind = if location.is_regex { "LocationMatch" } else { "Location" };
+                settings.limit_request_body = Some(BodyLimit { bytes, scope: format!("<{} {}>", kind, location.path) });
+            }
+            if location.require_all.is_some() {
+                settings.require_all = location.require_all;
+            }
+            if location.redirect.is_some() {
+                settings.redirect.clone_from(&location.redirect);
+            }
+            match &location.handler {
+                Some(LocationHandler::FastCgi(address)) => settings.handler = Some(address.clone()),
+                Some(LocationHandler::Default) => settings.handler = None,
+                None => {}
+            }
+        }
+        settings
+    }
+
+    /// Symlink policy for the link at `link`: that of the deepest
+    /// `<Directory>` holding it that sets one, else the vhost's
+    pub fn symlink_policy(&self, link: &Path) -> SymlinkPolicy {
+        let mut best: Option<(usize, SymlinkPolicy)> = None;
+        for directory in &self.directories {
+            let (Some(policy), Some(depth)) = (directory.symlinks, directory_match_depth(&directory.path, link)) else {
+                continue;
+            };
+            if best.is_none_or(|(best_depth, _)| depth >= best_depth) {
+                best = Some((depth, policy));
+            }
+        }
+        best.map(|(_, policy)| policy).or(self.symlinks).unwrap_or(SymlinkPolicy::Follow)
+    }
+
+    /// Map a URL path through the vhost's Alias directives. As in Apache, an
+    /// alias with a trailing slash only matches URLs that include that slash.
+    pub fn resolve_alias(&self, url_path: &str) -> Option<PathBuf> {
+        for alias in &self.aliases {
+            let prefix = alias.url_path.trim_end_matches('/');
+            let matched = if alias.url_path.ends_with('/') {
+                url_path.starts_with(&alias.url_path)
+            } else {
+                url_path == prefix || url_path.starts_with(&format!("{}/", prefix))
+            };
+            if matched {
+                let rest = url_path[prefix.len()..].trim_start_matches('/');
+                return Some(if rest.is_empty() { alias.path.clone() } else { alias.path.join(rest) });
+            }
+        }
+        None
+    }
+}
+
+/// LimitRequestBody for a file, Apache-style: the vhost-level value, then
+/// `<Directory>` blocks and `.htaccess` files, with the deepest directory
+/// winning and `.htaccess` beating a `<Directory>` for the same directory.
+/// A `<Location>` matching `url_path` beats them all. A winning value of 0
+/// means unlimited; None means nothing sets a limit.
+pub fn resolve_body_limit(vhost: Option<&VirtualHost>, doc_root: &Path, file: &Path, url_path: &str) -> Option<BodyLimit> {
+    if let Some(limit) = vhost.and_then(|v| v.location_settings(url_path).limit_request_body) {
+        return Some(limit);
+    }
+
+    let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let doc_root = fs::canonicalize(doc_root).unwrap_or_else(|_| doc_root.to_path_buf());
+
+    // (directory depth, limit)
+    let mut best: Option<(usize, BodyLimit)> = vhost.and_then(|v| v.limit_request_body).map(|bytes| {
+        (0, BodyLimit { bytes, scope: "VirtualHost".to_string() })
+    });
+
+    for directory in vhost.map(|v| v.directories.as_slice()).unwrap_or_default() {
+        let (Some(bytes), Some(depth)) = (directory.limit_request_body, directory_match_depth(&directory.path, &file)) else {
+            continue;
+        };
+        // A later block for the same directory overrides an earlier one
+        if best.as_ref().is_none_or(|(best_depth, _)| depth >= *best_depth) {
+            best = Some((depth, BodyLimit { bytes, scope: format!("<Directory {}>", directory.path) }));
+        }
+    }
+
+    // The deepest .htaccess that sets a limit
+    let htaccess_limit = file
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(&doc_root))
+        .find_map(|dir| {
+            let htaccess = dir.join(".htaccess");
+            let bytes = parse_htaccess(&htaccess)?.limit_request_body?;
+            Some((dir.components().count(), BodyLimit { bytes, scope: htaccess.display().to_string() }))
+        });
+    if let Some((depth, limit)) = htaccess_limit {
+        if best.as_ref().is_none_or(|(best_depth, _)| depth >= *best_depth) {
+            best = Some((depth, limit));
+        }
+    }
+
+    best.map(|(_, limit)| limit)
+}
+
+/// FallbackResource for a missing file, resolved like LimitRequestBody: the
+/// vhost-level value, then `<Directory>` blocks and `.htaccess` files, the
+/// deepest directory winning. The winner's URL-path, or None when there is
+/// none or it is `disabled`.
+pub fn resolve_fallback_resource(vhost: Option<&VirtualHost>, doc_root: &Path, file: &Path) -> Option<String> {
+    // The file is missing, so only the document root can be made canonical
+    let canonical_root = fs::canonicalize(doc_root).unwrap_or_else(|_| doc_root.to_path_buf());
+    let file = file.strip_prefix(doc_root).map(|rest| canonical_root.join(rest)).unwrap_or_else(|_| file.to_path_buf());
+    let doc_root = canonical_root;
+
+    // (directory depth, URL-path)
+    let mut best: Option<(usize, String)> = vhost.and_then(|v| v.fallback_resource.clone()).map(|resource| (0, resource));
+
+    for directory in vhost.map(|v| v.directories.as_slice()).unwrap_or_default() {
+        let (Some(resource), Some(depth)) = (&directory.fallback_resource, directory_match_depth(&directory.path, &file)) else {
+            continue;
+        };
+        if best.as_ref().is_none_or(|(best_depth, _)| depth >= *best_depth) {
+            best = Some((depth, resource.clone()));
+        }
+    }
+
+    // The deepest .htaccess that sets one
+    let htaccess_resource = file
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(&doc_root))
+        .find_map(|dir| Some((dir.components().count(), parse_htaccess(&dir.join(".htaccess"))?.fallback_resource?)));
+    if let Some((depth, resource)) = htaccess_resource {
+        if best.as_ref().is_none_or(|(best_depth, _)| depth >= *best_depth) {
+            best = Some((depth, resource));
+        }
+    }
+
+    best.map(|(_, resource)| resource).filter(|resource| !resource.eq_ignore_ascii_case("disabled"))
+}
+
+/// Depth of a `<Directory>` path if `file` is inside it
+fn directory_match_depth(pattern: &str, file: &Path) -> Option<usize> {
+    let pattern = Path::new(pattern.trim_end_matches('/'));
+    let depth = pattern.components().count();
+    let dir = file.ancestors().skip(1).find(|dir| dir.components().count() == depth)?;
+    wildcard_match(&pattern.to_string_lossy(), &dir.to_string_lossy()).then_some(depth)
+}
+
+/// LimitRequestBody's byte count; invalid values are ignored with a warning
+fn parse_limit_request_body(args: &[String]) -> Option<u64> {
+    let value = args.first()?;
+    match value.parse() {
+        Ok(bytes) => Some(bytes),
+        Err(_) => {
+            eprintln!("Warning: invalid LimitRequestBody '{}'", value);
+            None
+        }
+    }
+}
+
+/// The symlink policy after an `Options` line. Options all prefixed with `+`
+/// or `-` change `current`; otherwise the listed set replaces it, so
+/// `Options Indexes` turns FollowSymLinks off as in Apache. Other options are
+/// ignored.
+fn parse_symlink_options(args: &[String], current: Option<SymlinkPolicy>) -> SymlinkPolicy {
+    let current = current.unwrap_or(SymlinkPolicy::Follow);
+    let relative = args.iter().all(|arg| arg.starts_with(['+', '-']));
+    let (mut follow, mut owner_match) = match current {
+        _ if !relative => (false, false),
+        SymlinkPolicy::Follow => (true, false),
+        SymlinkPolicy::IfOwnerMatch => (false, true),
+        SymlinkPolicy::Refuse => (false, false),
+    };
+    for arg in args {
+        let (on, name) = match arg.strip_prefix('-') {
+            Some(name) => (false, name),
+            None => (true, arg.trim_start_matches('+')),
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "followsymlinks" => follow = on,
+            "symlinksifownermatch" => owner_match = on,
+            // All is everything but MultiViews and SymLinksIfOwnerMatch
+            "all" => follow = on,
+            "none" => (follow, owner_match) = (false, false),
+            _ => {}
+        }
+    }
+    // As in Apache, the owner check applies whenever it is on
+    if owner_match {
+        SymlinkPolicy::IfOwnerMatch
+    } else if follow {
+        SymlinkPolicy::Follow
+    } else {
+        SymlinkPolicy::Refuse
+    }
+}
+
+/// Check `file`, which lies under `root` (the document root or an Alias
+/// target), against the symlink policy of each directory on the way. The
+/// error names the first link that may not be followed.
+pub fn check_symlinks(vhost: Option<&VirtualHost>, root: &Path, file: &Path) -> Result<(), PathBuf> {
+    let (Some(vhost), Ok(relative)) = (vhost, file.strip_prefix(root)) else {
+        return Ok(());
+    };
+    // No link on the way when the real path is the one asked for
+    let real_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    if fs::canonicalize(file).is_ok_and(|real| real == real_root.join(relative)) {
+        return Ok(());
+    }
+
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            return Ok(());
+        };
+        if !meta.file_type().is_symlink() {
+            continue;
+        }
+        let allowed = match vhost.symlink_policy(&path) {
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::IfOwnerMatch => {
+                use std::os::unix::fs::MetadataExt;
+                fs::metadata(&path).is_ok_and(|target| target.uid() == meta.uid())
+            }
+            SymlinkPolicy::Refuse => false,
+        };
+        if !allowed {
+            return Err(path);
+        }
+    }
+    Ok(())
+}
+
+/// Suffixes left behind by editors and package managers
+const BACKUP_SUFFIXES: [&str; 10] = [
+    "~", ".bak", ".old", ".orig", ".swp", ".save",
+    ".dpkg-old", ".dpkg-dist", ".dpkg-new", ".rpmnew",
+];
+
+/// What wolfserve takes from the Apache configuration
+#[derive(Debug, Default)]
+pub struct LoadedConfig {
+    /// Directives outside any `<VirtualHost>` (the main server). They are
+    /// already inherited by `vhosts`, and answer requests no vhost matches.
+    pub server: VirtualHost,
+    pub vhosts: Vec<VirtualHost>,
+    pub connection: ConnectionDirectives,
+    /// Global ServerTokens, as written
+    pub server_tokens: Option<String>,
+    /// `Listen` directives of the main config
+    pub listen: Vec<Listen>,
+}
+
+/// `Listen [address:]port [protocol]`
+#[derive(Debug, Clone)]
+pub struct Listen {
+    pub port: u16,
+    /// `http` or `https` (lowercased), when given
+    pub protocol: Option<String>,
+}
+
+impl Listen {
+    fn parse(args: &[String]) -> Option<Listen> {
+        // The port follows the last colon of `1.2.3.4:80` and `[::]:80`
+        let address = args.first()?;
+        let port = address.rsplit(':').next()?.parse().ok().filter(|port| *port > 0)?;
+        Some(Listen { port, protocol: args.get(1).map(|p| p.to_lowercase()) })
+    }
+}
+
+/// Load the main server config and every vhost from `sites-enabled`. Entries
+/// are read in name order, like Apache's `IncludeOptional sites-enabled/*.conf`,
+/// and symlinks are followed. With `include_extensionless`, files without an
+/// extension are read as well. `verbose` lists each file as it is read;
+/// warnings are always printed.
+pub fn load_apache_config(config_dir: &Path, include_extensionless: bool, verbose: bool) -> LoadedConfig {
+    let mut loaded = LoadedConfig::default();
+    let env = apache_env(config_dir);
+    let site_files = site_files(config_dir, include_extensionless, verbose);
+
+    for path in &site_files {
+        let vhosts = parse_apache_file(path, config_dir, &env);
+        if verbose {
+            tracing::info!(file = %path.display(), vhosts = vhosts.len(), "loaded Apache site file");
+        }
+        loaded.vhosts.extend(vhosts);
+    }
+
+    // apache2.conf includes sites-enabled itself; without it, the global
+    // context of the site files is all there is
+    let main_file = main_config_file(config_dir);
+    let roots = main_file.iter().cloned().collect::<Vec<_>>();
+    for root in if roots.is_empty() { &site_files } else { &roots } {
+        read_server_config(root, config_dir, &env, 0, &mut |file, directory, directive, args| {
+            if directory.is_none() && main_file.is_some() && apply_connection_directive(&mut loaded.connection, file, directive, args) {
+                return;
+            }
+            if directory.is_none() && directive == "servertokens" {
+                loaded.server_tokens = args.first().cloned();
+                return;
+            }
+            if directory.is_none() && directive == "listen" {
+                match Listen::parse(args) {
+                    Some(listen) => loaded.listen.push(listen),
+                    None => eprintln!("Warning: {}: Listen {}: no port; ignored", file.display(), args.join(" ")),
+                }
+                return;
+            }
+            apply_server_directive(&mut loaded.server, directory, directive, args, config_dir);
+        });
+    }
+    loaded.connection.config_file = main_file;
+
+    for vhost in &mut loaded.vhosts {
+        vhost.inherit(&loaded.server);
+    }
+    loaded
+}
+
+/// The readable sites-enabled entries, in name order
+fn site_files(config_dir: &Path, include_extensionless: bool, verbose: bool) -> Vec<PathBuf> {
+    let sites_enabled = config_dir.join("sites-enabled");
+
+    if !sites_enabled.exists() {
+        return Vec::new();
+    }
+
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&sites_enabled) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(e) => {
+            eprintln!("Warning: cannot read {}: {}", sites_enabled.display(), e);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    if verbose {
+        tracing::info!(dir = %sites_enabled.display(), "loading Apache sites");
+    }
+    paths.retain(|path| {
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        if let Some(reason) = skip_reason(path, &file_name, include_extensionless) {
+            if verbose {
+                tracing::info!(file = %path.display(), reason, "skipped Apache site file");
+            }
+            return false;
+        }
+
+        // fs::metadata follows symlinks, so a dangling link fails here
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => true,
+            Ok(_) => {
+                if verbose {
+                    tracing::info!(file = %path.display(), reason = "not a regular file", "skipped Apache site file");
+                }
+                false
+            }
+            Err(e) => {
+                match fs::read_link(path) {
+                    Ok(target) => eprintln!("Warning: {}: skipped (broken symlink to {})", path.display(), target.display()),
+                    Err(_) => eprintln!("Warning: {}: skipped ({})", path.display(), e),
+                }
+                false
+            }
+        }
+    });
+    paths
+}
+
+/// Why a sites-enabled entry is not read as config, if it isn't
+fn skip_reason(path: &Path, file_name: &str, include_extensionless: bool) -> Option<&'static str> {
+    if file_name.starts_with('.') {
+        return Some("hidden file");
+    }
+    if BACKUP_SUFFIXES.iter().any(|suffix| file_name.ends_with(suffix)) {
+        return Some("backup file");
+    }
+    match path.extension() {
+        Some(ext) if ext == "conf" => None,
+        None if include_extensionless => None,
+        _ => Some("not a .conf file"),
+    }
+}
+
+/// Variables available for `${NAME}` expansion in config files: the process
+/// environment first, then `export NAME=value` lines from Debian's envvars
+/// file, with the stock APACHE_LOG_DIR as a last resort.
+fn apache_env(config_dir: &Path) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+
+    if let Ok(content) = fs::read_to_string(config_dir.join("envvars")) {
+        for line in content.lines() {
+            let Some(assignment) = line.trim().strip_prefix("export ") else {
+                continue;
+            };
+            if let Some((name, value)) = assignment.split_once('=') {
+                let value = value.trim().trim_matches('"').replace("$SUFFIX", "");
+                env.insert(name.trim().to_string(), value);
+            }
+        }
+    }
+
+    env.entry("APACHE_LOG_DIR".to_string())
+        .or_insert_with(|| "/var/log/apache2".to_string());
+
+    for (name, value) in std::env::vars() {
+        env.insert(name, value);
+    }
+    env
+}
+
+/// Replace `${NAME}` references with their values; unknown names are left as-is
+fn expand_env(line: &str, env: &HashMap<String, String>) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                let name = &after[..end];
+                match env.get(name) {
+                    Some(value) => result.push_str(value),
+                    None => result.push_str(&rest[start..start + end + 3]),
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn parse_apache_file(path: &Path, base_dir: &Path, env: &HashMap<String, String>) -> Vec<VirtualHost> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Warning: cannot read {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut vhosts = Vec::new();
+    let mut current_vhost: Option<VirtualHost> = None;
+    let mut current_directory: Option<DirectoryConfig> = None;
+    let mut current_location: Option<LocationConfig> = None;
+
+    for (line_number, line) in logical_lines(&content) {
+        let line = expand_env(&line, env);
+        let Some((directive, args)) = split_directive(&line) else {
+            continue;
+        };
+
+        if directive == "<virtualhost" {
+            // Parse port from <VirtualHost *:8080>
+            if let Some(addr_port) = args.first() {
+                let port_str = addr_port.split(':').next_back().unwrap_or("80");
+                let port = port_str.parse().unwrap_or(80);
+                let is_default = addr_port.split(':').next().is_some_and(|addr| addr.eq_ignore_ascii_case("_default_"));
+                
+                current_vhost = Some(VirtualHost {
+                    port,
+                    is_default,
+                    defined_at: Some(format!("{}:{}", path.display(), line_number)),
+                    ..VirtualHost::default()
+                });
+            }
+        } else if directive == "</virtualhost" {
+            if let Some(mut vhost) = current_vhost.take() {
+                vhost.directories.extend(current_directory.take());
+                vhost.locations.extend(current_location.take());
+                vhosts.push(vhost);
+            }
+        } else if let Some(location) = &mut current_location {
+            match directive.as_str() {
+                "</location" | "</locationmatch" => {
+                    if let Some(vhost) = &mut current_vhost {
+                        vhost.locations.extend(current_location.take());
+                    }
+                }
+                "limitrequestbody" => location.limit_request_body = parse_limit_request_body(&args),
+                "require" => match args.iter().map(|a| a.to_ascii_lowercase()).collect::<Vec<_>>().as_slice() {
+                    [all, granted] if all == "all" && granted == "granted" => location.require_all = Some(true),
+                    [all, denied] if all == "all" && denied == "denied" => location.require_all = Some(false),
+                    _ => eprintln!("Warning: {}: only 'Require all granted|denied' is supported in <Location {}>", path.display(), location.path),
+                },
+                "redirect" | "redirectpermanent" | "redirecttemp" => {
+                    location.redirect = parse_location_redirect(&directive, &args, &location.path);
+                }
+                "sethandler" => match args.first() {
+                    Some(handler) if handler.eq_ignore_ascii_case("none") || handler.eq_ignore_ascii_case("default-handler") => {
+                        location.handler = Some(LocationHandler::Default);
+                    }
+                    Some(handler) => match parse_fcgi_handler(handler) {
+                        Some(address) => location.handler = Some(LocationHandler::FastCgi(address)),
+                        None => eprintln!("Warning: {}: only FastCGI proxy handlers are supported in <Location {}>", path.display(), location.path),
+                    },
+                    None => {}
+                },
+                // Other directives only apply to this URL space, so they are
+                // not passed on to the vhost
+                _ => {}
+            }
+        } else if let Some(vhost) = &mut current_vhost {
+            match directive.as_str() {
+                // Regex forms (`<Directory ~ ...>`) are not supported
+                "<directory" if args.first().is_some_and(|a| a != "~") => {
+                    current_directory = Some(DirectoryConfig {
+                        path: resolve_config_path(&args[0], base_dir).to_string_lossy().into_owned(),
+                        limit_request_body: None,
+                        symlinks: None,
+                        fallback_resource: None,
+                    });
+                }
+                "</directory" => vhost.directories.extend(current_directory.take()),
+                "<location" | "<locationmatch" => {
+                    // `<Location ~ "regex">` is the same as <LocationMatch>
+                    let (is_regex, pattern) = match args.as_slice() {
+                        [tilde, pattern, ..] if directive == "<location" && tilde == "~" => (true, pattern),
+                        [pattern, ..] => (directive == "<locationmatch", pattern),
+                        [] => continue,
+                    };
+                    current_location = Some(LocationConfig {
+                        path: pattern.clone(),
+                        is_regex,
+                        limit_request_body: None,
+                        require_all: None,
+                        redirect: None,
+                        handler: None,
+                    });
+                }
+                "limitrequestbody" => {
+                    let limit = parse_limit_request_body(&args);
+                    match &mut current_directory {
+                        Some(directory) => directory.limit_request_body = limit,
+                        None => vhost.limit_request_body = limit,
+                    }
+                }
+                "fallbackresource" => {
+                    let resource = args.first().cloned();
+                    match &mut current_directory {
+                        Some(directory) => directory.fallback_resource = resource,
+                        None => vhost.fallback_resource = resource,
+                    }
+                }
+                "options" => {
+                    let inherited = vhost.symlinks;
+                    match &mut current_directory {
+                        Some(directory) => directory.symlinks = Some(parse_symlink_options(&args, directory.symlinks.or(inherited))),
+                        None => vhost.symlinks = Some(parse_symlink_options(&args, inherited)),
+                    }
+                }
+                _ => apply_vhost_directive(vhost, &directive, &args, base_dir),
+            }
+        }
+    }
+
+
+    vhosts
+}
+
+/// Connection directives from the main server config. Only the global
+/// context counts; copies inside `<VirtualHost>`, `<Directory>` and other
+/// sections are ignored.
+#[derive(Debug, Default)]
+pub struct ConnectionDirectives {
+    /// apache2.conf / httpd.conf, for log messages
+    pub config_file: Option<PathBuf>,
+    pub keep_alive: Option<bool>,
+    pub keep_alive_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    /// 0 means unlimited
+    pub max_keep_alive_requests: Option<u64>,
+}
+
+/// Nested Include depth after which we assume a loop
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// apache2.conf (Debian) or httpd.conf (RHEL), if there is one
+fn main_config_file(config_dir: &Path) -> Option<PathBuf> {
+    ["apache2.conf", "httpd.conf", "conf/httpd.conf"]
+        .iter()
+        .map(|name| config_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// How the server config reader treats a section
+enum Section {
+    /// `<IfModule>`: read as if every module were loaded
+    Transparent,
+    /// A top-level `<Directory>`, with its resolved path
+    Directory(String),
+    /// `<VirtualHost>`, negated `<IfModule !...>` and everything else
+    Skipped,
+}
+
+/// Walk the main server config (everything outside `<VirtualHost>`),
+/// following Include and IncludeOptional the way Apache does. `visit` gets
+/// each directive with the `<Directory>` it sits in, if any; later settings
+/// win, so visitors simply overwrite.
+fn read_server_config<F: FnMut(&Path, Option<&str>, &str, &[String])>(
+    path: &Path,
+    base_dir: &Path,
+    env: &HashMap<String, String>,
+    include_depth: usize,
+    visit: &mut F,
+) {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Warning: cannot read {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let mut sections: Vec<Section> = Vec::new();
+    for (_, line) in logical_lines(&content) {
+        let line = expand_env(&line, env);
+        let Some((directive, args)) = split_directive(&line) else {
+            continue;
+        };
+
+        if directive.starts_with("</") {
+            sections.pop();
+            continue;
+        }
+        let skipped = sections.iter().any(|s| matches!(s, Section::Skipped));
+        let directory = sections.iter().find_map(|s| match s {
+            Section::Directory(path) => Some(path.as_str()),
+            _ => None,
+        });
+        if directive.starts_with('<') {
+            let first = args.first().map(String::as_str).unwrap_or("");
+            sections.push(match directive.as_str() {
+                _ if skipped => Section::Skipped,
+                "<ifmodule" if !first.starts_with('!') => Section::Transparent,
+                // Regex forms (`<Directory ~ ...>`) are not supported
+                "<directory" if directory.is_none() && !first.is_empty() && first != "~" => {
+                    Section::Directory(resolve_config_path(first, base_dir).to_string_lossy().into_owned())
+                }
+                _ => Section::Skipped,
+            });
+            continue;
+        }
+        if skipped {
+            continue;
+        }
+
+        let value = args.first().map(String::as_str).unwrap_or("");
+        if directory.is_none() && (directive == "include" || directive == "includeoptional") {
+            if include_depth >= MAX_INCLUDE_DEPTH {
+                eprintln!("Warning: {}: Include nested too deeply, ignoring {}", path.display(), value);
+                continue;
+            }
+            let optional = directive == "includeoptional";
+            for included in expand_include(&resolve_config_path(value, base_dir), optional) {
+                read_server_config(&included, base_dir, env, include_depth + 1, visit);
+            }
+            continue;
+        }
+        visit(path, directory, &directive, &args);
+    }
+}
+
+/// Record KeepAlive, KeepAliveTimeout, Timeout or MaxKeepAliveRequests.
+/// Returns false for other directives.
+fn apply_connection_directive(directives: &mut ConnectionDirectives, path: &Path, directive: &str, args: &[String]) -> bool {
+    let value = args.first().map(String::as_str).unwrap_or("");
+    match directive {
+        "keepalive" => match value.to_ascii_lowercase().as_str() {
+            "on" => directives.keep_alive = Some(true),
+            "off" => directives.keep_alive = Some(false),
+            _ => eprintln!("Warning: {}: KeepAlive must be On or Off, got '{}'", path.display(), value),
+        },
+        "keepalivetimeout" => match parse_apache_duration(value) {
+            Some(d) => directives.keep_alive_timeout = Some(d),
+            None => eprintln!("Warning: {}: invalid KeepAliveTimeout '{}'", path.display(), value),
+        },
+        "timeout" => match parse_apache_duration(value) {
+            Some(d) => directives.timeout = Some(d),
+            None => eprintln!("Warning: {}: invalid Timeout '{}'", path.display(), value),
+        },
+        "maxkeepaliverequests" => match value.parse() {
+            Ok(n) => directives.max_keep_alive_requests = Some(n),
+            Err(_) => eprintln!("Warning: {}: invalid MaxKeepAliveRequests '{}'", path.display(), value),
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// Apply a directive from the main server config, inside `directory` if given
+fn apply_server_directive(server: &mut VirtualHost, directory: Option<&str>, directive: &str, args: &[String], base_dir: &Path) {
+    match (directory, directive) {
+        (Some(path), "limitrequestbody" | "options" | "fallbackresource") => {
+            if server.directories.last().is_none_or(|last| last.path != path) {
+                server.directories.push(DirectoryConfig {
+                    path: path.to_string(),
+                    limit_request_body: None,
+                    symlinks: None,
+                    fallback_resource: None,
+                });
+            }
+            let inherited = server.symlinks;
+            let Some(last) = server.directories.last_mut() else { return };
+            match directive {
+                "limitrequestbody" => last.limit_request_body = parse_limit_request_body(args),
+                "fallbackresource" => last.fallback_resource = args.first().cloned(),
+                _ => last.symlinks = Some(parse_symlink_options(args, last.symlinks.or(inherited))),
+            }
+        }
+        (Some(_), _) => {}
+        (None, "limitrequestbody") => server.limit_request_body = parse_limit_request_body(args),
+        (None, "fallbackresource") => server.fallback_resource = args.first().cloned(),
+        (None, "options") => server.symlinks = Some(parse_symlink_options(args, server.symlinks)),
+        (None, _) => apply_vhost_directive(server, directive, args, base_dir),
+    }
+}
+
+/// Files named by an Include: a file, every file in a directory, or a
+/// wildcard in the last path component, in name order
+fn expand_include(target: &Path, optional: bool) -> Vec<PathBuf> {
+    let file_name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let (dir, pattern) = if file_name.contains(['*', '?']) {
+        (target.parent().unwrap_or(Path::new("/")).to_path_buf(), Some(file_name))
+    } else if target.is_dir() {
+        (target.to_path_buf(), None)
+    } else if target.is_file() {
+        return vec![target.to_path_buf()];
+    } else {
+        if !optional {
+            eprintln!("Warning: Include {} not found", target.display());
+        }
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        path.is_file() && pattern.as_deref().is_none_or(|p| wildcard_match(p, &name))
+    });
+    files.sort();
+    if files.is_empty() && pattern.is_some() && !optional {
+        eprintln!("Warning: Include {} matched no files", target.display());
+    }
+    files
+}
+
+/// Seconds, or milliseconds with an `ms` suffix (`KeepAliveTimeout 500ms`)
+fn parse_apache_duration(value: &str) -> Option<Duration> {
+    match value.strip_suffix("ms") {
+        Some(ms) => ms.parse().ok().map(Duration::from_millis),
+        None => value.parse().ok().map(Duration::from_secs),
+    }
+}
+
+/// Handler or MIME type names that mean "run as PHP": mod_php's
+/// `application/x-httpd-php` and the `php-script`/`php8.2-script` handlers.
+/// `application/x-httpd-php-source` (highlighted source) is not one.
+fn is_php_handler(handler: &str) -> bool {
+    let handler = handler.to_ascii_lowercase();
+    handler == "application/x-httpd-php" || (handler.starts_with("php") && handler.ends_with("-script"))
+}
+
+/// Apply a single directive found inside a <VirtualHost> block
+fn apply_vhost_directive(vhost: &mut VirtualHost, directive: &str, args: &[String], base_dir: &Path) {
+    match directive {
+        "servername" => {
+            if let Some(value) = args.first() {
+                let (name, scheme, port) = parse_server_name(value);
+                vhost.server_name = Some(name);
+                vhost.server_name_scheme = scheme;
+                vhost.server_name_port = port;
+            }
+        }
+        "serveralias" => {
+            for alias in args {
+                vhost.server_aliases.push(parse_server_name(alias).0);
+            }
+        }
+        "serveradmin" => {
+            if let Some(contact) = args.first() {
+                vhost.server_admin = Some(contact.clone());
+            }
+        }
+        "documentroot" => {
+            if let Some(root) = args.first() {
+                vhost.document_root = Some(PathBuf::from(root));
+            }
+        }
+        "alias" => {
+            // Alias URL-path file-or-directory-path
+            if let [url_path, target, ..] = args {
+                vhost.aliases.push(Alias {
+                    url_path: url_path.to_string(),
+                    path: resolve_config_path(target, base_dir),
+                });
+            }
+        }
+        "sethandler" => {
+            // How Debian wires PHP-FPM pools:
+            // SetHandler "proxy:unix:/run/php/php8.2-fpm.sock|fcgi://localhost"
+            if let Some(address) = args.first().and_then(|handler| parse_fcgi_handler(handler)) {
+                vhost.fpm_address = Some(address);
+                vhost.php_mode = Some("fpm".to_string());
+            }
+        }
+        "addhandler" | "addtype" => {
+            // AddHandler application/x-httpd-php .phtml .php8
+            if let [handler, extensions @ ..] = args {
+                let extensions = extensions.iter().map(|e| e.trim_start_matches('.').to_ascii_lowercase()).filter(|e| !e.is_empty());
+                if is_php_handler(handler) {
+                    for extension in extensions {
+                        if !vhost.php_extensions.contains(&extension) {
+                            vhost.php_extensions.push(extension);
+                        }
+                    }
+                } else if let Some(address) = parse_fcgi_handler(handler).filter(|_| directive == "addhandler") {
+                    // AddHandler "proxy:fcgi://127.0.0.1:9100" .py
+                    for extension in extensions {
+                        vhost.fastcgi_handlers.insert(extension, address.clone());
+                    }
+                }
+            }
+        }
+        "usecanonicalname" => {
+            if let Some(value) = args.first() {
+                vhost.use_canonical_name = Some(value.eq_ignore_ascii_case("on"));
+            }
+        }
+        // Repeating these adds another certificate (RSA next to ECDSA), as in Apache
+        "sslcertificatefile" => {
+            if let Some(p) = args.first() {
+                let path = resolve_config_path(p, base_dir);
+                match &vhost.ssl_cert_file {
+                    Some(first) if *first != path && !vhost.ssl_extra_cert_files.contains(&path) => vhost.ssl_extra_cert_files.push(path),
+                    Some(_) => {}
+                    None => vhost.ssl_cert_file = Some(path),
+                }
+            }
+        }
+        "sslcertificatekeyfile" => {
+            if let Some(p) = args.first() {
+                let path = resolve_config_path(p, base_dir);
+                match &vhost.ssl_key_file {
+                    Some(first) if *first != path && !vhost.ssl_extra_key_files.contains(&path) => vhost.ssl_extra_key_files.push(path),
+                    Some(_) => {}
+                    None => vhost.ssl_key_file = Some(path),
+                }
+            }
+        }
+        "sslcertificatechainfile" => {
+            if let Some(p) = args.first() {
+                vhost.ssl_chain_file = Some(resolve_config_path(p, base_dir));
+            }
+        }
+        "sslengine" => {
+            if let Some(value) = args.first() {
+                match value.to_lowercase().as_str() {
+                    "on" => vhost.ssl_engine = Some(true),
+                    "off" => vhost.ssl_engine = Some(false),
+                    // "optional" is the RFC 2817 upgrade mode, which we don't do
+                    _ => eprintln!("Warning: unsupported SSLEngine value '{}'", value),
+                }
+            }
+        }
+        "sslprotocol" => {
+            if !args.is_empty() {
+                vhost.ssl_protocol = Some(args.to_vec());
+            }
+        }
+        "sslciphersuite" => {
+            // SSLCipherSuite [protocol] cipher-spec
+            match args {
+                [protocol, spec, ..] if protocol.eq_ignore_ascii_case("tlsv1.3") => {
+                    vhost.ssl_tls13_cipher_suites = Some(spec.clone());
+                }
+                [protocol, spec, ..] if protocol.eq_ignore_ascii_case("sslv3") => {
+                    vhost.ssl_cipher_suite = Some(spec.clone());
+                }
+                [spec] => vhost.ssl_cipher_suite = Some(spec.clone()),
+                _ => {}
+            }
+        }
+        "sslverifyclient" => {
+            if let Some(value) = args.first() {
+                vhost.ssl_verify_client = match value.to_lowercase().as_str() {
+                    "none" => Some(VerifyClient::None),
+                    "optional" => Some(VerifyClient::Optional),
+                    "require" => Some(VerifyClient::Require),
+                    "optional_no_ca" => {
+                        eprintln!("Warning: SSLVerifyClient optional_no_ca is not supported; client certificates must chain to SSLCACertificateFile (treated as optional)");
+                        Some(VerifyClient::Optional)
+                    }
+                    _ => {
+                        eprintln!("Warning: unsupported SSLVerifyClient value '{}'", value);
+                        vhost.ssl_verify_client
+                    }
+                };
+            }
+        }
+        "sslcacertificatefile" => {
+            if let Some(p) = args.first() {
+                vhost.ssl_ca_certificate_file = Some(resolve_config_path(p, base_dir));
+            }
+        }
+        "sslopensslconfcmd" => {
+            if let [command, value, ..] = args {
+                if command.eq_ignore_ascii_case("ciphersuites") {
+                    vhost.ssl_tls13_cipher_suites = Some(value.clone());
+                }
+            }
+        }
+        "errorlog" => {
+            // Pipes and syslog are not supported; those vhosts keep the global log
+            if let Some(target) = args.first() {
+                if !target.starts_with('|') && !target.starts_with("syslog") {
+                    vhost.error_log = Some(resolve_config_path(target, base_dir));
+                }
+            }
+        }
+        "customlog" => {
+            // CustomLog file format-or-nickname [env=...]
+            if let [target, format, ..] = args {
+                if !target.starts_with('|') {
+                    vhost.custom_logs.push(CustomLog {
+                        path: resolve_config_path(target, base_dir),
+                        format: format.to_string(),
+                    });
+                }
+            }
+        }
+        _ => {
+            // Redirect [status] URL-path URL, RedirectMatch [status] regex URL,
+            // RedirectPermanent / RedirectTemp URL-path URL
+            if let Some(rule) = parse_redirect_family(directive, args) {
+                vhost.redirects.push(rule);
+            }
+        }
+    }
+}
+
+/// FPM address from a mod_proxy_fcgi handler: `proxy:unix:/path|fcgi://...`
+/// gives `unix:/path`, `proxy:fcgi://127.0.0.1:9000/` gives `127.0.0.1:9000`
+fn parse_fcgi_handler(handler: &str) -> Option<String> {
+    let target = handler.get(..6).filter(|p| p.eq_ignore_ascii_case("proxy:")).map(|_| &handler[6..])?;
+    if let Some(path) = target.strip_prefix("unix:") {
+        let path = path.split('|').next().unwrap_or_default();
+        return (!path.is_empty()).then(|| format!("unix:{}", path));
+    }
+    let authority = target.strip_prefix("fcgi://")?.split('/').next().unwrap_or_default();
+    (!authority.is_empty()).then(|| authority.to_string())
+}
+
+/// Resolve a possibly relative path from the config against the Apache config dir
+fn resolve_config_path(value: &str, base_dir: &Path) -> PathBuf {
+    let p = PathBuf::from(value);
+    if p.is_absolute() { p } else { base_dir.join(p) }
+}
+
+/// Parse any member of the Redirect family (Redirect, RedirectMatch,
+/// RedirectPermanent, RedirectTemp). Returns None for other directives.
+fn parse_redirect_family(directive: &str, args: &[String]) -> Option<RedirectRule> {
+    match directive {
+        "redirectmatch" => parse_redirect_directive(args, true),
+        "redirect" => parse_redirect_directive(args, false),
+        // Shorthands for 301 and 302
+        "redirectpermanent" | "redirecttemp" => {
+            if args.len() < 2 {
+                return None;
+            }
+            Some(RedirectRule {
+                status: if directive == "redirectpermanent" { 301 } else { 302 },
+                from: args[0].to_string(),
+                to: Some(args[1].to_string()),
+                is_regex: false,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parse `Redirect [status] URL` inside a `<Location>`, where the URL-path
+/// is the block's own
+fn parse_location_redirect(directive: &str, args: &[String], location: &str) -> Option<RedirectRule> {
+    let mut full = args.to_vec();
+    // "Redirect gone" has no URL; otherwise the path goes before the URL
+    let at = match args.last().map(|a| a.to_ascii_lowercase()) {
+        Some(last) if last == "gone" || last.parse::<u16>().is_ok() => args.len(),
+        Some(_) => args.len() - 1,
+        None => return None,
+    };
+    full.insert(at, location.to_string());
+    parse_redirect_family(directive, &full)
+}
+
+/// Parse Apache Redirect or RedirectMatch directive
+fn parse_redirect_directive(parts: &[String], is_regex: bool) -> Option<RedirectRule> {
+    // Minimum: /path URL or pattern URL (gone takes only a path)
+    if parts.len() < 2 {
+        return None;
+    }
+    
+    // Check if first argument is a status code or keyword
+    let (status, from_idx) = match parts[0].to_ascii_lowercase().as_str() {
+        "permanent" | "301" => (301, 1),
+        "temp" | "302" => (302, 1),
+        "seeother" | "303" => (303, 1),
+        "gone" | "410" => (410, 1),
+        s if s.parse::<u16>().is_ok() => (s.parse().unwrap(), 1),
+        _ => (302, 0), // Default to temporary redirect
+    };
+    
+    if parts.len() <= from_idx {
+        return None;
+    }
+    
     let from = parts[from_idx].to_string();
     
-    // "gone" status has no target URL
-    let to = if status == 410 {
+    // Only redirects (3xx) take a target URL; "gone" and other statuses don't
+    let to = if !(300..400).contains(&status) {
         None
     } else if parts.len() > from_idx + 1 {
         Some(parts[from_idx + 1].to_string())
@@ -637,3 +2242,393 @@ fn parse_redirect_directive(line: &str, is_regex: bool) -> Option<RedirectRule>
         is_regex,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backslash_joins_continued_lines() {
+        let lines = logical_lines("RewriteEngine On\nRewriteRule ^a$ \\\n    /b [L]\nRewriteBase /\n");
+        assert_eq!(
+            lines,
+            vec![
+                (1, "RewriteEngine On".to_string()),
+                (2, "RewriteRule ^a$     /b [L]".to_string()),
+                (4, "RewriteBase /".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn continuation_on_the_last_line_still_counts() {
+        assert_eq!(logical_lines("RewriteBase \\\n/sub\\"), vec![(1, "RewriteBase /sub".to_string())]);
+    }
+
+    #[test]
+    fn continued_rewrite_cond_and_rule_parse_as_one_directive() {
+        let config = parse_htaccess_content(
+            "RewriteEngine On\n\
+             RewriteCond %{REQUEST_FILENAME} \\\n    !-f \\\n    [NC,OR]\n\
+             RewriteCond %{REQUEST_FILENAME} !-d\n\
+             RewriteRule ^(.*)$ \\\n    index.php?q=$1 \\\n    [L,QSA]\n",
+        );
+
+        assert_eq!(config.rewrite_rules.len(), 1);
+        let rule = &config.rewrite_rules[0];
+        assert_eq!(rule.pattern, "^(.*)$");
+        assert_eq!(rule.substitution, "index.php?q=$1");
+        assert!(rule.last && rule.qsappend);
+
+        assert_eq!(rule.conditions.len(), 2);
+        let first = &rule.conditions[0];
+        assert_eq!((first.test_string.as_str(), first.pattern.as_str()), ("%{REQUEST_FILENAME}", "-f"));
+        assert!(first.negate && first.nocase && first.or_next);
+        assert_eq!(rule.conditions[1].pattern, "-d");
+    }
+
+    #[test]
+    fn directive_names_are_case_insensitive_and_tabs_separate_arguments() {
+        let expected = Some(("rewriterule".to_string(), vec!["^old$".to_string(), "/new".to_string(), "[R=301,L]".to_string()]));
+        assert_eq!(split_directive("ReWriteRULE\t^old$\t\t/new \t[R=301,L]"), expected);
+        assert_eq!(split_directive("\trewriterule ^old$ /new\t[R=301,L]\t"), expected);
+
+        assert_eq!(
+            split_directive("<VirtualHost\t*:80>"),
+            Some(("<virtualhost".to_string(), vec!["*:80".to_string()]))
+        );
+        assert_eq!(split_directive("\t# RewriteRule ^a$ /b"), None);
+        assert_eq!(split_directive(" \t "), None);
+    }
+
+    #[test]
+    fn mixed_case_tab_separated_htaccess_is_understood() {
+        let config = parse_htaccess_content("REWRITEENGINE\ton\nrewriteCond\t%{HTTPS}\toff\nRewriteRule\t^(.*)$\thttps://%{HTTP_HOST}/$1\t[r=301,l]\n");
+        assert!(config.rewrite_engine);
+        assert_eq!(config.rewrite_rules.len(), 1);
+        let rule = &config.rewrite_rules[0];
+        assert_eq!(rule.redirect, Some(301));
+        assert!(rule.last);
+        assert_eq!(rule.conditions[0].pattern, "off");
+    }
+
+    #[test]
+    fn server_name_drops_scheme_and_port() {
+        assert_eq!(
+            parse_server_name("https://WWW.Example.com:443"),
+            ("www.example.com".to_string(), Some("https".to_string()), Some(443))
+        );
+        assert_eq!(parse_server_name("example.com:8080"), ("example.com".to_string(), None, Some(8080)));
+        assert_eq!(parse_server_name("Example.COM"), ("example.com".to_string(), None, None));
+        assert_eq!(
+            parse_server_name("http://[::1]:8080/"),
+            ("[::1]".to_string(), Some("http".to_string()), Some(8080))
+        );
+    }
+
+    #[test]
+    fn split_host_port_handles_names_and_ipv6_literals() {
+        assert_eq!(split_host_port("Example.com:8080"), ("example.com".to_string(), Some(8080)));
+        assert_eq!(split_host_port("example.com"), ("example.com".to_string(), None));
+        assert_eq!(split_host_port("[::1]:443"), ("[::1]".to_string(), Some(443)));
+        assert_eq!(split_host_port("[::1]"), ("[::1]".to_string(), None));
+        assert_eq!(split_host_port("::1"), ("::1".to_string(), None));
+        assert_eq!(split_host_port("example.com:http"), ("example.com".to_string(), None));
+    }
+
+    #[test]
+    fn idn_server_names_pass_through_unchanged() {
+        assert_eq!(parse_server_name("xn--bcher-kva.example").0, "xn--bcher-kva.example");
+        assert_eq!(parse_server_name("bücher.example:8443"), ("bücher.example".to_string(), None, Some(8443)));
+
+        let mut table = HostTable::default();
+        table.insert("xn--bcher-kva.example", "punycode");
+        table.insert("bücher.example", "unicode");
+        assert_eq!(table.get("XN--BCHER-KVA.example"), Some(&"punycode"));
+        assert_eq!(table.get("bücher.example."), Some(&"unicode"));
+    }
+
+    #[test]
+    fn wildcard_aliases_match_after_exact_names() {
+        let mut table = HostTable::default();
+        table.insert("*.example.com", "any subdomain");
+        table.insert("*.shop.example.com", "shop");
+        table.insert("www?.example.com", "numbered www");
+        table.insert("api.example.com", "api");
+
+        assert_eq!(table.get("api.example.com"), Some(&"api"));
+        assert_eq!(table.get("Blog.Example.com"), Some(&"any subdomain"));
+        assert_eq!(table.get("eu.shop.example.com"), Some(&"shop"));
+        assert_eq!(table.get("www2.example.com"), Some(&"numbered www"));
+        assert_eq!(table.get("example.com"), None);
+        assert_eq!(table.get("example.org"), None);
+    }
+
+    #[test]
+    fn quoted_arguments_keep_their_spaces() {
+        assert_eq!(
+            tokenize_args(r#"Alias "/my docs" "/srv/shared docs/""#),
+            vec!["Alias", "/my docs", "/srv/shared docs/"]
+        );
+        assert_eq!(tokenize_args("DocumentRoot\t'/var/www/my site'"), vec!["DocumentRoot", "/var/www/my site"]);
+        assert_eq!(tokenize_args(r#"Header set X-Quote "say \"hi\"""#), vec!["Header", "set", "X-Quote", r#"say "hi""#]);
+        // Unquoted tokens keep backslashes for regexes
+        assert_eq!(tokenize_args(r"RewriteRule ^a\.b$ /c"), vec!["RewriteRule", r"^a\.b$", "/c"]);
+    }
+
+    #[test]
+    fn document_root_and_alias_accept_quoted_paths_with_spaces() {
+        let mut vhost = VirtualHost::default();
+        for line in [r#"DocumentRoot "/var/www/my site""#, r#"Alias "/shared files" "/srv/shared files""#] {
+            let (directive, args) = split_directive(line).unwrap();
+            apply_vhost_directive(&mut vhost, &directive, &args, Path::new("/etc/apache2"));
+        }
+
+        assert_eq!(vhost.document_root, Some(PathBuf::from("/var/www/my site")));
+        assert_eq!(vhost.aliases.len(), 1);
+        assert_eq!(vhost.aliases[0].url_path, "/shared files");
+        assert_eq!(vhost.aliases[0].path, PathBuf::from("/srv/shared files"));
+    }
+
+    /// Run `htaccess`'s rules against a GET for `uri?query`
+    fn rewrite(htaccess: &str, uri: &str, query: &str) -> Option<RewriteResult> {
+        let root = Path::new("/nonexistent/docroot");
+        parse_htaccess_content(htaccess).apply_rewrites(&RewriteContext {
+            request_uri: uri,
+            request_filename: &root.join(uri.trim_start_matches('/')),
+            query_string: query,
+            http_host: "example.com",
+            request_method: "GET",
+            https: false,
+            document_root: root,
+        })
+    }
+
+    fn redirect_url(result: Option<RewriteResult>) -> String {
+        match result {
+            Some(RewriteResult::Redirect { url, .. }) => url,
+            other => panic!("expected a redirect, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn qsd_drops_the_request_query_string() {
+        let rules = "RewriteEngine On\nRewriteRule ^old$ /new [R=301,QSD]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/old", "utm_source=x&id=3")), "/new");
+        // Without QSD the query string is carried over
+        let rules = "RewriteEngine On\nRewriteRule ^old$ /new [R=301]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/old", "utm_source=x&id=3")), "/new?utm_source=x&id=3");
+
+        let rules = "RewriteEngine On\nRewriteRule ^page$ /index.php [QSD,L]\n";
+        match rewrite(rules, "/page", "a=1") {
+            Some(RewriteResult::InternalRewrite { path, query }) => assert_eq!((path.as_str(), query.as_str()), ("/index.php", "")),
+            other => panic!("expected an internal rewrite, got {:?}", other),
+        }
+
+        // A query string in the substitution survives, even with QSA
+        let rules = "RewriteEngine On\nRewriteRule ^old$ /new?page=2 [R,QSD,QSA]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/old", "a=1")), "/new?page=2");
+    }
+
+    #[test]
+    fn ne_leaves_the_target_unescaped() {
+        let rules = "RewriteEngine On\nRewriteRule ^tags/(.*)$ /#/tags/$1 [R=302,NE]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/tags/rust", "")), "/#/tags/rust");
+        let rules = "RewriteEngine On\nRewriteRule ^tags/(.*)$ /#/tags/$1 [R=302]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/tags/rust", "")), "/%23/tags/rust");
+
+        let rules = "RewriteEngine On\nRewriteRule ^docs$ \"/my docs\" [R,NE]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/docs", "")), "/my docs");
+        let rules = "RewriteEngine On\nRewriteRule ^docs$ \"/my docs\" [R]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/docs", "")), "/my%20docs");
+
+        // A fragment stays after the query string
+        let rules = "RewriteEngine On\nRewriteRule ^faq$ /help#faq [R,NE]\n";
+        assert_eq!(redirect_url(rewrite(rules, "/faq", "lang=en")), "/help?lang=en#faq");
+    }
+
+    #[test]
+    fn qsa_alone_does_not_stop_rule_processing() {
+        let rules = "RewriteEngine On\n\
+                     RewriteRule ^shop$ /catalog?view=grid [QSA]\n\
+                     RewriteRule ^shop$ /index.php?route=shop [QSA]\n";
+        let config = parse_htaccess_content(rules);
+        assert!(config.rewrite_rules.iter().all(|rule| rule.qsappend && !rule.last));
+
+        // The second rule still runs, appending the first one's query
+        match rewrite(rules, "/shop", "page=2") {
+            Some(RewriteResult::InternalRewrite { path, query }) => {
+                assert_eq!(path, "/index.php");
+                assert_eq!(query, "route=shop&view=grid&page=2");
+            }
+            other => panic!("expected an internal rewrite, got {:?}", other),
+        }
+
+        // With L the first rule is the last
+        let rules = rules.replacen("[QSA]", "[QSA,L]", 1);
+        match rewrite(&rules, "/shop", "page=2") {
+            Some(RewriteResult::InternalRewrite { path, query }) => {
+                assert_eq!(path, "/catalog");
+                assert_eq!(query, "view=grid&page=2");
+            }
+            other => panic!("expected an internal rewrite, got {:?}", other),
+        }
+
+        // Nor do other flags, or a flag value with an L in it
+        for flags in ["[PT,NC]", "[R=301]", "[E=LANG:en]", "[NE,QSD]"] {
+            let config = parse_htaccess_content(&format!("RewriteRule ^a$ /b {}", flags));
+            assert!(!config.rewrite_rules[0].last, "{} set last", flags);
+        }
+    }
+
+    #[test]
+    fn https_condition_follows_the_listener() {
+        let config = parse_htaccess_content(
+            "RewriteEngine On\nRewriteCond %{HTTPS} off\nRewriteRule ^(.*)$ https://%{HTTP_HOST}%{REQUEST_URI} [R=301,L]\n",
+        );
+        let root = Path::new("/nonexistent/docroot");
+        let filename = root.join("blog");
+        let plain = RewriteContext {
+            request_uri: "/blog/",
+            request_filename: &filename,
+            query_string: "page=2",
+            http_host: "example.com",
+            request_method: "GET",
+            https: false,
+            document_root: root,
+        };
+
+        match config.apply_rewrites(&plain) {
+            Some(RewriteResult::Redirect { url, status }) => {
+                assert_eq!((url.as_str(), status), ("https://example.com/blog/?page=2", 301))
+            }
+            other => panic!("expected a redirect, got {:?}", other),
+        }
+        assert!(config.apply_rewrites(&RewriteContext { https: true, ..plain }).is_none());
+    }
+
+    fn redirect(line: &str) -> RedirectRule {
+        let (directive, args) = split_directive(line).unwrap();
+        parse_redirect_family(&directive, &args).unwrap()
+    }
+
+    #[test]
+    fn prefix_redirect_keeps_the_trailing_path_and_query() {
+        let rule = redirect("Redirect permanent /old /new");
+        assert_eq!(rule.matches("/old", ""), Some((301, Some("/new".to_string()))));
+        assert_eq!(rule.matches("/old/a/b.html", ""), Some((301, Some("/new/a/b.html".to_string()))));
+        assert_eq!(rule.matches("/old/page", "a=1&b=2"), Some((301, Some("/new/page?a=1&b=2".to_string()))));
+        assert_eq!(rule.matches("/old", "a=1"), Some((301, Some("/new?a=1".to_string()))));
+        // Only whole path segments match
+        assert_eq!(rule.matches("/older", ""), None);
+        assert_eq!(rule.matches("/", ""), None);
+    }
+
+    #[test]
+    fn prefix_redirect_targets_with_their_own_query_or_fragment() {
+        let rule = redirect("Redirect /docs https://docs.example.com/v2?src=old");
+        assert_eq!(
+            rule.matches("/docs/intro", "a=1"),
+            Some((302, Some("https://docs.example.com/v2/intro?src=old".to_string())))
+        );
+
+        let rule = redirect("Redirect 301 /help https://example.com/support#top");
+        assert_eq!(
+            rule.matches("/help/faq", "lang=en"),
+            Some((301, Some("https://example.com/support/faq?lang=en#top".to_string())))
+        );
+
+        // An absolute target is otherwise left exactly as written
+        let rule = redirect("Redirect /shop https://Shop.Example.com:8443/Store%20Front");
+        assert_eq!(rule.matches("/shop", ""), Some((302, Some("https://Shop.Example.com:8443/Store%20Front".to_string()))));
+
+        let rule = redirect("Redirect gone /retired");
+        assert_eq!(rule.matches("/retired/page", "a=1"), Some((410, None)));
+    }
+
+    #[test]
+    fn vhosts_inherit_what_they_leave_unset_from_the_main_server() {
+        let base_dir = Path::new("/etc/apache2");
+        let mut server = VirtualHost::default();
+        for line in ["ServerName main.example.com", "DocumentRoot /var/www/html", "ErrorLog logs/error.log", "Redirect /old /new"] {
+            let (directive, args) = split_directive(line).unwrap();
+            apply_server_directive(&mut server, None, &directive, &args, base_dir);
+        }
+        let mut vhost = VirtualHost::default();
+        for line in ["ServerName shop.example.com", "DocumentRoot /srv/shop", "Redirect /old /shop/new"] {
+            let (directive, args) = split_directive(line).unwrap();
+            apply_vhost_directive(&mut vhost, &directive, &args, base_dir);
+        }
+        vhost.inherit(&server);
+
+        // What the vhost sets overrides the main server
+        assert_eq!(vhost.server_name.as_deref(), Some("shop.example.com"));
+        assert_eq!(vhost.document_root, Some(PathBuf::from("/srv/shop")));
+        // What it leaves unset comes from the main server
+        assert_eq!(vhost.error_log, server.error_log);
+        assert!(vhost.error_log.is_some());
+        // Redirects add up, the vhost's own first so they match first
+        let targets: Vec<_> = vhost.redirects.iter().map(|rule| rule.to.as_deref()).collect();
+        assert_eq!(targets, [Some("/shop/new"), Some("/new")]);
+
+        let mut bare = VirtualHost::default();
+        bare.inherit(&server);
+        assert_eq!(bare.server_name.as_deref(), Some("main.example.com"));
+        assert_eq!(bare.document_root, Some(PathBuf::from("/var/www/html")));
+    }
+
+    #[test]
+    fn statuses_outside_3xx_take_no_url() {
+        let rule = redirect("Redirect 451 /banned");
+        assert_eq!(rule.to, None);
+        assert_eq!(rule.matches("/banned/page", ""), Some((451, None)));
+        assert_eq!(redirect(r"RedirectMatch 410 ^/old/.*\.php$").matches("/old/a.php", ""), Some((410, None)));
+
+        assert_eq!(redirect("Redirect seeother /form /thanks").matches("/form", ""), Some((303, Some("/thanks".to_string()))));
+        // A 3xx without a URL is not a rule
+        let (directive, args) = split_directive("Redirect 301 /old").unwrap();
+        assert!(parse_redirect_family(&directive, &args).is_none());
+    }
+
+    #[test]
+    fn ten_capture_groups_expand_with_braces_and_the_full_match() {
+        let re = Regex::new(r"^/(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)$").unwrap();
+        let caps = re.captures("/abcdefghij").unwrap();
+
+        assert_eq!(expand_backrefs("$1$2$3$4$5$6$7$8$9", &caps, None), "abcdefghi");
+        assert_eq!(expand_backrefs("/${10}/${1}0/$10", &caps, None), "/j/a0/a0");
+        assert_eq!(expand_backrefs(r"$0?x=\$5&y=${11}", &caps, None), "/abcdefghij?x=$5&y=");
+        // Without condition groups a percent sign is literal
+        assert_eq!(expand_backrefs("/a%20b/%1", &caps, None), "/a%20b/%1");
+
+        let rule = RedirectRule {
+            status: 301,
+            from: r"^/(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)$".to_string(),
+            to: Some("/${10}/$9/$1".to_string()),
+            is_regex: true,
+        };
+        assert_eq!(rule.matches("/abcdefghij", ""), Some((301, Some("/j/i/a".to_string()))));
+    }
+
+    #[test]
+    fn percent_references_take_the_last_matched_condition_groups() {
+        let cond: Vec<Option<String>> = ["host.example.com", "host", "example.com"].map(|g| Some(g.to_string())).to_vec();
+        let re = Regex::new(r"^(\w+)/(\d+)$").unwrap();
+        let caps = re.captures("post/42").unwrap();
+
+        assert_eq!(
+            expand_backrefs("https://%2/%1/$1?id=$2&host=%0", &caps, Some(&cond)),
+            "https://example.com/host/post?id=42&host=host.example.com"
+        );
+        assert_eq!(expand_backrefs(r"/%9/100\%/%{HTTP_HOST}", &caps, Some(&cond)), "//100%/%{HTTP_HOST}");
+
+        let rules = "RewriteEngine On\n\
+                     RewriteCond %{HTTP_HOST} ^(example)\\.com$\n\
+                     RewriteCond %{QUERY_STRING} ^lang=(\\w+)$\n\
+                     RewriteRule ^(.*)$ /%1/$1 [L]\n";
+        match rewrite(rules, "/about", "lang=de") {
+            Some(RewriteResult::InternalRewrite { path, .. }) => assert_eq!(path, "/de/about"),
+            other => panic!("expected an internal rewrite, got {:?}", other),
+        }
+    }
+}
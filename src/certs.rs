@@ -0,0 +1,552 @@
+//! TLS certificates by SNI name, replaceable while the listeners keep running.
+//! Certificates are reloaded on SIGHUP, when one of their files changes on
+//! disk (certbot renewals) and from the admin API. A certificate that fails
+//! to load keeps serving its previous version. OCSP staples fetched by
+//! `ocsp` are attached to the keys here and survive reloads of the same
+//! certificate. Expiry dates are checked daily and after every reload, with
+//! a warning (and an optional webhook) when one comes within
+//! `[tls] expiry_warning_days`. A name may have several certificates (an
+//! ECDSA and an RSA one); each handshake gets the first the client supports,
+//! ECDSA first.
+
+use crate::acme;
+use crate::admin::{CertExpiry, ExpiryState};
+use crate::apache::{HostTable, VirtualHost};
+use crate::{load_ssl_keys, tls, AppState, Config};
+use chrono::{DateTime, Utc};
+use hyper::Method;
+use parking_lot::RwLock;
+use rustls::crypto::aws_lc_rs::ALL_CIPHER_SUITES;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{CipherSuite, SignatureAlgorithm, SignatureScheme};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The certificates of one name, in config order
+type CertSet = Vec<Arc<CertifiedKey>>;
+
+#[derive(Debug, Default, Clone)]
+struct CertTable {
+    certs: HostTable<CertSet>,
+    default_cert: CertSet,
+    /// Each loaded certificate
+    keys: Vec<LoadedCert>,
+}
+
+impl CertTable {
+    /// The staple of a previously loaded copy of `leaf`
+    fn staple_for(&self, leaf: &[u8]) -> Option<Vec<u8>> {
+        self.keys
+            .iter()
+            .find(|loaded| loaded.key.end_entity_cert().is_ok_and(|cert| cert.as_ref() == leaf))
+            .and_then(|loaded| loaded.key.ocsp.clone())
+    }
+}
+
+/// A certificate in service, under its source's label
+#[derive(Debug, Clone)]
+pub struct LoadedCert {
+    pub label: String,
+    /// SNI names it answers for; none for the default certificate
+    pub names: Vec<String>,
+    pub key: Arc<CertifiedKey>,
+    /// notAfter of the end-entity certificate
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// Picks the certificate for each TLS handshake
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    table: RwLock<Arc<CertTable>>,
+    /// TLS-ALPN-01 challenge certificates in progress, by name
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        // A client that can only settle on acme-tls/1 is a CA validating a
+        // challenge: it gets the challenge certificate or nothing, and no
+        // other client ever gets one
+        let offered: Vec<&[u8]> = client_hello.alpn().map(|protocols| protocols.collect()).unwrap_or_default();
+        if offered.contains(&tls::ACME_TLS_ALPN) && !offered.iter().any(|p| tls::HTTP_ALPN.contains(p)) {
+            let name = client_hello.server_name()?.to_ascii_lowercase();
+            return self.challenges.read().get(&name).cloned();
+        }
+        let table = self.table.read().clone();
+        let keys = client_hello
+            .server_name()
+            .and_then(|name| table.certs.get(name))
+            .unwrap_or(&table.default_cert);
+        choose_key(keys, client_hello.signature_schemes(), client_hello.cipher_suites())
+    }
+}
+
+/// Of one name's certificates, the first whose key the client can accept,
+/// preferring ECDSA; the first one when the client accepts none
+pub fn choose_key(keys: &[Arc<CertifiedKey>], schemes: &[SignatureScheme], suites: &[CipherSuite]) -> Option<Arc<CertifiedKey>> {
+    let usable = |key: &&Arc<CertifiedKey>| {
+        let algorithm = key.key.algorithm();
+        // TLS 1.2 suites are tied to the key type; TLS 1.3 suites fit any key
+        let suite_fits = ALL_CIPHER_SUITES
+            .iter()
+            .any(|suite| suites.contains(&suite.suite()) && suite.usable_for_signature_algorithm(algorithm));
+        suite_fits && key.key.choose_scheme(schemes).is_some()
+    };
+    keys.iter()
+        .filter(usable)
+        .min_by_key(|key| key.key.algorithm() != SignatureAlgorithm::ECDSA)
+        .or(keys.first())
+        .cloned()
+}
+
+/// Outcome of loading the certificate set
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// "name: cert file, expires ..." for each certificate loaded
+    pub loaded: Vec<String>,
+    /// `[tls] cert_dir` names left to a vhost's certificate
+    pub shadowed: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl LoadReport {
+    pub fn to_json(&self) -> Value {
+        json!({ "ok": self.errors.is_empty(), "loaded": self.loaded, "shadowed": self.shadowed, "errors": self.errors })
+    }
+}
+
+/// A certificate source: the `[tls]` default, a TLS vhost or a pair in
+/// `[tls] cert_dir`
+struct CertSource<'a> {
+    label: String,
+    names: Vec<&'a str>,
+    /// Certificate and key files, several for a name with RSA and ECDSA ones
+    pairs: Vec<(&'a Path, &'a Path)>,
+    chain: Option<&'a PathBuf>,
+    /// From `[tls] cert_dir`: also answers for the certificate's DNS names,
+    /// unless a vhost's certificate already does
+    from_dir: bool,
+}
+
+/// A certificate found in `[tls] cert_dir`
+#[derive(Debug, Clone)]
+pub struct DirCert {
+    /// The host name the files are named after
+    pub name: String,
+    pub cert: PathBuf,
+    /// The same as `cert` for a combined `<name>.pem`
+    pub key: PathBuf,
+}
+
+/// The `<name>.crt` + `<name>.key` pairs and combined `<name>.pem` files in
+/// `dir`, by name, and a message for each `.crt` without its key. A name
+/// with both forms uses the pair.
+pub fn scan_cert_dir(dir: &Path) -> (Vec<DirCert>, Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return (Vec::new(), vec![format!("[tls] cert_dir {}: {}", dir.display(), e)]),
+    };
+    let mut files: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+    files.sort();
+
+    let mut certs: BTreeMap<String, DirCert> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for path in &files {
+        let (Some(stem), Some(extension)) = (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) else {
+            continue;
+        };
+        let name = stem.to_ascii_lowercase();
+        match extension {
+            "crt" => {
+                let key = path.with_extension("key");
+                if !key.is_file() {
+                    errors.push(format!("[tls] cert_dir: {} has no {}", path.display(), key.display()));
+                    continue;
+                }
+                certs.insert(name.clone(), DirCert { name, cert: path.clone(), key });
+            }
+            "pem" if !certs.contains_key(&name) && !path.with_extension("crt").is_file() => {
+                certs.insert(name.clone(), DirCert { name, cert: path.clone(), key: path.clone() });
+            }
+            _ => {}
+        }
+    }
+    (certs.into_values().collect(), errors)
+}
+
+fn sources<'a>(config: &'a Config, vhosts: &'a [Arc<VirtualHost>], dir_certs: &'a [DirCert]) -> Vec<CertSource<'a>> {
+    let mut sources = Vec::new();
+    if let (Some(cert), Some(key)) = (&config.tls.certificate_file, &config.tls.certificate_key_file) {
+        sources.push(CertSource {
+            label: "[tls] default".to_string(),
+            names: Vec::new(),
+            pairs: vec![(cert.as_path(), key.as_path())],
+            chain: config.tls.certificate_chain_file.as_ref(),
+            from_dir: false,
+        });
+    }
+    for vhost in vhosts.iter().filter(|v| v.is_ssl()) {
+        let pairs = vhost.ssl_cert_pairs();
+        let Some((cert, _)) = pairs.first() else {
+            continue;
+        };
+        if vhost.acme && !cert.exists() {
+            // Not issued yet; the ACME client reloads once it is
+            continue;
+        }
+        sources.push(CertSource {
+            label: vhost.server_name.clone().unwrap_or_else(|| "default vhost".to_string()),
+            names: vhost.server_name.iter().chain(&vhost.server_aliases).map(String::as_str).collect(),
+            pairs,
+            chain: vhost.ssl_chain_file.as_ref(),
+            from_dir: false,
+        });
+    }
+    // After the vhosts, so their certificates win
+    for dir_cert in dir_certs {
+        sources.push(CertSource {
+            label: format!("{} (cert_dir)", dir_cert.name),
+            names: vec![&dir_cert.name],
+            pairs: vec![(dir_cert.cert.as_path(), dir_cert.key.as_path())],
+            chain: None,
+            from_dir: true,
+        });
+    }
+    sources
+}
+
+impl CertResolver {
+    /// Answer `acme-tls/1` handshakes for `name` with `key` until
+    /// [`remove_challenge`](Self::remove_challenge)
+    pub fn add_challenge(&self, name: &str, key: Arc<CertifiedKey>) {
+        self.challenges.write().insert(name.to_ascii_lowercase(), key);
+    }
+
+    pub fn remove_challenge(&self, name: &str) {
+        self.challenges.write().remove(&name.to_ascii_lowercase());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let table = self.table.read();
+        table.certs.is_empty() && table.default_cert.is_empty()
+    }
+
+    pub fn has_default(&self) -> bool {
+        !self.table.read().default_cert.is_empty()
+    }
+
+    /// Load the `[tls]` default certificate, those of every TLS vhost and
+    /// those in `[tls] cert_dir`, then swap them in. The default is `[tls]`'s,
+    /// else the first nameless vhost's.
+    pub fn load(&self, config: &Config, vhosts: &[Arc<VirtualHost>]) -> LoadReport {
+        let previous = self.table.read().clone();
+        let mut table = CertTable::default();
+        let mut report = LoadReport::default();
+        let passphrase = config.tls.passphrase();
+        let dir_certs = match &config.tls.cert_dir {
+            Some(dir) => {
+                let (dir_certs, errors) = scan_cert_dir(dir);
+                report.errors.extend(errors);
+                dir_certs
+            }
+            None => Vec::new(),
+        };
+        // Label of the certificate serving each name so far
+        let mut owners: HostTable<String> = HostTable::default();
+
+        for source in sources(config, vhosts, &dir_certs) {
+            let is_default = source.names.is_empty();
+            let previous_set = match source.names.first() {
+                Some(name) => previous.certs.get(name).cloned().unwrap_or_default(),
+                None => previous.default_cert.clone(),
+            };
+            let mut set = CertSet::new();
+            for (index, (cert, key)) in source.pairs.iter().enumerate() {
+                let key = match load_ssl_keys(cert, key, source.chain, &passphrase) {
+                    Ok(certified_key) => {
+                        let expiry = certified_key
+                            .end_entity_cert()
+                            .ok()
+                            .and_then(|leaf| tls::not_after(leaf))
+                            .map_or("unknown".to_string(), |t| t.format("%Y-%m-%d %H:%M UTC").to_string());
+                        report.loaded.push(format!("{}: {}, expires {}", source.label, cert.display(), expiry));
+                        let mut certified_key = certified_key;
+                        certified_key.ocsp = certified_key
+                            .end_entity_cert()
+                            .ok()
+                            .and_then(|leaf| previous.staple_for(leaf.as_ref()));
+                        Some(Arc::new(certified_key))
+                    }
+                    Err(e) => {
+                        let kept = previous_set.get(index).cloned();
+                        report.errors.push(format!(
+                            "{}: {}: {}{}",
+                            source.label,
+                            cert.display(),
+                            e,
+                            if kept.is_some() { "; keeping the previous certificate" } else { "" }
+                        ));
+                        kept
+                    }
+                };
+                set.extend(key);
+            }
+            if set.is_empty() {
+                continue;
+            }
+
+            let mut names: Vec<String> = source.names.iter().map(|name| name.to_string()).collect();
+            if source.from_dir {
+                for name in set.iter().filter_map(|key| key.end_entity_cert().ok()).flat_map(|leaf| tls::dns_names(leaf)) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                names.retain(|name| match owners.get(name) {
+                    Some(owner) => {
+                        report.shadowed.push(format!("{}: {} is served by {}", source.label, name, owner));
+                        false
+                    }
+                    None => true,
+                });
+                if names.is_empty() {
+                    continue;
+                }
+            }
+            for name in &names {
+                owners.insert(name, source.label.clone());
+            }
+            for key in &set {
+                let label = if source.pairs.len() > 1 {
+                    format!("{} ({:?})", source.label, key.key.algorithm())
+                } else {
+                    source.label.clone()
+                };
+                table.keys.push(LoadedCert {
+                    label,
+                    names: names.clone(),
+                    not_after: key.end_entity_cert().ok().and_then(|leaf| tls::not_after(leaf)),
+                    key: key.clone(),
+                });
+            }
+            if is_default {
+                if table.default_cert.is_empty() {
+                    table.default_cert = set;
+                }
+            } else {
+                for name in &names {
+                    table.certs.insert(name, set.clone());
+                }
+            }
+        }
+
+        *self.table.write() = Arc::new(table);
+        report
+    }
+
+    /// Every loaded certificate
+    pub fn keys(&self) -> Vec<LoadedCert> {
+        self.table.read().keys.clone()
+    }
+
+    /// Staple `response` to every key whose end-entity certificate is `leaf`,
+    /// or drop their staple when it is `None`
+    pub fn staple(&self, leaf: &[u8], response: Option<Vec<u8>>) {
+        let mut table = self.table.write();
+        let mut updated = CertTable::clone(&table);
+        let mut changed = false;
+        let keys = updated
+            .certs
+            .values_mut()
+            .flatten()
+            .chain(updated.default_cert.iter_mut())
+            .chain(updated.keys.iter_mut().map(|loaded| &mut loaded.key));
+        for key in keys {
+            if key.end_entity_cert().is_ok_and(|cert| cert.as_ref() == leaf) && key.ocsp != response {
+                let mut stapled = CertifiedKey::clone(key);
+                stapled.ocsp = response.clone();
+                *key = Arc::new(stapled);
+                changed = true;
+            }
+        }
+        if changed {
+            *table = Arc::new(updated);
+        }
+    }
+}
+
+/// Reload every certificate and log the outcome
+pub fn reload(state: &AppState, reason: &str) -> LoadReport {
+    let vhosts = state.sites.read().list.clone();
+    let report = state.certs.load(&state.config, &vhosts);
+    println!("Reloading TLS certificates ({})", reason);
+    for line in &report.loaded {
+        println!("  {}", line);
+    }
+    for shadowed in &report.shadowed {
+        eprintln!("Warning: certificate {}", shadowed);
+    }
+    for error in &report.errors {
+        eprintln!("ERROR: certificate {}", error);
+    }
+    check_expiry(state);
+    report
+}
+
+/// Record each certificate's expiry for the dashboard, logging the ones that
+/// have come within `[tls] expiry_warning_days` or expired since the last
+/// check (and at startup) and sending them to `[tls] expiry_webhook`
+pub fn check_expiry(state: &AppState) {
+    let warning_days = state.config.tls.expiry_warning_days;
+    let previous = state.admin_state.certificates.read().clone();
+    let mut expiries = BTreeMap::new();
+    let mut crossed = Vec::new();
+
+    for loaded in state.certs.keys() {
+        let Some(not_after) = loaded.not_after else {
+            continue;
+        };
+        let expiry = CertExpiry::new(loaded.names, not_after, warning_days);
+        let was = previous.get(&loaded.label).map(|e| e.refreshed().state);
+        match expiry.state {
+            ExpiryState::Expired if was != Some(ExpiryState::Expired) => {
+                eprintln!("ERROR: certificate for {} expired on {}", loaded.label, not_after.format("%Y-%m-%d %H:%M UTC"));
+                crossed.push((loaded.label.clone(), expiry.clone()));
+            }
+            ExpiryState::Expiring if was.is_none_or(|was| was == ExpiryState::Ok) => {
+                eprintln!(
+                    "WARNING: certificate for {} expires in {} day(s), on {}",
+                    loaded.label,
+                    expiry.days_left,
+                    not_after.format("%Y-%m-%d %H:%M UTC")
+                );
+                crossed.push((loaded.label.clone(), expiry.clone()));
+            }
+            ExpiryState::Ok if was.is_some_and(|was| was != ExpiryState::Ok) => {
+                println!("Certificate for {} renewed, now valid until {}", loaded.label, not_after.format("%Y-%m-%d"));
+            }
+            _ => {}
+        }
+        expiries.insert(loaded.label, expiry);
+    }
+    *state.admin_state.certificates.write() = expiries;
+
+    if let Some(url) = state.config.tls.expiry_webhook.clone().filter(|_| !crossed.is_empty()) {
+        let ca_file = state.config.acme.ca_file.clone();
+        tokio::spawn(async move {
+            for (label, expiry) in crossed {
+                if let Err(e) = notify(&url, ca_file.as_deref(), &label, &expiry).await {
+                    eprintln!("Warning: certificate expiry webhook {}: {:#}", url, e);
+                }
+            }
+        });
+    }
+}
+
+/// POST one certificate's expiry to the webhook as JSON
+async fn notify(url: &str, ca_file: Option<&Path>, label: &str, expiry: &CertExpiry) -> anyhow::Result<()> {
+    let body = json!({
+        "certificate": label,
+        "names": expiry.names,
+        "not_after": expiry.not_after.to_rfc3339(),
+        "days_left": expiry.days_left,
+        "state": expiry.state,
+    });
+    let tls = match url.starts_with("https:") {
+        true => Some(acme::client_tls(ca_file)?),
+        false => None,
+    };
+    let reply = acme::request(tls.as_ref(), Method::POST, url, Some(("application/json", body.to_string().into_bytes()))).await?;
+    if !reply.status.is_success() {
+        anyhow::bail!("{}", reply.status);
+    }
+    Ok(())
+}
+
+/// Check certificate expiry now and then once a day
+pub fn spawn_expiry_check(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            check_expiry(&state);
+            tokio::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
+        }
+    });
+}
+
+/// Modification time and size of each certificate, key and chain file
+type Snapshot = BTreeMap<PathBuf, Option<(SystemTime, u64)>>;
+
+fn snapshot(state: &AppState) -> Snapshot {
+    let vhosts = state.sites.read().list.clone();
+    let dir = state.config.tls.cert_dir.as_deref();
+    let dir_certs = dir.map(|dir| scan_cert_dir(dir).0).unwrap_or_default();
+    sources(&state.config, &vhosts, &dir_certs)
+        .iter()
+        .flat_map(|s| s.pairs.iter().flat_map(|&(cert, key)| [cert, key]).chain(s.chain.map(PathBuf::as_path)))
+        // The directory itself changes when files are added or removed
+        .chain(dir)
+        .map(|path| {
+            // metadata follows symlinks, so certbot's live/ links are covered
+            let stamp = fs::metadata(path).ok().and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+            (path.to_path_buf(), stamp)
+        })
+        .collect()
+}
+
+/// `snapshot` on the blocking pool, where its stats and the `[tls] cert_dir`
+/// scan hold up no connections
+async fn snapshot_in_background(state: &Arc<AppState>) -> Snapshot {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || snapshot(&state)).await.unwrap_or_default()
+}
+
+/// Reload on SIGHUP and whenever a certificate file changes. Files are checked
+/// every `[tls] check_interval` seconds; 0 leaves only SIGHUP and the admin API.
+pub fn spawn(state: Arc<AppState>) {
+    let interval = state.config.tls.check_interval;
+    tokio::spawn(async move {
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                eprintln!("Warning: cannot listen for SIGHUP: {}", e);
+                None
+            }
+        };
+        let mut last = snapshot_in_background(&state).await;
+
+        loop {
+            let sighup = async {
+                match &mut hangup {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let tick = async {
+                match interval {
+                    0 => std::future::pending().await,
+                    secs => tokio::time::sleep(Duration::from_secs(secs)).await,
+                }
+            };
+            tokio::select! {
+                _ = sighup => {
+                    let reloading = state.clone();
+                    let _ = tokio::task::spawn_blocking(move || reload(&reloading, "SIGHUP")).await;
+                    last = snapshot_in_background(&state).await;
+                }
+                _ = tick => {
+                    let current = snapshot_in_background(&state).await;
+                    if current != last {
+                        last = current;
+                        let reloading = state.clone();
+                        let _ = tokio::task::spawn_blocking(move || reload(&reloading, "certificate files changed")).await;
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,397 @@
+//! Configuration checks shared by `wolfserve --check-config` and normal startup.
+//! In check mode every finding is printed and errors set a non-zero exit code;
+//! at startup only problems are printed, as warnings, and the server carries on.
+
+use crate::apache::{self, Listen, LocationHandler, VirtualHost};
+use crate::listeners::{self, Protocol};
+use crate::{certs, load_ssl_keys, shed, tls, Config, Passphrase, PhpBackend};
+use std::collections::BTreeSet;
+use std::path::Path;
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::{timeout, Duration};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+pub struct Finding {
+    pub severity: Severity,
+    pub subject: String,
+    pub message: String,
+    /// `file:line` the finding is about, when it is about a vhost
+    pub location: Option<String>,
+}
+
+impl Finding {
+    /// `subject (file:line)`
+    fn located_subject(&self) -> String {
+        match &self.location {
+            Some(location) => format!("{} ({})", self.subject, location),
+            None => self.subject.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn push(&mut self, severity: Severity, subject: &str, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            subject: subject.to_string(),
+            message: message.into(),
+            location: None,
+        });
+    }
+
+    /// Give the findings from `first` on that have no location this one
+    fn locate(&mut self, first: usize, location: Option<&str>) {
+        for finding in &mut self.findings[first..] {
+            if finding.location.is_none() {
+                finding.location = location.map(str::to_string);
+            }
+        }
+    }
+
+    pub fn ok(&mut self, subject: &str, message: impl Into<String>) {
+        self.push(Severity::Ok, subject, message);
+    }
+
+    pub fn warn(&mut self, subject: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, subject, message);
+    }
+
+    pub fn error(&mut self, subject: &str, message: impl Into<String>) {
+        self.push(Severity::Error, subject, message);
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Errors as `subject: message` lines. They leave out the location, so
+    /// an error moved by an edit elsewhere in its file is still the same one.
+    pub fn errors(&self) -> impl Iterator<Item = String> + '_ {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| format!("{}: {}", f.subject, f.message))
+    }
+
+    /// Errors as `subject (file:line): message` lines, each after its line
+    /// from `errors`
+    pub fn located_errors(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| (format!("{}: {}", f.subject, f.message), format!("{}: {}", f.located_subject(), f.message)))
+    }
+
+    /// Full report for `--check-config`
+    pub fn print(&self) {
+        for finding in &self.findings {
+            let tag = match finding.severity {
+                Severity::Ok => "[ OK ]",
+                Severity::Warning => "[WARN]",
+                Severity::Error => "[FAIL]",
+            };
+            println!("{} {}: {}", tag, finding.located_subject(), finding.message);
+        }
+        println!();
+        println!(
+            "{} error(s), {} warning(s)",
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        );
+        println!("{}", if self.has_errors() { "Configuration has errors" } else { "Syntax OK" });
+    }
+
+    /// Problems only, as startup warnings; the server keeps running
+    pub fn print_problems(&self) {
+        for finding in self.findings.iter().filter(|f| f.severity != Severity::Ok) {
+            eprintln!("Warning: {}: {}", finding.located_subject(), finding.message);
+        }
+    }
+}
+
+fn vhost_label(vhost: &VirtualHost) -> String {
+    format!(
+        "vhost {}:{}",
+        vhost.server_name.as_deref().unwrap_or("(default)"),
+        vhost.port
+    )
+}
+
+/// Validate the loaded configuration. FPM reachability is only probed when
+/// `check_fpm` is set, since FPM is often started after wolfserve.
+pub async fn validate(config: &Config, listen: &[Listen], vhosts: &[VirtualHost], check_fpm: bool) -> Report {
+    let mut report = Report::default();
+
+    let apache_dir = Path::new(&config.apache.config_dir);
+    if apache_dir.join("sites-enabled").is_dir() {
+        report.ok("apache", format!("{} vhost(s) loaded from {}", vhosts.len(), apache_dir.display()));
+    } else {
+        report.warn("apache", format!("{}/sites-enabled not found; no Apache vhosts loaded", apache_dir.display()));
+    }
+
+    let has_default_cert = check_default_certificate(config, &mut report);
+    if let Err(e) = tls::version_bounds(config.tls.min_version.as_deref(), config.tls.max_version.as_deref()) {
+        report.error("tls", e);
+    }
+    check_shed(config, &mut report);
+    if let Some(path) = &config.tls.key_log_file {
+        report.warn("tls", format!("key_log_file writes TLS session keys to {}; use it for debugging only", path.display()));
+    }
+    if let Some(dir) = &config.tls.cert_dir {
+        check_cert_dir(dir, &config.tls.passphrase(), &mut report);
+    }
+
+    for vhost in vhosts {
+        let first = report.findings.len();
+        check_vhost(vhost, has_default_cert, &config.tls.passphrase(), &mut report);
+        if vhost.acme {
+            check_acme(config, vhost, &mut report);
+        }
+        report.locate(first, vhost.defined_at.as_deref());
+    }
+
+    for (name, overrides) in &config.vhosts {
+        let is_tls_vhost = |v: &VirtualHost| v.acme && v.server_name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name));
+        if overrides.acme && !vhosts.iter().any(is_tls_vhost) {
+            report.warn(name, "acme = true but no vhost with this ServerName has SSLEngine on; no certificate will be ordered");
+        }
+    }
+
+    check_ports(config, listen, vhosts, &mut report);
+
+    if config.admin.enabled {
+        match config.admin.refusal() {
+            Some(refusal) => report.error("admin", refusal),
+            None => report.ok("admin", format!("dashboard on {}:{}", config.admin.host, config.admin.port)),
+        }
+    }
+
+    if let Some(page) = &config.maintenance.page {
+        if !Path::new(page).is_file() {
+            report.warn("maintenance", format!("page {} not found; the built-in page will be used", page));
+        }
+    }
+
+    if check_fpm {
+        // [php] plus every vhost with a pool of its own, each checked once
+        let backends: BTreeSet<PhpBackend> = std::iter::once(config.php_backend(None))
+            .chain(vhosts.iter().map(|vhost| config.php_backend(Some(vhost))))
+            .collect();
+        for backend in backends {
+            check_php_backend(backend, &mut report).await;
+        }
+        // Upstreams of other extensions, from [fastcgi] and AddHandler, and
+        // of <Location> SetHandler
+        let location_handlers = vhosts.iter().flat_map(|vhost| &vhost.locations).filter_map(|location| match &location.handler {
+            Some(LocationHandler::FastCgi(address)) => Some(address),
+            _ => None,
+        });
+        let upstreams: BTreeSet<&str> = config
+            .fastcgi
+            .values()
+            .chain(vhosts.iter().flat_map(|vhost| vhost.fastcgi_handlers.values()))
+            .chain(location_handlers)
+            .map(String::as_str)
+            .collect();
+        for addr in upstreams {
+            check_fastcgi_connect("fastcgi", "FastCGI upstream", addr, &mut report).await;
+        }
+    }
+
+    report
+}
+
+/// Returns whether a usable `[tls]` default certificate is configured
+fn check_default_certificate(config: &Config, report: &mut Report) -> bool {
+    match (&config.tls.certificate_file, &config.tls.certificate_key_file) {
+        (Some(cert), Some(key)) => match load_ssl_keys(cert, key, config.tls.certificate_chain_file.as_ref(), &config.tls.passphrase()) {
+            Ok(_) => {
+                report.ok("tls", format!("default certificate {} loaded", cert.display()));
+                true
+            }
+            Err(e) => {
+                report.error("tls", format!("default certificate {}: {}", cert.display(), e));
+                false
+            }
+        },
+        (None, None) => false,
+        _ => {
+            report.error("tls", "certificate_file and certificate_key_file must be set together");
+            false
+        }
+    }
+}
+
+/// Every certificate in `[tls] cert_dir` must load and match its key
+fn check_cert_dir(dir: &Path, passphrase: &Passphrase, report: &mut Report) {
+    let (dir_certs, errors) = certs::scan_cert_dir(dir);
+    for error in errors {
+        report.error("tls", error);
+    }
+    for dir_cert in dir_certs {
+        match load_ssl_keys(&dir_cert.cert, &dir_cert.key, None, passphrase) {
+            Ok(_) => report.ok("tls", format!("cert_dir certificate {} loaded", dir_cert.cert.display())),
+            Err(e) => report.error("tls", format!("cert_dir certificate {}: {}", dir_cert.cert.display(), e)),
+        }
+    }
+}
+
+fn check_vhost(vhost: &VirtualHost, has_default_cert: bool, passphrase: &Passphrase, report: &mut Report) {
+    let label = vhost_label(vhost);
+
+    match &vhost.document_root {
+        Some(root) if root.is_dir() => {
+            report.ok(&label, format!("DocumentRoot {}", root.display()));
+            let htaccess = root.join(".htaccess");
+            if let Some(config) = apache::parse_htaccess(&htaccess) {
+                for error in config.invalid_patterns() {
+                    report.error(&label, format!("{}: {}", htaccess.display(), error));
+                }
+            }
+        }
+        Some(root) if root.exists() => report.error(&label, format!("DocumentRoot {} is not a directory", root.display())),
+        Some(root) => report.error(&label, format!("DocumentRoot {} does not exist", root.display())),
+        None => report.warn(&label, "no DocumentRoot; ./public will be served"),
+    }
+
+    for error in vhost.redirects.iter().filter_map(|r| r.pattern_error()) {
+        report.error(&label, error);
+    }
+    for error in vhost.locations.iter().filter_map(|l| l.pattern_error()) {
+        report.error(&label, error);
+    }
+
+    if !vhost.is_ssl() {
+        return;
+    }
+    match (&vhost.ssl_cert_file, &vhost.ssl_key_file) {
+        (Some(cert), Some(_)) if vhost.acme && !cert.exists() => {
+            report.ok(&label, "certificate not issued yet; the ACME client will order one")
+        }
+        (Some(_), Some(_)) => {
+            for (cert, key) in vhost.ssl_cert_pairs() {
+                match load_ssl_keys(cert, key, vhost.ssl_chain_file.as_ref(), passphrase) {
+                    Ok(_) => report.ok(&label, format!("certificate {} matches its key", cert.display())),
+                    Err(e) => report.error(&label, format!("certificate {}: {}", cert.display(), e)),
+                }
+            }
+        }
+        _ if has_default_cert => report.ok(&label, "SSLEngine on; using the [tls] default certificate"),
+        _ => report.error(&label, "SSLEngine on but no certificate and no [tls] default certificate"),
+    }
+}
+
+fn check_acme(config: &Config, vhost: &VirtualHost, report: &mut Report) {
+    let label = vhost_label(vhost);
+    let has_email = vhost
+        .server_name
+        .as_deref()
+        .and_then(|name| config.vhost_overrides(name))
+        .is_some_and(|o| o.acme_email.is_some());
+    if !has_email && config.acme.email.is_none() {
+        report.error(&label, "acme = true needs acme_email or [acme] email");
+    }
+    for alias in vhost.server_aliases.iter().filter(|a| a.contains('*')) {
+        report.warn(&label, format!("ACME HTTP-01 cannot validate {}; it is left off the certificate", alias));
+    }
+}
+
+/// `[shed]` configures load-shedding statuses only, and its files should exist
+fn check_shed(config: &Config, report: &mut Report) {
+    for (status, page) in &config.shed {
+        let subject = format!("shed {}", status);
+        if !status.parse::<u16>().is_ok_and(|status| shed::STATUSES.contains(&status)) {
+            report.error(&subject, "only 413, 429 and 503 responses can be configured");
+            continue;
+        }
+        for path in page.html_page.iter().chain(&page.json_page) {
+            if !path.is_file() {
+                report.warn(&subject, format!("{} not found; the built-in body is served", path.display()));
+            }
+        }
+    }
+}
+
+fn check_ports(config: &Config, listen: &[Listen], vhosts: &[VirtualHost], report: &mut Report) {
+    let plan = listeners::plan(config, listen, vhosts);
+    for error in &plan.errors {
+        report.error("listeners", error);
+    }
+    for warning in &plan.warnings {
+        report.warn("listeners", warning);
+    }
+
+    for listener in &plan.listeners {
+        let subject = format!("port {}", listener.port);
+        if config.admin.enabled && listener.port == config.admin.port {
+            report.error(&subject, "also used by the admin dashboard");
+            continue;
+        }
+        match listener.protocol {
+            Some(Protocol::Https) => {
+                let tls_vhosts: Vec<&VirtualHost> = vhosts.iter().filter(|v| v.port == listener.port && v.is_ssl()).collect();
+                match tls::client_verifier_for_port(listener.port, &tls_vhosts) {
+                    Ok(Some(_)) => report.ok(&subject, "HTTPS with client certificates"),
+                    Ok(None) => report.ok(&subject, "HTTPS"),
+                    Err(e) => report.error(&subject, e),
+                }
+            }
+            Some(Protocol::Http) => report.ok(&subject, "HTTP"),
+            // Reported with the listener errors
+            None => {}
+        }
+    }
+}
+
+async fn check_php_backend(backend: PhpBackend<'_>, report: &mut Report) {
+    let addr = match backend {
+        PhpBackend::Cgi(cgi_path) => {
+            let cgi = Path::new(cgi_path);
+            let found = cgi.is_file()
+                || std::env::var_os("PATH")
+                    .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(cgi).is_file()));
+            if found {
+                report.ok("php", format!("{} found", cgi_path));
+            } else {
+                report.error("php", format!("{} not found", cgi_path));
+            }
+            return;
+        }
+        PhpBackend::Fpm(Some(addr)) => addr,
+        PhpBackend::Fpm(None) => {
+            report.error("php", "mode is fpm but fpm_address is not set");
+            return;
+        }
+    };
+    check_fastcgi_connect("php", "PHP-FPM", addr, report).await;
+}
+
+/// Connect to a FastCGI address (`host:port` or `unix:/path`) and report
+/// whether it answered
+async fn check_fastcgi_connect(section: &str, name: &str, addr: &str, report: &mut Report) {
+    let connect_timeout = Duration::from_secs(2);
+    let result = match addr.strip_prefix("unix:") {
+        Some(path) => timeout(connect_timeout, UnixStream::connect(path)).await.map(|r| r.map(|_| ())),
+        None => timeout(connect_timeout, TcpStream::connect(addr)).await.map(|r| r.map(|_| ())),
+    };
+    match result {
+        Ok(Ok(())) => report.ok(section, format!("{} reachable at {}", name, addr)),
+        Ok(Err(e)) => report.error(section, format!("{} unreachable at {}: {}", name, addr, e)),
+        Err(_) => report.error(section, format!("{} connect to {} timed out", name, addr)),
+    }
+}
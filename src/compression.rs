@@ -0,0 +1,88 @@
+//! Which responses get compressed: a configurable list of compressible MIME
+//! types, with already-compressed media always excluded.
+
+use axum::http::{header, Response};
+use hyper::body::Body;
+use std::sync::Arc;
+use tower_http::compression::Predicate;
+
+/// Compressible types used when `[compression] types` is not set
+pub const DEFAULT_TYPES: [&str; 14] = [
+    "text/*",
+    "application/javascript",
+    "application/json",
+    "application/ld+json",
+    "application/manifest+json",
+    "application/xml",
+    "application/xhtml+xml",
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/wasm",
+    "image/svg+xml",
+    "font/ttf",
+    "font/otf",
+    "application/vnd.ms-fontobject",
+];
+
+/// Never compressed, whatever the configuration says: the data is already
+/// compressed, or (event streams) buffering would break the response
+const NEVER_COMPRESS: [&str; 15] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/*",
+    "audio/*",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-7z-compressed",
+    "text/event-stream",
+];
+
+/// `type/subtype` or `type/*` match against a bare (lowercase) MIME type
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime.split('/').next() == Some(top_level),
+        None => pattern == mime,
+    }
+}
+
+#[derive(Clone)]
+pub struct CompressibleTypes {
+    enabled: bool,
+    types: Arc<Vec<String>>,
+}
+
+impl CompressibleTypes {
+    pub fn new(enabled: bool, types: &[String]) -> Self {
+        Self {
+            enabled,
+            types: Arc::new(types.iter().map(|t| t.trim().to_lowercase()).collect()),
+        }
+    }
+
+    fn allows(&self, mime: &str) -> bool {
+        self.enabled
+            && !NEVER_COMPRESS.iter().any(|never| mime_matches(never, mime))
+            && self.types.iter().any(|pattern| mime_matches(pattern, mime))
+    }
+}
+
+impl Predicate for CompressibleTypes {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: Body,
+    {
+        response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or("").trim().to_lowercase())
+            .is_some_and(|mime| self.allows(&mime))
+    }
+}
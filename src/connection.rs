@@ -0,0 +1,332 @@
+//! Connection handling for the site listeners. Keep-alive and timeout
+//! settings come from wolfserve.toml, else the global directives in Apache's
+//! main config (KeepAlive, KeepAliveTimeout, Timeout, MaxKeepAliveRequests),
+//! else Apache's own defaults. The HTTP versions a connection may speak come
+//! from its listener's `http_versions`. Responses to HTTP/1.0 requests always
+//! say whether the connection stays open.
+
+use crate::apache::ConnectionDirectives;
+use crate::listeners::HttpVersion;
+use crate::tls::Session;
+use crate::{is_common_connection_error, ServerConfig};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{header, HeaderValue, Version};
+use axum::response::Response;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use tower::Service as _;
+
+/// Where an effective setting came from
+#[derive(Clone, Debug)]
+pub enum Source {
+    Toml,
+    Apache(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Toml => write!(f, "wolfserve.toml"),
+            Source::Apache(path) => write!(f, "{}", path.display()),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionSettings {
+    pub keep_alive: Setting<bool>,
+    /// How long an idle HTTP/1 connection waits for its next request
+    pub keep_alive_timeout: Setting<Duration>,
+    /// How long a new connection may take to send its first request, and PHP
+    /// to answer one
+    pub timeout: Setting<Duration>,
+    /// Requests per HTTP/1 connection before it is closed; 0 is unlimited
+    pub max_keep_alive_requests: Setting<u64>,
+    /// Whether HTTP/1.0 clients that ask for keep-alive get it
+    pub http10_keep_alive: Setting<bool>,
+}
+
+impl ConnectionSettings {
+    /// Explicit wolfserve.toml values win over Apache's, which win over the defaults
+    pub fn resolve(server: &ServerConfig, apache: &ConnectionDirectives) -> Self {
+        let apache_source = || Source::Apache(apache.config_file.clone().unwrap_or_default());
+        fn pick<T>(toml: Option<T>, apache: Option<T>, apache_source: impl Fn() -> Source, default: T) -> Setting<T> {
+            match (toml, apache) {
+                (Some(value), _) => Setting { value, source: Source::Toml },
+                (None, Some(value)) => Setting { value, source: apache_source() },
+                (None, None) => Setting { value: default, source: Source::Default },
+            }
+        }
+
+        Self {
+            keep_alive: pick(server.keep_alive, apache.keep_alive, apache_source, true),
+            keep_alive_timeout: pick(
+                server.keep_alive_timeout.map(Duration::from_secs),
+                apache.keep_alive_timeout,
+                apache_source,
+                Duration::from_secs(5),
+            ),
+            timeout: pick(
+                server.timeout.map(Duration::from_secs),
+                apache.timeout,
+                apache_source,
+                Duration::from_secs(60),
+            ),
+            max_keep_alive_requests: pick(
+                server.max_keep_alive_requests,
+                apache.max_keep_alive_requests,
+                apache_source,
+                100,
+            ),
+            // Apache has no directive of its own for this
+            http10_keep_alive: pick(server.http10_keep_alive, None, apache_source, true),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "KeepAlive {} ({}), KeepAliveTimeout {:?} ({}), Timeout {:?} ({}), MaxKeepAliveRequests {} ({}), HTTP/1.0 keep-alive {} ({})",
+            if self.keep_alive.value { "On" } else { "Off" },
+            self.keep_alive.source,
+            self.keep_alive_timeout.value,
+            self.keep_alive_timeout.source,
+            self.timeout.value,
+            self.timeout.source,
+            self.max_keep_alive_requests.value,
+            self.max_keep_alive_requests.source,
+            if self.http10_keep_alive.value { "On" } else { "Off" },
+            self.http10_keep_alive.source,
+        )
+    }
+}
+
+/// Longest pause between attempts while accept() keeps failing
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The next connection on `listener`. Errors of a single connection (reset
+/// before it was accepted) are skipped; others, such as running out of file
+/// descriptors, back off from 10ms up to a second instead of spinning.
+pub async fn accept(listener: &TcpListener, port: u16) -> TcpStream {
+    let mut backoff = Duration::ZERO;
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => return stream,
+            Err(e) if is_connection_error(&e) => continue,
+            Err(e) => {
+                if backoff.is_zero() {
+                    eprintln!("Warning: accepting connections on port {} failed: {}; backing off", port, e);
+                }
+                backoff = (backoff * 2).clamp(Duration::from_millis(10), MAX_ACCEPT_BACKOFF);
+                sleep(backoff).await;
+            }
+        }
+    }
+}
+
+/// accept() errors that concern only the connection being accepted
+fn is_connection_error(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), ConnectionAborted | ConnectionReset | ConnectionRefused | Interrupted | WouldBlock)
+}
+
+/// Wait for a new connection's first bytes (request line or TLS ClientHello).
+/// hyper only times out idle connections between requests, so a client that
+/// connects and sends nothing is dropped here after Timeout, as in Apache.
+pub async fn wait_for_request(stream: &TcpStream, settings: &ConnectionSettings) -> bool {
+    matches!(timeout(settings.timeout.value, stream.readable()).await, Ok(Ok(())))
+}
+
+/// The HTTP versions one connection may speak
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpMode {
+    /// HTTP/1.1, or HTTP/2 if the client opens with the HTTP/2 preface
+    Auto,
+    Http1,
+    Http2,
+}
+
+impl HttpMode {
+    /// For a connection to a listener speaking `versions`, or for a TLS one
+    /// by the protocol ALPN settled on
+    pub fn new(versions: &[HttpVersion], alpn: Option<&[u8]>) -> HttpMode {
+        match alpn {
+            Some(b"h2") => return HttpMode::Http2,
+            Some(b"http/1.1") => return HttpMode::Http1,
+            _ => {}
+        }
+        match (versions.contains(&HttpVersion::Http11), versions.contains(&HttpVersion::H2)) {
+            (true, true) => HttpMode::Auto,
+            (false, true) => HttpMode::Http2,
+            _ => HttpMode::Http1,
+        }
+    }
+}
+
+/// What an HTTP/2 connection opens with
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Whether a plain connection opens with the HTTP/2 preface, decided as soon
+/// as its first bytes tell it apart from an HTTP/1 request line
+pub async fn sends_h2_preface(stream: &TcpStream, settings: &ConnectionSettings) -> bool {
+    let peek = async {
+        let mut buf = [0; H2_PREFACE.len()];
+        loop {
+            let n = stream.peek(&mut buf).await.ok()?;
+            if n == 0 || buf[..n] != H2_PREFACE[..n] {
+                return Some(false);
+            }
+            // "P" may still be POST; "PRI " cannot be anything else
+            if n >= 4 {
+                return Some(true);
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    matches!(timeout(settings.timeout.value, peek).await, Ok(Some(true)))
+}
+
+/// Answer an HTTP/1 client of an HTTP/2-only listener with a 505 it can
+/// show, instead of an HTTP/2 error it cannot read
+pub async fn refuse_http1<I: AsyncWrite + Unpin>(mut io: I, port: u16) {
+    let body = format!(
+        "Port {} speaks HTTP/2 only and this client sent an HTTP/1 request. \
+         Connect with HTTP/2 (prior knowledge on plain HTTP, ALPN h2 over TLS), \
+         or add \"http/1.1\" to [ports] {} http_versions in wolfserve.toml.\n",
+        port, port
+    );
+    let response = format!(
+        "HTTP/1.1 505 HTTP Version Not Supported\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    tracing::info!(port, "refused an HTTP/1 client on an HTTP/2-only listener");
+    let _ = io.write_all(response.as_bytes()).await;
+    let _ = io.shutdown().await;
+}
+
+/// Serve one accepted connection (plain or TLS) with the keep-alive settings
+/// applied, speaking the HTTP versions `mode` allows. `tls` describes a TLS
+/// connection and is given to each request.
+pub async fn serve<I>(io: I, peer: Option<SocketAddr>, app: Router, settings: &ConnectionSettings, tls: Option<Session>, mode: HttpMode)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder = match mode {
+        HttpMode::Auto => builder,
+        HttpMode::Http1 => builder.http1_only(),
+        HttpMode::Http2 => builder.http2_only(),
+    };
+    // hyper's header read timer also runs while an idle connection waits
+    // for its next request, which is what KeepAliveTimeout limits
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(settings.keep_alive.value)
+        .header_read_timeout(settings.keep_alive_timeout.value);
+
+    let service = RequestLimit {
+        app,
+        served: Arc::new(AtomicU64::new(0)),
+        max: settings.max_keep_alive_requests.value,
+        http10_keep_alive: settings.keep_alive.value && settings.http10_keep_alive.value,
+        tls,
+        peer,
+    };
+
+    if let Err(err) = builder.serve_connection(TokioIo::new(io), service).await {
+        if !is_common_connection_error(err.as_ref()) {
+            eprintln!("Error serving connection: {:?}", err);
+        }
+    }
+}
+
+/// Adds `Connection: close` to the response that reaches MaxKeepAliveRequests,
+/// so hyper closes the HTTP/1 connection once it is sent. HTTP/1.0 responses
+/// get `Connection: keep-alive` or `close` either way: hyper closes them
+/// silently unless told otherwise, and old clients wait for the close to
+/// find the end of a response without a length.
+#[derive(Clone)]
+struct RequestLimit {
+    app: Router,
+    served: Arc<AtomicU64>,
+    max: u64,
+    /// KeepAlive and `http10_keep_alive` are both on
+    http10_keep_alive: bool,
+    tls: Option<Session>,
+    /// The client's address, given to each request as ConnectInfo
+    peer: Option<SocketAddr>,
+}
+
+/// Whether a request's Connection header lists `token`
+fn connection_has(req: &Request<Incoming>, token: &str) -> bool {
+    req.headers()
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+impl hyper::service::Service<Request<Incoming>> for RequestLimit {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        if let Some(session) = &self.tls {
+            req.extensions_mut().insert(session.clone());
+        }
+        if let Some(peer) = self.peer {
+            req.extensions_mut().insert(ConnectInfo(peer));
+        }
+        let served = self.served.fetch_add(1, Ordering::Relaxed) + 1;
+        let limit_reached = self.max > 0 && served >= self.max;
+        let connection = match req.version() {
+            Version::HTTP_10 if self.http10_keep_alive && !limit_reached && connection_has(&req, "keep-alive") => Some("keep-alive"),
+            Version::HTTP_10 => Some("close"),
+            Version::HTTP_11 if limit_reached => Some("close"),
+            _ => None,
+        };
+        let version = req.version();
+        let response = self.app.clone().call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            if let Some(connection) = connection {
+                response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static(connection));
+            }
+            // hyper only closes an HTTP/1.0 connection for a response it
+            // sees as HTTP/1.0; an HTTP/1.1 one gets its keep-alive back
+            if version == Version::HTTP_10 {
+                *response.version_mut() = Version::HTTP_10;
+            }
+            Ok(response)
+        })
+    }
+}
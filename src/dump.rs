@@ -0,0 +1,167 @@
+//! The resolved vhost model as JSON, for `wolfserve --dump-vhosts` and the
+//! admin API. Each vhost keeps its VirtualHost fields and gains the derived
+//! ones (`id`, `names`, `document_root_exists`, `tls`, `certificate`,
+//! `htaccess`, `php`, `port_default`).
+//! `ports` are the listeners, with the protocol each one speaks and why.
+//! `server` is the main server config that vhosts inherit from.
+
+use crate::apache::{self, VirtualHost};
+use crate::listeners::{self, PortListener, Protocol};
+use crate::{tls, Config, PhpBackend, Sites, VERSION};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The vhosts, by `name:port`, that one vhost list has and another doesn't,
+/// or has with other settings
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VhostChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl VhostChanges {
+    pub fn between<'a>(old: impl Iterator<Item = &'a VirtualHost>, new: impl Iterator<Item = &'a VirtualHost>) -> Self {
+        let (old, new) = (by_key(old), by_key(new));
+        VhostChanges {
+            added: new.keys().filter(|key| !old.contains_key(*key)).cloned().collect(),
+            removed: old.keys().filter(|key| !new.contains_key(*key)).cloned().collect(),
+            changed: new
+                .iter()
+                .filter(|(key, vhost)| old.get(*key).is_some_and(|before| before != *vhost))
+                .map(|(key, _)| key.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Each vhost's settings by `name:port`, leaving out where it is defined,
+/// which moves with edits above it in its file
+fn by_key<'a>(vhosts: impl Iterator<Item = &'a VirtualHost>) -> BTreeMap<String, Value> {
+    vhosts
+        .map(|vhost| {
+            let mut value = serde_json::to_value(vhost).unwrap_or(Value::Null);
+            if let Some(fields) = value.as_object_mut() {
+                fields.remove("defined_at");
+            }
+            (listeners::vhost_key(vhost), value)
+        })
+        .collect()
+}
+
+pub fn vhosts_json(config: &Config, sites: &Sites, listeners: &[PortListener]) -> Value {
+    let ports: Vec<Value> = listeners
+        .iter()
+        .map(|listener| {
+            let mut value = serde_json::to_value(listener).unwrap_or_else(|_| json!({}));
+            let default = sites.ports.get(&listener.port).and_then(|p| p.default.as_ref());
+            if let Some(fields) = value.as_object_mut() {
+                // The listeners are fixed at startup; the vhosts are reloaded
+                let vhosts: Vec<String> =
+                    sites.list.iter().filter(|v| v.port == listener.port).map(|v| listeners::vhost_name(v)).collect();
+                fields.insert("vhosts".into(), json!(vhosts));
+                fields.insert("tls".into(), json!(listener.protocol == Some(Protocol::Https)));
+                fields.insert("default_vhost".into(), json!(default.map(|v| listeners::vhost_name(v))));
+            }
+            value
+        })
+        .collect();
+
+    let vhosts: Vec<Value> = sites
+        .list
+        .iter()
+        .map(|vhost| {
+            let is_port_default = sites
+                .ports
+                .get(&vhost.port)
+                .and_then(|p| p.default.as_ref())
+                .is_some_and(|default| Arc::ptr_eq(default, vhost));
+            vhost_json(config, vhost, is_port_default)
+        })
+        .collect();
+
+    json!({
+        "wolfserve_version": VERSION,
+        "apache_config_dir": config.apache.config_dir,
+        "ports": ports,
+        "vhosts": vhosts,
+        "server": sites.server.as_ref().map(|server| vhost_json(config, server, false)),
+    })
+}
+
+fn vhost_json(config: &Config, vhost: &VirtualHost, is_port_default: bool) -> Value {
+    let mut value = serde_json::to_value(vhost).unwrap_or_else(|_| json!({}));
+    let Some(fields) = value.as_object_mut() else {
+        return value;
+    };
+
+    let names: Vec<&String> = vhost.server_name.iter().chain(&vhost.server_aliases).collect();
+    fields.insert("id".into(), json!(listeners::vhost_key(vhost)));
+    fields.insert("names".into(), json!(names));
+    fields.insert("document_root_exists".into(), json!(vhost.document_root.as_ref().map(|root| root.is_dir())));
+    fields.insert("tls".into(), json!(vhost.is_ssl()));
+    fields.insert("port_default".into(), json!(is_port_default));
+    fields.insert("certificate".into(), certificate_json(vhost));
+    fields.insert("htaccess".into(), htaccess_json(vhost));
+    fields.insert("php".into(), php_json(config, vhost));
+    fields.insert("fastcgi".into(), fastcgi_json(config, vhost));
+    value
+}
+
+fn certificate_json(vhost: &VirtualHost) -> Value {
+    let Some(cert) = &vhost.ssl_cert_file else {
+        return Value::Null;
+    };
+    let mut value = certificate_file_json(cert);
+    if !vhost.ssl_extra_cert_files.is_empty() {
+        let additional: Vec<Value> = vhost.ssl_extra_cert_files.iter().map(|cert| certificate_file_json(cert)).collect();
+        value["additional"] = json!(additional);
+    }
+    value
+}
+
+fn certificate_file_json(cert: &Path) -> Value {
+    match tls::certificate_expiry(cert) {
+        Ok(expiry) => json!({
+            "file": cert,
+            "not_after": expiry.to_rfc3339(),
+            "days_left": (expiry - Utc::now()).num_days(),
+        }),
+        Err(e) => json!({ "file": cert, "error": e }),
+    }
+}
+
+fn htaccess_json(vhost: &VirtualHost) -> Value {
+    let Some(path) = vhost.document_root.as_ref().map(|root| root.join(".htaccess")) else {
+        return Value::Null;
+    };
+    match apache::parse_htaccess(&path) {
+        Some(htaccess) => json!({
+            "path": path,
+            "rewrite_engine": htaccess.rewrite_engine,
+            "rewrite_rules": htaccess.rewrite_rules.len(),
+            "redirects": htaccess.redirects.len(),
+        }),
+        None => Value::Null,
+    }
+}
+
+/// Extensions run by other FastCGI upstreams, the vhost's over `[fastcgi]`
+fn fastcgi_json(config: &Config, vhost: &VirtualHost) -> Value {
+    let mut handlers: std::collections::BTreeMap<String, &String> =
+        config.fastcgi.iter().map(|(ext, address)| (ext.trim_start_matches('.').to_ascii_lowercase(), address)).collect();
+    handlers.extend(vhost.fastcgi_handlers.iter().map(|(ext, address)| (ext.clone(), address)));
+    json!(handlers)
+}
+
+fn php_json(config: &Config, vhost: &VirtualHost) -> Value {
+    let extensions: Vec<&String> = config.php.extensions.iter().chain(&vhost.php_extensions).collect();
+    match config.php_backend(Some(vhost)) {
+        PhpBackend::Cgi(cgi_path) => json!({ "mode": "cgi", "target": cgi_path, "extensions": extensions }),
+        PhpBackend::Fpm(address) => json!({ "mode": "fpm", "target": address, "extensions": extensions }),
+    }
+}
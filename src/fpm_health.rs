@@ -0,0 +1,135 @@
+//! PHP-FPM pool health for the dashboard.
+//! A background task pings every pool the configuration names, `[php]
+//! fpm_address` and each vhost's own, with a FastCGI request for
+//! `/fpm-status?json`. A pool that answers at all is up; one whose
+//! `pm.status_path` is `/fpm-status` also reports its active and idle
+//! workers and its listen queue. Pools are re-read from the loaded vhosts on
+//! every round, so a reload adds and drops them.
+
+use crate::admin::{FpmPoolStatus, FpmWorkers};
+use crate::{listeners, AppState, PhpBackend};
+use chrono::Utc;
+use fastcgi_client::{Client, Params, Request as FcgiRequest};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::timeout;
+
+/// Seconds between pings
+const CHECK_INTERVAL: u64 = 10;
+
+/// Longest a pool gets to connect and answer a ping
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The status page asked for; pools serve it when `pm.status_path` names it
+const STATUS_PATH: &str = "/fpm-status";
+
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            check(&state).await;
+            tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL)).await;
+        }
+    });
+}
+
+/// Every pool by address, with the `name:port` of each loaded vhost whose
+/// PHP runs in it
+fn pools(state: &AppState) -> BTreeMap<String, Vec<String>> {
+    let mut pools: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let PhpBackend::Fpm(Some(address)) = state.config.php_backend(None) {
+        pools.insert(address.to_string(), Vec::new());
+    }
+    for vhost in &state.sites.read().list {
+        if let PhpBackend::Fpm(Some(address)) = state.config.php_backend(Some(vhost.as_ref())) {
+            pools.entry(address.to_string()).or_default().push(listeners::vhost_key(vhost));
+        }
+    }
+    pools
+}
+
+/// Ping every pool and update the dashboard, logging pools that go down or
+/// come back
+async fn check(state: &AppState) {
+    let previous = state.admin_state.fpm_pools.read().clone();
+    let mut statuses = BTreeMap::new();
+
+    for (address, vhosts) in pools(state) {
+        let last = previous.get(&address);
+        let now = Utc::now();
+        let status = match ping(&address).await {
+            Ok(workers) => {
+                if last.is_some_and(|s| !s.reachable) {
+                    println!("PHP-FPM pool {} is reachable again", address);
+                }
+                let message = match workers {
+                    Some(_) => "up".to_string(),
+                    None => format!("up; no status page at {}", STATUS_PATH),
+                };
+                FpmPoolStatus { time: now, reachable: true, last_ok: Some(now), consecutive_failures: 0, message, vhosts, workers }
+            }
+            Err(message) => {
+                if last.is_none_or(|s| s.reachable) {
+                    eprintln!("Warning: PHP-FPM pool {} is down: {}", address, message);
+                }
+                FpmPoolStatus {
+                    time: now,
+                    reachable: false,
+                    last_ok: last.and_then(|s| s.last_ok),
+                    consecutive_failures: last.map_or(0, |s| s.consecutive_failures) + 1,
+                    message,
+                    vhosts,
+                    workers: None,
+                }
+            }
+        };
+        statuses.insert(address, status);
+    }
+
+    *state.admin_state.fpm_pools.write() = statuses;
+}
+
+/// Ask the pool at `address` for its status page: Err when it doesn't
+/// answer, and no workers when it answers without one
+async fn ping(address: &str) -> Result<Option<FpmWorkers>, String> {
+    let mut params = Params::default();
+    params.insert(Cow::Borrowed("REQUEST_METHOD"), Cow::Borrowed("GET"));
+    params.insert(Cow::Borrowed("SCRIPT_NAME"), Cow::Borrowed(STATUS_PATH));
+    params.insert(Cow::Borrowed("SCRIPT_FILENAME"), Cow::Borrowed(STATUS_PATH));
+    params.insert(Cow::Borrowed("REQUEST_URI"), Cow::Owned(format!("{}?json", STATUS_PATH)));
+    params.insert(Cow::Borrowed("QUERY_STRING"), Cow::Borrowed("json"));
+    params.insert(Cow::Borrowed("GATEWAY_INTERFACE"), Cow::Borrowed("CGI/1.1"));
+    let request = FcgiRequest::new(params, tokio::io::empty());
+
+    let exchange = async {
+        let output = match address.strip_prefix("unix:") {
+            Some(path) => {
+                let stream = UnixStream::connect(path).await.map_err(|e| e.to_string())?;
+                Client::new(stream).execute_once(request).await
+            }
+            None => {
+                let stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+                Client::new(stream).execute_once(request).await
+            }
+        };
+        output.map_err(|e| e.to_string())
+    };
+    let output = timeout(PING_TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("no answer within {}s", PING_TIMEOUT.as_secs()))??;
+    Ok(output.stdout.as_deref().and_then(workers))
+}
+
+/// Worker counts from the JSON status page in a FastCGI response's output
+fn workers(stdout: &[u8]) -> Option<FpmWorkers> {
+    let output = String::from_utf8_lossy(stdout);
+    let (_, body) = output.split_once("\r\n\r\n")?;
+    let status: serde_json::Value = serde_json::from_str(body).ok()?;
+    Some(FpmWorkers {
+        active: status["active processes"].as_u64()?,
+        idle: status["idle processes"].as_u64()?,
+        listen_queue: status["listen queue"].as_u64()?,
+    })
+}
@@ -0,0 +1,201 @@
+//! Private key files of the TLS certificates: PEM PKCS#1, PKCS#8 and SEC1
+//! keys, and PKCS#8 keys encrypted with a passphrase (`ENCRYPTED PRIVATE
+//! KEY`, PBES2 with PBKDF2 and AES-CBC, as `openssl pkcs8 -topk8` and
+//! `openssl genpkey -aes256` write them). The passphrase comes from `[tls]`
+//! key_passphrase, else the environment variable named by key_passphrase_env,
+//! else the output of key_passphrase_command, which like mod_ssl's
+//! `SSLPassPhraseDialog exec:` is asked once per encrypted key file.
+
+use crate::tls::{der_element, dotted_oid};
+use anyhow::{anyhow, bail, Context};
+use aws_lc_rs::cipher::{self, DecryptionContext, PaddedBlockDecryptingKey, UnboundCipherKey};
+use aws_lc_rs::iv::FixedLength;
+use aws_lc_rs::pbkdf2;
+use base64::Engine;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::process::Command;
+
+// Object identifiers (contents only)
+const OID_PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+const OID_PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+const OID_HMAC_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x07];
+const OID_HMAC_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+const OID_HMAC_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0a];
+const OID_HMAC_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0b];
+const OID_AES_128_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x02];
+const OID_AES_192_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x16];
+const OID_AES_256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+
+/// How to re-encrypt a key in a format that can be read
+const CONVERT_HINT: &str = "re-encrypt it with `openssl pkcs8 -topk8 -v2 aes-256-cbc`";
+
+/// Where the passphrase of encrypted keys comes from, in order
+#[derive(Clone, Debug, Default)]
+pub struct Passphrase {
+    pub value: Option<String>,
+    pub env: Option<String>,
+    pub command: Option<String>,
+}
+
+impl Passphrase {
+    /// The passphrase for the encrypted key at `key_path`
+    fn get(&self, key_path: &Path) -> anyhow::Result<String> {
+        if let Some(value) = &self.value {
+            return Ok(value.clone());
+        }
+        if let Some(name) = &self.env {
+            return std::env::var(name).map_err(|_| anyhow!("key_passphrase_env: ${} is not set", name));
+        }
+        if let Some(command) = &self.command {
+            // The key file is $1, so a script can keep one passphrase per key
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .arg("wolfserve")
+                .arg(key_path)
+                .output()
+                .with_context(|| format!("key_passphrase_command `{}`", command))?;
+            if !output.status.success() {
+                bail!("key_passphrase_command `{}` failed ({})", command, output.status);
+            }
+            let passphrase = String::from_utf8(output.stdout)
+                .map_err(|_| anyhow!("key_passphrase_command `{}` printed invalid UTF-8", command))?;
+            return Ok(passphrase.trim_end_matches(['\r', '\n']).to_string());
+        }
+        bail!(
+            "private key {} is encrypted but no passphrase is configured; set [tls] key_passphrase, key_passphrase_env or key_passphrase_command",
+            key_path.display()
+        )
+    }
+}
+
+/// The first private key in the PEM file at `path`, decrypted if need be
+pub fn load_private_key(path: &Path, passphrase: &Passphrase) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+
+    if let Some(der) = pem_section(&pem, "ENCRYPTED PRIVATE KEY") {
+        let der = der.map_err(|e| anyhow!("private key {}: {}", path.display(), e))?;
+        let passphrase = passphrase.get(path)?;
+        let key = decrypt_pkcs8(&der, passphrase.as_bytes()).map_err(|e| anyhow!("private key {}: {}", path.display(), e))?;
+        return Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)));
+    }
+    if pem.windows(b"Proc-Type: 4,ENCRYPTED".len()).any(|w| w == b"Proc-Type: 4,ENCRYPTED") {
+        bail!(
+            "private key {} uses legacy PEM encryption (DEK-Info), which is not supported; {}",
+            path.display(),
+            CONVERT_HINT
+        );
+    }
+
+    for item in rustls_pemfile::read_all(&mut pem.as_slice()) {
+        match item? {
+            rustls_pemfile::Item::Pkcs1Key(key) => return Ok(key.into()),
+            rustls_pemfile::Item::Pkcs8Key(key) => return Ok(key.into()),
+            rustls_pemfile::Item::Sec1Key(key) => return Ok(key.into()),
+            _ => {}
+        }
+    }
+    bail!("No private keys found in {}", path.display())
+}
+
+/// The base64-decoded body of the first `label` section, if there is one
+fn pem_section(pem: &[u8], label: &str) -> Option<Result<Vec<u8>, String>> {
+    let text = String::from_utf8_lossy(pem);
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let start = text.find(&begin)? + begin.len();
+    let Some(len) = text[start..].find(&end) else {
+        return Some(Err(format!("no {} line", end)));
+    };
+    let body: String = text[start..start + len].split_whitespace().collect();
+    Some(base64::engine::general_purpose::STANDARD.decode(body).map_err(|e| format!("bad base64: {}", e)))
+}
+
+/// Decrypt a DER EncryptedPrivateKeyInfo into its PKCS#8 PrivateKeyInfo
+fn decrypt_pkcs8(der: &[u8], passphrase: &[u8]) -> Result<Vec<u8>, String> {
+    let malformed = || "malformed encrypted key".to_string();
+    let unsupported = |oid: &[u8]| format!("unsupported key encryption {}; {}", algorithm_name(oid), CONVERT_HINT);
+
+    let (_, info, _) = der_element(der).filter(|(tag, ..)| *tag == 0x30).ok_or_else(malformed)?;
+    let (_, algorithm, rest) = der_element(info).ok_or_else(malformed)?;
+    let (_, encrypted, _) = der_element(rest).filter(|(tag, ..)| *tag == 0x04).ok_or_else(malformed)?;
+    let (_, scheme_oid, scheme_params) = der_element(algorithm).ok_or_else(malformed)?;
+    if scheme_oid != OID_PBES2 {
+        return Err(unsupported(scheme_oid));
+    }
+
+    // PBES2-params: the key derivation function and the cipher
+    let (_, pbes2, _) = der_element(scheme_params).ok_or_else(malformed)?;
+    let (_, kdf, rest) = der_element(pbes2).ok_or_else(malformed)?;
+    let (_, cipher_algorithm, _) = der_element(rest).ok_or_else(malformed)?;
+    let (_, kdf_oid, kdf_params) = der_element(kdf).ok_or_else(malformed)?;
+    if kdf_oid != OID_PBKDF2 {
+        return Err(unsupported(kdf_oid));
+    }
+
+    // PBKDF2-params: salt, iterations, optional key length, PRF (default SHA-1)
+    let (_, kdf_params, _) = der_element(kdf_params).ok_or_else(malformed)?;
+    let (_, salt, rest) = der_element(kdf_params).filter(|(tag, ..)| *tag == 0x04).ok_or_else(malformed)?;
+    let (_, iterations, mut rest) = der_element(rest).filter(|(tag, ..)| *tag == 0x02).ok_or_else(malformed)?;
+    let iterations = iterations
+        .iter()
+        .try_fold(0u32, |acc, b| acc.checked_mul(256).map(|acc| acc | *b as u32))
+        .and_then(NonZeroU32::new)
+        .ok_or_else(malformed)?;
+    if let Some((0x02, _, after)) = der_element(rest) {
+        rest = after;
+    }
+    let prf = match der_element(rest) {
+        Some((_, prf, _)) => der_element(prf).ok_or_else(malformed)?.1,
+        None => OID_HMAC_SHA1,
+    };
+    let prf = match prf {
+        OID_HMAC_SHA1 => pbkdf2::PBKDF2_HMAC_SHA1,
+        OID_HMAC_SHA256 => pbkdf2::PBKDF2_HMAC_SHA256,
+        OID_HMAC_SHA384 => pbkdf2::PBKDF2_HMAC_SHA384,
+        OID_HMAC_SHA512 => pbkdf2::PBKDF2_HMAC_SHA512,
+        other => return Err(unsupported(other)),
+    };
+
+    let (_, cipher_oid, cipher_params) = der_element(cipher_algorithm).ok_or_else(malformed)?;
+    let (cipher, key_len) = match cipher_oid {
+        OID_AES_128_CBC => (&cipher::AES_128, cipher::AES_128_KEY_LEN),
+        OID_AES_192_CBC => (&cipher::AES_192, cipher::AES_192_KEY_LEN),
+        OID_AES_256_CBC => (&cipher::AES_256, cipher::AES_256_KEY_LEN),
+        other => return Err(unsupported(other)),
+    };
+    let (_, iv, _) = der_element(cipher_params).filter(|(tag, ..)| *tag == 0x04).ok_or_else(malformed)?;
+    let iv: [u8; 16] = iv.try_into().map_err(|_| malformed())?;
+
+    let mut key = vec![0; key_len];
+    pbkdf2::derive(prf, iterations, salt, passphrase, &mut key);
+    let key = UnboundCipherKey::new(cipher, &key)
+        .and_then(PaddedBlockDecryptingKey::cbc_pkcs7)
+        .map_err(|_| malformed())?;
+    let mut data = encrypted.to_vec();
+    // A wrong passphrase nearly always breaks the padding; the rare one that
+    // does not still leaves something other than a single DER SEQUENCE
+    let wrong = || "wrong passphrase".to_string();
+    let plain = key.decrypt(&mut data, DecryptionContext::Iv128(FixedLength::from(iv))).map_err(|_| wrong())?;
+    match der_element(plain) {
+        Some((0x30, _, [])) => Ok(plain.to_vec()),
+        _ => Err(wrong()),
+    }
+}
+
+/// Name of an encryption algorithm OpenSSL can write but we cannot read
+fn algorithm_name(oid: &[u8]) -> String {
+    let dotted = dotted_oid(oid);
+    match dotted.as_str() {
+        "1.2.840.113549.3.7" => "des-ede3-cbc",
+        "1.3.14.3.2.7" => "des-cbc",
+        "1.3.6.1.4.1.11591.4.11" => "scrypt",
+        "1.2.840.113549.1.5.3" => "PBES1 (MD5 and DES)",
+        "1.2.840.113549.1.5.10" => "PBES1 (SHA-1 and DES)",
+        "1.2.840.113549.1.12.1.3" => "PKCS#12 3DES",
+        _ => return dotted,
+    }
+    .to_string()
+}
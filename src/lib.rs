@@ -0,0 +1,3180 @@
+use axum::{
+    extract::{ConnectInfo, Extension, Request, State},
+    http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Response, IntoResponse},
+    routing::any,
+    Router,
+};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use fastcgi_client::{Client, ClientError, Params, Request as FcgiRequest};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::{timeout, Duration, Instant};
+use hyper::body::Body as _;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::num::{NonZeroU64, NonZeroUsize};
+use rustls::sign::CertifiedKey;
+use std::fs::File;
+use std::io::{BufReader, IsTerminal};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tokio_rustls::TlsAcceptor;
+use futures_util::future::{join_all, BoxFuture};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tower_http::compression::{predicate::SizeAbove, CompressionLayer, Predicate};
+use chrono::{DateTime, Utc};
+
+mod apache;
+mod admin;
+mod logfiles;
+mod tls;
+mod check;
+mod request_body;
+mod compression;
+mod tap;
+mod stats_file;
+mod connection;
+mod reload;
+mod dump;
+mod certs;
+mod acme;
+mod ocsp;
+mod keyfile;
+mod listeners;
+mod log_ring;
+mod shed;
+mod login_throttle;
+mod snapshot;
+mod fpm_health;
+use apache::{BodyLimit, VirtualHost, HostTable, RewriteContext, RewriteResult};
+use admin::admin_router;
+pub use admin::{generate_dashboard_html, AdminState, RequestLogEntry};
+use logfiles::LogFiles;
+use request_body::{ReadError, RequestBody};
+pub use keyfile::{load_private_key, Passphrase};
+pub use admin::{ServerStats, StatsSnapshot};
+pub use acme::{publish_tls_alpn_challenge, withdraw_tls_alpn_challenge};
+
+fn load_ssl_keys(cert_path: &Path, key_path: &Path, chain_path: Option<&PathBuf>, passphrase: &Passphrase) -> anyhow::Result<CertifiedKey> {
+    let cert_file = &mut BufReader::new(File::open(cert_path)?);
+
+    let mut cert_chain = rustls_pemfile::certs(cert_file)
+        .collect::<Result<Vec<_>, _>>()?;
+    if cert_chain.is_empty() {
+        anyhow::bail!("No certificates found in {}", cert_path.display());
+    }
+    
+    if let Some(cp) = chain_path {
+        let chain_file = &mut BufReader::new(File::open(cp)?);
+        let extra_certs = rustls_pemfile::certs(chain_file)
+            .collect::<Result<Vec<_>, _>>()?;
+        cert_chain.extend(extra_certs);
+    }
+    
+    let private_key = load_private_key(key_path, passphrase)?;
+    let key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&private_key)
+        .map_err(|_| anyhow::anyhow!("Invalid private key"))?;
+
+    let certified_key = CertifiedKey::new(cert_chain, key);
+    if let Err(rustls::Error::InconsistentKeys(rustls::InconsistentKeys::KeyMismatch)) = certified_key.keys_match() {
+        anyhow::bail!("private key {} does not match the certificate", key_path.display());
+    }
+    Ok(certified_key)
+}
+
+
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Config {
+    server: ServerConfig,
+    php: PhpConfig,
+    #[serde(default)]
+    apache: ApacheConfig,
+    #[serde(default)]
+    maintenance: MaintenanceConfig,
+    #[serde(default)]
+    tls: TlsConfig,
+    #[serde(default)]
+    compression: CompressionConfig,
+    #[serde(default)]
+    acme: AcmeConfig,
+    #[serde(default)]
+    admin: AdminConfig,
+    /// Requests logged in full at startup; the dashboard can change it
+    debug_tap: Option<tap::DebugTap>,
+    /// FastCGI upstreams for extensions other than PHP's, e.g.
+    /// `py = "127.0.0.1:9100"` or `fcgi = "unix:/run/app.sock"`
+    #[serde(default)]
+    fastcgi: BTreeMap<String, String>,
+    /// Per-vhost settings keyed by ServerName
+    #[serde(default)]
+    vhosts: HashMap<String, VhostOverrides>,
+    /// Protocol and HTTP versions of a port, e.g. `8080 = "http"` or
+    /// `8080 = { http_versions = ["h2", "http/1.1"] }`
+    #[serde(default)]
+    ports: BTreeMap<String, listeners::PortSetting>,
+    /// Bodies and Retry-After of the load-shedding statuses, keyed by
+    /// status: `[shed.503]`
+    #[serde(default)]
+    shed: BTreeMap<String, shed::ShedPage>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_compression_min_size() -> u16 {
+    256
+}
+
+fn default_compressible_types() -> Vec<String> {
+    compression::DEFAULT_TYPES.iter().map(|t| t.to_string()).collect()
+}
+
+/// Response compression (gzip/brotli/deflate, negotiated per request)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct CompressionConfig {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    /// Responses smaller than this many bytes are sent as-is
+    #[serde(default = "default_compression_min_size")]
+    min_size: u16,
+    /// MIME types to compress; `type/*` matches a whole family
+    #[serde(default = "default_compressible_types")]
+    types: Vec<String>,
+    /// Serve `file.br` in place of `file` to clients that accept Brotli
+    #[serde(default = "default_true")]
+    precompressed: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: default_compression_min_size(),
+            types: default_compressible_types(),
+            precompressed: true,
+        }
+    }
+}
+
+/// Server-wide TLS settings
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct TlsConfig {
+    /// Default certificate for TLS vhosts without their own (SSLEngine on with
+    /// no SSLCertificateFile) and for unknown SNI names
+    certificate_file: Option<PathBuf>,
+    certificate_key_file: Option<PathBuf>,
+    certificate_chain_file: Option<PathBuf>,
+    /// Directory of `<name>.crt` + `<name>.key` or `<name>.pem` certificates,
+    /// served for `<name>` and their DNS names unless a vhost has its own
+    cert_dir: Option<PathBuf>,
+    /// Seconds between checks of the certificate files for changes; 0 disables
+    #[serde(default = "default_cert_check_interval")]
+    check_interval: u64,
+    /// Fetch OCSP responses from each certificate's responder and staple them
+    #[serde(default = "default_true")]
+    ocsp_stapling: bool,
+    /// Oldest and newest TLS versions offered ("1.2" or "1.3")
+    min_version: Option<String>,
+    max_version: Option<String>,
+    /// Write session keys here in NSS key log format; for debugging only
+    key_log_file: Option<PathBuf>,
+    /// Warn when a certificate expires within this many days
+    #[serde(default = "default_expiry_warning_days")]
+    expiry_warning_days: u64,
+    /// POSTed a JSON notice when a certificate enters that window or expires
+    expiry_webhook: Option<String>,
+    /// What to do with a request whose Host is another vhost than its SNI name
+    #[serde(default)]
+    sni_mismatch: SniMismatch,
+    /// Seconds a new connection has to complete the TLS handshake
+    #[serde(default = "default_handshake_timeout")]
+    handshake_timeout: u64,
+    /// Passphrase of encrypted private keys, given directly, as the name of
+    /// an environment variable, or as a command printing it (like
+    /// SSLPassPhraseDialog exec:); the first one set is used. A configuration
+    /// export leaves the passphrase itself out.
+    #[serde(skip_serializing)]
+    key_passphrase: Option<String>,
+    key_passphrase_env: Option<String>,
+    key_passphrase_command: Option<String>,
+}
+
+impl TlsConfig {
+    fn passphrase(&self) -> Passphrase {
+        Passphrase {
+            value: self.key_passphrase.clone(),
+            env: self.key_passphrase_env.clone(),
+            command: self.key_passphrase_command.clone(),
+        }
+    }
+}
+
+/// `[tls] sni_mismatch`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SniMismatch {
+    /// 421 Misdirected Request, as Apache answers
+    #[default]
+    Reject,
+    /// Serve the Host's vhost and log a warning
+    Warn,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            certificate_file: None,
+            certificate_key_file: None,
+            certificate_chain_file: None,
+            cert_dir: None,
+            check_interval: default_cert_check_interval(),
+            ocsp_stapling: true,
+            min_version: None,
+            max_version: None,
+            key_log_file: None,
+            expiry_warning_days: default_expiry_warning_days(),
+            expiry_webhook: None,
+            sni_mismatch: SniMismatch::default(),
+            handshake_timeout: default_handshake_timeout(),
+            key_passphrase: None,
+            key_passphrase_env: None,
+            key_passphrase_command: None,
+        }
+    }
+}
+
+fn default_cert_check_interval() -> u64 {
+    60
+}
+
+fn default_expiry_warning_days() -> u64 {
+    21
+}
+
+fn default_handshake_timeout() -> u64 {
+    10
+}
+
+/// Built-in ACME client for vhosts with `acme = true`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct AcmeConfig {
+    /// ACME directory; point it at the Let's Encrypt staging URL for tests
+    #[serde(default = "default_acme_directory")]
+    directory_url: String,
+    /// Contact for vhosts without an `acme_email` of their own
+    email: Option<String>,
+    /// Where account keys and issued certificates are kept
+    #[serde(default = "default_acme_state_dir")]
+    state_dir: PathBuf,
+    /// Renew certificates this many days before they expire
+    #[serde(default = "default_acme_renew_days")]
+    renew_days: i64,
+    /// CA bundle for talking to the ACME server; the system bundle if unset
+    ca_file: Option<PathBuf>,
+    /// How domain control is proven to the CA
+    #[serde(default)]
+    challenge: AcmeChallenge,
+}
+
+/// `[acme] challenge`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum AcmeChallenge {
+    /// A file under `/.well-known/acme-challenge/`, fetched over port 80
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    /// A self-signed certificate sent to `acme-tls/1` handshakes on port 443
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            directory_url: default_acme_directory(),
+            email: None,
+            state_dir: default_acme_state_dir(),
+            renew_days: default_acme_renew_days(),
+            ca_file: None,
+            challenge: AcmeChallenge::default(),
+        }
+    }
+}
+
+fn default_acme_directory() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_acme_state_dir() -> PathBuf {
+    PathBuf::from("/var/lib/wolfserve/acme")
+}
+
+fn default_acme_renew_days() -> i64 {
+    30
+}
+
+/// Settings for a single vhost that Apache config has no directive for
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+struct VhostOverrides {
+    /// Redirect aliases to this host; `true` uses the vhost's ServerName
+    canonical_host: Option<CanonicalHost>,
+    /// Overrides `[server] strip_index` for this vhost
+    strip_index: Option<bool>,
+    /// Overrides `[server] redirect_to_https` for this vhost
+    redirect_to_https: Option<bool>,
+    /// Replaces `[server] deny_extensions` for this vhost
+    deny_extensions: Option<Vec<String>>,
+    /// PHP mode (`fpm` or `cgi`) for this vhost instead of `[php] mode`
+    php_mode: Option<String>,
+    /// This vhost's own PHP-FPM pool
+    fpm_address: Option<String>,
+    /// FastCGI upstreams by extension for this vhost, on top of `[fastcgi]`
+    #[serde(default)]
+    fastcgi: BTreeMap<String, String>,
+    /// Obtain and renew this vhost's certificate with the built-in ACME client
+    #[serde(default)]
+    acme: bool,
+    /// ACME contact for this vhost instead of `[acme] email`
+    acme_email: Option<String>,
+    /// Cross-Origin-Opener-Policy for every response of this vhost
+    cross_origin_opener_policy: Option<OpenerPolicy>,
+    /// Cross-Origin-Embedder-Policy for every response of this vhost
+    cross_origin_embedder_policy: Option<EmbedderPolicy>,
+    /// SERVER_NAME that PHP sees, whatever the request's Host
+    php_server_name: Option<String>,
+    /// HTTP_HOST that PHP sees, whatever the request's Host
+    php_http_host: Option<String>,
+}
+
+/// Cross-Origin-Opener-Policy values
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum OpenerPolicy {
+    UnsafeNone,
+    SameOriginAllowPopups,
+    SameOrigin,
+    NoopenerAllowPopups,
+}
+
+impl OpenerPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            OpenerPolicy::UnsafeNone => "unsafe-none",
+            OpenerPolicy::SameOriginAllowPopups => "same-origin-allow-popups",
+            OpenerPolicy::SameOrigin => "same-origin",
+            OpenerPolicy::NoopenerAllowPopups => "noopener-allow-popups",
+        }
+    }
+}
+
+/// Cross-Origin-Embedder-Policy values
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum EmbedderPolicy {
+    UnsafeNone,
+    RequireCorp,
+    Credentialless,
+}
+
+impl EmbedderPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            EmbedderPolicy::UnsafeNone => "unsafe-none",
+            EmbedderPolicy::RequireCorp => "require-corp",
+            EmbedderPolicy::Credentialless => "credentialless",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+enum CanonicalHost {
+    FromServerName(bool),
+    Name(String),
+}
+
+impl VhostOverrides {
+    /// Apply these settings to a loaded vhost
+    fn apply(&self, vhost: &mut VirtualHost) {
+        match &self.canonical_host {
+            Some(CanonicalHost::FromServerName(true)) => vhost.canonical_host = vhost.server_name.clone(),
+            Some(CanonicalHost::Name(name)) => vhost.canonical_host = Some(name.trim().trim_end_matches('.').to_lowercase()),
+            _ => {}
+        }
+        if self.strip_index.is_some() {
+            vhost.strip_index = self.strip_index;
+        }
+        if self.redirect_to_https.is_some() {
+            vhost.redirect_to_https = self.redirect_to_https;
+        }
+        if self.deny_extensions.is_some() {
+            vhost.deny_extensions.clone_from(&self.deny_extensions);
+        }
+        if let Some(address) = &self.fpm_address {
+            vhost.fpm_address = Some(address.clone());
+            vhost.php_mode = Some("fpm".to_string());
+        }
+        if self.php_mode.is_some() {
+            vhost.php_mode.clone_from(&self.php_mode);
+        }
+        for (extension, address) in &self.fastcgi {
+            vhost.fastcgi_handlers.insert(extension.trim_start_matches('.').to_ascii_lowercase(), address.clone());
+        }
+        if let Some(policy) = self.cross_origin_opener_policy {
+            vhost.cross_origin_opener_policy = Some(policy.as_str().to_string());
+        }
+        if let Some(policy) = self.cross_origin_embedder_policy {
+            vhost.cross_origin_embedder_policy = Some(policy.as_str().to_string());
+        }
+        if self.php_server_name.is_some() {
+            vhost.php_server_name.clone_from(&self.php_server_name);
+        }
+        if self.php_http_host.is_some() {
+            vhost.php_http_host.clone_from(&self.php_http_host);
+        }
+    }
+}
+
+fn default_retry_after() -> u64 {
+    300
+}
+
+fn default_admin_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_admin_port() -> u16 {
+    5000
+}
+
+fn default_session_timeout_hours() -> NonZeroU64 {
+    NonZeroU64::new(24).unwrap()
+}
+
+fn default_remember_me_days() -> u64 {
+    30
+}
+
+fn default_stats_file() -> PathBuf {
+    PathBuf::from("wolfserve_stats.json")
+}
+
+fn default_stats_save_interval() -> u64 {
+    60
+}
+
+fn default_log_entries() -> NonZeroUsize {
+    NonZeroUsize::new(admin::DEFAULT_LOG_ENTRIES).unwrap()
+}
+
+fn default_log_file_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+/// The admin dashboard
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct AdminConfig {
+    /// Serve the dashboard at all; when false no credentials file is created
+    #[serde(default = "default_true")]
+    enabled: bool,
+    /// Address the dashboard binds to; localhost only by default
+    #[serde(default = "default_admin_host")]
+    host: String,
+    #[serde(default = "default_admin_port")]
+    port: u16,
+    /// Hours a dashboard login lasts
+    #[serde(default = "default_session_timeout_hours")]
+    session_timeout_hours: NonZeroU64,
+    /// Days a "remember me" login lasts, with a persistent cookie; 0 removes
+    /// the checkbox
+    #[serde(default = "default_remember_me_days")]
+    remember_me_days: u64,
+    /// Statistics and request log kept across restarts, next to the
+    /// credentials file by default
+    #[serde(default = "default_stats_file")]
+    stats_file: PathBuf,
+    /// Seconds between saves of `stats_file`; 0 saves only on shutdown
+    #[serde(default = "default_stats_save_interval")]
+    stats_save_interval: u64,
+    /// Requests the dashboard keeps in memory
+    #[serde(default = "default_log_entries")]
+    log_entries: NonZeroUsize,
+    /// NDJSON file the request log is also written to, so the dashboard can
+    /// page back further than memory holds
+    log_file: Option<PathBuf>,
+    /// Bytes of `log_file` before it is rotated to `<log_file>.1`
+    #[serde(default = "default_log_file_max_bytes")]
+    log_file_max_bytes: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            host: default_admin_host(),
+            port: default_admin_port(),
+            session_timeout_hours: default_session_timeout_hours(),
+            remember_me_days: default_remember_me_days(),
+            stats_file: default_stats_file(),
+            stats_save_interval: default_stats_save_interval(),
+            log_entries: default_log_entries(),
+            log_file: None,
+            log_file_max_bytes: default_log_file_max_bytes(),
+        }
+    }
+}
+
+impl AdminConfig {
+    fn addr(&self) -> Result<SocketAddr, String> {
+        match self.host.parse::<std::net::IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, self.port)),
+            Err(_) => Err(format!("[admin] host {:?} is not an IP address", self.host)),
+        }
+    }
+
+    /// Why the dashboard may not be served as configured: the default
+    /// admin/admin login is only accepted on a loopback address
+    fn refusal(&self) -> Option<String> {
+        let addr = match self.addr() {
+            Ok(addr) => addr,
+            Err(e) => return Some(e),
+        };
+        if addr.ip().is_loopback() || !admin::has_default_credentials() {
+            return None;
+        }
+        Some(format!(
+            "the admin dashboard still has the default admin/admin login and [admin] host {} is reachable from other machines; \
+             change the password at http://127.0.0.1:{}/change-password with host = \"127.0.0.1\" first, or set [admin] enabled = false",
+            self.host, self.port
+        ))
+    }
+
+    fn session_timeouts(&self) -> admin::SessionTimeouts {
+        admin::SessionTimeouts {
+            session: chrono::Duration::hours(self.session_timeout_hours.get() as i64),
+            remember_me: Some(self.remember_me_days)
+                .filter(|days| *days > 0)
+                .map(|days| chrono::Duration::days(days as i64)),
+        }
+    }
+}
+
+/// Maintenance mode: matching sites answer 503 with a static page
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct MaintenanceConfig {
+    /// Start with every site in maintenance
+    #[serde(default)]
+    enabled: bool,
+    /// Start with only these sites (ServerName) in maintenance
+    #[serde(default)]
+    sites: Vec<String>,
+    /// HTML file served while in maintenance; a built-in page is used if unset
+    page: Option<String>,
+    /// Seconds sent in the Retry-After header
+    #[serde(default = "default_retry_after")]
+    retry_after: u64,
+    /// Client IPs that bypass maintenance mode
+    #[serde(default)]
+    allow_ips: Vec<String>,
+}
+
+impl MaintenanceConfig {
+    /// Whether `client` still sees the live site while in maintenance
+    fn allows(&self, client: Option<IpAddr>) -> bool {
+        client.is_some_and(|ip| {
+            self.allow_ips
+                .iter()
+                .filter_map(|allowed| allowed.parse::<IpAddr>().ok())
+                .any(|allowed| allowed.to_canonical() == ip.to_canonical())
+        })
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sites: Vec::new(),
+            page: None,
+            retry_after: default_retry_after(),
+            allow_ips: Vec::new(),
+        }
+    }
+}
+
+fn default_apache_dir() -> String {
+    "/etc/apache2".to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct ApacheConfig {
+    #[serde(default = "default_apache_dir")]
+    config_dir: String,
+    /// Also read sites-enabled files that have no extension (older layouts)
+    #[serde(default)]
+    include_extensionless: bool,
+    /// Reload vhosts automatically when files under config_dir change
+    #[serde(default)]
+    watch: bool,
+    /// Seconds between checks for changes
+    #[serde(default = "default_watch_interval")]
+    watch_interval: u64,
+}
+
+fn default_watch_interval() -> u64 {
+    2
+}
+
+impl Default for ApacheConfig {
+    fn default() -> Self {
+        Self {
+            config_dir: default_apache_dir(),
+            include_extensionless: false,
+            watch: false,
+            watch_interval: default_watch_interval(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct ServerConfig {
+    host: String,
+    port: u16,
+    /// Redirect explicit index URLs (`/dir/index.html`) to the directory (`/dir/`)
+    #[serde(default)]
+    strip_index: bool,
+    /// 301-redirect other host names to the ServerName of vhosts with
+    /// `UseCanonicalName On`
+    #[serde(default)]
+    canonical_redirect: bool,
+    /// Paths never sent to the canonical host (health checks, ACME challenges)
+    #[serde(default = "default_canonical_exempt")]
+    canonical_exempt: Vec<String>,
+    /// Answer 403 for paths with a dot-segment (`.htaccess`, `.git/`, `.env`);
+    /// `.well-known` is always allowed
+    #[serde(default = "default_true")]
+    deny_dotfiles: bool,
+    /// Answer 403 instead of serving files with these extensions (backups,
+    /// dumps, includes); vhosts can replace the list
+    #[serde(default = "default_deny_extensions")]
+    deny_extensions: Vec<String>,
+    /// Serve `/.well-known/` from this directory instead of the document root
+    well_known_dir: Option<PathBuf>,
+    /// 301-redirect plain HTTP requests for names that have a TLS vhost
+    #[serde(default)]
+    redirect_to_https: bool,
+    /// Peers whose X-Forwarded-Proto and X-Forwarded-Port say how the client
+    /// reached them
+    #[serde(default)]
+    trusted_proxies: Vec<IpAddr>,
+    /// Take the Host from X-Forwarded-Host on requests from `trusted_proxies`,
+    /// for choosing the vhost and everything after
+    #[serde(default)]
+    trust_forwarded_host: bool,
+    /// `Server` header: `full`, `prod` (name only) or `off`; unset follows
+    /// Apache's ServerTokens, else `full`
+    server_tokens: Option<ServerTokens>,
+    /// Connection settings; unset ones come from Apache's KeepAlive,
+    /// KeepAliveTimeout (seconds), Timeout (seconds) and MaxKeepAliveRequests
+    keep_alive: Option<bool>,
+    keep_alive_timeout: Option<u64>,
+    timeout: Option<u64>,
+    max_keep_alive_requests: Option<u64>,
+    /// Keep HTTP/1.0 connections open when the client asks with
+    /// `Connection: keep-alive`; false closes them after every response
+    http10_keep_alive: Option<bool>,
+    /// Threads serving requests; unset runs one per CPU core
+    worker_threads: Option<NonZeroUsize>,
+    /// Threads for filesystem work that would otherwise stall a worker;
+    /// unset leaves tokio's limit of 512
+    max_blocking_threads: Option<NonZeroUsize>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct PhpConfig {
+    fpm_address: Option<String>,
+    #[serde(default = "default_php_mode")]
+    mode: String, // "fpm" or "cgi"
+    #[serde(default = "default_cgi_path")]
+    cgi_path: String,
+    /// PHP session save path (e.g., "/mnt/shared/wolfserve/sessions")
+    /// Used by shell scripts for PHP-FPM configuration
+    #[allow(dead_code)]
+    session_save_path: Option<String>,
+    /// Request bodies larger than this (bytes) are spilled to a temp file
+    #[serde(default = "default_body_memory_limit")]
+    body_memory_limit: usize,
+    /// Directory for spilled request bodies (defaults to the system temp dir)
+    body_temp_dir: Option<PathBuf>,
+    /// Largest request body (bytes) where no LimitRequestBody applies; 0 is unlimited
+    #[serde(default = "default_max_body_size")]
+    max_body_size: u64,
+    /// File extensions run as PHP, besides those vhosts map with AddHandler
+    #[serde(default = "default_php_extensions")]
+    extensions: Vec<String>,
+    /// PHP requests in flight at once across every vhost; unlimited if unset
+    max_concurrent: Option<NonZeroUsize>,
+    /// Seconds a PHP request over max_concurrent waits for a slot before it
+    /// is answered 503; 0 answers at once
+    #[serde(default = "default_php_queue_timeout")]
+    queue_timeout: u64,
+    /// Say why PHP or a FastCGI upstream failed in the 502/503/504 page, not
+    /// only in the ErrorLog
+    #[serde(default)]
+    error_detail: bool,
+}
+
+fn default_php_queue_timeout() -> u64 {
+    5
+}
+
+fn default_php_extensions() -> Vec<String> {
+    ["php", "phtml", "php5", "php7"].map(String::from).to_vec()
+}
+
+fn default_body_memory_limit() -> usize {
+    1024 * 1024
+}
+
+fn default_max_body_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_deny_extensions() -> Vec<String> {
+    ["inc", "bak", "sql", "log", "env", "sqlite"].map(String::from).to_vec()
+}
+
+fn default_canonical_exempt() -> Vec<String> {
+    ["/health", "/healthz", "/.well-known/acme-challenge/"].map(String::from).to_vec()
+}
+
+fn default_php_mode() -> String {
+    "fpm".to_string()
+}
+
+fn default_cgi_path() -> String {
+    "php-cgi".to_string()
+}
+
+pub struct AppState {
+    config: Config,
+    /// Vhost tables; replaced wholesale when the Apache config is reloaded
+    sites: parking_lot::RwLock<Arc<Sites>>,
+    /// The listener of each port; changing them needs a restart
+    listeners: Vec<listeners::PortListener>,
+    admin_state: Arc<AdminState>,
+    log_files: LogFiles,
+    connection: Arc<connection::ConnectionSettings>,
+    /// TLS certificates, reloadable without restarting the listeners
+    certs: Arc<certs::CertResolver>,
+    /// HTTP-01 challenges the ACME client is waiting on
+    acme_challenges: acme::Challenges,
+    server_tokens: ServerTokens,
+    /// `[tls] key_log_file`, shared by every HTTPS listener
+    key_log: Option<Arc<tls::KeyLogFile>>,
+    /// One permit per PHP request allowed in flight, from `[php] max_concurrent`
+    php_slots: Option<tokio::sync::Semaphore>,
+    /// Held while the Apache config is reloaded; guards the errors the running
+    /// config already had, which don't block a reload
+    reload_lock: tokio::sync::Mutex<std::collections::BTreeSet<String>>,
+}
+
+impl AppState {
+    fn new(
+        config: Config,
+        sites: Sites,
+        listeners: Vec<listeners::PortListener>,
+        connection: Arc<connection::ConnectionSettings>,
+        certs: Arc<certs::CertResolver>,
+        server_tokens: ServerTokens,
+    ) -> Self {
+        // Shared admin state for statistics and logging
+        let admin_state = Arc::new(AdminState::new());
+        *admin_state.listeners.write() = listeners.clone();
+        *admin_state.session_timeouts.write() = config.admin.session_timeouts();
+        admin_state.set_log_capacity(config.admin.log_entries.get());
+        *admin_state.debug_tap.write() = config.debug_tap.clone();
+        {
+            let mut maintenance = admin_state.maintenance.write();
+            maintenance.global = config.maintenance.enabled;
+            maintenance.sites = config.maintenance.sites.iter().map(|s| s.to_lowercase()).collect();
+        }
+        let key_log = open_key_log(&config.tls);
+        let php_slots = config.php.max_concurrent.map(|slots| tokio::sync::Semaphore::new(slots.get()));
+        AppState {
+            config,
+            sites: parking_lot::RwLock::new(Arc::new(sites)),
+            listeners,
+            admin_state,
+            log_files: LogFiles::default(),
+            connection,
+            certs,
+            acme_challenges: acme::Challenges::default(),
+            server_tokens,
+            key_log,
+            php_slots,
+            reload_lock: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// The dashboard's statistics as they stand
+    pub fn stats(&self) -> StatsSnapshot {
+        self.admin_state.stats.snapshot()
+    }
+
+    /// The HTTP versions of the listener on `port`, preferred first
+    fn http_versions(&self, port: u16) -> Vec<listeners::HttpVersion> {
+        self.listeners
+            .iter()
+            .find(|listener| listener.port == port)
+            .map_or_else(|| vec![listeners::HttpVersion::H2, listeners::HttpVersion::Http11], |l| l.http_versions.clone())
+    }
+}
+
+/// Open `[tls] key_log_file`, warning loudly that it exposes the traffic
+fn open_key_log(tls_config: &TlsConfig) -> Option<Arc<tls::KeyLogFile>> {
+    let path = tls_config.key_log_file.as_ref()?;
+    match tls::KeyLogFile::open(path) {
+        Ok(key_log) => {
+            eprintln!("WARNING: ******************************************************************");
+            eprintln!("WARNING: TLS session keys are being written to {}", path.display());
+            eprintln!("WARNING: anyone who can read it can decrypt this server's HTTPS traffic.");
+            eprintln!("WARNING: key_log_file is for debugging only; remove it from [tls] when done.");
+            eprintln!("WARNING: ******************************************************************");
+            Some(Arc::new(key_log))
+        }
+        Err(e) => {
+            eprintln!("ERROR: cannot open key_log_file {}: {}; TLS keys will not be logged", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Vhosts declared for one listening port
+#[derive(Default)]
+struct PortVhosts {
+    names: HostTable<Arc<VirtualHost>>,
+    /// The `_default_` vhost, else the first one listed for the port
+    default: Option<Arc<VirtualHost>>,
+}
+
+/// The loaded vhosts, indexed for request routing
+#[derive(Default)]
+struct Sites {
+    /// Vhosts grouped by the port they are declared for
+    ports: HashMap<u16, PortVhosts>,
+    vhosts: HostTable<Arc<VirtualHost>>, // Map Host header -> VirtualHost, all ports
+    /// TLS vhosts by name, for redirecting plain HTTP requests to them
+    tls_names: HostTable<Arc<VirtualHost>>,
+    /// The first vhost without a ServerName, for ports with no vhosts of
+    /// their own; every other port has its own default in `ports`
+    default_vhost: Option<Arc<VirtualHost>>,
+    /// Every vhost, in config order
+    list: Vec<Arc<VirtualHost>>,
+    /// The main server config, for requests that no vhost takes
+    server: Option<Arc<VirtualHost>>,
+}
+
+impl Sites {
+    fn build(list: Vec<Arc<VirtualHost>>, server: Arc<VirtualHost>) -> Self {
+        let mut sites = Sites {
+            server: Some(server),
+            ..Sites::default()
+        };
+
+        for vhost in &list {
+            let on_port = sites.ports.entry(vhost.port).or_default();
+            // The first listed vhost is the port's default unless a _default_ one exists
+            let replaces_default = match &on_port.default {
+                None => true,
+                Some(current) => vhost.is_default && !current.is_default,
+            };
+            if replaces_default {
+                on_port.default = Some(vhost.clone());
+            }
+
+            if let Some(name) = &vhost.server_name {
+                sites.vhosts.insert(name, vhost.clone());
+                on_port.names.insert(name, vhost.clone());
+                for alias in &vhost.server_aliases {
+                    sites.vhosts.insert(alias, vhost.clone());
+                    on_port.names.insert(alias, vhost.clone());
+                }
+            } else if sites.default_vhost.is_none() {
+                sites.default_vhost = Some(vhost.clone());
+            }
+        }
+        // Prefer the vhost on 443 when a name is served on several TLS ports
+        let (standard, other): (Vec<_>, Vec<_>) = list.iter().filter(|v| v.is_ssl()).partition(|v| v.port == 443);
+        for vhost in other.into_iter().chain(standard) {
+            for name in vhost.server_name.iter().chain(&vhost.server_aliases) {
+                sites.tls_names.insert(name, vhost.clone());
+            }
+        }
+        sites.list = list;
+        sites
+    }
+
+    /// Log each vhost at info level
+    fn log(&self) {
+        for vhost in &self.list {
+            tracing::info!(
+                vhost = vhost.server_name.as_deref().unwrap_or("default"),
+                port = vhost.port,
+                tls = vhost.is_ssl(),
+                document_root = ?vhost.document_root,
+                "loaded vhost"
+            );
+        }
+        if let Some(root) = self.server.as_ref().and_then(|server| server.document_root.as_ref()) {
+            tracing::info!(document_root = ?root, "main server serves requests no vhost takes");
+        }
+    }
+
+    /// The TLS vhost serving a host name, on any port
+    fn find_tls_vhost(&self, host: &str) -> Option<&Arc<VirtualHost>> {
+        self.tls_names.get(host)
+    }
+
+    /// Apache-style vhost selection: ServerName/ServerAlias among the vhosts
+    /// of the listening port, else that port's default. A port with no vhosts
+    /// of its own (the [server] port behind a proxy) searches every vhost,
+    /// then falls back to the main server config.
+    fn find_vhost(&self, port: u16, host: Option<&str>) -> Option<&Arc<VirtualHost>> {
+        match self.ports.get(&port) {
+            Some(port_vhosts) => host
+                .and_then(|h| port_vhosts.names.get(h))
+                .or(port_vhosts.default.as_ref()),
+            None => host
+                .and_then(|h| self.vhosts.get(h))
+                .or(self.default_vhost.as_ref())
+                .or(self.server.as_ref()),
+        }
+    }
+}
+
+impl Config {
+    /// Apply the `[vhosts."<ServerName>"]` settings for a loaded vhost, and
+    /// `[server] canonical_redirect` for vhosts with UseCanonicalName On
+    fn apply_vhost_overrides(&self, vhost: &mut VirtualHost) {
+        if self.server.canonical_redirect && vhost.use_canonical_name == Some(true) {
+            vhost.canonical_host = vhost.server_name.clone();
+        }
+        let Some(name) = vhost.server_name.clone() else {
+            return;
+        };
+        if let Some(overrides) = self.vhost_overrides(&name) {
+            overrides.apply(vhost);
+            if overrides.acme && vhost.is_ssl() {
+                // ACME-issued certificates replace any SSLCertificateFile
+                let dir = self.acme.state_dir.join(name.to_lowercase());
+                vhost.ssl_cert_file = Some(dir.join("cert.pem"));
+                vhost.ssl_key_file = Some(dir.join("key.pem"));
+                vhost.ssl_chain_file = None;
+                vhost.ssl_extra_cert_files.clear();
+                vhost.ssl_extra_key_files.clear();
+                vhost.acme = true;
+            }
+        }
+    }
+
+    /// wolfserve.toml settings for the vhost with this ServerName
+    fn vhost_overrides(&self, server_name: &str) -> Option<&VhostOverrides> {
+        self.vhosts.iter().find(|(key, _)| key.eq_ignore_ascii_case(server_name)).map(|(_, o)| o)
+    }
+
+    /// How PHP runs for a vhost: its own mode and FPM pool when it has them,
+    /// else the `[php]` settings
+    fn php_backend<'a>(&'a self, vhost: Option<&'a VirtualHost>) -> PhpBackend<'a> {
+        let mode = vhost.and_then(|v| v.php_mode.as_deref()).unwrap_or(&self.php.mode);
+        if mode == "cgi" {
+            return PhpBackend::Cgi(&self.php.cgi_path);
+        }
+        PhpBackend::Fpm(vhost.and_then(|v| v.fpm_address.as_deref()).or(self.php.fpm_address.as_deref()))
+    }
+
+    /// Whether files with extension `ext` run as PHP on a vhost. Case is
+    /// ignored: on case-insensitive filesystems `/x.PHP` opens x.php, which
+    /// must not go out as a static download of its source.
+    fn is_php_extension(&self, vhost: Option<&VirtualHost>, ext: &std::ffi::OsStr) -> bool {
+        let vhost_extensions = vhost.map(|v| v.php_extensions.as_slice()).unwrap_or_default();
+        self.php.extensions.iter().chain(vhost_extensions).any(|php| ext.eq_ignore_ascii_case(php.trim_start_matches('.')))
+    }
+
+    /// The FastCGI upstream that runs files with extension `ext` on a vhost:
+    /// the vhost's own mapping, else `[fastcgi]`
+    fn fastcgi_upstream<'a>(&'a self, vhost: Option<&'a VirtualHost>, ext: &std::ffi::OsStr) -> Option<&'a str> {
+        let ext = ext.to_str()?.to_ascii_lowercase();
+        vhost
+            .and_then(|v| v.fastcgi_handlers.get(&ext))
+            .or_else(|| self.fastcgi.iter().find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&ext)).map(|(_, a)| a))
+            .map(String::as_str)
+    }
+
+    /// Whether a file must not be served on a vhost because of its extension.
+    /// A dotfile such as `.env` counts as having the extension `env`.
+    fn is_denied_extension(&self, vhost: Option<&VirtualHost>, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        let Some((_, ext)) = name.rsplit_once('.') else {
+            return false;
+        };
+        vhost
+            .and_then(|v| v.deny_extensions.as_ref())
+            .unwrap_or(&self.server.deny_extensions)
+            .iter()
+            .any(|denied| ext.eq_ignore_ascii_case(denied.trim_start_matches('.')))
+    }
+
+    /// Whether a URL path is exempt from canonical host redirects
+    fn canonical_exempt(&self, uri_path: &str) -> bool {
+        self.server.canonical_exempt.iter().any(|exempt| {
+            if exempt.ends_with('/') {
+                uri_path.starts_with(exempt.as_str())
+            } else {
+                uri_path == exempt || uri_path.starts_with(&format!("{}/", exempt))
+            }
+        })
+    }
+}
+
+/// How much the `Server` header and SERVER_SOFTWARE give away
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ServerTokens {
+    /// `wolfserve/0.2.2`
+    Full,
+    /// `wolfserve`
+    #[serde(alias = "name-only")]
+    Prod,
+    /// No `Server` header at all
+    Off,
+}
+
+impl ServerTokens {
+    /// Apache's ServerTokens value; everything but Prod shows the version
+    fn from_apache(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "prod" | "productonly" => Some(ServerTokens::Prod),
+            "major" | "minor" | "min" | "minimal" | "os" | "full" => Some(ServerTokens::Full),
+            _ => None,
+        }
+    }
+
+    /// `[server] server_tokens`, else Apache's ServerTokens, else Full
+    fn resolve(config: &Config, loaded: &apache::LoadedConfig) -> Self {
+        config.server.server_tokens.unwrap_or_else(|| {
+            loaded.server_tokens.as_deref().map_or(ServerTokens::Full, |value| {
+                ServerTokens::from_apache(value).unwrap_or_else(|| {
+                    eprintln!("Warning: unknown ServerTokens '{}'; using Full", value);
+                    ServerTokens::Full
+                })
+            })
+        })
+    }
+
+    /// Value of the `Server` header, if one is sent
+    fn banner(self) -> Option<String> {
+        match self {
+            ServerTokens::Full => Some(format!("wolfserve/{}", VERSION)),
+            ServerTokens::Prod => Some("wolfserve".to_string()),
+            ServerTokens::Off => None,
+        }
+    }
+
+    /// SERVER_SOFTWARE for PHP, which always gets at least the name
+    fn server_software(self) -> String {
+        self.banner().unwrap_or_else(|| "wolfserve".to_string())
+    }
+}
+
+/// Where a request's PHP scripts run
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PhpBackend<'a> {
+    /// php-cgi binary
+    Cgi(&'a str),
+    /// PHP-FPM address, if one is configured
+    Fpm(Option<&'a str>),
+}
+
+/// The listener a request arrived on, attached to each listener's router
+#[derive(Clone, Copy)]
+struct Listener {
+    port: u16,
+    tls: bool,
+}
+
+/// The scheme and port a client used: the listener's, or for a request from
+/// one of `[server] trusted_proxies`, those the proxy reports
+#[derive(Clone, Copy, Debug)]
+struct Origin {
+    https: bool,
+    port: u16,
+}
+
+impl Origin {
+    fn of(server: &ServerConfig, listener: Listener, headers: &HeaderMap, peer: Option<IpAddr>) -> Self {
+        let direct = Origin { https: listener.tls, port: listener.port };
+        if !server.is_trusted_proxy(peer) {
+            return direct;
+        }
+        let forwarded = |name: &str| forwarded_value(headers, name);
+        let https = forwarded("x-forwarded-proto").map_or(direct.https, |proto| proto.eq_ignore_ascii_case("https"));
+        let port = match forwarded("x-forwarded-port").and_then(|port| port.parse().ok()) {
+            Some(port) => port,
+            None if https == direct.https => direct.port,
+            None if https => 443,
+            None => 80,
+        };
+        Origin { https, port }
+    }
+
+    fn scheme(self) -> &'static str {
+        if self.https { "https" } else { "http" }
+    }
+}
+
+impl ServerConfig {
+    fn is_trusted_proxy(&self, peer: Option<IpAddr>) -> bool {
+        peer.is_some_and(|ip| self.trusted_proxies.iter().any(|trusted| trusted.to_canonical() == ip.to_canonical()))
+    }
+
+    /// The client's address: the peer's, or for a request from a trusted
+    /// proxy, the one it reports in X-Forwarded-For or X-Real-IP
+    fn client_addr(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        if !self.is_trusted_proxy(peer) {
+            return peer;
+        }
+        forwarded_value(headers, "x-forwarded-for")
+            .or_else(|| forwarded_value(headers, "x-real-ip"))
+            .and_then(|ip| ip.parse().ok())
+            .or(peer)
+    }
+
+    /// The X-Forwarded-Host of a trusted proxy, when `trust_forwarded_host`
+    /// says to route by it
+    fn forwarded_host(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<HeaderValue> {
+        if !self.trust_forwarded_host || !self.is_trusted_proxy(peer) {
+            return None;
+        }
+        let host = forwarded_value(headers, "x-forwarded-host").filter(|host| !host.is_empty())?;
+        HeaderValue::from_str(host).ok()
+    }
+}
+
+/// The first value of a forwarding header. Proxies in a chain each append
+/// their own; the first is the client's.
+fn forwarded_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok()).and_then(|v| v.split(',').next()).map(str::trim)
+}
+
+/// SERVER_NAME and HTTP_HOST for PHP and FastCGI scripts: the request's
+/// Host, unless the vhost sets `php_server_name` or `php_http_host`
+#[derive(Clone)]
+struct ScriptHost {
+    server_name: Option<String>,
+    http_host: Option<String>,
+}
+
+impl ScriptHost {
+    fn of(vhost: Option<&VirtualHost>, headers: &HeaderMap) -> Self {
+        let http_host = vhost
+            .and_then(|v| v.php_http_host.clone())
+            .or_else(|| headers.get(header::HOST).and_then(|v| v.to_str().ok()).map(str::to_string));
+        let server_name = vhost
+            .and_then(|v| v.php_server_name.clone())
+            .or_else(|| http_host.as_deref().map(|host| apache::split_host_port(host).0).filter(|name| !name.is_empty()));
+        ScriptHost { server_name, http_host }
+    }
+}
+
+fn is_common_connection_error(err: &dyn std::error::Error) -> bool {
+    let s = format!("{:?}", err);
+    s.contains("BrokenPipe") || 
+    s.contains("ConnectionReset") || 
+    s.contains("UnexpectedEof") ||
+    s.contains("ConnectionAborted") ||
+    s.contains("NotConnected") ||
+    s.contains("TimedOut") ||
+    s.contains("HeaderTimeout") ||
+    s.contains("IncompleteMessage")
+}
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Every option with its defaults and comments; written by --generate-config
+/// and when wolfserve.toml is missing
+pub const ANNOTATED_CONFIG: &str = include_str!("../wolfserve.toml.example");
+
+/// Write the annotated config to `path`, refusing to replace an existing
+/// file unless `force`
+pub fn generate_config(path: &Path, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!("{} already exists; add --force to overwrite it", path.display()));
+    }
+    std::fs::write(path, ANNOTATED_CONFIG).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
+
+/// The runtime main() runs the server on, sized by `[server] worker_threads`
+/// and `max_blocking_threads` in `config` (the text of wolfserve.toml).
+/// Without the file, or if it doesn't parse, tokio's defaults apply; run()
+/// reports the problem once the runtime is up.
+pub fn build_runtime(config: Option<&str>) -> std::io::Result<tokio::runtime::Runtime> {
+    let server = config.and_then(|text| toml::from_str::<Config>(text).ok()).map(|config| config.server);
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(threads) = server.as_ref().and_then(|server| server.worker_threads) {
+        builder.worker_threads(threads.get());
+    }
+    if let Some(threads) = server.as_ref().and_then(|server| server.max_blocking_threads) {
+        builder.max_blocking_threads(threads.get());
+    }
+    builder.build()
+}
+
+/// Run the server: the `wolfserve` binary's whole job
+pub async fn run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // --check-config validates everything and exits, like `apachectl configtest`
+    let check_only = args.iter().any(|a| a == "--check-config");
+    let check_fpm = args.iter().any(|a| a == "--check-fpm");
+    // --dump-vhosts prints the resolved vhost model as JSON and exits
+    let dump_vhosts = args.iter().any(|a| a == "--dump-vhosts");
+
+    // `wolfserve admin ...` manages the dashboard users and exits
+    if args.first().is_some_and(|a| a == "admin") {
+        std::process::exit(admin::run_cli(&args[1..]));
+    }
+
+    // --generate-config <path> writes the annotated config and exits
+    if let Some(i) = args.iter().position(|a| a == "--generate-config") {
+        let Some(path) = args.get(i + 1).filter(|path| !path.starts_with("--")) else {
+            eprintln!("usage: wolfserve --generate-config <path> [--force]");
+            std::process::exit(2);
+        };
+        match generate_config(Path::new(path), args.iter().any(|a| a == "--force")) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // The banner is for interactive runs; journald and log files get only the log lines
+    let interactive = std::io::stdout().is_terminal();
+    let show_banner = interactive && !args.iter().any(|a| a == "--no-banner");
+
+    if show_banner && !check_only && !dump_vhosts {
+        println!(r#"
+ __          ______  _      ______  _____  ______  _____ __      __ ______ 
+ \ \        / / __ \| |    |  ____|/ ____||  ____||  __ \\ \    / /|  ____|
+  \ \  /\  / / |  | | |    | |__  | (___  | |__   | |__) |\ \  / / | |__   
+   \ \/  \/ /| |  | | |    |  __|  \___ \ |  __|  |  _  /  \ \/ /  |  __|  
+    \  /\  / | |__| | |____| |     ____) || |____ | | \ \   \  /   | |____ 
+     \/  \/   \____/|______|_|    |_____/ |______||_|  \_\   \/    |______|
+                                                                          v{}                                                    
+ (C)2025 Wolf Software Systems Ltd - http://wolf.uk.com
+"#, VERSION);
+    }
+
+    // Startup output is logged at info; RUST_LOG overrides. The debug tap
+    // only logs what an admin asked for, so it is always let through.
+    let filter = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into()).from_env_lossy();
+    let filter = filter.add_directive(format!("{}=debug", tap::TARGET).parse().unwrap());
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(interactive)
+        .init();
+
+    // Load configuration
+    let config_str = match fs::read_to_string("wolfserve.toml").await {
+        Ok(s) => s,
+        Err(e) if check_only => {
+            println!("[FAIL] config: cannot read wolfserve.toml: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) if dump_vhosts => {
+            eprintln!("cannot read wolfserve.toml: {}", e);
+            std::process::exit(1);
+        }
+        Err(_) => {
+            eprintln!("Configuration file 'wolfserve.toml' not found. Creating default.");
+            fs::write("wolfserve.toml", ANNOTATED_CONFIG).await.unwrap();
+            ANNOTATED_CONFIG.to_string()
+        }
+    };
+
+    let config: Config = match toml::from_str(&config_str) {
+        Ok(config) => config,
+        Err(e) if check_only => {
+            println!("[FAIL] config: wolfserve.toml: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) if dump_vhosts => {
+            eprintln!("wolfserve.toml: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => panic!("Failed to parse wolfserve.toml: {}", e),
+    };
+    
+    let mut vhosts_without_cert: Vec<String> = Vec::new();
+
+    if dump_vhosts {
+        let mut loaded = apache::load_apache_config(Path::new(&config.apache.config_dir), config.apache.include_extensionless, false);
+        for vhost in &mut loaded.vhosts {
+            config.apply_vhost_overrides(vhost);
+        }
+        let plan = listeners::plan(&config, &loaded.listen, &loaded.vhosts);
+        let sites = Sites::build(loaded.vhosts.into_iter().map(Arc::new).collect(), Arc::new(loaded.server));
+        println!("{}", serde_json::to_string_pretty(&dump::vhosts_json(&config, &sites, &plan.listeners)).unwrap());
+        return;
+    }
+
+    let mut loaded = apache::load_apache_config(Path::new(&config.apache.config_dir), config.apache.include_extensionless, true);
+    for vhost in &mut loaded.vhosts {
+        config.apply_vhost_overrides(vhost);
+    }
+
+    let report = check::validate(&config, &loaded.listen, &loaded.vhosts, check_fpm).await;
+    if check_only {
+        report.print();
+        std::process::exit(if report.has_errors() { 1 } else { 0 });
+    }
+    report.print_problems();
+
+    let connection_settings = Arc::new(connection::ConnectionSettings::resolve(
+        &config.server,
+        &loaded.connection,
+    ));
+    tracing::info!(settings = %connection_settings, "connections");
+
+    let server_tokens = ServerTokens::resolve(&config, &loaded);
+
+    // What each port serves; a port cannot be both plain HTTP and TLS
+    let plan = listeners::plan(&config, &loaded.listen, &loaded.vhosts);
+    if !plan.errors.is_empty() {
+        for error in &plan.errors {
+            eprintln!("ERROR: {}", error);
+        }
+        eprintln!("ERROR: cannot set up the listeners; not starting");
+        std::process::exit(1);
+    }
+    let http_ports = plan.ports(listeners::Protocol::Http);
+    let https_ports = plan.ports(listeners::Protocol::Https);
+
+    if config.admin.enabled {
+        if let Some(refusal) = config.admin.refusal() {
+            eprintln!("ERROR: {}", refusal);
+            eprintln!("ERROR: not starting");
+            std::process::exit(1);
+        }
+    }
+
+    let mut vhosts = Vec::new();
+    for vhost in loaded.vhosts {
+        // SSLEngine on without its own certificate uses the default one
+        let served_over_tls = plan.get(vhost.port).and_then(|l| l.protocol) == Some(listeners::Protocol::Https);
+        if vhost.is_ssl() && served_over_tls && (vhost.ssl_cert_file.is_none() || vhost.ssl_key_file.is_none()) {
+            vhosts_without_cert.push(vhost.server_name.clone().unwrap_or_else(|| "default vhost".to_string()));
+        }
+        vhosts.push(Arc::new(vhost));
+    }
+
+    let sites = Sites::build(vhosts, Arc::new(loaded.server));
+    sites.log();
+
+    // Certificates by SNI name; swapped in place when they are reloaded
+    let cert_resolver = Arc::new(certs::CertResolver::default());
+    let cert_report = cert_resolver.load(&config, &sites.list);
+    for shadowed in &cert_report.shadowed {
+        eprintln!("Warning: certificate {}", shadowed);
+    }
+    for error in &cert_report.errors {
+        eprintln!("Failed to load TLS certificate {}", error);
+    }
+    for loaded in cert_resolver.keys() {
+        tracing::info!(
+            certificate = %loaded.label,
+            names = ?loaded.names,
+            not_after = %loaded.not_after.map_or("unknown".to_string(), |t| t.to_rfc3339()),
+            "loaded certificate"
+        );
+    }
+
+    if !vhosts_without_cert.is_empty() && !cert_resolver.has_default() {
+        eprintln!(
+            "ERROR: SSLEngine is on for {} but no certificate is configured for them and there is no default certificate; set SSLCertificateFile/SSLCertificateKeyFile or [tls] certificate_file/certificate_key_file",
+            vhosts_without_cert.join(", ")
+        );
+    }
+
+    let state = Arc::new(AppState::new(
+        config.clone(),
+        sites,
+        plan.listeners,
+        connection_settings.clone(),
+        cert_resolver.clone(),
+        server_tokens,
+    ));
+    let admin_state = state.admin_state.clone();
+    if config.admin.enabled {
+        let weak_state = Arc::downgrade(&state);
+        *admin_state.vhost_dump.write() = Some(Box::new(move || match weak_state.upgrade() {
+            Some(state) => dump::vhosts_json(&state.config, &state.sites.read(), &state.listeners),
+            None => serde_json::Value::Null,
+        }));
+        let weak_state = Arc::downgrade(&state);
+        *admin_state.config_export.write() = Some(Box::new(move || match weak_state.upgrade() {
+            Some(state) => snapshot::export(&state.config, &state.sites.read()),
+            None => serde_json::Value::Null,
+        }));
+        let weak_state = Arc::downgrade(&state);
+        *admin_state.config_import.write() = Some(Box::new(move |document| match weak_state.upgrade() {
+            Some(state) => snapshot::preview(document, &state.config, &state.sites.read()),
+            None => Err("Server is shutting down".to_string()),
+        }));
+        let weak_state = Arc::downgrade(&state);
+        *admin_state.config_reload.write() = Some(Box::new(move |trigger| {
+            let weak_state = weak_state.clone();
+            Box::pin(async move { reload::reload_now(&*weak_state.upgrade()?, &trigger).await }) as BoxFuture<'static, _>
+        }));
+        let server = config.server.clone();
+        *admin_state.client_addr.write() = Some(Box::new(move |headers, peer| server.client_addr(headers, peer)));
+        let weak_state = Arc::downgrade(&state);
+        *admin_state.cert_reload.write() = Some(Box::new(move || match weak_state.upgrade() {
+            Some(state) => certs::reload(&state, "admin request").to_json(),
+            None => serde_json::Value::Null,
+        }));
+        admin::init_credentials();
+        admin_state.load_sessions(Path::new(admin::SESSIONS_FILE));
+        if let Some(path) = &config.admin.log_file {
+            *admin_state.log_ring.write() = Some(log_ring::LogRing::spawn(path.clone(), config.admin.log_file_max_bytes));
+        }
+        stats_file::load(&admin_state, &config.admin.stats_file);
+        stats_file::spawn(state.clone());
+        fpm_health::spawn(state.clone());
+    }
+    reload::set_known_errors(&state, &report);
+    if config.apache.watch {
+        reload::spawn(state.clone());
+    }
+    if !https_ports.is_empty() {
+        certs::spawn(state.clone());
+        certs::spawn_expiry_check(state.clone());
+        if config.tls.ocsp_stapling {
+            ocsp::spawn(state.clone());
+        }
+    }
+    if config.vhosts.values().any(|overrides| overrides.acme) {
+        acme::spawn(state.clone());
+    }
+
+    // Settings of each HTTPS listener; vhosts that disagree on client
+    // certificates cannot share one, and bad [tls] versions stop startup
+    let mut listener_configs = HashMap::new();
+    for &port in &https_ports {
+        match tls_config_for_port(&state, port) {
+            Ok(listener_config) => {
+                listener_configs.insert(port, listener_config);
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut tasks = Vec::new();
+    let host_ip = config.server.host.clone();
+
+    // Start Admin Dashboard on [admin] host:port (127.0.0.1:5000 by default),
+    // checked above
+    if let (true, Ok(admin_addr)) = (config.admin.enabled, config.admin.addr()) {
+        let admin_app = admin_router(admin_state.clone())
+            .layer(middleware::from_fn_with_state(state.clone(), server_header));
+        let default_login = admin::has_default_credentials();
+        tasks.push(tokio::spawn(async move {
+            let url = format!("http://{}/", admin_addr);
+            if default_login {
+                tracing::info!(%url, "admin dashboard listening (login: admin/admin, change it)");
+            } else {
+                tracing::info!(%url, "admin dashboard listening");
+            }
+            let listener = tokio::net::TcpListener::bind(&admin_addr).await.unwrap();
+            // The peer address, or the client a trusted proxy forwards,
+            // keys the sign-in lockout
+            axum::serve(listener, admin_app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+        }));
+    } else {
+        tracing::info!("admin dashboard disabled");
+    }
+
+    // Start HTTP Listeners
+    for port in http_ports {
+        let addr: SocketAddr = format!("{}:{}", host_ip, port).parse().unwrap();
+        let state = state.clone();
+        tasks.push(tokio::spawn(async move {
+            tracing::info!(%addr, port, "HTTP listening");
+            let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+            serve_http(state, listener, port).await;
+        }));
+    }
+
+    // Start HTTPS Listeners
+    // ACME vhosts may have no certificate until their first order completes
+    let acme_pending = state.sites.read().list.iter().any(|vhost| vhost.acme);
+    if !https_ports.is_empty() && (!cert_resolver.is_empty() || acme_pending) {
+        for port in https_ports {
+            let addr: SocketAddr = format!("{}:{}", host_ip, port).parse().unwrap();
+            let state = state.clone();
+            let Some(listener_config) = listener_configs.remove(&port) else {
+                continue;
+            };
+            tasks.push(tokio::spawn(async move {
+                tracing::info!(%addr, port, "HTTPS listening");
+                let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+                serve_https(state, listener, port, listener_config).await;
+            }));
+        }
+    } else if !https_ports.is_empty() {
+        eprintln!("ERROR: no usable TLS certificate; not listening on HTTPS ports {:?}", https_ports);
+    }
+
+    join_all(tasks).await;
+}
+
+/// Accept plain HTTP connections for `port`
+pub async fn serve_http(state: Arc<AppState>, listener: tokio::net::TcpListener, port: u16) {
+    let app = site_router(&state, port, false);
+    let mode = connection::HttpMode::new(&state.http_versions(port), None);
+    loop {
+        let stream = connection::accept(&listener, port).await;
+        let peer = stream.peer_addr().ok();
+        let app = app.clone();
+        let settings = state.connection.clone();
+        tokio::spawn(async move {
+            if !connection::wait_for_request(&stream, &settings).await {
+                return;
+            }
+            if mode == connection::HttpMode::Http2 && !connection::sends_h2_preface(&stream, &settings).await {
+                connection::refuse_http1(stream, port).await;
+                return;
+            }
+            connection::serve(stream, peer, app, &settings, None, mode).await;
+        });
+    }
+}
+
+/// Accept TLS connections for `port`
+pub async fn serve_https(state: Arc<AppState>, listener: tokio::net::TcpListener, port: u16, tls: tls::ListenerConfig) {
+    let app = site_router(&state, port, true);
+    let acceptor = TlsAcceptor::from(tls.server);
+    let handshake_timeout = Duration::from_secs(state.config.tls.handshake_timeout);
+    loop {
+        let stream = connection::accept(&listener, port).await;
+        let peer = stream.peer_addr().ok();
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let settings = state.connection.clone();
+        let client_auth = tls.client_auth;
+        let http_versions = tls.http_versions.clone();
+        let admin_state = state.admin_state.clone();
+
+        tokio::spawn(async move {
+            // The deadline covers the wait for the ClientHello too, so a
+            // client that connects and goes silent is dropped with it
+            let handshake = async {
+                if !connection::wait_for_request(&stream, &settings).await {
+                    return None;
+                }
+                Some(acceptor.accept(stream).await)
+            };
+            match timeout(handshake_timeout, handshake).await {
+                Ok(Some(Ok(tls_stream))) => {
+                    let conn = tls_stream.get_ref().1;
+                    let session = tls::Session::new(conn.protocol_version(), None, conn.server_name());
+                    let cipher = conn.negotiated_cipher_suite().map_or("unknown".to_string(), |suite| format!("{:?}", suite.suite()));
+                    admin_state.stats.record_tls_handshake(session.protocol, &cipher);
+                    // A TLS-ALPN-01 validation is over once the handshake is done
+                    if conn.alpn_protocol() == Some(tls::ACME_TLS_ALPN) {
+                        return;
+                    }
+                    let mode = connection::HttpMode::new(&http_versions, conn.alpn_protocol());
+                    // A client without ALPN speaks HTTP/1 unless it says otherwise
+                    if mode == connection::HttpMode::Http2 && conn.alpn_protocol().is_none() {
+                        connection::refuse_http1(tls_stream, port).await;
+                        return;
+                    }
+                    let client = client_auth.then(|| tls::ClientVerify::from_peer(conn.peer_certificates()));
+                    let session = tls::Session { client, ..session };
+                    connection::serve(tls_stream, peer, app, &settings, Some(session), mode).await
+                }
+                Ok(Some(Err(e))) => {
+                    admin_state.stats.record_tls_handshake_failure();
+                    let no_common_alpn = e
+                        .get_ref()
+                        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+                        .is_some_and(|inner| *inner == rustls::Error::NoApplicationProtocol);
+                    if no_common_alpn {
+                        eprintln!(
+                            "TLS Accept Error: port {}: the client offered none of the listener's HTTP versions ({}); see [ports] http_versions",
+                            port,
+                            http_versions.iter().map(|v| String::from_utf8_lossy(v.alpn())).collect::<Vec<_>>().join(", ")
+                        );
+                    } else if !is_common_connection_error(&e) {
+                        eprintln!("TLS Accept Error: {}", e);
+                    }
+                }
+                Ok(None) => {} // closed, or silent past Timeout
+                Err(_) => admin_state.stats.record_tls_handshake_failure(),
+            }
+        });
+    }
+}
+
+/// TLS settings of the HTTPS listener on `port`, from its vhosts
+pub fn tls_config_for_port(state: &AppState, port: u16) -> Result<tls::ListenerConfig, String> {
+    let sites = state.sites.read().clone();
+    let vhosts: Vec<&VirtualHost> = sites.list.iter().filter(|v| v.port == port && v.is_ssl()).map(|v| v.as_ref()).collect();
+    let tls_config = &state.config.tls;
+    let bounds = tls::version_bounds(tls_config.min_version.as_deref(), tls_config.max_version.as_deref())?;
+    let key_log = state.key_log.clone().map(|key_log| key_log as Arc<dyn rustls::KeyLog>);
+    tls::listener_config(port, &vhosts, state.certs.clone(), &bounds, key_log, &state.http_versions(port))
+}
+
+/// Router serving the sites, shared by every listener
+fn site_app(state: &Arc<AppState>) -> Router {
+    let compress = &state.config.compression;
+    Router::new()
+        .fallback(any(handle_request))
+        .layer(CompressionLayer::new().compress_when(
+            SizeAbove::new(compress.min_size)
+                .and(compression::CompressibleTypes::new(compress.enabled, &compress.types)),
+        ))
+        .layer(middleware::from_fn(encoding_aware_etag))
+        .layer(middleware::from_fn_with_state(state.clone(), access_log))
+        .layer(middleware::from_fn(authority_as_host))
+        .layer(middleware::from_fn_with_state(state.clone(), server_header))
+        .with_state(state.clone())
+}
+
+/// Router for one listener, as `run` serves it on `port`
+pub fn site_router(state: &Arc<AppState>, port: u16, tls: bool) -> Router {
+    site_app(state).layer(Extension(Listener { port, tls }))
+}
+
+/// Load the Apache sites and certificates `config` points at into a ready
+/// state, without checks, listeners or background tasks
+pub fn load_state(config: Config) -> Arc<AppState> {
+    let mut loaded = apache::load_apache_config(Path::new(&config.apache.config_dir), config.apache.include_extensionless, false);
+    for vhost in &mut loaded.vhosts {
+        config.apply_vhost_overrides(vhost);
+    }
+    let connection_settings = Arc::new(connection::ConnectionSettings::resolve(&config.server, &loaded.connection));
+    let server_tokens = ServerTokens::resolve(&config, &loaded);
+    let plan = listeners::plan(&config, &loaded.listen, &loaded.vhosts);
+    let sites = Sites::build(loaded.vhosts.into_iter().map(Arc::new).collect(), Arc::new(loaded.server));
+    let certs = Arc::new(certs::CertResolver::default());
+    for error in certs.load(&config, &sites.list).errors {
+        eprintln!("Failed to load TLS certificate {}", error);
+    }
+    Arc::new(AppState::new(config, sites, plan.listeners, connection_settings, certs, server_tokens))
+}
+
+async fn handle_request(State(state): State<Arc<AppState>>, Extension(listener): Extension<Listener>, mut headers: HeaderMap, mut req: Request) -> Response {
+    let tapped = tap_request(&state, &headers, &mut req);
+    let misdirected = req.extensions().get::<tls::Session>().and_then(|session| sni_mismatch(&state, listener, &headers, session));
+    if let Some((message, vhost)) = &misdirected {
+        if state.config.tls.sni_mismatch == SniMismatch::Reject {
+            let mut response = error_page(StatusCode::MISDIRECTED_REQUEST, "Misdirected Request");
+            response.extensions_mut().insert(RequestError(format!("{}; 421 Misdirected Request", message)));
+            response = render_error_page(response, vhost.server_admin.as_deref());
+            response.extensions_mut().insert(ServedBy(vhost.clone()));
+            return response;
+        }
+    }
+
+    let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+    let origin = Origin::of(&state.config.server, listener, &headers, peer);
+    req.extensions_mut().insert(origin);
+    // The client's Host, from behind a proxy that forwards to an internal name
+    if let Some(host) = state.config.server.forwarded_host(&headers, peer) {
+        headers.insert(header::HOST, host.clone());
+        req.headers_mut().insert(header::HOST, host);
+    }
+
+    let mut served_by = None;
+    let mut response = route_request(&state, listener, origin, &headers, req, &mut served_by).await;
+    if response.extensions().get::<BackendError>().is_some() && !state.config.php.error_detail {
+        let summary = backend_error_summary(response.status());
+        response.extensions_mut().insert(ErrorPage(summary.to_string()));
+    }
+    if response.extensions().get::<ErrorPage>().is_some() {
+        // Requests no vhost takes fall to the main server's ServerAdmin
+        let server_admin = match &served_by {
+            Some(vhost) => vhost.server_admin.clone(),
+            None => state.sites.read().server.as_ref().and_then(|server| server.server_admin.clone()),
+        };
+        let status = response.status();
+        response = if shed::is_shed_status(status) {
+            shed::render(response, state.config.shed.get(status.as_str()), &headers, server_admin.as_deref()).await
+        } else {
+            render_error_page(response, server_admin.as_deref())
+        };
+    }
+    if let Some(vhost) = &served_by {
+        cross_origin_headers(vhost, &mut response);
+    }
+    if let Some(uri) = tapped {
+        tracing::debug!(
+            target: tap::TARGET,
+            %uri,
+            status = response.status().as_u16(),
+            vhost = served_by.as_deref().map_or("(none)".to_string(), listeners::vhost_name),
+            document_root = ?served_by.as_ref().and_then(|vhost| vhost.document_root.as_ref()),
+            headers = ?tap::headers(response.headers()),
+            "tapped response"
+        );
+    }
+    if let Some(vhost) = served_by {
+        response.extensions_mut().insert(ServedBy(vhost));
+    }
+    if let Some((message, _)) = misdirected {
+        if response.extensions().get::<RequestError>().is_none() {
+            response.extensions_mut().insert(RequestError(format!("Warning: {}; served anyway", message)));
+        }
+    }
+    response
+}
+
+/// Log a request the debug tap matches and mark it for the PHP handlers;
+/// its URI, to tie the response to it
+fn tap_request(state: &AppState, headers: &HeaderMap, req: &mut Request) -> Option<String> {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok()).map(|h| apache::split_host_port(h).0).unwrap_or_default();
+    if !state.admin_state.debug_tap.read().as_ref().is_some_and(|tap| tap.matches(&host, req.uri().path())) {
+        return None;
+    }
+    let uri = req.uri().to_string();
+    tracing::debug!(
+        target: tap::TARGET,
+        method = %req.method(),
+        %uri,
+        version = ?req.version(),
+        client_ip = %client_ip(&state.config.server, req),
+        headers = ?tap::headers(headers),
+        "tapped request"
+    );
+    req.extensions_mut().insert(tap::Tapped);
+    Some(uri)
+}
+
+/// The vhost's Cross-Origin-Opener/Embedder-Policy, unless PHP set its own
+fn cross_origin_headers(vhost: &VirtualHost, response: &mut Response) {
+    let policies = [
+        (HeaderName::from_static("cross-origin-opener-policy"), &vhost.cross_origin_opener_policy),
+        (HeaderName::from_static("cross-origin-embedder-policy"), &vhost.cross_origin_embedder_policy),
+    ];
+    for (name, policy) in policies {
+        if let Some(value) = policy.as_deref().and_then(|p| HeaderValue::from_str(p).ok()) {
+            response.headers_mut().entry(name).or_insert(value);
+        }
+    }
+}
+
+/// When the Host of a request on a TLS connection picks another vhost than
+/// the connection's SNI name: the message for the ErrorLog and the Host's
+/// vhost. Names of the same vhost (aliases, wildcards) are no mismatch.
+fn sni_mismatch(state: &AppState, listener: Listener, headers: &HeaderMap, session: &tls::Session) -> Option<(String, Arc<VirtualHost>)> {
+    let sni = session.sni.as_deref()?;
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(|h| apache::split_host_port(h).0)
+        .filter(|h| !h.is_empty())?;
+    let sites = state.sites.read().clone();
+    let host_vhost = sites.find_vhost(listener.port, Some(&host))?;
+    let sni_vhost = sites.find_vhost(listener.port, Some(sni))?;
+    if Arc::ptr_eq(host_vhost, sni_vhost) {
+        return None;
+    }
+    let message = format!("Host {} does not belong to the vhost of SNI name {}", host, sni);
+    Some((message, host_vhost.clone()))
+}
+
+/// The vhost that answered a request, carried on the response for its logs
+#[derive(Clone)]
+struct ServedBy(Arc<VirtualHost>);
+
+async fn route_request(state: &Arc<AppState>, listener: Listener, origin: Origin, headers: &HeaderMap, mut req: Request, served_by: &mut Option<Arc<VirtualHost>>) -> Response {
+    let uri_path = req.uri().path().to_string();
+    let query_string = req.uri().query().unwrap_or("").to_string();
+    let method = req.method().to_string();
+    let client = request_client(&state.config.server, headers, req.extensions());
+
+    // hyper answers `Expect: 100-continue` with 100 Continue once the body is
+    // read, and a request refused before that never gets one; no other
+    // expectation is supported
+    if let Some(expect) = headers.get(header::EXPECT).filter(|v| !v.as_bytes().eq_ignore_ascii_case(b"100-continue")) {
+        let mut response = error_page(StatusCode::EXPECTATION_FAILED, "Expectation Failed");
+        response
+            .extensions_mut()
+            .insert(RequestError(format!("Unsupported Expect: {}", String::from_utf8_lossy(expect.as_bytes()))));
+        return response;
+    }
+    
+    // Safety: prevent traversing up
+    let clean_path = uri_path.trim_start_matches('/');
+    let decoded_path = apache::decode_url_path(&uri_path);
+    if clean_path.contains("..") || decoded_path.contains("..") {
+        return error_page(StatusCode::FORBIDDEN, "Forbidden");
+    }
+    if state.config.server.deny_dotfiles && is_hidden_path(&decoded_path) {
+        return error_page(StatusCode::FORBIDDEN, "Forbidden");
+    }
+
+    // Challenges of our own ACME orders, on every host and port
+    if let Some(key_authorization) = uri_path
+        .strip_prefix("/.well-known/acme-challenge/")
+        .and_then(|token| state.acme_challenges.get(token))
+    {
+        return ([(header::CONTENT_TYPE, "application/octet-stream")], key_authorization).into_response();
+    }
+
+    // Determine Document Root and VHost based on Host header and listening port
+    let host_name = headers.get("host")
+        .and_then(|v| v.to_str().ok())
+        // Remove port if present; names compare case-insensitively
+        .map(|h| apache::split_host_port(h).0)
+        .unwrap_or_default();
+    let sites = state.sites.read().clone();
+    let current_vhost = sites.find_vhost(listener.port, Some(host_name.as_str()).filter(|h| !h.is_empty()));
+    let doc_root = current_vhost
+        .and_then(|vhost| vhost.document_root.clone())
+        .unwrap_or_else(|| PathBuf::from("public"));
+
+    *served_by = current_vhost.cloned();
+
+    // Plain HTTP for a name with a TLS vhost goes to that vhost's port, even
+    // when no HTTP vhost exists for the name. ACME challenges stay on HTTP.
+    if !origin.https && !host_name.is_empty() && !uri_path.starts_with("/.well-known/acme-challenge/") {
+        if let Some(tls_vhost) = sites
+            .find_tls_vhost(&host_name)
+            .filter(|v| v.redirect_to_https.unwrap_or(state.config.server.redirect_to_https))
+        {
+            let port = if tls_vhost.port == 443 { String::new() } else { format!(":{}", tls_vhost.port) };
+            let query = if query_string.is_empty() { String::new() } else { format!("?{}", query_string) };
+            *served_by = Some(tls_vhost.clone());
+            return handle_redirect(301, Some(format!("https://{}{}{}{}", host_name, port, uri_path, query)));
+        }
+    }
+
+    // Send aliases and bare-IP requests to the canonical host, keeping scheme,
+    // port, path and query. Vhosts without a ServerName never redirect.
+    let canonical_host = current_vhost
+        .filter(|v| v.server_name.is_some())
+        .and_then(|v| v.canonical_host.as_deref());
+    if let Some(canonical) = canonical_host {
+        if !host_name.is_empty() && !host_name.eq_ignore_ascii_case(canonical) && !state.config.canonical_exempt(&uri_path) {
+            let port = headers.get("host")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|h| apache::split_host_port(h).1)
+                .map(|p| format!(":{}", p))
+                .unwrap_or_default();
+            let query = if query_string.is_empty() { String::new() } else { format!("?{}", query_string) };
+            let target = format!("{}://{}{}{}{}", origin.scheme(), canonical, port, uri_path, query);
+            return handle_redirect(301, Some(target));
+        }
+    }
+
+    // ACME challenges and security.txt, from a shared directory if configured
+    if let (Some(dir), Some(rest)) = (&state.config.server.well_known_dir, decoded_path.strip_prefix("/.well-known/")) {
+        let file = dir.join(rest);
+        if !fs::metadata(&file).await.is_ok_and(|meta| meta.is_file()) {
+            return error_page(StatusCode::NOT_FOUND, "Not Found");
+        }
+        if state.config.is_denied_extension(current_vhost.map(|v| v.as_ref()), &file) {
+            return error_page(StatusCode::FORBIDDEN, "Forbidden");
+        }
+        return serve_static_file(file, accepts_precompressed(state, headers), RangeRequest::of(req.method(), headers)).await;
+    }
+
+    // Maintenance mode short-circuits everything except allowlisted clients
+    let site = current_vhost.and_then(|v| v.server_name.as_deref());
+    if state.admin_state.in_maintenance(site) && !state.config.maintenance.allows(client) {
+        return maintenance_response(&state.config.maintenance).await;
+    }
+
+    // Check for redirects from vhost config first
+    if let Some(vhost) = current_vhost {
+        for redirect in &vhost.redirects {
+            if let Some((status_code, target)) = redirect.matches(&uri_path, &query_string) {
+                return handle_redirect(status_code, target);
+            }
+        }
+    }
+
+    // <Location> blocks match the decoded URL, before it is mapped to a file
+    let location = current_vhost.map(|vhost| vhost.location_settings(&decoded_path)).unwrap_or_default();
+    if location.require_all == Some(false) {
+        return error_page(StatusCode::FORBIDDEN, "Forbidden");
+    }
+    if let Some(redirect) = &location.redirect {
+        if let Some((status_code, target)) = redirect.matches(&redirect.from, &query_string) {
+            return handle_redirect(status_code, target);
+        }
+    }
+
+    // Alias mappings point outside the document root, so the document
+    // root's .htaccess does not apply to them
+    let alias_path = current_vhost.and_then(|vhost| vhost.resolve_alias(&uri_path));
+
+    // Check for .htaccess in document root
+    let htaccess_path = doc_root.join(".htaccess");
+    let mut rewritten_path = uri_path.clone();
+    
+    if alias_path.is_none() {
+        if let Some(htaccess) = read_htaccess(&htaccess_path).await {
+            // Check .htaccess redirects
+            for redirect in &htaccess.redirects {
+                if let Some((status_code, target)) = redirect.matches(&uri_path, &query_string) {
+                    return handle_redirect(status_code, target);
+                }
+            }
+            
+            // Check rewrite rules
+            let request_filename = doc_root.join(clean_path);
+            let ctx = RewriteContext {
+                request_uri: &uri_path,
+                request_filename: &request_filename,
+                query_string: &query_string,
+                http_host: &host_name,
+                request_method: &method,
+                https: origin.https,
+                document_root: &doc_root,
+            };
+            
+            if let Some(result) = htaccess.apply_rewrites(&ctx) {
+                match result {
+                    RewriteResult::Redirect { url, status } => {
+                        return handle_redirect(status, Some(url));
+                    }
+                    RewriteResult::InternalRewrite { path, query } => {
+                        rewritten_path = path;
+                        if query != query_string {
+                            // PHP sees the rewritten query string
+                            let uri = if query.is_empty() { uri_path.clone() } else { format!("{}?{}", uri_path, query) };
+                            if let Ok(uri) = uri.parse() {
+                                *req.uri_mut() = uri;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Use the rewritten path
+    if state.config.server.deny_dotfiles && is_hidden_path(&rewritten_path) {
+        return error_page(StatusCode::FORBIDDEN, "Forbidden");
+    }
+    let clean_rewritten = rewritten_path.trim_start_matches('/');
+    // Symlinks are checked from the directory the URL was mapped into
+    let file_root = alias_path
+        .as_ref()
+        .and_then(|alias_path| current_vhost?.aliases.iter().map(|a| a.path.clone()).find(|root| alias_path.starts_with(root)))
+        .unwrap_or_else(|| doc_root.clone());
+    let mut path = alias_path.unwrap_or_else(|| doc_root.join(clean_rewritten));
+
+    // Only URLs that map straight to the filesystem get canonicalized, so
+    // internal rewrites can never produce a redirect loop
+    let not_rewritten = rewritten_path == uri_path;
+    let query_suffix = if query_string.is_empty() { String::new() } else { format!("?{}", query_string) };
+
+    // Resolve directory index
+    if fs::metadata(&path).await.is_ok_and(|meta| meta.is_dir()) {
+        if not_rewritten && !uri_path.ends_with('/') {
+            // Relative links inside the directory need the trailing slash
+            return handle_redirect(301, Some(format!("{}/{}", uri_path, query_suffix)));
+        }
+        match directory_index(&path).await {
+            Some(index) => path = index,
+            None => return error_page(StatusCode::FORBIDDEN, "Directory listing denied"),
+        }
+    } else if not_rewritten
+        && (method == "GET" || method == "HEAD")
+        && current_vhost.and_then(|v| v.strip_index).unwrap_or(state.config.server.strip_index)
+    {
+        // Only the file the directory would serve anyway is stripped
+        let index = match path.parent() {
+            Some(dir) => directory_index(dir).await,
+            None => None,
+        };
+        if let Some(dir_url) = uri_path.rfind('/').map(|slash| &uri_path[..=slash]).filter(|_| index.as_deref() == Some(path.as_path())) {
+            return handle_redirect(301, Some(format!("{}{}", dir_url, query_suffix)));
+        }
+    }
+
+    // If file doesn't exist after rewrite, still try to serve (WordPress may handle it)
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        // For WordPress: if we have a rewrite to index.php, use that
+        let index_php = doc_root.join("index.php");
+        if rewritten_path != uri_path && fs::try_exists(&index_php).await.unwrap_or(false) {
+            // This was an internal rewrite - WordPress will handle routing
+            if let Some(response) = symlink_refusal(current_vhost, &doc_root, &index_php).await {
+                return response;
+            }
+            if let Some(limit) = body_limit(state, current_vhost, &doc_root, &index_php, &decoded_path).await {
+                req.extensions_mut().insert(limit);
+            }
+            return handle_php(state.clone(), current_vhost.map(|v| v.as_ref()), &doc_root, req, index_php).await;
+        }
+        // FallbackResource answers for URLs that match no file
+        if let Some(resource) = fallback_resource(current_vhost, &doc_root, &path).await {
+            let fallback = doc_root.join(resource.trim_start_matches('/'));
+            if fs::try_exists(&fallback).await.unwrap_or(false) {
+                if let Some(response) = symlink_refusal(current_vhost, &doc_root, &fallback).await {
+                    return response;
+                }
+                let is_php = fallback.extension().is_some_and(|ext| state.config.is_php_extension(current_vhost.map(|v| v.as_ref()), ext));
+                if is_php {
+                    if let Some(limit) = body_limit(state, current_vhost, &doc_root, &fallback, &resource).await {
+                        req.extensions_mut().insert(limit);
+                    }
+                    // REQUEST_URI stays the URL asked for, so the script can route on it
+                    req.extensions_mut().insert(ScriptName(resource));
+                    return handle_php(state.clone(), current_vhost.map(|v| v.as_ref()), &doc_root, req, fallback).await;
+                }
+                if state.config.is_denied_extension(current_vhost.map(|v| v.as_ref()), &fallback) {
+                    return error_page(StatusCode::FORBIDDEN, "Forbidden");
+                }
+                return serve_static_file(fallback, accepts_precompressed(state, headers), RangeRequest::of(req.method(), headers)).await;
+            }
+        }
+        return error_page(StatusCode::NOT_FOUND, "Not Found");
+    }
+
+    if let Some(response) = symlink_refusal(current_vhost, &file_root, &path).await {
+        return response;
+    }
+
+    // A <Location> SetHandler runs every file in its URL space
+    if let Some(address) = &location.handler {
+        if let Some(limit) = body_limit(state, current_vhost, &doc_root, &path, &decoded_path).await {
+            req.extensions_mut().insert(limit);
+        }
+        return handle_fastcgi_script(state.clone(), current_vhost.map(|v| v.as_ref()), &doc_root, req, path, address).await;
+    }
+
+    if let Some(ext) = path.extension() {
+        if state.config.is_php_extension(current_vhost.map(|v| v.as_ref()), ext) {
+            if let Some(limit) = body_limit(state, current_vhost, &doc_root, &path, &decoded_path).await {
+                req.extensions_mut().insert(limit);
+            }
+            return handle_php(state.clone(), current_vhost.map(|v| v.as_ref()), &doc_root, req, path).await;
+        }
+        if let Some(address) = state.config.fastcgi_upstream(current_vhost.map(|v| v.as_ref()), ext) {
+            if let Some(limit) = body_limit(state, current_vhost, &doc_root, &path, &decoded_path).await {
+                req.extensions_mut().insert(limit);
+            }
+            return handle_fastcgi_script(state.clone(), current_vhost.map(|v| v.as_ref()), &doc_root, req, path, address).await;
+        }
+    }
+
+    if state.config.is_denied_extension(current_vhost.map(|v| v.as_ref()), &path) {
+        return error_page(StatusCode::FORBIDDEN, "Forbidden");
+    }
+
+    // Serve static file
+    serve_static_file(path, accepts_precompressed(state, headers), RangeRequest::of(req.method(), headers)).await
+}
+
+/// Run filesystem work that tokio::fs has no form of (symlink walks, the
+/// `.htaccess` files up a path) on the blocking pool, so a slow disk holds up
+/// this request rather than every connection on its worker thread
+async fn on_blocking_pool<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// A `.htaccess` file, None if it is missing or unreadable
+async fn read_htaccess(path: &Path) -> Option<apache::HtaccessConfig> {
+    let content = fs::read_to_string(path).await.ok()?;
+    Some(apache::parse_htaccess_content(&content))
+}
+
+/// The LimitRequestBody for a request to `file` (see apache::resolve_body_limit),
+/// else `[php] max_body_size`; None if the one in force is 0
+async fn body_limit(state: &AppState, vhost: Option<&Arc<VirtualHost>>, doc_root: &Path, file: &Path, url_path: &str) -> Option<BodyLimit> {
+    let vhost = vhost.cloned();
+    let (doc_root, file, url_path) = (doc_root.to_path_buf(), file.to_path_buf(), url_path.to_string());
+    let limit = on_blocking_pool(move || apache::resolve_body_limit(vhost.as_deref(), &doc_root, &file, &url_path))
+        .await
+        .unwrap_or_else(|| BodyLimit {
+            bytes: state.config.php.max_body_size,
+            scope: "[php] max_body_size".to_string(),
+        });
+    Some(limit).filter(|limit| limit.bytes > 0)
+}
+
+/// The FallbackResource for a missing `file`; see apache::resolve_fallback_resource
+async fn fallback_resource(vhost: Option<&Arc<VirtualHost>>, doc_root: &Path, file: &Path) -> Option<String> {
+    let vhost = vhost.cloned();
+    let (doc_root, file) = (doc_root.to_path_buf(), file.to_path_buf());
+    on_blocking_pool(move || apache::resolve_fallback_resource(vhost.as_deref(), &doc_root, &file)).await
+}
+
+/// 403 for a file reached through a symlink that `Options` does not let us follow
+async fn symlink_refusal(vhost: Option<&Arc<VirtualHost>>, root: &Path, file: &Path) -> Option<Response> {
+    let vhost = vhost?.clone();
+    let (root, file) = (root.to_path_buf(), file.to_path_buf());
+    let link = on_blocking_pool(move || apache::check_symlinks(Some(&vhost), &root, &file)).await.err()?;
+    let mut response = error_page(StatusCode::FORBIDDEN, "Forbidden");
+    response.extensions_mut().insert(RequestError(format!(
+        "Symbolic link not allowed or link target not accessible: {}",
+        link.display()
+    )));
+    Some(response)
+}
+
+/// Whether a URL path has a segment starting with a dot, other than `.well-known`
+fn is_hidden_path(url_path: &str) -> bool {
+    url_path
+        .split('/')
+        .any(|segment| segment.starts_with('.') && segment != ".well-known")
+}
+
+/// The address of the client behind a request; see `ServerConfig::client_addr`
+fn request_client(server: &ServerConfig, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    let peer = extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+    server.client_addr(headers, peer)
+}
+
+/// Client address for logs, `-` when the connection has none
+fn client_ip(server: &ServerConfig, req: &Request) -> String {
+    request_client(server, req.headers(), req.extensions()).map_or_else(|| "-".to_string(), |ip| ip.to_string())
+}
+
+fn header_string(headers: &HeaderMap, name: &str) -> String {
+    headers.get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// HTTP/2 sends the host as the `:authority` pseudo-header, which hyper puts
+/// in the URI; copy it to `Host` so vhost selection, logs and PHP all see it
+async fn authority_as_host(mut req: Request, next: Next) -> Response {
+    if !req.headers().contains_key(header::HOST) {
+        if let Some(host) = req.uri().authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()) {
+            req.headers_mut().insert(header::HOST, host);
+        }
+    }
+    next.run(req).await
+}
+
+/// Adds the `Server` header allowed by ServerTokens to every response
+async fn server_header(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    if let Some(banner) = state.server_tokens.banner().and_then(|b| HeaderValue::from_str(&b).ok()) {
+        response.headers_mut().insert(header::SERVER, banner);
+    }
+    response
+}
+
+/// Records every request in the admin log, when the dashboard is enabled, and
+/// the serving vhost's CustomLog/ErrorLog files once the final response is known
+async fn access_log(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let start_time = Instant::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let query = req.uri().query().unwrap_or("").to_string();
+    let protocol = format!("{:?}", req.version());
+    let client_ip = client_ip(&state.config.server, &req);
+    let headers = req.headers();
+    let host = header_string(headers, "host");
+    let user_agent = header_string(headers, "user-agent");
+    let referer = header_string(headers, "referer");
+    let tls_protocol = req.extensions().get::<tls::Session>().map_or("", |session| session.protocol);
+
+    let response = next.run(req).await;
+    let elapsed = start_time.elapsed();
+    let status = response.status().as_u16();
+
+    let admin_enabled = state.config.admin.enabled;
+    if admin_enabled {
+        state.admin_state.log_request(RequestLogEntry {
+            timestamp: Utc::now(),
+            method: method.clone(),
+            path: path.clone(),
+            status,
+            duration_ms: elapsed.as_millis() as u64,
+            client_ip: client_ip.clone(),
+            host,
+            user_agent: user_agent.clone(),
+            protocol: protocol.clone(),
+            php_duration_ms: php_duration_ms(&response),
+        });
+    }
+
+    let error = response.extensions().get::<RequestError>();
+    let Some(ServedBy(vhost)) = response.extensions().get::<ServedBy>() else {
+        if let Some(RequestError(message)) = error {
+            eprintln!("{}", message);
+        }
+        return response;
+    };
+    if admin_enabled {
+        state.admin_state.stats.record_vhost(&listeners::vhost_key(vhost), status, elapsed.as_millis() as u64);
+    }
+
+    if !vhost.custom_logs.is_empty() {
+        let record = logfiles::AccessRecord {
+            time: chrono::Local::now(),
+            client_ip: &client_ip,
+            method: &method,
+            path: &path,
+            query: &query,
+            protocol: &protocol,
+            tls_protocol,
+            status,
+            // Streamed files give no exact size hint, but say their length
+            // (which HEAD responses don't send)
+            bytes_sent: response.body().size_hint().exact().or_else(|| {
+                response
+                    .headers()
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|length| length.to_str().ok()?.parse().ok())
+                    .filter(|_| method != "HEAD")
+            }),
+            duration_us: elapsed.as_micros(),
+            server_name: vhost.server_name.as_deref().unwrap_or(""),
+            port: vhost.port,
+            referer: &referer,
+            user_agent: &user_agent,
+        };
+        for custom_log in &vhost.custom_logs {
+            let line = logfiles::format_access_line(&custom_log.format, &record);
+            if !state.log_files.append(&custom_log.path, &line) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    if let Some(RequestError(message)) = error {
+        let written = vhost.error_log.as_ref().is_some_and(|path| {
+            state.log_files.append(path, &logfiles::format_error_line(&client_ip, message))
+        });
+        if !written {
+            eprintln!("{}", message);
+        }
+    }
+
+    response
+}
+
+const MAINTENANCE_HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="UTF-8"><title>Down for maintenance</title></head>
+<body style="font-family: sans-serif; text-align: center; padding-top: 80px;">
+    <h1>Down for maintenance</h1>
+    <p>We're making some improvements and will be back shortly.</p>
+</body>
+</html>"#;
+
+/// 503 page with Retry-After, read fresh so it can be edited while live
+async fn maintenance_response(config: &MaintenanceConfig) -> Response {
+    let body = match &config.page {
+        Some(page) => fs::read_to_string(page).await.unwrap_or_else(|e| {
+            eprintln!("Cannot read maintenance page {}: {}", page, e);
+            MAINTENANCE_HTML.to_string()
+        }),
+        None => MAINTENANCE_HTML.to_string(),
+    };
+
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(axum::http::header::RETRY_AFTER, config.retry_after.to_string())
+        .header(axum::http::header::CACHE_CONTROL, "no-store")
+        .body(axum::body::Body::from(body))
+        .unwrap()
+}
+
+/// Index file served for a directory, in order of preference
+async fn directory_index(dir: &Path) -> Option<PathBuf> {
+    for name in ["index.php", "index.html"] {
+        let index = dir.join(name);
+        if fs::try_exists(&index).await.unwrap_or(false) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Handle redirect responses based on status code
+fn handle_redirect(status_code: u16, target: Option<String>) -> Response {
+    let status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::FOUND);
+    
+    match target {
+        Some(url) => {
+            // Create redirect response with Location header
+            let mut response = Response::builder()
+                .status(status)
+                .header(axum::http::header::LOCATION, &url)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            
+            // For 3xx redirects, add a helpful HTML body
+            if (300..400).contains(&status_code) {
+                let body = format!(
+                    "<!DOCTYPE HTML PUBLIC \"-//IETF//DTD HTML 2.0//EN\">\n\
+                    <html><head>\n\
+                    <title>{} {}</title>\n\
+                    </head><body>\n\
+                    <h1>{}</h1>\n\
+                    <p>The document has moved <a href=\"{}\">here</a>.</p>\n\
+                    </body></html>",
+                    status_code,
+                    status.canonical_reason().unwrap_or("Redirect"),
+                    status.canonical_reason().unwrap_or("Redirect"),
+                    url
+                );
+                response = Response::builder()
+                    .status(status)
+                    .header(axum::http::header::LOCATION, &url)
+                    .header(axum::http::header::CONTENT_TYPE, "text/html; charset=iso-8859-1")
+                    .body(axum::body::Body::from(body))
+                    .unwrap();
+            }
+            response
+        }
+        None => {
+            // No target URL - likely a 410 Gone response
+            let body = format!(
+                "<!DOCTYPE HTML PUBLIC \"-//IETF//DTD HTML 2.0//EN\">\n\
+                <html><head>\n\
+                <title>{} {}</title>\n\
+                </head><body>\n\
+                <h1>{}</h1>\n\
+                <p>The requested resource is no longer available on this server.</p>\n\
+                </body></html>",
+                status_code,
+                status.canonical_reason().unwrap_or("Gone"),
+                status.canonical_reason().unwrap_or("Gone")
+            );
+            Response::builder()
+                .status(status)
+                .header(axum::http::header::CONTENT_TYPE, "text/html; charset=iso-8859-1")
+                .body(axum::body::Body::from(body))
+                .unwrap()
+        }
+    }
+}
+
+/// Whether a `.br` sibling may be sent: `[compression] precompressed` is on
+/// and the client accepts Brotli
+fn accepts_precompressed(state: &AppState, headers: &HeaderMap) -> bool {
+    state.config.compression.precompressed
+        && headers.get_all(header::ACCEPT_ENCODING).iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')).any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            params.next().is_some_and(|name| name.eq_ignore_ascii_case("br"))
+                && params.all(|param| param.strip_prefix("q=").is_none_or(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0)))
+        })
+}
+
+/// `file.br` when it exists and is not older than `file`
+async fn precompressed_sibling(path: &Path, meta: &std::fs::Metadata) -> Option<(PathBuf, std::fs::Metadata)> {
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".br");
+    let sibling = path.with_file_name(name);
+    let sibling_meta = fs::metadata(&sibling).await.ok().filter(|m| m.is_file())?;
+    if sibling_meta.modified().ok()? < meta.modified().ok()? {
+        return None;
+    }
+    Some((sibling, sibling_meta))
+}
+
+/// A GET's `Range` header and the `If-Range` validator it depends on
+struct RangeRequest {
+    range: String,
+    if_range: Option<String>,
+}
+
+impl RangeRequest {
+    /// Only GET is answered in part
+    fn of(method: &Method, headers: &HeaderMap) -> Option<Self> {
+        if method != Method::GET {
+            return None;
+        }
+        let value = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        Some(RangeRequest { range: value(header::RANGE)?, if_range: value(header::IF_RANGE) })
+    }
+
+    /// Whether the range is of the version the client holds: no If-Range,
+    /// or one naming the file's strong ETag or exact modification time.
+    /// Anything else gets the whole file, so a resumed download never
+    /// splices two versions together.
+    fn applies_to(&self, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+        match self.if_range.as_deref().map(str::trim) {
+            None => true,
+            Some(tag) if tag.starts_with('"') => tag == etag,
+            Some(tag) if tag.starts_with("W/") => false,
+            Some(date) => DateTime::parse_from_rfc2822(date)
+                .ok()
+                .zip(modified)
+                .is_some_and(|(date, modified)| date.timestamp() == modified.timestamp()),
+        }
+    }
+}
+
+/// How a `Range` header applies to a file of some length
+enum ByteRange {
+    /// Bytes `first..=last`
+    Satisfiable(u64, u64),
+    /// Starts past the end of the file: 416
+    Unsatisfiable,
+    /// Several ranges, another unit or bad syntax: the whole file is sent
+    Ignored,
+}
+
+impl ByteRange {
+    fn parse(range: &str, len: u64) -> Self {
+        let spec = range.trim().strip_prefix("bytes=").filter(|spec| !spec.contains(','));
+        let Some((first, last)) = spec.and_then(|spec| spec.split_once('-')) else {
+            return ByteRange::Ignored;
+        };
+        let (first, last) = match (first.trim(), last.trim()) {
+            // `-N`: the last N bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return ByteRange::Unsatisfiable,
+                Ok(suffix) => (len.saturating_sub(suffix), u64::MAX),
+                Err(_) => return ByteRange::Ignored,
+            },
+            (first, last) => match (first.parse::<u64>(), last) {
+                (Ok(first), "") => (first, u64::MAX),
+                (Ok(first), last) => match last.parse::<u64>() {
+                    Ok(last) if last >= first => (first, last),
+                    _ => return ByteRange::Ignored,
+                },
+                (Err(_), _) => return ByteRange::Ignored,
+            },
+        };
+        if first >= len {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Satisfiable(first, last.min(len - 1))
+    }
+}
+
+/// HTTP-date of a modification time, for Last-Modified
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+async fn serve_static_file(path: PathBuf, brotli: bool, range: Option<RangeRequest>) -> Response {
+    let Ok(meta) = fs::metadata(&path).await else {
+        return error_page(StatusCode::INTERNAL_SERVER_ERROR, "Error reading file");
+    };
+    // The Content-Type is the original file's either way
+    let mime_type = mime_guess::from_path(&path).first_or_text_plain();
+    let sibling = if brotli { precompressed_sibling(&path, &meta).await } else { None };
+    let (file, meta, encoding) = match sibling {
+        Some((sibling, sibling_meta)) => (sibling, sibling_meta, Some("br")),
+        None => (path, meta, None),
+    };
+
+    match fs::read(&file).await {
+        Ok(mut content) => {
+            let etag = file_etag(&meta);
+            let modified = meta.modified().ok().map(DateTime::<Utc>::from);
+            let len = content.len() as u64;
+            // The ETag the client was sent, encoding and all
+            let sent_etag = encoded_etag(&etag, encoding);
+            let range = range.filter(|range| range.applies_to(&sent_etag, modified)).map(|range| ByteRange::parse(&range.range, len));
+            let (status, content_range) = match range {
+                Some(ByteRange::Satisfiable(first, last)) => {
+                    content.truncate(last as usize + 1);
+                    content.drain(..first as usize);
+                    (StatusCode::PARTIAL_CONTENT, Some(format!("bytes {}-{}/{}", first, last, len)))
+                }
+                Some(ByteRange::Unsatisfiable) => {
+                    let mut response = error_page(StatusCode::RANGE_NOT_SATISFIABLE, "Range Not Satisfiable");
+                    if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", len)) {
+                        response.headers_mut().insert(header::CONTENT_RANGE, value);
+                    }
+                    return response;
+                }
+                Some(ByteRange::Ignored) | None => (StatusCode::OK, None),
+            };
+
+            let mut response = (
+                status,
+                [
+                    (header::CONTENT_TYPE, mime_type.to_string()),
+                    (header::ETAG, etag),
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                ],
+                content,
+            ).into_response();
+            let headers = response.headers_mut();
+            if let Some(encoding) = encoding {
+                headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            }
+            if let Some(value) = modified.and_then(|modified| HeaderValue::from_str(&http_date(modified)).ok()) {
+                headers.insert(header::LAST_MODIFIED, value);
+            }
+            if let Some(value) = content_range.and_then(|range| HeaderValue::from_str(&range).ok()) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            response
+        }
+        Err(_) => error_page(StatusCode::INTERNAL_SERVER_ERROR, "Error reading file"),
+    }
+}
+
+/// Strong validator from size and modification time; the content encoding is
+/// appended later by `encoding_aware_etag`
+fn file_etag(meta: &std::fs::Metadata) -> String {
+    let modified = meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}{:08x}\"", meta.len(), modified.as_secs(), modified.subsec_nanos())
+}
+
+/// `etag` as sent with a body in `encoding`
+fn encoded_etag(etag: &str, encoding: Option<&str>) -> String {
+    match encoding {
+        Some(encoding) => format!("{}-{}\"", etag.trim_end_matches('"'), encoding),
+        None => etag.to_string(),
+    }
+}
+
+/// Runs outside the compression layer, so it sees the Content-Encoding that
+/// is actually sent: gives each encoding its own ETag (a shared cache must not
+/// hand a gzip body to an identity client) and answers If-None-Match.
+async fn encoding_aware_etag(req: Request, next: Next) -> Response {
+    let if_none_match = req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let conditional_method = req.method() == Method::GET || req.method() == Method::HEAD;
+
+    let mut response = next.run(req).await;
+
+    let Some(base) = response.headers().get(header::ETAG).and_then(|v| v.to_str().ok()).map(str::to_string) else {
+        return response;
+    };
+    let etag = encoded_etag(&base, response.headers().get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok()));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+
+    let varies_on_encoding = response.headers()
+        .get_all(header::VARY)
+        .iter()
+        .any(|v| v.to_str().is_ok_and(|v| v.to_ascii_lowercase().contains("accept-encoding")));
+    if !varies_on_encoding {
+        response.headers_mut().append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    let matched = if_none_match.is_some_and(|candidates| {
+        candidates.split(',').map(str::trim).any(|candidate| {
+            candidate == "*" || candidate.trim_start_matches("W/") == etag
+        })
+    });
+    // If-None-Match goes before Range: a cached copy beats a part of one
+    let succeeded = matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT);
+    if conditional_method && succeeded && matched {
+        let (mut parts, _) = response.into_parts();
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_TYPE);
+        parts.headers.remove(header::CONTENT_ENCODING);
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_RANGE);
+        return Response::from_parts(parts, axum::body::Body::empty());
+    }
+
+    response
+}
+
+/// 403 for a canonical script path outside the site. Rewrites, aliases and
+/// symlinks must not lead a backend to such a script, whatever path the
+/// request was mapped to.
+async fn script_refusal(script_path: &Path, doc_root: &Path, vhost: Option<&VirtualHost>) -> Option<Response> {
+    if is_script_allowed(script_path, doc_root, vhost).await {
+        return None;
+    }
+    let mut response = error_page(StatusCode::FORBIDDEN, "Forbidden");
+    response.extensions_mut().insert(RequestError(format!(
+        "Refused to run {}: outside the document root {} and the vhost's Alias directories",
+        script_path.display(),
+        doc_root.display()
+    )));
+    Some(response)
+}
+
+async fn handle_php(state: Arc<AppState>, vhost: Option<&VirtualHost>, doc_root: &Path, mut req: Request, script_path: PathBuf) -> Response {
+    let Ok(script_path) = fs::canonicalize(&script_path).await else {
+        return error_page(StatusCode::NOT_FOUND, "Script not found on disk");
+    };
+    if let Some(response) = script_refusal(&script_path, doc_root, vhost).await {
+        return response;
+    }
+    if req.extensions().get::<tap::Tapped>().is_some() {
+        tracing::debug!(target: tap::TARGET, uri = %req.uri(), script = %script_path.display(), "tapped PHP script");
+    }
+    let script_host = ScriptHost::of(vhost, req.headers());
+    req.extensions_mut().insert(script_host);
+    // Held until the backend has answered, so at most max_concurrent
+    // requests reach PHP; static files never wait for a slot
+    let _slot = match &state.php_slots {
+        Some(slots) => match php_slot(slots, state.config.php.queue_timeout).await {
+            Some(slot) => Some(slot),
+            None => return php_busy(&state),
+        },
+        None => None,
+    };
+    match state.config.php_backend(vhost) {
+        PhpBackend::Cgi(_) => handle_php_cgi(state.clone(), req, script_path).await,
+        PhpBackend::Fpm(Some(fpm_addr)) => handle_fastcgi(state.clone(), req, script_path, FastCgiUpstream { address: fpm_addr, php: true }).await,
+        PhpBackend::Fpm(None) => error_page(StatusCode::INTERNAL_SERVER_ERROR, "PHP-FPM address not configured"),
+    }
+}
+
+/// Run a file through the FastCGI upstream its extension is mapped to
+async fn handle_fastcgi_script(state: Arc<AppState>, vhost: Option<&VirtualHost>, doc_root: &Path, mut req: Request, script_path: PathBuf, address: &str) -> Response {
+    let Ok(script_path) = fs::canonicalize(&script_path).await else {
+        return error_page(StatusCode::NOT_FOUND, "Script not found on disk");
+    };
+    if let Some(response) = script_refusal(&script_path, doc_root, vhost).await {
+        return response;
+    }
+    if req.extensions().get::<tap::Tapped>().is_some() {
+        tracing::debug!(target: tap::TARGET, uri = %req.uri(), script = %script_path.display(), upstream = address, "tapped FastCGI script");
+    }
+    let script_host = ScriptHost::of(vhost, req.headers());
+    req.extensions_mut().insert(script_host);
+    handle_fastcgi(state, req, script_path, FastCgiUpstream { address, php: false }).await
+}
+
+/// A slot for one PHP request, waiting up to `queue_timeout` seconds
+async fn php_slot(slots: &tokio::sync::Semaphore, queue_timeout: u64) -> Option<tokio::sync::SemaphorePermit<'_>> {
+    if queue_timeout == 0 {
+        return slots.try_acquire().ok();
+    }
+    timeout(Duration::from_secs(queue_timeout), slots.acquire()).await.ok()?.ok()
+}
+
+/// Every PHP slot stayed busy: 503 with Retry-After instead of piling onto
+/// the backend
+fn php_busy(state: &AppState) -> Response {
+    let limit = state.config.php.max_concurrent.map_or(0, NonZeroUsize::get);
+    let mut response = error_page(StatusCode::SERVICE_UNAVAILABLE, "The server is too busy to run this page; please retry shortly");
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(state.config.php.queue_timeout.max(1)));
+    response.extensions_mut().insert(RequestError(format!(
+        "All {} PHP slots ([php] max_concurrent) stayed busy for {}s; 503",
+        limit, state.config.php.queue_timeout
+    )));
+    response
+}
+
+/// Whether canonical `script` lies under the document root or an Alias target
+async fn is_script_allowed(script: &Path, doc_root: &Path, vhost: Option<&VirtualHost>) -> bool {
+    let aliases = vhost.into_iter().flat_map(|vhost| &vhost.aliases).map(|alias| alias.path.as_path());
+    for root in std::iter::once(doc_root).chain(aliases) {
+        if fs::canonicalize(root).await.is_ok_and(|root| script.starts_with(root)) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Buffer a request body for PHP, spilling large ones to disk and enforcing
+/// the LimitRequestBody attached to the request
+async fn read_php_body(state: &AppState, parts: &axum::http::request::Parts, body: axum::body::Body) -> Result<RequestBody, Response> {
+    let temp_dir = state.config.php.body_temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let limit = parts.extensions.get::<BodyLimit>();
+    request_body::read_body(body, state.config.php.body_memory_limit, limit.map(|l| l.bytes), &temp_dir)
+        .await
+        .map_err(|e| {
+            let (status, message, error) = match e {
+                ReadError::TooLarge => (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Request Entity Too Large",
+                    limit
+                        .map(|l| format!("Request body exceeds LimitRequestBody {} (set by {})", l.bytes, l.scope))
+                        .unwrap_or_default(),
+                ),
+                ReadError::Io(e) => (StatusCode::BAD_REQUEST, "Failed to read body", format!("Failed to read request body: {}", e)),
+            };
+            let mut response = error_page(status, message);
+            response.extensions_mut().insert(RequestError(error));
+            response
+        })
+}
+
+async fn handle_php_cgi(state: Arc<AppState>, req: Request, script_path: PathBuf) -> Response {
+    let mut cmd = tokio::process::Command::new(&state.config.php.cgi_path);
+    
+    // handle_php has canonicalized it
+    let script_filename = script_path.to_string_lossy().to_string();
+
+    cmd.env("REDIRECT_STATUS", "200")
+       .env("SCRIPT_FILENAME", script_filename)
+       .env("SCRIPT_NAME", script_name(req.extensions(), req.uri()))
+       .env("REQUEST_METHOD", req.method().as_str())
+       .env("SERVER_SOFTWARE", state.server_tokens.server_software())
+       .env("SERVER_PROTOCOL", format!("{:?}", req.version()));
+       
+    if let Some(client) = request_client(&state.config.server, req.headers(), req.extensions()) {
+        cmd.env("REMOTE_ADDR", client.to_string());
+    }
+    if let Some(query) = req.uri().query() {
+        cmd.env("QUERY_STRING", query);
+    }
+    
+    for (name, value) in req.headers() {
+         // HTTP_HOST is the script's host, below
+         if name == header::HOST {
+             continue;
+         }
+         let key = format!("HTTP_{}", name.as_str().replace('-', "_").to_uppercase());
+         if let Ok(val) = value.to_str() {
+             cmd.env(key, val);
+         }
+         if name == "content-type" {
+             if let Ok(val) = value.to_str() { cmd.env("CONTENT_TYPE", val); }
+         }
+    }
+    if let Some(origin) = req.extensions().get::<Origin>() {
+        if origin.https {
+            cmd.env("HTTPS", "on");
+        }
+        cmd.env("REQUEST_SCHEME", origin.scheme()).env("SERVER_PORT", origin.port.to_string());
+    }
+    if let Some(host) = req.extensions().get::<ScriptHost>() {
+        if let Some(name) = &host.server_name {
+            cmd.env("SERVER_NAME", name);
+        }
+        if let Some(http_host) = &host.http_host {
+            cmd.env("HTTP_HOST", http_host);
+        }
+    }
+    // TLS protocol and client certificate identity (SSLVerifyClient)
+    if let Some(session) = req.extensions().get::<tls::Session>() {
+        cmd.envs(session.variables());
+    }
+    let has_content_length = req.headers().contains_key(header::CONTENT_LENGTH);
+    let tapped = req.extensions().get::<tap::Tapped>().is_some().then(|| req.uri().to_string());
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    // Dropped on timeout, which must not leave php-cgi running
+    cmd.kill_on_drop(true);
+
+    // Read the client body before starting the clock so upload time
+    // is not counted as PHP time
+    let (parts, body) = req.into_parts();
+    let body = match read_php_body(&state, &parts, body).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+
+    if has_content_length || body.content_length() > 0 {
+        cmd.env("CONTENT_LENGTH", body.content_length().to_string());
+    }
+
+    // A spilled body is handed to php-cgi as its stdin file directly
+    let body_bytes = match body {
+        RequestBody::Memory(bytes) => {
+            cmd.stdin(Stdio::piped());
+            Some(bytes)
+        }
+        RequestBody::File(file, _) => {
+            cmd.stdin(Stdio::from(file.into_std().await));
+            None
+        }
+    };
+
+    if let Some(uri) = tapped {
+        let env = cmd.as_std().get_envs().map(|(name, value)| {
+            (name.to_string_lossy(), value.map(|v| v.to_string_lossy()).unwrap_or_default())
+        });
+        tracing::debug!(target: tap::TARGET, %uri, params = ?tap::params(env), "tapped CGI environment");
+    }
+
+    let backend_start = Instant::now();
+    let mut child = match cmd.spawn() {
+        Ok(c) => c,
+        Err(e) => return php_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to spawn php-cgi: {}", e), backend_start),
+    };
+
+    if let (Some(bytes), Some(mut stdin)) = (body_bytes, child.stdin.take()) {
+        if stdin.write_all(&bytes).await.is_err() {
+             // Ignore write error
+        }
+    }
+
+    let output = match timeout(state.connection.timeout.value, child.wait_with_output()).await {
+        Ok(Ok(o)) => o,
+        Ok(Err(e)) => return php_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to wait for php-cgi: {}", e), backend_start),
+        Err(_) => return php_timeout(&state, backend_start),
+    };
+    
+    let mut response = with_php_timing(parse_php_response(output.stdout), backend_start);
+    if !output.stderr.is_empty() {
+        response.extensions_mut().insert(RequestError(format!("PHP CGI Error: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+    response
+}
+
+/// Where handle_fastcgi sends a request: PHP-FPM, or the upstream of another
+/// extension. Only PHP's time is counted as PHP time.
+#[derive(Clone, Copy)]
+struct FastCgiUpstream<'a> {
+    address: &'a str,
+    php: bool,
+}
+
+impl FastCgiUpstream<'_> {
+    /// What the upstream is called in error messages
+    fn name(self) -> &'static str {
+        if self.php { "PHP-FPM" } else { "FastCGI upstream" }
+    }
+
+    fn error(self, status: StatusCode, message: impl Into<String>, backend_start: Instant) -> Response {
+        if self.php {
+            return php_error(status, message, backend_start);
+        }
+        backend_error(status, message)
+    }
+
+    /// Why an exchange that got connected failed, and the status that says so
+    fn failure(self, error: &ClientError) -> (StatusCode, String) {
+        let (name, address) = (self.name(), self.address);
+        match error {
+            ClientError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                (StatusCode::BAD_GATEWAY, format!("{} at {} closed the connection mid-response", name, address))
+            }
+            ClientError::Io(e) => (StatusCode::BAD_GATEWAY, format!("{} at {} connection failed: {}", name, address, e)),
+            // Records for another request, or of a type FastCGI doesn't have
+            ClientError::RequestIdNotFound { .. } | ClientError::ResponseNotFound { .. } | ClientError::UnknownRequestType { .. } => {
+                (StatusCode::BAD_GATEWAY, format!("{} at {} broke the FastCGI protocol: {}", name, address, error))
+            }
+            ClientError::EndRequestOverloaded { .. } => {
+                (StatusCode::SERVICE_UNAVAILABLE, format!("{} at {} is overloaded and refused the request", name, address))
+            }
+            _ => (StatusCode::BAD_GATEWAY, format!("{} at {} refused the request: {}", name, address, error)),
+        }
+    }
+
+    fn timeout(self, state: &AppState, backend_start: Instant) -> Response {
+        if self.php {
+            return php_timeout(state, backend_start);
+        }
+        let limit = &state.connection.timeout;
+        self.error(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("FastCGI upstream {} did not respond within {:?} (Timeout from {})", self.address, limit.value, limit.source),
+            backend_start,
+        )
+    }
+
+    fn response(self, response: Response, backend_start: Instant) -> Response {
+        if self.php { with_php_timing(response, backend_start) } else { response }
+    }
+}
+
+async fn handle_fastcgi(state: Arc<AppState>, req: Request, script_path: PathBuf, upstream: FastCgiUpstream<'_>) -> Response {
+    // Basic FastCGI connection with timeout and optional Unix socket support
+    let connect_timeout = Duration::from_secs(2);
+
+    enum StreamKind {
+        Tcp(TcpStream),
+        Unix(UnixStream),
+    }
+
+    // Read body
+    let (parts, body) = req.into_parts();
+    let body = match read_php_body(&state, &parts, body).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+
+    // handle_php has canonicalized it
+    let script_filename = script_path.to_string_lossy().to_string();
+
+    // Backend time covers connecting to the upstream and executing the script
+    let backend_start = Instant::now();
+    let (name, address) = (upstream.name(), upstream.address);
+    let stream = if let Some(path) = address.strip_prefix("unix:") {
+        match timeout(connect_timeout, UnixStream::connect(path)).await {
+            Ok(Ok(s)) => StreamKind::Unix(s),
+            Ok(Err(e)) => return upstream.error(StatusCode::BAD_GATEWAY, format!("{} unreachable at unix:{}: {}", name, path, e), backend_start),
+            Err(_) => return upstream.error(StatusCode::GATEWAY_TIMEOUT, format!("{} connect timed out (unix:{})", name, path), backend_start),
+        }
+    } else {
+        match timeout(connect_timeout, TcpStream::connect(address)).await {
+            Ok(Ok(s)) => StreamKind::Tcp(s),
+            Ok(Err(e)) => return upstream.error(StatusCode::BAD_GATEWAY, format!("{} unreachable at {}: {}", name, address, e), backend_start),
+            Err(_) => return upstream.error(StatusCode::GATEWAY_TIMEOUT, format!("{} connect timed out ({})", name, address), backend_start),
+        }
+    };
+
+    // Construct FastCGI params
+    let mut params = Params::default();
+    params.insert(Cow::Borrowed("REQUEST_METHOD"), Cow::Owned(parts.method.as_str().to_string()));
+    params.insert(Cow::Borrowed("SCRIPT_FILENAME"), Cow::Owned(script_filename));
+    params.insert(Cow::Borrowed("SCRIPT_NAME"), Cow::Owned(script_name(&parts.extensions, &parts.uri)));
+    params.insert(Cow::Borrowed("REQUEST_URI"), Cow::Owned(parts.uri.path_and_query().map(|pq| pq.to_string()).unwrap_or_else(|| parts.uri.path().to_string())));
+    params.insert(Cow::Borrowed("QUERY_STRING"), Cow::Owned(parts.uri.query().unwrap_or("").to_string()));
+    params.insert(Cow::Borrowed("SERVER_SOFTWARE"), Cow::Owned(state.server_tokens.server_software()));
+    params.insert(Cow::Borrowed("SERVER_PROTOCOL"), Cow::Owned(format!("{:?}", parts.version)));
+    params.insert(Cow::Borrowed("GATEWAY_INTERFACE"), Cow::Borrowed("CGI/1.1"));
+    
+    // The client, as a trusted proxy reports it
+    if let Some(client) = request_client(&state.config.server, &parts.headers, &parts.extensions) {
+        params.insert(Cow::Borrowed("REMOTE_ADDR"), Cow::Owned(client.to_string()));
+    }
+    
+    // How the client reached us, through a trusted proxy if there is one
+    if let Some(origin) = parts.extensions.get::<Origin>() {
+        if origin.https {
+            params.insert(Cow::Borrowed("HTTPS"), Cow::Borrowed("on"));
+        }
+        params.insert(Cow::Borrowed("REQUEST_SCHEME"), Cow::Borrowed(origin.scheme()));
+        params.insert(Cow::Borrowed("SERVER_PORT"), Cow::Owned(origin.port.to_string()));
+    }
+    
+    // Server name from the Host header, or the vhost's php_server_name and php_http_host
+    if let Some(host) = parts.extensions.get::<ScriptHost>() {
+        if let Some(name) = &host.server_name {
+            params.insert(Cow::Borrowed("SERVER_NAME"), Cow::Owned(name.clone()));
+        }
+        if let Some(http_host) = &host.http_host {
+            params.insert(Cow::Borrowed("HTTP_HOST"), Cow::Owned(http_host.clone()));
+        }
+    }
+    
+    // Handle headers; HTTP_HOST is set above
+    for (name, value) in parts.headers.iter().filter(|(name, _)| *name != header::HOST) {
+        let key = format!("HTTP_{}", name.as_str().replace('-', "_").to_uppercase());
+        if let Ok(val) = value.to_str() {
+             params.insert(Cow::Owned(key), Cow::Owned(val.to_string()));
+        }
+    }
+    
+    // Content Headers
+    if let Some(ct) = parts.headers.get("content-type") {
+        if let Ok(v) = ct.to_str() {
+             params.insert(Cow::Borrowed("CONTENT_TYPE"), Cow::Owned(v.to_string()));
+        }
+    }
+    // TLS protocol and client certificate identity (SSLVerifyClient)
+    if let Some(session) = parts.extensions.get::<tls::Session>() {
+        for (name, value) in session.variables() {
+            params.insert(Cow::Borrowed(name), Cow::Owned(value));
+        }
+    }
+    // The length actually received, so chunked uploads reach $_POST too
+    if parts.headers.contains_key(header::CONTENT_LENGTH) || body.content_length() > 0 {
+        params.insert(Cow::Borrowed("CONTENT_LENGTH"), Cow::Owned(body.content_length().to_string()));
+    }
+
+    if parts.extensions.get::<tap::Tapped>().is_some() {
+        tracing::debug!(target: tap::TARGET, uri = %parts.uri, params = ?tap::params(params.iter()), "tapped FastCGI params");
+    }
+
+    let fcgi_req = FcgiRequest::new(params, body.into_reader());
+
+    let php_timeout_limit = state.connection.timeout.value;
+    let result = match stream {
+        StreamKind::Tcp(s) => timeout(php_timeout_limit, Client::new(s).execute_once(fcgi_req)).await,
+        StreamKind::Unix(s) => timeout(php_timeout_limit, Client::new(s).execute_once(fcgi_req)).await,
+    };
+    let output = match result {
+        Ok(Ok(o)) => o,
+        Ok(Err(e)) => {
+            let (status, message) = upstream.failure(&e);
+            return upstream.error(status, message, backend_start);
+        }
+        Err(_) => return upstream.timeout(&state, backend_start),
+    };
+
+    let stdout = match output.stdout {
+        Some(s) => s,
+        None => return upstream.error(StatusCode::BAD_GATEWAY, format!("{} at {} ended the request without output", name, address), backend_start),
+    };
+    
+    let mut response = upstream.response(parse_php_response(stdout), backend_start);
+    if let Some(stderr) = output.stderr.filter(|e| !e.is_empty()) {
+        response.extensions_mut().insert(RequestError(format!("{} stderr: {}", name, String::from_utf8_lossy(&stderr))));
+    }
+    response
+}
+
+/// Time spent in the PHP backend, carried on the response for request logging
+#[derive(Clone, Copy)]
+struct PhpTiming(Duration);
+
+fn with_php_timing(mut response: Response, backend_start: Instant) -> Response {
+    response.extensions_mut().insert(PhpTiming(backend_start.elapsed()));
+    response
+}
+
+/// URL-path of the script run in place of the one requested, as for a
+/// FallbackResource
+#[derive(Clone)]
+struct ScriptName(String);
+
+/// SCRIPT_NAME for a request: the script it was handed to, else its path
+fn script_name(extensions: &axum::http::Extensions, uri: &Uri) -> String {
+    extensions.get::<ScriptName>().map_or_else(|| uri.path().to_string(), |name| name.0.clone())
+}
+
+/// Error detail carried on the response so it lands in the vhost's ErrorLog
+#[derive(Clone)]
+struct RequestError(String);
+
+/// Text of an error response wolfserve wrote itself; handle_request turns it
+/// into the serving vhost's error page
+#[derive(Clone)]
+struct ErrorPage(String);
+
+fn error_page(status: StatusCode, message: impl Into<String>) -> Response {
+    let message = message.into();
+    let mut response = (status, message.clone()).into_response();
+    response.extensions_mut().insert(ErrorPage(message));
+    response
+}
+
+/// An Apache-style HTML error page for `response`, naming the vhost's
+/// ServerAdmin as the contact when it has one
+fn render_error_page(response: Response, server_admin: Option<&str>) -> Response {
+    let Some(ErrorPage(message)) = response.extensions().get::<ErrorPage>().cloned() else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    let status = parts.status;
+    let reason = status.canonical_reason().unwrap_or("Error");
+    let contact = match server_admin {
+        Some(admin) if admin.contains("://") => {
+            format!(r#"the server administrator at <a href="{0}">{0}</a>"#, admin::html_escape(admin))
+        }
+        Some(admin) => format!(r#"the server administrator at <a href="mailto:{0}">{0}</a>"#, admin::html_escape(admin)),
+        None => "the server administrator".to_string(),
+    };
+    let body = format!(
+        "<!DOCTYPE html>\n<html><head>\n<title>{code} {reason}</title>\n</head><body>\n<h1>{reason}</h1>\n<p>{message}</p>\n\
+         <p>If you think this is a server error, please contact {contact}.</p>\n</body></html>\n",
+        code = status.as_u16(),
+        reason = reason,
+        message = admin::html_escape(&message),
+        contact = contact,
+    );
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, axum::body::Body::from(body))
+}
+
+/// Marks an error page whose message describes a backend failure; without
+/// `[php] error_detail` handle_request replaces it with a generic one
+#[derive(Clone, Copy)]
+struct BackendError;
+
+/// Backend failure response: the message is the ErrorLog entry, and the body
+/// with `[php] error_detail`
+fn backend_error(status: StatusCode, message: impl Into<String>) -> Response {
+    let message = message.into();
+    let mut response = error_page(status, message.clone());
+    response.extensions_mut().insert(RequestError(message));
+    response.extensions_mut().insert(BackendError);
+    response
+}
+
+fn php_error(status: StatusCode, message: impl Into<String>, backend_start: Instant) -> Response {
+    with_php_timing(backend_error(status, message), backend_start)
+}
+
+/// What clients are told of a backend failure when its detail is withheld
+fn backend_error_summary(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::GATEWAY_TIMEOUT => "The application did not respond in time",
+        StatusCode::SERVICE_UNAVAILABLE => "The application is overloaded; please retry shortly",
+        StatusCode::BAD_GATEWAY => "The application server failed to answer this request",
+        _ => "The application could not be run",
+    }
+}
+
+/// PHP ran past the configured Timeout
+fn php_timeout(state: &AppState, backend_start: Instant) -> Response {
+    let limit = &state.connection.timeout;
+    php_error(
+        StatusCode::GATEWAY_TIMEOUT,
+        format!("PHP did not respond within {:?} (Timeout from {})", limit.value, limit.source),
+        backend_start,
+    )
+}
+
+/// PHP backend time in milliseconds, if the response came from PHP
+fn php_duration_ms(response: &Response) -> Option<u64> {
+    response.extensions().get::<PhpTiming>().map(|t| t.0.as_millis() as u64)
+}
+
+fn parse_php_response(stdout: Vec<u8>) -> Response {
+    let mut status_code = StatusCode::OK;
+    let mut headers = HeaderMap::new();
+
+    let split_indices = stdout.windows(4).position(|window| window == b"\r\n\r\n");
+    
+    let body_data = if let Some(idx) = split_indices {
+        let header_part = &stdout[0..idx];
+        let body_part = &stdout[idx+4..];
+        
+        // Header names are ASCII but values may carry any byte (Latin-1
+        // filenames in Content-Disposition, say), so parse byte-wise
+        for line in header_part.split(|&b| b == b'\n') {
+            let Some(colon) = line.iter().position(|&b| b == b':') else {
+                continue;
+            };
+            let key = line[..colon].trim_ascii();
+            let value = line[colon + 1..].trim_ascii();
+            if key.eq_ignore_ascii_case(b"Status") {
+                let code = value.split(|b| b.is_ascii_whitespace()).next().unwrap_or_default();
+                if let Some(s) = std::str::from_utf8(code).ok().and_then(|c| c.parse::<u16>().ok()).and_then(|c| StatusCode::from_u16(c).ok()) {
+                    status_code = s;
+                }
+            } else if let (Ok(hname), Ok(hval)) = (
+                axum::http::header::HeaderName::from_bytes(key),
+                axum::http::header::HeaderValue::from_bytes(value),
+            ) {
+                // Use append for Set-Cookie to allow multiple cookies
+                // (insert would replace previous values)
+                if hname == axum::http::header::SET_COOKIE {
+                    headers.append(hname, hval);
+                } else {
+                    headers.insert(hname, hval);
+                }
+            }
+        }
+        body_part.to_vec()
+    } else {
+        stdout
+    };
+
+    (status_code, headers, body_data).into_response()
+}
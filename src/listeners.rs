@@ -0,0 +1,301 @@
+//! What each port serves: its protocol and vhosts, from the vhosts'
+//! SSLEngine, Apache's `Listen port protocol` and the `[ports]` table of
+//! wolfserve.toml. A listener speaks either plain HTTP or TLS, so a port
+//! with both kinds of vhost is an error unless `[ports]` picks one.
+//!
+//! `[ports]` also sets the HTTP versions of a listener: the ALPN list of an
+//! HTTPS port, and whether a plain port takes HTTP/2 with prior knowledge
+//! (h2c). HTTPS ports default to h2 and HTTP/1.1, plain ports to HTTP/1.1.
+//!
+//! The ports are those of the vhosts, `[server] port` and `[ports]`. A
+//! `Listen` for any other port opens nothing; Debian's ports.conf lists 80
+//! and 443 whether or not anything is served there.
+
+use crate::apache::{Listen, VirtualHost};
+use crate::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The port Apache serves TLS on when `Listen` does not say
+const HTTPS_PORT: u16 = 443;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http,
+    Https,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::Http => "HTTP",
+            Protocol::Https => "HTTPS",
+        }
+    }
+}
+
+/// An HTTP version a listener may speak, by its ALPN name. On a plain
+/// listener, `h2` is cleartext HTTP/2 with prior knowledge.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpVersion {
+    #[serde(rename = "http/1.1")]
+    Http11,
+    #[serde(rename = "h2")]
+    H2,
+}
+
+impl HttpVersion {
+    pub fn alpn(self) -> &'static [u8] {
+        match self {
+            HttpVersion::Http11 => b"http/1.1",
+            HttpVersion::H2 => b"h2",
+        }
+    }
+}
+
+/// A `[ports]` entry: the protocol alone, or a table
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum PortSetting {
+    Protocol(Protocol),
+    Table {
+        protocol: Option<Protocol>,
+        http_versions: Option<Vec<HttpVersion>>,
+    },
+}
+
+impl PortSetting {
+    fn protocol(&self) -> Option<Protocol> {
+        match self {
+            PortSetting::Protocol(protocol) => Some(*protocol),
+            PortSetting::Table { protocol, .. } => *protocol,
+        }
+    }
+
+    fn http_versions(&self) -> Option<&[HttpVersion]> {
+        match self {
+            PortSetting::Protocol(_) => None,
+            PortSetting::Table { http_versions, .. } => http_versions.as_deref(),
+        }
+    }
+}
+
+/// Where a listener's protocol came from
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// `[ports]` in wolfserve.toml
+    Ports,
+    /// The vhosts on the port, all SSL or all plain
+    Vhosts,
+    /// `Listen port protocol`
+    Listen,
+    /// HTTPS for 443, HTTP otherwise
+    Default,
+}
+
+/// One listening port
+#[derive(Serialize, Clone, Debug)]
+pub struct PortListener {
+    pub port: u16,
+    /// None when the vhosts disagree and `[ports]` does not settle it
+    pub protocol: Option<Protocol>,
+    pub source: Option<Source>,
+    /// Names of the vhosts on the port
+    pub vhosts: Vec<String>,
+    /// Vhosts declared for the other protocol, served over this one because
+    /// `[ports]` says so
+    pub mismatched: Vec<String>,
+    /// In order of preference
+    pub http_versions: Vec<HttpVersion>,
+}
+
+/// The listeners, and the problems found working them out
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub listeners: Vec<PortListener>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Plan {
+    pub fn get(&self, port: u16) -> Option<&PortListener> {
+        self.listeners.iter().find(|listener| listener.port == port)
+    }
+
+    pub fn ports(&self, protocol: Protocol) -> Vec<u16> {
+        self.listeners.iter().filter(|l| l.protocol == Some(protocol)).map(|l| l.port).collect()
+    }
+}
+
+/// The vhosts of one port, and what `Listen` said about it
+#[derive(Default)]
+struct PortVhosts {
+    ssl: Vec<String>,
+    plain: Vec<String>,
+    listen: Option<Protocol>,
+}
+
+pub fn vhost_name(vhost: &VirtualHost) -> String {
+    vhost.server_name.clone().unwrap_or_else(|| "(default)".to_string())
+}
+
+/// `name:port`, which tells apart vhosts of one name on different ports
+pub fn vhost_key(vhost: &VirtualHost) -> String {
+    format!("{}:{}", vhost_name(vhost), vhost.port)
+}
+
+/// Work out the listener of every port
+pub fn plan<'a>(config: &Config, listen: &[Listen], vhosts: impl IntoIterator<Item = &'a VirtualHost>) -> Plan {
+    let mut plan = Plan::default();
+    let mut ports: BTreeMap<u16, PortVhosts> = BTreeMap::new();
+    ports.entry(config.server.port).or_default();
+    for vhost in vhosts {
+        let entry = ports.entry(vhost.port).or_default();
+        if vhost.is_ssl() {
+            entry.ssl.push(vhost_name(vhost));
+        } else {
+            entry.plain.push(vhost_name(vhost));
+        }
+    }
+
+    let mut overrides = BTreeMap::new();
+    let mut versions = BTreeMap::new();
+    for (key, setting) in &config.ports {
+        let port = match key.parse::<u16>() {
+            Ok(port) if port > 0 => port,
+            _ => {
+                plan.errors.push(format!("[ports] {}: not a port number", key));
+                continue;
+            }
+        };
+        ports.entry(port).or_default();
+        if let Some(protocol) = setting.protocol() {
+            overrides.insert(port, protocol);
+        }
+        match setting.http_versions() {
+            Some([]) => plan.errors.push(format!("[ports] {}: http_versions is empty", key)),
+            Some(list) => {
+                versions.insert(port, list.to_vec());
+            }
+            None => {}
+        }
+    }
+
+    for directive in listen {
+        let Some(entry) = ports.get_mut(&directive.port) else {
+            continue;
+        };
+        match directive.protocol.as_deref() {
+            None => {}
+            Some("http") => entry.listen = Some(Protocol::Http),
+            Some("https") => entry.listen = Some(Protocol::Https),
+            Some(other) => plan
+                .warnings
+                .push(format!("Listen {} {}: unknown protocol, ignored", directive.port, other)),
+        }
+    }
+
+    for (port, vhosts) in ports {
+        let by_vhosts = match (vhosts.ssl.is_empty(), vhosts.plain.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(Protocol::Https),
+            (true, false) => Some(Protocol::Http),
+            (false, false) => {
+                if !overrides.contains_key(&port) {
+                    let names: Vec<String> = vhosts
+                        .ssl
+                        .iter()
+                        .map(|name| format!("{} (SSL)", name))
+                        .chain(vhosts.plain.iter().cloned())
+                        .collect();
+                    plan.errors.push(format!(
+                        "port {} has both SSL and non-SSL vhosts: {}; move one kind to another port or set [ports] {} = \"http\" or \"https\"",
+                        port,
+                        names.join(", "),
+                        port
+                    ));
+                }
+                None
+            }
+        };
+        if let (Some(listen), Some(vhosts_protocol)) = (vhosts.listen, by_vhosts) {
+            if listen != vhosts_protocol && !overrides.contains_key(&port) {
+                plan.warnings.push(format!(
+                    "Listen {} {} ignored: the port's vhosts are {}",
+                    port,
+                    listen.name().to_lowercase(),
+                    vhosts_protocol.name()
+                ));
+            }
+        }
+
+        let (protocol, source) = if let Some(protocol) = overrides.get(&port) {
+            (Some(*protocol), Some(Source::Ports))
+        } else if let Some(protocol) = by_vhosts {
+            (Some(protocol), Some(Source::Vhosts))
+        } else if !vhosts.ssl.is_empty() && !vhosts.plain.is_empty() {
+            // Reported above
+            (None, None)
+        } else if let Some(protocol) = vhosts.listen {
+            (Some(protocol), Some(Source::Listen))
+        } else if port == HTTPS_PORT {
+            (Some(Protocol::Https), Some(Source::Default))
+        } else {
+            (Some(Protocol::Http), Some(Source::Default))
+        };
+
+        let mismatched = match protocol.filter(|_| source == Some(Source::Ports)) {
+            Some(Protocol::Https) => vhosts.plain.clone(),
+            Some(Protocol::Http) => vhosts.ssl.clone(),
+            None => Vec::new(),
+        };
+        if let (false, Some(protocol)) = (mismatched.is_empty(), protocol) {
+            plan.warnings.push(format!(
+                "port {} is {} by [ports]; {} served over {} too",
+                port,
+                protocol.name(),
+                mismatched.join(", "),
+                protocol.name()
+            ));
+        }
+
+        let http_versions = versions.remove(&port).unwrap_or_else(|| match protocol {
+            Some(Protocol::Https) => vec![HttpVersion::H2, HttpVersion::Http11],
+            _ => vec![HttpVersion::Http11],
+        });
+
+        plan.listeners.push(PortListener {
+            port,
+            protocol,
+            source,
+            vhosts: vhosts.ssl.into_iter().chain(vhosts.plain).collect(),
+            mismatched,
+            http_versions,
+        });
+    }
+    plan
+}
+
+/// "2 HTTP, 1 HTTPS", for the dashboard
+pub fn summary(listeners: &[PortListener]) -> String {
+    let count = |protocol| listeners.iter().filter(|l| l.protocol == Some(protocol)).count();
+    format!("{} HTTP, {} HTTPS", count(Protocol::Http), count(Protocol::Https))
+}
+
+/// One "port protocol: vhosts" line per listener, for the dashboard tooltip
+pub fn details(listeners: &[PortListener]) -> String {
+    listeners
+        .iter()
+        .map(|listener| {
+            let protocol = listener.protocol.map_or("conflict", Protocol::name);
+            if listener.vhosts.is_empty() {
+                format!("{} {}: no vhosts", listener.port, protocol)
+            } else {
+                format!("{} {}: {}", listener.port, protocol, listener.vhosts.join(", "))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,107 @@
+//! The request log on disk, so the dashboard can page back further than the
+//! in-memory buffer holds: `[admin] log_file` gets one JSON entry per line,
+//! appended by a background task. Past `log_file_max_bytes` the file is
+//! rotated to `<log_file>.1`, replacing the previous one, so the ring takes
+//! at most twice that on disk.
+//!
+//! log_request only hands entries to the writer's queue; when the disk
+//! cannot keep up, entries are dropped from the file rather than delaying
+//! requests. A file that cannot be read, or lines that don't parse, leave
+//! the dashboard showing what it can, down to the in-memory buffer alone.
+
+use crate::admin::RequestLogEntry;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// Entries waiting for the writer before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Entries written between flushes at most
+const WRITE_BATCH: usize = 256;
+
+#[derive(Clone)]
+pub struct LogRing {
+    sender: mpsc::Sender<RequestLogEntry>,
+    path: PathBuf,
+}
+
+/// Where the file is rotated to
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+impl LogRing {
+    /// Start the writer appending to `path`
+    pub fn spawn(path: PathBuf, max_bytes: u64) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write(receiver, path.clone(), max_bytes));
+        LogRing { sender, path }
+    }
+
+    /// Queue `entry` for the file, or drop it if the writer is behind
+    pub fn push(&self, entry: &RequestLogEntry) {
+        let _ = self.sender.try_send(entry.clone());
+    }
+
+    /// Every entry on disk, oldest first; None if the file cannot be read.
+    /// Lines that don't parse, such as one cut short by a crash, are skipped.
+    pub async fn read(&self) -> Option<Vec<RequestLogEntry>> {
+        let mut entries = Vec::new();
+        for path in [rotated_path(&self.path), self.path.clone()] {
+            let data = match fs::read_to_string(&path).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    eprintln!("Warning: cannot read request log {}: {}; showing the in-memory log only", path.display(), e);
+                    return None;
+                }
+            };
+            entries.extend(data.lines().filter_map(|line| serde_json::from_str::<RequestLogEntry>(line).ok()));
+        }
+        Some(entries)
+    }
+}
+
+async fn open_append(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+/// Append queued entries in batches, rotating the file past `max_bytes`
+async fn write(mut receiver: mpsc::Receiver<RequestLogEntry>, path: PathBuf, max_bytes: u64) {
+    // Taken while in use, so an error leaves it to be reopened
+    let mut file: Option<(BufWriter<File>, u64)> = None;
+    let mut batch = Vec::with_capacity(WRITE_BATCH);
+    while receiver.recv_many(&mut batch, WRITE_BATCH).await > 0 {
+        let result = async {
+            let (mut writer, mut size) = match file.take() {
+                Some(open) => open,
+                None => open_append(&path).await?,
+            };
+            for entry in batch.drain(..) {
+                let mut line = serde_json::to_vec(&entry).map_err(std::io::Error::other)?;
+                line.push(b'\n');
+                if size > 0 && size + line.len() as u64 > max_bytes {
+                    writer.flush().await?;
+                    fs::rename(&path, rotated_path(&path)).await?;
+                    (writer, size) = open_append(&path).await?;
+                }
+                writer.write_all(&line).await?;
+                size += line.len() as u64;
+            }
+            writer.flush().await?;
+            file = Some((writer, size));
+            Ok::<_, std::io::Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("Warning: cannot write request log {}: {}", path.display(), e);
+            batch.clear();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Per-vhost log files for the Apache ErrorLog and CustomLog directives.
+//! Files are opened lazily and a single handle is shared when several vhosts
+//! point at the same path.
+
+use chrono::{DateTime, Local};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Open log file handles keyed by path. A failed open is remembered as None
+/// so the warning is printed once and callers fall back to the global log.
+#[derive(Default)]
+pub struct LogFiles {
+    handles: Mutex<HashMap<PathBuf, Option<Arc<Mutex<File>>>>>,
+}
+
+impl LogFiles {
+    /// Append a line to the log at `path`. Returns false when the file
+    /// cannot be opened or written.
+    pub fn append(&self, path: &Path, line: &str) -> bool {
+        let Some(handle) = self.handle(path) else {
+            return false;
+        };
+        let mut file = handle.lock();
+        file.write_all(format!("{}\n", line).as_bytes()).is_ok()
+    }
+
+    fn handle(&self, path: &Path) -> Option<Arc<Mutex<File>>> {
+        let mut handles = self.handles.lock();
+        if let Some(handle) = handles.get(path) {
+            return handle.clone();
+        }
+
+        let opened = match open_log(path) {
+            Ok(file) => Some(Arc::new(Mutex::new(file))),
+            Err(e) => {
+                eprintln!("Cannot open log file {}: {} (using the global log instead)", path.display(), e);
+                None
+            }
+        };
+        handles.insert(path.to_path_buf(), opened.clone());
+        opened
+    }
+}
+
+fn open_log(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Request details available to access log formats
+pub struct AccessRecord<'a> {
+    pub time: DateTime<Local>,
+    pub client_ip: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub protocol: &'a str,
+    /// Negotiated TLS version (`TLSv1.3`), empty for plain HTTP
+    pub tls_protocol: &'a str,
+    pub status: u16,
+    pub bytes_sent: Option<u64>,
+    pub duration_us: u128,
+    pub server_name: &'a str,
+    pub port: u16,
+    pub referer: &'a str,
+    pub user_agent: &'a str,
+}
+
+/// Expand the stock Apache format nicknames; anything else is treated as a
+/// literal format string
+fn resolve_format(format: &str) -> &str {
+    match format {
+        "common" => r#"%h %l %u %t "%r" %>s %O"#,
+        "combined" => r#"%h %l %u %t "%r" %>s %O "%{Referer}i" "%{User-Agent}i""#,
+        "vhost_combined" => r#"%v:%p %h %l %u %t "%r" %>s %O "%{Referer}i" "%{User-Agent}i""#,
+        "referer" => "%{Referer}i -> %U",
+        "agent" => "%{User-agent}i",
+        other => other,
+    }
+}
+
+/// Render an access log line using the subset of mod_log_config format
+/// codes that wolfserve can supply
+pub fn format_access_line(format: &str, record: &AccessRecord) -> String {
+    let format = resolve_format(format);
+    let mut out = String::with_capacity(format.len() + 64);
+    let mut chars = format.chars().peekable();
+    let dash_if_empty = |s: &str| if s.is_empty() { "-".to_string() } else { s.to_string() };
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        // %{Name}i style argument
+        let mut arg = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                arg.push(c);
+            }
+        }
+        // %>s means the final status, which is the only one we have
+        if chars.peek() == Some(&'>') || chars.peek() == Some(&'<') {
+            chars.next();
+        }
+
+        match chars.next() {
+            Some('h') | Some('a') => out.push_str(record.client_ip),
+            Some('l') | Some('u') => out.push('-'),
+            Some('t') => out.push_str(&record.time.format("[%d/%b/%Y:%H:%M:%S %z]").to_string()),
+            Some('r') => {
+                let query = if record.query.is_empty() { String::new() } else { format!("?{}", record.query) };
+                out.push_str(&format!("{} {}{} {}", record.method, record.path, query, record.protocol));
+            }
+            Some('s') => out.push_str(&record.status.to_string()),
+            Some('b') => out.push_str(&record.bytes_sent.filter(|b| *b > 0).map_or("-".to_string(), |b| b.to_string())),
+            Some('B') | Some('O') => out.push_str(&record.bytes_sent.unwrap_or(0).to_string()),
+            Some('D') => out.push_str(&record.duration_us.to_string()),
+            Some('T') => out.push_str(&(record.duration_us / 1_000_000).to_string()),
+            Some('v') | Some('V') => out.push_str(&dash_if_empty(record.server_name)),
+            Some('p') => out.push_str(&record.port.to_string()),
+            Some('m') => out.push_str(record.method),
+            Some('U') => out.push_str(record.path),
+            Some('q') => {
+                if !record.query.is_empty() {
+                    out.push('?');
+                    out.push_str(record.query);
+                }
+            }
+            Some('H') => out.push_str(record.protocol),
+            Some('i') => {
+                let value = if arg.eq_ignore_ascii_case("referer") {
+                    record.referer
+                } else if arg.eq_ignore_ascii_case("user-agent") {
+                    record.user_agent
+                } else {
+                    ""
+                };
+                out.push_str(&dash_if_empty(value));
+            }
+            // mod_ssl's %{SSL_PROTOCOL}x; other variables are unknown
+            Some('x') => {
+                let value = if arg.eq_ignore_ascii_case("ssl_protocol") { record.tls_protocol } else { "" };
+                out.push_str(&dash_if_empty(value));
+            }
+            Some('%') => out.push('%'),
+            // Unsupported code: emit "-" like Apache does for missing data
+            Some(_) => out.push('-'),
+            None => out.push('%'),
+        }
+    }
+
+    out
+}
+
+/// Render an error log line in Apache's layout
+pub fn format_error_line(client_ip: &str, message: &str) -> String {
+    format!(
+        "[{}] [wolfserve:error] [pid {}] [client {}] {}",
+        Local::now().format("%a %b %d %H:%M:%S%.6f %Y"),
+        std::process::id(),
+        client_ip,
+        message.trim_end()
+    )
+}
@@ -0,0 +1,211 @@
+//! Failed admin sign-ins, counted per client address and per username.
+//!
+//! Past `FREE_FAILURES` an address is locked out, for a second after the next
+//! failure and twice as long after each one after that, up to `MAX_LOCKOUT`.
+//! A username is never locked: anyone can type one, so that would let any
+//! client lock the real admin out. Past `FREE_FAILURES` a sign-in as it waits
+//! instead before its password is checked: a second, then twice as long after
+//! each further failure, up to `MAX_BACKOFF`. That slows a guesser spread over
+//! many addresses.
+//!
+//! Each table holds at most `MAX_KEYS` entries. Those whose last failure is
+//! older than `FORGET_AFTER` and that are no longer locked are dropped; when
+//! the table is full of live ones, the one that failed longest ago goes.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Failures allowed before an address is locked out or a username slowed
+const FREE_FAILURES: u32 = 5;
+/// Longest an address is locked out for
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Longest a sign-in as a username waits
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Failures are forgotten this long after the last one
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Entries each table holds at most
+const MAX_KEYS: usize = 10_000;
+/// Characters of a username that key its failures
+const MAX_USERNAME: usize = 64;
+
+/// Connections without a known address share one entry
+type Client = Option<IpAddr>;
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_stale(&self, now: Instant) -> bool {
+        now.duration_since(self.last) > FORGET_AFTER && self.locked_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Failures by key, at most `MAX_KEYS` of them
+struct Table<K>(HashMap<K, Failures>);
+
+impl<K> Default for Table<K> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone> Table<K> {
+    /// Count a failure of `key`, starting afresh from stale ones
+    fn record(&mut self, key: K, now: Instant) -> &mut Failures {
+        if !self.0.contains_key(&key) && self.0.len() >= MAX_KEYS {
+            self.0.retain(|_, entry| !entry.is_stale(now));
+            if self.0.len() >= MAX_KEYS {
+                let oldest = self.0.iter().min_by_key(|(_, entry)| entry.last).map(|(key, _)| key.clone());
+                self.0.remove(&oldest.expect("table is full"));
+            }
+        }
+        let entry = self.0.entry(key).or_insert(Failures { count: 0, last: now, locked_until: None });
+        if entry.is_stale(now) {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.last = now;
+        entry
+    }
+}
+
+#[derive(Default)]
+pub struct LoginThrottle {
+    clients: Mutex<Table<Client>>,
+    usernames: Mutex<Table<String>>,
+}
+
+/// Doubling wait after `count` failures, from a second once past `FREE_FAILURES`
+fn doubling(count: u32, max: Duration) -> Option<Duration> {
+    let doublings = count.checked_sub(FREE_FAILURES + 1)?;
+    Some(Duration::from_secs(1u64 << doublings.min(20)).min(max))
+}
+
+fn username_key(username: &str) -> String {
+    username.chars().take(MAX_USERNAME).collect()
+}
+
+impl LoginThrottle {
+    /// How much longer sign-ins from `client` are refused, if they are
+    pub fn locked_for(&self, client: Client) -> Option<Duration> {
+        let now = Instant::now();
+        self.clients
+            .lock()
+            .0
+            .get(&client)?
+            .locked_until?
+            .checked_duration_since(now)
+            .filter(|left| !left.is_zero())
+    }
+
+    /// How long a sign-in as `username` waits before its password is checked
+    pub fn delay_for(&self, username: &str) -> Option<Duration> {
+        let usernames = self.usernames.lock();
+        let entry = usernames.0.get(&username_key(username))?;
+        if entry.is_stale(Instant::now()) {
+            return None;
+        }
+        // The wait the next failure would bring on
+        doubling(entry.count + 1, MAX_BACKOFF)
+    }
+
+    /// Count a failed sign-in from `client` as `username`; the lockout of
+    /// `client` it starts, if any
+    pub fn record_failure(&self, client: Client, username: &str) -> Option<Duration> {
+        let now = Instant::now();
+        self.usernames.lock().record(username_key(username), now);
+        let mut clients = self.clients.lock();
+        let entry = clients.record(client, now);
+        let duration = doubling(entry.count, MAX_LOCKOUT)?;
+        entry.locked_until = Some(now + duration);
+        Some(duration)
+    }
+
+    /// Forget the failures of `client` and `username` after a sign-in
+    pub fn record_success(&self, client: Client, username: &str) {
+        self.clients.lock().0.remove(&client);
+        self.usernames.lock().0.remove(&username_key(username));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> Client {
+        Some(IpAddr::from([192, 0, 2, last]))
+    }
+
+    #[test]
+    fn an_address_is_locked_out_past_the_free_failures() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..FREE_FAILURES {
+            assert_eq!(throttle.record_failure(addr(1), "admin"), None);
+        }
+        assert_eq!(throttle.locked_for(addr(1)), None);
+
+        assert_eq!(throttle.record_failure(addr(1), "admin"), Some(Duration::from_secs(1)));
+        assert!(throttle.locked_for(addr(1)).is_some());
+        assert_eq!(throttle.record_failure(addr(1), "admin"), Some(Duration::from_secs(2)));
+        // Other addresses are not locked
+        assert_eq!(throttle.locked_for(addr(2)), None);
+
+        throttle.record_success(addr(1), "admin");
+        assert_eq!(throttle.locked_for(addr(1)), None);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_longest() {
+        assert_eq!(doubling(FREE_FAILURES, MAX_LOCKOUT), None);
+        assert_eq!(doubling(FREE_FAILURES + 3, MAX_LOCKOUT), Some(Duration::from_secs(4)));
+        assert_eq!(doubling(FREE_FAILURES + 100, MAX_LOCKOUT), Some(MAX_LOCKOUT));
+        assert_eq!(doubling(FREE_FAILURES + 100, MAX_BACKOFF), Some(MAX_BACKOFF));
+    }
+
+    #[test]
+    fn a_username_guessed_from_many_addresses_is_slowed_not_locked() {
+        let throttle = LoginThrottle::default();
+        for last in 0..FREE_FAILURES as u8 {
+            assert_eq!(throttle.delay_for("admin"), None);
+            throttle.record_failure(addr(last), "admin");
+        }
+        assert_eq!(throttle.delay_for("admin"), Some(Duration::from_secs(1)));
+        throttle.record_failure(addr(100), "admin");
+        assert_eq!(throttle.delay_for("admin"), Some(Duration::from_secs(2)));
+        // No address failed often enough to be locked out
+        assert!((0..=100).all(|last| throttle.locked_for(addr(last)).is_none()));
+        assert_eq!(throttle.delay_for("someone"), None);
+
+        throttle.record_success(addr(7), "admin");
+        assert_eq!(throttle.delay_for("admin"), None);
+    }
+
+    #[test]
+    fn usernames_are_keyed_by_their_start() {
+        let throttle = LoginThrottle::default();
+        let long = "x".repeat(10 * MAX_USERNAME);
+        for last in 0..=FREE_FAILURES as u8 {
+            throttle.record_failure(addr(last), &long);
+        }
+        assert_eq!(throttle.usernames.lock().0.keys().map(String::len).collect::<Vec<_>>(), [MAX_USERNAME]);
+        assert!(throttle.delay_for(&long[..MAX_USERNAME]).is_some());
+    }
+
+    #[test]
+    fn full_tables_drop_the_oldest_failure() {
+        let mut table = Table::default();
+        let start = Instant::now();
+        for i in 0..=MAX_KEYS {
+            table.record(i, start + Duration::from_millis(i as u64));
+        }
+        assert_eq!(table.0.len(), MAX_KEYS);
+        assert!(!table.0.contains_key(&0));
+        assert!(table.0.contains_key(&MAX_KEYS));
+    }
+}
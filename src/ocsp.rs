@@ -0,0 +1,389 @@
+//! OCSP stapling for the served certificates.
+//! A background task asks the responder named in each certificate's
+//! Authority Information Access extension for the certificate's status and
+//! staples the response to its key, so rustls sends it in the handshake.
+//! Responses are cached by certificate and refreshed halfway to their
+//! nextUpdate. A failed fetch only means no staple (an expired one is
+//! dropped); the response signature is left to clients to verify.
+
+use crate::acme::{self, der, sequence};
+use crate::admin::OcspStatus;
+use crate::certs::LoadedCert;
+use crate::tls::{der_element, der_time, tbs_fields};
+use crate::AppState;
+use anyhow::{bail, Context};
+use aws_lc_rs::digest;
+use chrono::{DateTime, Utc};
+use hyper::Method;
+use rustls::pki_types::CertificateDer;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Seconds between checks for staples that are due; failed fetches are
+/// retried at the same pace
+const CHECK_INTERVAL: u64 = 600;
+
+// Object identifiers (contents only)
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+const OID_OCSP: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+
+const MALFORMED: &str = "malformed OCSP response";
+
+/// A good response for one certificate
+struct Staple {
+    response: Vec<u8>,
+    fetched: DateTime<Utc>,
+    this_update: DateTime<Utc>,
+    next_update: Option<DateTime<Utc>>,
+}
+
+impl Staple {
+    /// Halfway between thisUpdate and nextUpdate; daily without a nextUpdate
+    fn refresh_at(&self) -> DateTime<Utc> {
+        match self.next_update {
+            Some(next) => self.this_update + (next - self.this_update) / 2,
+            None => self.this_update + chrono::Duration::days(1),
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.next_update.is_some_and(|next| next <= Utc::now())
+    }
+
+    fn status(&self) -> OcspStatus {
+        let message = match self.next_update {
+            Some(next) => format!("good, next update {}", next.format("%Y-%m-%d %H:%M UTC")),
+            None => "good".to_string(),
+        };
+        OcspStatus {
+            time: self.fetched,
+            stapled: true,
+            message,
+            produced: Some(self.this_update),
+            next_update: self.next_update,
+        }
+    }
+}
+
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        // Keyed by end-entity certificate, so a reloaded copy keeps its staple
+        let mut cache = HashMap::new();
+        loop {
+            refresh(&state, &mut cache).await;
+            tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL)).await;
+        }
+    });
+}
+
+/// Fetch the responses that are missing or due, staple them and update the
+/// dashboard
+async fn refresh(state: &AppState, cache: &mut HashMap<Vec<u8>, Staple>) {
+    let keys = state.certs.keys();
+    let previous = state.admin_state.ocsp.read().clone();
+    let mut statuses = BTreeMap::new();
+
+    for LoadedCert { label, key, .. } in &keys {
+        let Some(leaf) = key.cert.first().map(|cert| cert.as_ref().to_vec()) else {
+            continue;
+        };
+        if let Some(staple) = cache.get(&leaf).filter(|staple| Utc::now() < staple.refresh_at()) {
+            statuses.insert(label.clone(), staple.status());
+            continue;
+        }
+
+        let status = match fetch(state, &key.cert).await {
+            Ok(staple) => {
+                state.certs.staple(&leaf, Some(staple.response.clone()));
+                let status = staple.status();
+                if !previous.get(label).is_some_and(|s| s.stapled) {
+                    println!("OCSP response stapled for {} ({})", label, status.message);
+                }
+                cache.insert(leaf, staple);
+                status
+            }
+            Err(e) => {
+                let message = format!("{:#}", e);
+                if previous.get(label).is_none_or(|s| s.message != message) {
+                    eprintln!("Warning: no OCSP staple for {}: {}", label, message);
+                }
+                if cache.get(&leaf).is_some_and(Staple::expired) {
+                    cache.remove(&leaf);
+                    state.certs.staple(&leaf, None);
+                }
+                match cache.get(&leaf) {
+                    // Keep serving the older response until it expires
+                    Some(staple) => OcspStatus { message: format!("refresh failed: {}", message), ..staple.status() },
+                    None => OcspStatus { time: Utc::now(), stapled: false, message, produced: None, next_update: None },
+                }
+            }
+        };
+        statuses.insert(label.clone(), status);
+    }
+
+    cache.retain(|leaf, _| keys.iter().any(|loaded| loaded.key.cert.first().is_some_and(|cert| cert.as_ref() == leaf.as_slice())));
+    *state.admin_state.ocsp.write() = statuses;
+}
+
+async fn fetch(state: &AppState, chain: &[CertificateDer<'static>]) -> anyhow::Result<Staple> {
+    let leaf = chain.first().context("empty certificate chain")?;
+    let url = responder_url(leaf).context("no OCSP responder URL in the certificate")?;
+    let issuer = chain.get(1).context("no issuer certificate in the chain")?;
+    let id = CertId::new(leaf, issuer).context("cannot read the certificate or its issuer")?;
+
+    let request = request_der(&id);
+    let tls = match url.starts_with("https:") {
+        true => Some(acme::client_tls(state.config.acme.ca_file.as_deref())?),
+        false => None,
+    };
+    let reply = acme::request(tls.as_ref(), Method::POST, &url, Some(("application/ocsp-request", request))).await?;
+    if !reply.status.is_success() {
+        bail!("{}: {}", url, reply.status);
+    }
+    parse_response(&reply.body, &id.serial).with_context(|| url.clone())
+}
+
+/// The OCSP URL from the Authority Information Access extension
+fn responder_url(cert: &[u8]) -> Option<String> {
+    let mut fields = tbs_fields(cert)?;
+    let extensions = loop {
+        let (tag, content, rest) = der_element(fields)?;
+        if tag == 0xa3 {
+            break der_element(content)?.1;
+        }
+        fields = rest;
+    };
+
+    let mut extensions = extensions;
+    while !extensions.is_empty() {
+        let (_, extension, rest) = der_element(extensions)?;
+        extensions = rest;
+        let (_, oid, value) = der_element(extension)?;
+        if oid != OID_AUTHORITY_INFO_ACCESS {
+            continue;
+        }
+        let (mut tag, mut value, rest) = der_element(value)?;
+        if tag == 0x01 {
+            // critical flag
+            (tag, value, _) = der_element(rest)?;
+        }
+        if tag != 0x04 {
+            return None;
+        }
+        let (_, mut descriptions, _) = der_element(value)?;
+        while !descriptions.is_empty() {
+            let (_, description, rest) = der_element(descriptions)?;
+            descriptions = rest;
+            let (_, method, location) = der_element(description)?;
+            let (tag, location, _) = der_element(location)?;
+            // uniformResourceIdentifier
+            if method == OID_OCSP && tag == 0x86 {
+                return String::from_utf8(location.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// The certificate a request asks about: SHA-1 hashes of the issuer's name
+/// and public key, and the serial number
+struct CertId {
+    name_hash: Vec<u8>,
+    key_hash: Vec<u8>,
+    serial: Vec<u8>,
+}
+
+impl CertId {
+    fn new(leaf: &[u8], issuer: &[u8]) -> Option<CertId> {
+        // serial, signature, issuer
+        let (_, serial, rest) = der_element(tbs_fields(leaf)?)?;
+        let (_, _, rest) = der_element(rest)?;
+        let (_, _, after_name) = der_element(rest)?;
+        let issuer_name = &rest[..rest.len() - after_name.len()];
+
+        // serial, signature, issuer, validity, subject, subjectPublicKeyInfo
+        let mut fields = tbs_fields(issuer)?;
+        for _ in 0..5 {
+            fields = der_element(fields)?.2;
+        }
+        let (_, key_info, _) = der_element(fields)?;
+        let (_, _, key_info) = der_element(key_info)?;
+        let (_, key, _) = der_element(key_info)?;
+        // BIT STRING contents start with the count of unused bits
+        let key = key.get(1..)?;
+
+        Some(CertId {
+            name_hash: digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, issuer_name).as_ref().to_vec(),
+            key_hash: digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, key).as_ref().to_vec(),
+            serial: serial.to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        sequence(&[
+            sequence(&[der(0x06, OID_SHA1), der(0x05, &[])]),
+            der(0x04, &self.name_hash),
+            der(0x04, &self.key_hash),
+            der(0x02, &self.serial),
+        ])
+    }
+}
+
+/// An unsigned request for one certificate without a nonce:
+/// OCSPRequest → tbsRequest → requestList → Request → reqCert
+fn request_der(id: &CertId) -> Vec<u8> {
+    sequence(&[sequence(&[sequence(&[sequence(&[id.encode()])])])])
+}
+
+/// Check that `body` says the certificate with `serial` is good and still
+/// current
+fn parse_response(body: &[u8], serial: &[u8]) -> anyhow::Result<Staple> {
+    let (_, response, _) = der_element(body).context(MALFORMED)?;
+    let (tag, status, rest) = der_element(response).context(MALFORMED)?;
+    if tag != 0x0a {
+        bail!(MALFORMED);
+    }
+    match status {
+        [0] => {}
+        [1] => bail!("responder says the request is malformed"),
+        [2] => bail!("responder internal error"),
+        [3] => bail!("responder busy (tryLater)"),
+        [5] => bail!("responder requires signed requests"),
+        [6] => bail!("responder is not authorized for this certificate"),
+        _ => bail!("responder status {:?}", status),
+    }
+
+    // [0] ResponseBytes: responseType, response (a BasicOCSPResponse)
+    let (_, bytes, _) = der_element(rest).context(MALFORMED)?;
+    let (_, bytes, _) = der_element(bytes).context(MALFORMED)?;
+    let (_, kind, rest) = der_element(bytes).context(MALFORMED)?;
+    if kind != OID_OCSP_BASIC {
+        bail!("unsupported OCSP response type");
+    }
+    let (_, basic, _) = der_element(rest).context(MALFORMED)?;
+    let (_, basic, _) = der_element(basic).context(MALFORMED)?;
+    let (_, data, _) = der_element(basic).context(MALFORMED)?;
+
+    // ResponseData: [0] version, responderID, producedAt, responses
+    let mut fields = data;
+    if der_element(fields).context(MALFORMED)?.0 == 0xa0 {
+        fields = der_element(fields).context(MALFORMED)?.2;
+    }
+    for _ in 0..2 {
+        fields = der_element(fields).context(MALFORMED)?.2;
+    }
+    let (_, mut responses, _) = der_element(fields).context(MALFORMED)?;
+
+    while !responses.is_empty() {
+        let (_, single, rest) = der_element(responses).context(MALFORMED)?;
+        responses = rest;
+        // certID: hashAlgorithm, issuerNameHash, issuerKeyHash, serialNumber
+        let (_, cert_id, rest) = der_element(single).context(MALFORMED)?;
+        let mut id_fields = cert_id;
+        for _ in 0..3 {
+            id_fields = der_element(id_fields).context(MALFORMED)?.2;
+        }
+        if der_element(id_fields).context(MALFORMED)?.1 != serial {
+            continue;
+        }
+
+        let (tag, _, rest) = der_element(rest).context(MALFORMED)?;
+        match tag {
+            0x80 => {}
+            0xa1 => bail!("the certificate is revoked"),
+            _ => bail!("the responder does not know the certificate"),
+        }
+        let (tag, this_update, rest) = der_element(rest).context(MALFORMED)?;
+        let this_update = der_time(tag, this_update).context(MALFORMED)?;
+        let next_update = match der_element(rest) {
+            Some((0xa0, next, _)) => Some(der_element(next).and_then(|(tag, time, _)| der_time(tag, time)).context(MALFORMED)?),
+            _ => None,
+        };
+        let staple = Staple { response: body.to_vec(), fetched: Utc::now(), this_update, next_update };
+        if staple.expired() {
+            bail!("the response expired (nextUpdate in the past)");
+        }
+        return Ok(staple);
+    }
+    bail!("no response for the certificate")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CA, certificates it issued with serials 1234 and 5678 (revoked), and
+    // what `openssl ocsp` sent and answered for them
+    const CA: &[u8] = include_bytes!("../tests/fixtures/ocsp/ca.der");
+    const LEAF: &[u8] = include_bytes!("../tests/fixtures/ocsp/leaf.der");
+    const REVOKED_LEAF: &[u8] = include_bytes!("../tests/fixtures/ocsp/revoked_cert.der");
+    const REQUEST: &[u8] = include_bytes!("../tests/fixtures/ocsp/good.req");
+    const GOOD: &[u8] = include_bytes!("../tests/fixtures/ocsp/good.der");
+    const REVOKED: &[u8] = include_bytes!("../tests/fixtures/ocsp/revoked.der");
+    /// Good, but with a nextUpdate in 2020
+    const STALE: &[u8] = include_bytes!("../tests/fixtures/ocsp/stale.der");
+
+    fn error(body: &[u8], serial: &[u8]) -> String {
+        match parse_response(body, serial) {
+            Ok(_) => panic!("response accepted"),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn responder_url_is_read_from_the_certificate() {
+        assert_eq!(responder_url(LEAF).as_deref(), Some("http://ocsp.example.test/"));
+        assert_eq!(responder_url(CA), None);
+    }
+
+    #[test]
+    fn request_matches_openssl() {
+        let id = CertId::new(LEAF, CA).unwrap();
+        assert_eq!(id.serial, [0x12, 0x34]);
+        assert_eq!(request_der(&id), REQUEST);
+    }
+
+    #[test]
+    fn good_response_is_stapled_as_received() {
+        let id = CertId::new(LEAF, CA).unwrap();
+        let staple = parse_response(GOOD, &id.serial).unwrap();
+        assert_eq!(staple.response, GOOD);
+        let next_update = staple.next_update.unwrap();
+        assert!(staple.this_update < Utc::now() && Utc::now() < next_update);
+        assert!(staple.refresh_at() < next_update);
+    }
+
+    #[test]
+    fn revoked_certificate_is_not_stapled() {
+        let id = CertId::new(REVOKED_LEAF, CA).unwrap();
+        assert_eq!(error(REVOKED, &id.serial), "the certificate is revoked");
+    }
+
+    #[test]
+    fn response_for_another_certificate_is_not_stapled() {
+        let id = CertId::new(REVOKED_LEAF, CA).unwrap();
+        assert_eq!(error(GOOD, &id.serial), "no response for the certificate");
+    }
+
+    #[test]
+    fn stale_response_is_not_stapled() {
+        let id = CertId::new(LEAF, CA).unwrap();
+        assert_eq!(error(STALE, &id.serial), "the response expired (nextUpdate in the past)");
+    }
+
+    #[test]
+    fn truncated_or_garbage_responses_are_refused() {
+        let id = CertId::new(LEAF, CA).unwrap();
+        for len in 0..GOOD.len() {
+            assert!(parse_response(&GOOD[..len], &id.serial).is_err(), "accepted {} bytes", len);
+        }
+        assert_eq!(error(b"<html>Service Unavailable</html>", &id.serial), MALFORMED);
+        assert_eq!(error(&[0x30, 0x03, 0x0a, 0x01, 0x03], &id.serial), "responder busy (tryLater)");
+        let mut flipped = GOOD.to_vec();
+        flipped[2..].iter_mut().for_each(|b| *b = b.rotate_left(3));
+        assert!(parse_response(&flipped, &id.serial).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Reloading the Apache vhost configuration: automatically when
+//! `[apache] watch` is on, and from the dashboard's Reload button.
+//! The config dir is polled for changed, added or removed files; once it has
+//! been quiet for a full interval the vhosts are re-parsed, checked and
+//! swapped into the running server.
+//!
+//! Polling rather than inotify is deliberate: sites-enabled is mostly
+//! symlinks into sites-available, whose targets a watch on the config dir
+//! would not see change, and inotify misses edits on network filesystems.
+//! A scan stats every file under the dir, on the blocking pool, once per
+//! `watch_interval`; for a config dir that is negligible. A reload that introduces new errors is
+//! rejected and the running configuration stays in place. .htaccess files
+//! need no watching: they are read on every request.
+//!
+//! One reload runs at a time; the dashboard's is refused while another is
+//! under way, the watcher's waits for it.
+
+use crate::admin::ReloadStatus;
+use crate::dump::VhostChanges;
+use crate::listeners::{Protocol, Source};
+use crate::{apache, certs, check, AppState, Sites};
+use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Modification time and size of every file under the config dir
+type Snapshot = BTreeMap<PathBuf, Option<(SystemTime, u64)>>;
+
+/// Note the errors of the running config, from its startup check report;
+/// errors already present there don't block a reload
+pub fn set_known_errors(state: &AppState, startup: &check::Report) {
+    if let Ok(mut known_errors) = state.reload_lock.try_lock() {
+        *known_errors = startup.errors().collect();
+    }
+}
+
+/// Watch `[apache] config_dir` and reload on changes
+pub fn spawn(state: Arc<AppState>) {
+    let config_dir = PathBuf::from(&state.config.apache.config_dir);
+    let interval = Duration::from_secs(state.config.apache.watch_interval.max(1));
+
+    println!("Watching {} for configuration changes (every {:?})", config_dir.display(), interval);
+    tokio::spawn(async move {
+        let mut last = scan(config_dir.clone()).await;
+        let mut pending = false;
+
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = scan(config_dir.clone()).await;
+            if current != last {
+                // Still changing; wait until edits settle
+                last = current;
+                pending = true;
+            } else if pending {
+                pending = false;
+                let mut known_errors = state.reload_lock.lock().await;
+                reload(&state, "configuration files changed", &mut known_errors).await;
+            }
+        }
+    });
+}
+
+async fn scan(config_dir: PathBuf) -> Snapshot {
+    tokio::task::spawn_blocking(move || {
+        let mut snapshot = Snapshot::new();
+        scan_dir(&config_dir, &mut snapshot);
+        snapshot
+    })
+    .await
+    .unwrap_or_default()
+}
+
+fn scan_dir(dir: &Path, snapshot: &mut Snapshot) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Symlinked files (sites-enabled) are followed, symlinked dirs are not
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            scan_dir(&path, snapshot);
+            continue;
+        }
+        let stamp = fs::metadata(&path).ok().and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+        snapshot.insert(path, stamp);
+    }
+}
+
+/// Reload now, unless a reload is already under way (None)
+pub async fn reload_now(state: &AppState, trigger: &str) -> Option<ReloadStatus> {
+    let mut known_errors = state.reload_lock.try_lock().ok()?;
+    Some(reload(state, trigger, &mut known_errors).await)
+}
+
+async fn reload(state: &AppState, trigger: &str, known_errors: &mut BTreeSet<String>) -> ReloadStatus {
+    println!("Reloading the Apache configuration ({})", trigger);
+    let config_dir = PathBuf::from(&state.config.apache.config_dir);
+    let include_extensionless = state.config.apache.include_extensionless;
+    let loaded = tokio::task::spawn_blocking(move || apache::load_apache_config(&config_dir, include_extensionless, true))
+        .await
+        .unwrap_or_default();
+    let mut vhosts = loaded.vhosts;
+    for vhost in &mut vhosts {
+        state.config.apply_vhost_overrides(vhost);
+    }
+
+    let report = check::validate(&state.config, &loaded.listen, &vhosts, false).await;
+    let new_errors: Vec<String> =
+        report.located_errors().filter(|(error, _)| !known_errors.contains(error)).map(|(_, located)| located).collect();
+    if !new_errors.is_empty() {
+        eprintln!("ERROR: configuration reload failed; keeping the running configuration");
+        for error in &new_errors {
+            eprintln!("ERROR:   {}", error);
+        }
+        return record(state, ReloadStatus {
+            time: Utc::now(),
+            ok: false,
+            message: new_errors.join("; "),
+            trigger: trigger.to_string(),
+            vhosts: VhostChanges::default(),
+            errors: new_errors,
+            certificates: None,
+        });
+    }
+    report.print_problems();
+
+    for vhost in &vhosts {
+        let name = vhost.server_name.as_deref().unwrap_or("default vhost");
+        let protocol = if vhost.is_ssl() { Protocol::Https } else { Protocol::Http };
+        match state.listeners.iter().find(|listener| listener.port == vhost.port) {
+            None => eprintln!(
+                "Warning: {} is on port {}, which is not being listened on; restart wolfserve to serve it",
+                name, vhost.port
+            ),
+            Some(listener) if listener.source != Some(Source::Ports) && listener.protocol != Some(protocol) => eprintln!(
+                "Warning: {} is {} but port {} is listening for {}; restart wolfserve to serve it",
+                name,
+                if vhost.is_ssl() { "SSL" } else { "non-SSL" },
+                vhost.port,
+                if vhost.is_ssl() { "plain HTTP" } else { "HTTPS" }
+            ),
+            Some(_) => {}
+        }
+    }
+
+    let changes = VhostChanges::between(state.sites.read().list.iter().map(|vhost| &**vhost), vhosts.iter());
+    let vhosts: Vec<_> = vhosts.into_iter().map(Arc::new).collect();
+    let count = vhosts.len();
+    let sites = Sites::build(vhosts, Arc::new(loaded.server));
+    sites.log();
+    *state.sites.write() = Arc::new(sites);
+    let certificates = (!state.certs.is_empty()).then(|| certs::reload(state, "configuration reloaded").to_json());
+    *known_errors = report.errors().collect();
+    let message = format!(
+        "{} vhost(s) loaded: {} added, {} removed, {} changed",
+        count,
+        changes.added.len(),
+        changes.removed.len(),
+        changes.changed.len()
+    );
+    println!("Configuration reloaded: {}", message);
+    record(state, ReloadStatus {
+        time: Utc::now(),
+        ok: true,
+        message,
+        trigger: trigger.to_string(),
+        vhosts: changes,
+        errors: Vec::new(),
+        certificates,
+    })
+}
+
+fn record(state: &AppState, status: ReloadStatus) -> ReloadStatus {
+    *state.admin_state.last_reload.write() = Some(status.clone());
+    status
+}
@@ -0,0 +1,170 @@
+//! Request bodies for the PHP backends. Small bodies stay in memory; once a
+//! body grows past the configured threshold it is spilled to a temp file so
+//! uploads don't sit in RAM while PHP runs.
+
+use axum::body::{Body, Bytes};
+use http_body_util::BodyExt;
+use hyper::body::Body as _;
+use std::io;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+pub enum RequestBody {
+    Memory(Bytes),
+    /// Spilled body and its length; the file is already unlinked, so it
+    /// disappears when closed
+    File(File, u64),
+}
+
+impl RequestBody {
+    /// Bytes received, which is what PHP gets as CONTENT_LENGTH: chunked
+    /// requests carry no Content-Length header of their own
+    pub fn content_length(&self) -> u64 {
+        match self {
+            RequestBody::Memory(bytes) => bytes.len() as u64,
+            RequestBody::File(_, len) => *len,
+        }
+    }
+
+    /// Reader positioned at the start of the body
+    pub fn into_reader(self) -> Box<dyn AsyncRead + Unpin + Send> {
+        match self {
+            RequestBody::Memory(bytes) => Box::new(io::Cursor::new(bytes)),
+            RequestBody::File(file, _) => Box::new(file),
+        }
+    }
+}
+
+pub enum ReadError {
+    /// The body is larger than the LimitRequestBody in force
+    TooLarge,
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// Read a request body, switching to a temp file in `temp_dir` once more
+/// than `memory_limit` bytes have arrived. Bodies over `max_size` are
+/// refused, up front when Content-Length already says so.
+pub async fn read_body(body: Body, memory_limit: usize, max_size: Option<u64>, temp_dir: &Path) -> Result<RequestBody, ReadError> {
+    let mut body = body;
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut spill: Option<File> = None;
+    let mut received: u64 = 0;
+
+    if max_size.is_some_and(|max| body.size_hint().lower() > max) {
+        return Err(ReadError::TooLarge);
+    }
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(io::Error::other)?;
+        let Ok(chunk) = frame.into_data() else {
+            continue; // trailers
+        };
+
+        received += chunk.len() as u64;
+        if max_size.is_some_and(|max| received > max) {
+            return Err(ReadError::TooLarge);
+        }
+
+        if let Some(file) = spill.as_mut() {
+            file.write_all(&chunk).await?;
+        } else if buffer.len() + chunk.len() > memory_limit {
+            let mut file = create_temp_file(temp_dir).await?;
+            file.write_all(&buffer).await?;
+            file.write_all(&chunk).await?;
+            buffer = Vec::new();
+            spill = Some(file);
+        } else {
+            buffer.extend_from_slice(&chunk);
+        }
+    }
+
+    match spill {
+        Some(mut file) => {
+            file.flush().await?;
+            file.rewind().await?;
+            Ok(RequestBody::File(file, received))
+        }
+        None => Ok(RequestBody::Memory(Bytes::from(buffer))),
+    }
+}
+
+/// Private temp file that is unlinked straight away: the open handle keeps
+/// the data reachable, and nothing is left behind on any exit path
+async fn create_temp_file(temp_dir: &Path) -> io::Result<File> {
+    let path = temp_dir.join(format!("wolfserve-body-{}", Uuid::new_v4()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .await?;
+    tokio::fs::remove_file(&path).await?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// A body arriving in `chunks`, without a length up front
+    fn streamed(chunks: &[&'static [u8]]) -> Body {
+        let chunks: Vec<io::Result<Bytes>> = chunks.iter().map(|chunk| Ok(Bytes::from_static(chunk))).collect();
+        Body::from_stream(futures_util::stream::iter(chunks))
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("wolfserve-body-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    async fn contents(body: RequestBody) -> Vec<u8> {
+        let mut read = Vec::new();
+        body.into_reader().read_to_end(&mut read).await.unwrap();
+        read
+    }
+
+    #[tokio::test]
+    async fn small_bodies_stay_in_memory() {
+        let dir = temp_dir();
+        let body = read_body(streamed(&[b"abc", b"def"]), 6, None, &dir).await.ok().unwrap();
+        assert!(matches!(body, RequestBody::Memory(_)));
+        assert_eq!(body.content_length(), 6);
+        assert_eq!(contents(body).await, b"abcdef");
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_past_the_memory_limit_spill_to_an_unlinked_file() {
+        let dir = temp_dir();
+        let body = read_body(streamed(&[b"abc", b"def", b"g"]), 4, None, &dir).await.ok().unwrap();
+        assert!(matches!(body, RequestBody::File(..)));
+        assert_eq!(body.content_length(), 7);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(contents(body).await, b"abcdefg");
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_maximum_are_refused() {
+        let dir = temp_dir();
+        // Known from the length alone
+        assert!(matches!(read_body(Body::from("x".repeat(11)), 4, Some(10), &dir).await, Err(ReadError::TooLarge)));
+        // Found out while spilling, leaving no file behind
+        let body = streamed(&[b"abcd", b"efgh", b"ijk"]);
+        assert!(matches!(read_body(body, 4, Some(10), &dir).await, Err(ReadError::TooLarge)));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(read_body(streamed(&[b"abcdefghij"]), 4, Some(10), &dir).await.ok().unwrap().content_length(), 10);
+        std::fs::remove_dir(dir).unwrap();
+    }
+}
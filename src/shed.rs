@@ -0,0 +1,119 @@
+//! Load-shedding responses: 413 (a body over LimitRequestBody), 429 and 503
+//! (the server is too busy). `[shed.503]` and the like in wolfserve.toml set
+//! an HTML and a JSON body and the Retry-After of each status, and the
+//! client's Accept header picks the body: API clients that rank JSON above
+//! HTML get JSON, everyone else HTML. Without a file of its own, HTML is the
+//! usual error page and JSON a built-in error object.
+
+use crate::{render_error_page, ErrorPage};
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The statuses `[shed]` may configure
+pub const STATUSES: [u16; 3] = [413, 429, 503];
+
+/// Bodies and Retry-After of one load-shedding status. The files are read
+/// for every response, so they can be edited while the server runs.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ShedPage {
+    /// Served to browsers and clients that don't prefer JSON
+    pub html_page: Option<PathBuf>,
+    /// Served to clients that rank application/json above text/html
+    pub json_page: Option<PathBuf>,
+    /// Seconds sent in Retry-After, replacing any the response already has
+    pub retry_after: Option<u64>,
+}
+
+/// The q-value `accept` gives `mime`, from its most specific matching range
+fn quality(accept: &str, mime: &str) -> f32 {
+    let top_level = mime.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let range = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let specificity = match range.strip_suffix("/*") {
+            _ if range == mime => 2,
+            Some(top) if top == top_level => 1,
+            Some("*") => 0,
+            _ => continue,
+        };
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()))
+            .unwrap_or(1.0);
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+/// Whether the request's Accept ranks JSON above HTML
+pub fn prefers_json(headers: &HeaderMap) -> bool {
+    let accept = headers.get_all(header::ACCEPT).iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(",");
+    quality(&accept, "application/json") > quality(&accept, "text/html")
+}
+
+/// `response` with `body` of `content_type`, keeping its status, headers and
+/// extensions
+fn with_body(response: Response, body: impl Into<Body>, content_type: &'static str) -> Response {
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body.into())
+}
+
+async fn read_page(path: &Path) -> Option<Vec<u8>> {
+    tokio::fs::read(path)
+        .await
+        .inspect_err(|e| eprintln!("Cannot read load-shedding page {}: {}", path.display(), e))
+        .ok()
+}
+
+/// The body for a load-shedding error response wolfserve wrote itself,
+/// negotiated by `headers`; other responses are returned as they are
+pub async fn render(response: Response, page: Option<&ShedPage>, headers: &HeaderMap, server_admin: Option<&str>) -> Response {
+    let Some(ErrorPage(message)) = response.extensions().get::<ErrorPage>().cloned() else {
+        return response;
+    };
+    let status = response.status();
+    let json = prefers_json(headers);
+    let file = page.and_then(|page| if json { page.json_page.as_deref() } else { page.html_page.as_deref() });
+    let body = match file {
+        Some(path) => read_page(path).await,
+        None => None,
+    };
+    let retry_after = page.and_then(|page| page.retry_after);
+
+    let mut response = match (body, json) {
+        (Some(body), true) => with_body(response, body, "application/json"),
+        (Some(body), false) => with_body(response, body, "text/html; charset=utf-8"),
+        (None, true) => {
+            let error = serde_json::json!({
+                "status": status.as_u16(),
+                "error": status.canonical_reason().unwrap_or("Error"),
+                "message": message,
+                "retry_after": retry_after.or_else(|| retry_after_header(&response)),
+            });
+            with_body(response, error.to_string(), "application/json")
+        }
+        (None, false) => render_error_page(response, server_admin),
+    };
+    if let Some(secs) = retry_after {
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("Accept"));
+    response
+}
+
+/// Seconds of the Retry-After the response already carries
+fn retry_after_header(response: &Response) -> Option<u64> {
+    response.headers().get(header::RETRY_AFTER)?.to_str().ok()?.parse().ok()
+}
+
+/// Whether `[shed]` configures `status`'s responses
+pub fn is_shed_status(status: StatusCode) -> bool {
+    STATUSES.contains(&status.as_u16())
+}
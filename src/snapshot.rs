@@ -0,0 +1,94 @@
+//! The effective configuration as one portable document, for moving to
+//! another host or keeping a backup: the wolfserve.toml settings with their
+//! defaults filled in, and the vhosts as loaded from the Apache config.
+//! Certificates and keys are listed by path, never embedded, and
+//! `[tls] key_passphrase` is left out.
+//!
+//! Importing only previews: it checks that a document parses and that the
+//! files it names exist here, says what differs from the running server,
+//! and renders the wolfserve.toml to install. The vhosts still come from
+//! the Apache config, so nothing is applied.
+
+use crate::apache::VirtualHost;
+use crate::dump::VhostChanges;
+use crate::{Config, Sites, VERSION};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Bumped when the document changes in a way older versions cannot read
+const FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    format: u32,
+    wolfserve_version: String,
+    exported_at: DateTime<Utc>,
+    config: Config,
+    vhosts: Vec<VirtualHost>,
+    /// Certificate, key and CA files the config and vhosts name
+    files: BTreeSet<PathBuf>,
+}
+
+/// Certificate, key and CA files `config` and `vhosts` name
+fn referenced_files(config: &Config, vhosts: &[VirtualHost]) -> BTreeSet<PathBuf> {
+    let tls = &config.tls;
+    let server = [&tls.certificate_file, &tls.certificate_key_file, &tls.certificate_chain_file].into_iter().flatten();
+    let vhost_files = vhosts.iter().flat_map(|vhost| {
+        [&vhost.ssl_cert_file, &vhost.ssl_key_file, &vhost.ssl_chain_file, &vhost.ssl_ca_certificate_file]
+            .into_iter()
+            .flatten()
+            .chain(&vhost.ssl_extra_cert_files)
+            .chain(&vhost.ssl_extra_key_files)
+    });
+    server.chain(vhost_files).cloned().collect()
+}
+
+pub fn export(config: &Config, sites: &Sites) -> Value {
+    let vhosts: Vec<VirtualHost> = sites.list.iter().map(|vhost| (**vhost).clone()).collect();
+    let snapshot = Snapshot {
+        format: FORMAT,
+        wolfserve_version: VERSION.to_string(),
+        exported_at: Utc::now(),
+        files: referenced_files(config, &vhosts),
+        config: config.clone(),
+        vhosts,
+    };
+    serde_json::to_value(&snapshot).unwrap_or(Value::Null)
+}
+
+/// What importing `document` would change on this server, or why it cannot
+/// be imported
+pub fn preview(document: Value, config: &Config, sites: &Sites) -> Result<Value, String> {
+    let format = document["format"].as_u64().ok_or("not a wolfserve configuration snapshot: no format")?;
+    if format > FORMAT as u64 {
+        return Err(format!("snapshot format {} is newer than this wolfserve reads ({})", format, FORMAT));
+    }
+    let snapshot: Snapshot =
+        serde_json::from_value(document).map_err(|e| format!("not a wolfserve configuration snapshot: {}", e))?;
+    let wolfserve_toml = toml::to_string(&snapshot.config).map_err(|e| format!("cannot render wolfserve.toml: {}", e))?;
+
+    // Named in the snapshot but absent here
+    let roots = snapshot.vhosts.iter().filter_map(|vhost| vhost.document_root.as_ref());
+    let missing: BTreeSet<&PathBuf> = snapshot.files.iter().chain(roots).filter(|path| !path.exists()).collect();
+
+    let changes = VhostChanges::between(sites.list.iter().map(|vhost| &**vhost), snapshot.vhosts.iter());
+
+    // Top-level sections, `[server]`, `[php]`, ..., that differ
+    let current = serde_json::to_value(config).unwrap_or(Value::Null);
+    let proposed = serde_json::to_value(&snapshot.config).unwrap_or(Value::Null);
+    let sections: BTreeSet<&String> = current.as_object().into_iter().chain(proposed.as_object()).flat_map(|table| table.keys()).collect();
+    let changed_settings: Vec<&String> = sections.into_iter().filter(|section| current[section.as_str()] != proposed[section.as_str()]).collect();
+
+    Ok(json!({
+        "ready": missing.is_empty(),
+        "wolfserve_version": snapshot.wolfserve_version,
+        "exported_at": snapshot.exported_at,
+        "missing_files": missing,
+        "vhosts": changes,
+        "changed_settings": changed_settings,
+        "wolfserve_toml": wolfserve_toml,
+    }))
+}
@@ -0,0 +1,113 @@
+//! Statistics and the recent request log kept across restarts: saved as JSON
+//! to `[admin] stats_file` every `stats_save_interval` seconds and on
+//! SIGTERM/SIGINT, and loaded at startup. A file that cannot be read is
+//! ignored with a warning and overwritten by the next save.
+
+use crate::admin::{AdminState, RequestLogEntry, StatsSnapshot};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+#[derive(Serialize, Deserialize)]
+struct SavedStats {
+    saved_at: DateTime<Utc>,
+    stats: StatsSnapshot,
+    /// Oldest first
+    logs: Vec<RequestLogEntry>,
+}
+
+/// Carry on from the counters and log of the last run, if it saved any
+pub fn load(admin_state: &AdminState, path: &Path) {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("Warning: cannot read statistics file {}: {}; starting from zero", path.display(), e);
+            return;
+        }
+    };
+    let saved: SavedStats = match serde_json::from_str(&data) {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("Warning: ignoring corrupt statistics file {}: {}; starting from zero", path.display(), e);
+            return;
+        }
+    };
+    admin_state.stats.restore(&saved.stats);
+    admin_state.restore_logs(saved.logs);
+    tracing::info!(
+        file = %path.display(),
+        saved_at = %saved.saved_at,
+        total_requests = saved.stats.total_requests,
+        "restored statistics"
+    );
+}
+
+/// Write the current counters and log, through a temporary file so a crash
+/// mid-write leaves the last good save in place
+pub fn save(admin_state: &AdminState, path: &Path) -> std::io::Result<()> {
+    let mut stats = admin_state.stats.snapshot();
+    // The time until now was served; whatever follows until the next start
+    // is downtime
+    stats.previous_uptime_secs = stats.served_secs();
+    let saved = SavedStats {
+        saved_at: Utc::now(),
+        stats,
+        logs: admin_state.logs.read().iter().cloned().collect(),
+    };
+    let json = serde_json::to_vec(&saved).map_err(std::io::Error::other)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn save_or_warn(state: &AppState) {
+    let path = &state.config.admin.stats_file;
+    if let Err(e) = save(&state.admin_state, path) {
+        eprintln!("Warning: cannot save statistics to {}: {}", path.display(), e);
+    }
+}
+
+/// Save every `stats_save_interval` seconds, and once more before exiting on
+/// SIGTERM or SIGINT
+pub fn spawn(state: Arc<AppState>) {
+    let interval = state.config.admin.stats_save_interval;
+    tokio::spawn(async move {
+        let mut signals = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+            (Ok(terminate), Ok(interrupt)) => Some((terminate, interrupt)),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("Warning: cannot listen for SIGTERM/SIGINT: {}; statistics are not saved on shutdown", e);
+                None
+            }
+        };
+        loop {
+            let shutdown = async {
+                match &mut signals {
+                    Some((terminate, interrupt)) => tokio::select! {
+                        _ = terminate.recv() => {}
+                        _ = interrupt.recv() => {}
+                    },
+                    None => std::future::pending().await,
+                }
+            };
+            let tick = async {
+                match interval {
+                    0 => std::future::pending().await,
+                    secs => tokio::time::sleep(Duration::from_secs(secs)).await,
+                }
+            };
+            tokio::select! {
+                _ = tick => save_or_warn(&state),
+                _ = shutdown => break,
+            }
+        }
+        save_or_warn(&state);
+        tracing::info!(file = %state.config.admin.stats_file.display(), "statistics saved, shutting down");
+        std::process::exit(0);
+    });
+}
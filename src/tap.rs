@@ -0,0 +1,75 @@
+//! Debug tap: verbose logging of the requests to one host and path glob, set
+//! from `[debug_tap]` or the admin dashboard. A tapped request logs its
+//! headers, the script it resolved to, the CGI parameters sent to PHP and the
+//! response, at debug level under the `wolfserve::tap` target. Credentials
+//! (Authorization, Cookie, Set-Cookie) are redacted.
+
+use crate::apache::wildcard_match;
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+/// The tracing target of tap events, always enabled in the log filter
+pub const TARGET: &str = "wolfserve::tap";
+
+/// Headers whose values never reach the log
+const SENSITIVE_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+fn default_path() -> String {
+    "*".to_string()
+}
+
+/// Which requests are tapped: `*` and `?` globs over the Host (without port,
+/// case-insensitive) and the URL path
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DebugTap {
+    pub host: String,
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+impl DebugTap {
+    pub fn matches(&self, host: &str, path: &str) -> bool {
+        wildcard_match(&self.host.to_ascii_lowercase(), &host.to_ascii_lowercase()) && wildcard_match(&self.path, path)
+    }
+}
+
+/// Marks a tapped request, so the PHP handlers log what they send
+#[derive(Clone, Copy)]
+pub struct Tapped;
+
+fn is_sensitive(header: &str) -> bool {
+    SENSITIVE_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(header))
+}
+
+/// "name: value" per header, credentials redacted
+pub fn headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if is_sensitive(name.as_str()) {
+                format!("{}: [redacted]", name)
+            } else {
+                format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()))
+            }
+        })
+        .collect()
+}
+
+/// "NAME=value" per CGI parameter in name order, with the HTTP_ copies of
+/// credential headers redacted
+pub fn params<K: AsRef<str>, V: AsRef<str>>(params: impl IntoIterator<Item = (K, V)>) -> Vec<String> {
+    let mut params: Vec<String> = params
+        .into_iter()
+        .map(|(name, value)| {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            let header = name.strip_prefix("HTTP_").map(|h| h.replace('_', "-"));
+            if header.is_some_and(|h| is_sensitive(&h)) {
+                format!("{}=[redacted]", name)
+            } else {
+                format!("{}={}", name, value)
+            }
+        })
+        .collect();
+    params.sort();
+    params
+}
@@ -0,0 +1,791 @@
+//! TLS policy for HTTPS listeners.
+//! Maps Apache's SSLProtocol and SSLCipherSuite directives onto the protocol
+//! versions and cipher suites rustls supports. A listener has a single rustls
+//! config, so the policies of all vhosts sharing a port are intersected.
+//! Client certificate authentication (SSLVerifyClient) is per listener too,
+//! but there the vhosts of a port must agree. `[tls] min_version` and
+//! `max_version` bound every listener, and `key_log_file` logs session keys
+//! for debugging. Certificate expiry dates and client certificate subjects
+//! are read here as well.
+
+use crate::acme::pem;
+use crate::apache::{VerifyClient, VirtualHost};
+use crate::listeners::HttpVersion;
+use rustls::pki_types::CertificateDer;
+use rustls::{KeyLog, ProtocolVersion};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ResolvesServerCert, WebPkiClientVerifier};
+use rustls::{ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// ALPN protocols of HTTP that a listener may offer
+pub const HTTP_ALPN: [&[u8]; 2] = [b"h2", b"http/1.1"];
+/// ALPN protocol of ACME TLS-ALPN-01 validation handshakes (RFC 8737)
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Protocol names understood by SSLProtocol, oldest first
+const PROTOCOLS: [&str; 5] = ["SSLv3", "TLSv1", "TLSv1.1", "TLSv1.2", "TLSv1.3"];
+
+/// OpenSSL TLS 1.2 cipher names for the suites rustls implements
+const OPENSSL_TLS12_NAMES: [(&str, &str); 6] = [
+    ("ECDHE-ECDSA-AES128-GCM-SHA256", "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"),
+    ("ECDHE-ECDSA-AES256-GCM-SHA384", "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"),
+    ("ECDHE-ECDSA-CHACHA20-POLY1305", "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256"),
+    ("ECDHE-RSA-AES128-GCM-SHA256", "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"),
+    ("ECDHE-RSA-AES256-GCM-SHA384", "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"),
+    ("ECDHE-RSA-CHACHA20-POLY1305", "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256"),
+];
+
+/// Protocol versions and cipher suites a vhost allows.
+/// Suite sets hold rustls suite names; None means no restriction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsPolicy {
+    tls12: bool,
+    tls13: bool,
+    tls12_suites: Option<BTreeSet<String>>,
+    tls13_suites: Option<BTreeSet<String>>,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        Self {
+            tls12: true,
+            tls13: true,
+            tls12_suites: None,
+            tls13_suites: None,
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// Build the policy for a vhost, warning about anything rustls cannot honour
+    pub fn from_vhost(vhost: &VirtualHost) -> Self {
+        let name = vhost.server_name.as_deref().unwrap_or("default vhost");
+        let mut policy = TlsPolicy::default();
+
+        if let Some(args) = &vhost.ssl_protocol {
+            let enabled = parse_ssl_protocol(args, name);
+            let legacy: Vec<&str> = PROTOCOLS[..3].iter().copied().filter(|p| enabled.contains(p)).collect();
+            if !legacy.is_empty() {
+                eprintln!(
+                    "WARNING: {}: SSLProtocol enables {} which rustls does not support; only TLSv1.2/TLSv1.3 can be offered",
+                    name,
+                    legacy.join(", ")
+                );
+            }
+            policy.tls12 = enabled.contains("TLSv1.2");
+            policy.tls13 = enabled.contains("TLSv1.3");
+        }
+
+        if let Some(spec) = &vhost.ssl_cipher_suite {
+            policy.tls12_suites = parse_tls12_ciphers(spec, name);
+        }
+        if let Some(spec) = &vhost.ssl_tls13_cipher_suites {
+            policy.tls13_suites = parse_tls13_ciphers(spec, name);
+        }
+
+        policy
+    }
+
+    /// What both policies allow
+    fn intersect(&self, other: &TlsPolicy) -> TlsPolicy {
+        fn both(a: &Option<BTreeSet<String>>, b: &Option<BTreeSet<String>>) -> Option<BTreeSet<String>> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.intersection(b).cloned().collect()),
+                (Some(set), None) | (None, Some(set)) => Some(set.clone()),
+                (None, None) => None,
+            }
+        }
+
+        TlsPolicy {
+            tls12: self.tls12 && other.tls12,
+            tls13: self.tls13 && other.tls13,
+            tls12_suites: both(&self.tls12_suites, &other.tls12_suites),
+            tls13_suites: both(&self.tls13_suites, &other.tls13_suites),
+        }
+    }
+
+    fn allows_suite(&self, suite: &SupportedCipherSuite) -> bool {
+        let name = format!("{:?}", suite.suite());
+        let allowed = match suite {
+            SupportedCipherSuite::Tls12(_) => &self.tls12_suites,
+            SupportedCipherSuite::Tls13(_) => &self.tls13_suites,
+        };
+        allowed.as_ref().is_none_or(|set| set.contains(&name))
+    }
+
+    fn versions(&self) -> Vec<&'static SupportedProtocolVersion> {
+        let mut versions = Vec::new();
+        if self.tls13 {
+            versions.push(&rustls::version::TLS13);
+        }
+        if self.tls12 {
+            versions.push(&rustls::version::TLS12);
+        }
+        versions
+    }
+
+    /// rustls config for this policy, or an error if nothing usable remains
+    fn server_config(
+        &self,
+        resolver: Arc<dyn ResolvesServerCert>,
+        client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    ) -> Result<ServerConfig, String> {
+        let versions = self.versions();
+        if versions.is_empty() {
+            return Err("no TLS protocol version left".to_string());
+        }
+
+        let mut provider = rustls::crypto::aws_lc_rs::default_provider();
+        provider.cipher_suites.retain(|suite| self.allows_suite(suite));
+        if provider.cipher_suites.is_empty() {
+            return Err("no cipher suite left".to_string());
+        }
+
+        let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&versions)
+            .map_err(|e| e.to_string())?;
+        Ok(match client_verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        }
+        .with_cert_resolver(resolver))
+    }
+}
+
+impl fmt::Display for TlsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let versions: Vec<&str> = [(self.tls12, "TLSv1.2"), (self.tls13, "TLSv1.3")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "protocols [{}]", versions.join(" "))?;
+        for (label, suites) in [("TLSv1.2 ciphers", &self.tls12_suites), ("TLSv1.3 ciphers", &self.tls13_suites)] {
+            if let Some(suites) = suites {
+                write!(f, ", {} [{}]", label, suites.iter().cloned().collect::<Vec<_>>().join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Policy allowing the versions from `[tls] min_version` to `max_version`
+/// (`1.2`, `TLSv1.3`, ...); an error names a setting rustls cannot honour
+pub fn version_bounds(min: Option<&str>, max: Option<&str>) -> Result<TlsPolicy, String> {
+    fn parse(setting: &str, value: &str) -> Result<u8, String> {
+        let lower = value.trim().to_ascii_lowercase();
+        let number = lower.strip_prefix("tlsv").or_else(|| lower.strip_prefix("tls")).unwrap_or(&lower);
+        match number {
+            "1.2" => Ok(2),
+            "1.3" => Ok(3),
+            "1.0" | "1" | "1.1" => Err(format!("[tls] {} = \"{}\": only TLS 1.2 and 1.3 are supported", setting, value)),
+            _ => Err(format!("[tls] {} = \"{}\" is not a TLS version (use \"1.2\" or \"1.3\")", setting, value)),
+        }
+    }
+
+    let min = min.map(|v| parse("min_version", v)).transpose()?.unwrap_or(2);
+    let max = max.map(|v| parse("max_version", v)).transpose()?.unwrap_or(3);
+    if min > max {
+        return Err(format!("[tls] min_version TLSv1.{} is above max_version TLSv1.{}", min, max));
+    }
+    Ok(TlsPolicy {
+        tls12: min <= 2,
+        tls13: max >= 3,
+        ..TlsPolicy::default()
+    })
+}
+
+/// Apply SSLProtocol's grammar: `+X` adds, `-X` removes, a bare `X` replaces
+fn parse_ssl_protocol(args: &[String], vhost_name: &str) -> BTreeSet<&'static str> {
+    let mut enabled: BTreeSet<&'static str> = BTreeSet::new();
+
+    for arg in args {
+        let (action, name) = match arg.as_bytes().first() {
+            Some(b'+') => ('+', &arg[1..]),
+            Some(b'-') => ('-', &arg[1..]),
+            _ => ('=', arg.as_str()),
+        };
+
+        let protocols: Vec<&'static str> = if name.eq_ignore_ascii_case("all") {
+            // Apache 2.4 no longer includes SSLv3 in "all"
+            PROTOCOLS[1..].to_vec()
+        } else if let Some(protocol) = PROTOCOLS.iter().find(|p| p.eq_ignore_ascii_case(name)) {
+            vec![*protocol]
+        } else {
+            eprintln!("WARNING: {}: unknown SSLProtocol '{}' ignored", vhost_name, arg);
+            continue;
+        };
+
+        match action {
+            '+' => enabled.extend(protocols),
+            '-' => enabled.retain(|p| !protocols.contains(p)),
+            _ => enabled = protocols.into_iter().collect(),
+        }
+    }
+
+    enabled
+}
+
+/// Map an OpenSSL TLS 1.2 cipher string onto rustls suites. Keyword-based
+/// strings (`HIGH:!aNULL`) select broad groups that are already a superset
+/// of what rustls offers, so they leave the defaults in place.
+fn parse_tls12_ciphers(spec: &str, vhost_name: &str) -> Option<BTreeSet<String>> {
+    let mut suites = BTreeSet::new();
+
+    for entry in spec.split([':', ',', ' ']).map(str::trim).filter(|e| !e.is_empty()) {
+        if entry.starts_with(['!', '-', '+', '@']) {
+            continue;
+        }
+        if let Some((_, rustls_name)) = OPENSSL_TLS12_NAMES.iter().find(|(openssl, iana)| {
+            openssl.eq_ignore_ascii_case(entry) || iana.eq_ignore_ascii_case(entry)
+        }) {
+            suites.insert(rustls_name.to_string());
+        } else if !entry.contains('-') && !entry.contains('_') {
+            // A keyword such as HIGH or ECDHE
+            return None;
+        } else {
+            eprintln!("WARNING: {}: SSLCipherSuite '{}' is not a cipher rustls supports; ignored", vhost_name, entry);
+        }
+    }
+
+    if suites.is_empty() {
+        eprintln!(
+            "WARNING: {}: SSLCipherSuite '{}' names no cipher rustls supports; using the default TLSv1.2 ciphers",
+            vhost_name, spec
+        );
+        return None;
+    }
+    Some(suites)
+}
+
+/// Map TLS 1.3 suite names (`TLS_AES_128_GCM_SHA256:...`) onto rustls suites
+fn parse_tls13_ciphers(spec: &str, vhost_name: &str) -> Option<BTreeSet<String>> {
+    let known: Vec<String> = rustls::crypto::aws_lc_rs::default_provider()
+        .cipher_suites
+        .iter()
+        .filter(|suite| matches!(suite, SupportedCipherSuite::Tls13(_)))
+        .map(|suite| format!("{:?}", suite.suite()))
+        .collect();
+    let mut suites = BTreeSet::new();
+    for entry in spec.split([':', ',', ' ']).map(str::trim).filter(|e| !e.is_empty()) {
+        let name = entry.to_uppercase().strip_prefix("TLS_").map(|rest| format!("TLS13_{}", rest));
+        match name.filter(|name| known.contains(name)) {
+            Some(name) => {
+                suites.insert(name);
+            }
+            None => eprintln!("WARNING: {}: TLSv1.3 cipher '{}' is not one rustls supports; ignored", vhost_name, entry),
+        }
+    }
+
+    if suites.is_empty() {
+        eprintln!(
+            "WARNING: {}: TLSv1.3 cipher list '{}' is not usable; using the default TLSv1.3 ciphers",
+            vhost_name, spec
+        );
+        return None;
+    }
+    Some(suites)
+}
+
+/// rustls settings of one HTTPS listener
+pub struct ListenerConfig {
+    pub server: Arc<ServerConfig>,
+    /// Whether clients are asked for a certificate (SSLVerifyClient)
+    pub client_auth: bool,
+    /// HTTP versions offered by ALPN, preferred first
+    pub http_versions: Vec<HttpVersion>,
+}
+
+/// Build the listener config for one HTTPS port from its TLS vhosts. Their
+/// protocol and cipher policies are intersected with each other and with
+/// the server-wide `bounds`; their client certificate settings must match,
+/// since rustls asks for certificates per listener.
+pub fn listener_config(
+    port: u16,
+    vhosts: &[&VirtualHost],
+    resolver: Arc<dyn ResolvesServerCert>,
+    bounds: &TlsPolicy,
+    key_log: Option<Arc<dyn KeyLog>>,
+    http_versions: &[HttpVersion],
+) -> Result<ListenerConfig, String> {
+    let policies: Vec<(String, TlsPolicy)> = vhosts.iter().map(|v| (vhost_name(v), TlsPolicy::from_vhost(v))).collect();
+    let verifier = client_verifier_for_port(port, vhosts)?;
+    let client_auth = verifier.is_some();
+    let mut server = server_config_for_port(port, &policies, bounds, resolver, verifier);
+    if let Some(key_log) = key_log {
+        server.key_log = key_log;
+    }
+    // The connection builder speaks whichever version the client picked.
+    // acme-tls/1 is only ever picked by a CA validating a TLS-ALPN-01 challenge.
+    server.alpn_protocols = http_versions.iter().map(|v| v.alpn()).chain([ACME_TLS_ALPN]).map(|p| p.to_vec()).collect();
+    Ok(ListenerConfig {
+        client_auth,
+        server: Arc::new(server),
+        http_versions: http_versions.to_vec(),
+    })
+}
+
+fn vhost_name(vhost: &VirtualHost) -> String {
+    vhost.server_name.clone().unwrap_or_else(|| "default vhost".to_string())
+}
+
+/// Client certificate verifier for a port from its TLS vhosts' SSLVerifyClient
+/// and SSLCACertificateFile, which must be the same for all of them
+pub fn client_verifier_for_port(port: u16, vhosts: &[&VirtualHost]) -> Result<Option<Arc<dyn ClientCertVerifier>>, String> {
+    let client_auth = |vhost: &VirtualHost| (vhost.ssl_verify_client.unwrap_or_default(), vhost.ssl_ca_certificate_file.clone());
+    let (mode, ca_file) = vhosts.first().map(|v| client_auth(v)).unwrap_or_default();
+    if vhosts.iter().any(|v| client_auth(v) != (mode, ca_file.clone())) {
+        let settings: Vec<String> = vhosts
+            .iter()
+            .map(|v| {
+                let (mode, ca_file) = client_auth(v);
+                match ca_file {
+                    Some(ca) => format!("{}: {:?} ({})", vhost_name(v), mode, ca.display()),
+                    None => format!("{}: {:?}", vhost_name(v), mode),
+                }
+            })
+            .collect();
+        return Err(format!(
+            "vhosts on port {} disagree on SSLVerifyClient/SSLCACertificateFile ({}); client certificates are requested per port, so give these vhosts separate ports",
+            port,
+            settings.join(", ")
+        ));
+    }
+    client_verifier(mode, ca_file.as_ref()).map_err(|e| format!("port {}: {}", port, e))
+}
+
+/// Verifier for SSLVerifyClient `mode` against the CAs in `ca_file`
+fn client_verifier(mode: VerifyClient, ca_file: Option<&PathBuf>) -> Result<Option<Arc<dyn ClientCertVerifier>>, String> {
+    if mode == VerifyClient::None {
+        return Ok(None);
+    }
+    let ca_file = ca_file.ok_or("SSLVerifyClient needs SSLCACertificateFile")?;
+    let file = File::open(ca_file).map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert = cert.map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+        roots.add(cert).map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("no CA certificates in {}", ca_file.display()));
+    }
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    if mode == VerifyClient::Optional {
+        builder = builder.allow_unauthenticated();
+    }
+    builder.build().map(Some).map_err(|e| e.to_string())
+}
+
+/// Build the rustls config for one HTTPS port from the policies of its
+/// vhosts, warning loudly when they disagree or leave nothing to negotiate
+fn server_config_for_port(
+    port: u16,
+    policies: &[(String, TlsPolicy)],
+    bounds: &TlsPolicy,
+    resolver: Arc<dyn ResolvesServerCert>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> ServerConfig {
+    let combined = policies
+        .iter()
+        .fold(bounds.clone(), |acc, (_, policy)| acc.intersect(policy));
+
+    if policies.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+        eprintln!("WARNING: vhosts on port {} disagree on SSLProtocol/SSLCipherSuite:", port);
+        for (name, policy) in policies {
+            eprintln!("WARNING:   {}: {}", name, policy);
+        }
+        eprintln!("WARNING:   port {} will use the intersection: {}", port, combined);
+    }
+
+    match combined.server_config(resolver.clone(), client_verifier.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("WARNING: TLS settings for port {} leave {}; falling back to the [tls] defaults ({})", port, e, bounds);
+            bounds
+                .server_config(resolver, client_verifier)
+                .expect("[tls] version bounds always leave a usable policy")
+        }
+    }
+}
+
+/// Session keys in the NSS key log format (as written for SSLKEYLOGFILE),
+/// which Wireshark reads to decrypt captured traffic
+#[derive(Debug)]
+pub struct KeyLogFile {
+    file: Mutex<File>,
+}
+
+impl KeyLogFile {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(KeyLogFile { file: Mutex::new(file) })
+    }
+}
+
+impl KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let line = format!("{} {} {}\n", label, hex(client_random), hex(secret));
+        if let Err(e) = self.file.lock().write_all(line.as_bytes()) {
+            eprintln!("Warning: cannot write the TLS key log: {}", e);
+        }
+    }
+}
+
+/// The TLS side of a connection, attached to each of its requests
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// Negotiated protocol as mod_ssl names it (`TLSv1.3`)
+    pub protocol: &'static str,
+    /// Client certificate state when the listener has SSLVerifyClient
+    pub client: Option<ClientVerify>,
+    /// Host name the client sent in SNI, if any
+    pub sni: Option<String>,
+}
+
+impl Session {
+    pub fn new(protocol: Option<ProtocolVersion>, client: Option<ClientVerify>, sni: Option<&str>) -> Self {
+        let protocol = match protocol {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3",
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2",
+            _ => "",
+        };
+        Session { protocol, client, sni: sni.map(str::to_ascii_lowercase) }
+    }
+
+    /// SSL_PROTOCOL, SSL_TLS_SNI and the client certificate variables
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        let mut variables = vec![("SSL_PROTOCOL", self.protocol.to_string())];
+        if let Some(sni) = &self.sni {
+            variables.push(("SSL_TLS_SNI", sni.clone()));
+        }
+        if let Some(client) = &self.client {
+            variables.extend(client.variables());
+        }
+        variables
+    }
+}
+
+/// Expiry (notAfter) of the first certificate in a PEM file
+pub fn certificate_expiry(path: &Path) -> Result<DateTime<Utc>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let cert = rustls_pemfile::certs(&mut BufReader::new(file))
+        .next()
+        .ok_or("no certificate found")?
+        .map_err(|e| e.to_string())?;
+    not_after(&cert).ok_or_else(|| "cannot read the validity period".to_string())
+}
+
+/// One DER element: (tag, contents, rest of the input)
+pub fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, &rest[count..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// The fields of a certificate's tbsCertificate from the serial number on
+pub fn tbs_fields(cert: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let (tag, _, rest) = der_element(tbs)?;
+    // v1 certificates have no version field
+    Some(if tag == 0xa0 { rest } else { tbs })
+}
+
+/// notAfter from an X.509 certificate:
+/// Certificate → tbsCertificate → [version], serial, signature, issuer, validity
+pub fn not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    let mut rest = tbs_fields(der)?;
+    for _ in 0..3 {
+        rest = der_element(rest)?.2; // serial, signature, issuer
+    }
+    let (_, validity, _) = der_element(rest)?;
+    let (_, _, validity) = der_element(validity)?; // skip notBefore
+    let (tag, time, _) = der_element(validity)?;
+    der_time(tag, time)
+}
+
+/// The DNS names in an X.509 certificate's subjectAltName extension:
+/// tbsCertificate → ..., subjectPublicKeyInfo, [3] extensions
+pub fn dns_names(der: &[u8]) -> Vec<String> {
+    const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+    let extensions = (|| {
+        let mut rest = tbs_fields(der)?;
+        for _ in 0..6 {
+            rest = der_element(rest)?.2; // serial, signature, issuer, validity, subject, key
+        }
+        // Skip the optional issuerUniqueID [1] and subjectUniqueID [2]
+        loop {
+            let (tag, extensions, after) = der_element(rest)?;
+            if tag == 0xa3 {
+                return Some(der_element(extensions)?.1);
+            }
+            rest = after;
+        }
+    })();
+
+    let mut names = Vec::new();
+    let mut extensions = extensions.unwrap_or_default();
+    while let Some((_, extension, rest)) = der_element(extensions) {
+        extensions = rest;
+        let Some((_, oid, mut fields)) = der_element(extension) else { continue };
+        if oid != OID_SUBJECT_ALT_NAME {
+            continue;
+        }
+        // The value is the last field, after an optional critical flag
+        let mut value = None;
+        while let Some((tag, contents, rest)) = der_element(fields) {
+            value = (tag == 0x04).then_some(contents);
+            fields = rest;
+        }
+        let Some((_, mut general_names, _)) = value.and_then(der_element) else { continue };
+        while let Some((tag, name, rest)) = der_element(general_names) {
+            // dNSName
+            if tag == 0x82 {
+                names.push(String::from_utf8_lossy(name).to_ascii_lowercase());
+            }
+            general_names = rest;
+        }
+    }
+    names
+}
+
+/// A UTCTime (tag 0x17) or GeneralizedTime (0x18); fractional seconds are dropped
+pub fn der_time(tag: u8, time: &[u8]) -> Option<DateTime<Utc>> {
+    let time = std::str::from_utf8(time).ok()?;
+    // UTCTime has a two-digit year: 50-99 are 19xx
+    let time = match tag {
+        0x17 => format!("{}{}", if time.get(..2)? >= "50" { "19" } else { "20" }, time),
+        0x18 => time.to_string(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(time.get(..14)?, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Client certificate of a connection on a listener with SSLVerifyClient,
+/// carried in its Session for the SSL_CLIENT_* variables
+#[derive(Clone, Debug)]
+pub enum ClientVerify {
+    /// No certificate was sent (SSLVerifyClient optional)
+    None,
+    /// A certificate that passed verification: its subject DN and PEM
+    Success { subject: String, pem: String },
+}
+
+impl ClientVerify {
+    /// From the certificates the client presented, which rustls has verified
+    pub fn from_peer(certs: Option<&[CertificateDer<'_>]>) -> Self {
+        match certs.and_then(|certs| certs.first()) {
+            Some(cert) => ClientVerify::Success {
+                subject: subject_dn(cert).unwrap_or_default(),
+                pem: pem("CERTIFICATE", cert),
+            },
+            None => ClientVerify::None,
+        }
+    }
+
+    /// SSL_CLIENT_VERIFY, SSL_CLIENT_S_DN and SSL_CLIENT_CERT as mod_ssl sets them
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ClientVerify::None => vec![("SSL_CLIENT_VERIFY", "NONE".to_string())],
+            ClientVerify::Success { subject, pem } => vec![
+                ("SSL_CLIENT_VERIFY", "SUCCESS".to_string()),
+                ("SSL_CLIENT_S_DN", subject.clone()),
+                ("SSL_CLIENT_CERT", pem.clone()),
+            ],
+        }
+    }
+}
+
+/// Subject of a certificate in RFC 2253 form, as mod_ssl formats
+/// SSL_CLIENT_S_DN: last RDN first, e.g. `CN=alice,O=Example,C=GB`
+pub fn subject_dn(cert: &[u8]) -> Option<String> {
+    // serial, signature, issuer, validity, subject
+    let mut fields = tbs_fields(cert)?;
+    for _ in 0..4 {
+        fields = der_element(fields)?.2;
+    }
+    let (_, mut name, _) = der_element(fields)?;
+
+    let mut rdns = Vec::new();
+    while !name.is_empty() {
+        let (_, mut set, rest) = der_element(name)?;
+        name = rest;
+        let mut attributes = Vec::new();
+        while !set.is_empty() {
+            let (_, attribute, rest) = der_element(set)?;
+            set = rest;
+            let (_, oid, value) = der_element(attribute)?;
+            let (tag, value, _) = der_element(value)?;
+            let value = match tag {
+                // BMPString
+                0x1e => String::from_utf16_lossy(&value.chunks(2).map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])).collect::<Vec<_>>()),
+                _ => String::from_utf8_lossy(value).into_owned(),
+            };
+            attributes.push(format!("{}={}", attribute_name(oid), escape_dn_value(&value)));
+        }
+        rdns.push(attributes.join("+"));
+    }
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+/// Short name of a DN attribute type, else its dotted OID
+fn attribute_name(oid: &[u8]) -> String {
+    let dotted = dotted_oid(oid);
+    match dotted.as_str() {
+        "2.5.4.3" => "CN",
+        "2.5.4.5" => "serialNumber",
+        "2.5.4.6" => "C",
+        "2.5.4.7" => "L",
+        "2.5.4.8" => "ST",
+        "2.5.4.9" => "street",
+        "2.5.4.10" => "O",
+        "2.5.4.11" => "OU",
+        "2.5.4.12" => "title",
+        "0.9.2342.19200300.100.1.1" => "UID",
+        "0.9.2342.19200300.100.1.25" => "DC",
+        "1.2.840.113549.1.9.1" => "emailAddress",
+        _ => return dotted,
+    }
+    .to_string()
+}
+
+/// An OID's contents in dotted form (`2.5.4.3`)
+pub fn dotted_oid(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value = 0u64;
+    for byte in oid {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                // The first byte packs the first two arcs
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Escape an attribute value per RFC 2253
+fn escape_dn_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let edge = (i == 0 && (c == '#' || c == ' ')) || (c == ' ' && i + 1 == value.chars().count());
+        if edge || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocols(args: &str) -> BTreeSet<&'static str> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse_ssl_protocol(&args, "test.local")
+    }
+
+    fn set(names: &[&str]) -> Option<BTreeSet<String>> {
+        Some(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    #[test]
+    fn ssl_protocol_adds_removes_and_replaces() {
+        assert_eq!(protocols("-all +TLSv1.3"), BTreeSet::from(["TLSv1.3"]));
+        assert_eq!(protocols("all -TLSv1 -TLSv1.1"), BTreeSet::from(["TLSv1.2", "TLSv1.3"]));
+        assert_eq!(protocols("TLSv1.3 tlsv1.2"), BTreeSet::from(["TLSv1.2"]));
+        // Apache 2.4's "all" leaves SSLv3 out
+        assert!(!protocols("all").contains("SSLv3"));
+    }
+
+    #[test]
+    fn ssl_protocol_ignores_unknown_names() {
+        assert_eq!(protocols("TLSv1.2 +TLSv9 +QUIC"), BTreeSet::from(["TLSv1.2"]));
+        assert_eq!(protocols("+TLSv9"), BTreeSet::new());
+    }
+
+    #[test]
+    fn legacy_protocols_only_leave_no_version() {
+        let vhost = VirtualHost {
+            ssl_protocol: Some(vec!["TLSv1".to_string(), "+TLSv1.1".to_string()]),
+            ..VirtualHost::default()
+        };
+        let policy = TlsPolicy::from_vhost(&vhost);
+        assert!(policy.versions().is_empty());
+    }
+
+    #[test]
+    fn tls12_ciphers_take_openssl_and_iana_names() {
+        assert_eq!(
+            parse_tls12_ciphers("ECDHE-ECDSA-AES128-GCM-SHA256:TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384", "test.local"),
+            set(&["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256", "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"])
+        );
+        // Exclusions only narrow keyword groups, which rustls's list already is
+        assert_eq!(parse_tls12_ciphers("ECDHE-RSA-CHACHA20-POLY1305:!aNULL:!MD5", "test.local"), set(&["TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256"]));
+        assert_eq!(parse_tls12_ciphers("HIGH:!aNULL:!MD5", "test.local"), None);
+    }
+
+    #[test]
+    fn unknown_tls12_ciphers_are_dropped() {
+        assert_eq!(
+            parse_tls12_ciphers("DES-CBC3-SHA:ECDHE-RSA-AES128-GCM-SHA256:RC4-MD5", "test.local"),
+            set(&["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"])
+        );
+        // Nothing usable keeps the defaults rather than leaving no cipher
+        assert_eq!(parse_tls12_ciphers("DES-CBC3-SHA:RC4-MD5", "test.local"), None);
+    }
+
+    #[test]
+    fn tls13_ciphers_map_onto_rustls_names() {
+        assert_eq!(
+            parse_tls13_ciphers("TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256", "test.local"),
+            set(&["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"])
+        );
+        assert_eq!(parse_tls13_ciphers("TLS_AES_128_CCM_SHA256:TLS_AES_128_GCM_SHA256", "test.local"), set(&["TLS13_AES_128_GCM_SHA256"]));
+        assert_eq!(parse_tls13_ciphers("TLS_AES_128_CCM_SHA256", "test.local"), None);
+    }
+
+    #[test]
+    fn vhosts_sharing_a_port_get_the_intersection() {
+        let policy = |protocol: &str, ciphers: &str| TlsPolicy {
+            tls12: protocol.contains("1.2"),
+            tls13: protocol.contains("1.3"),
+            tls12_suites: parse_tls12_ciphers(ciphers, "test.local"),
+            tls13_suites: None,
+        };
+        let a = policy("1.2 1.3", "ECDHE-RSA-AES128-GCM-SHA256:ECDHE-RSA-AES256-GCM-SHA384");
+        let b = policy("1.2", "ECDHE-RSA-AES256-GCM-SHA384:ECDHE-RSA-CHACHA20-POLY1305");
+        assert_eq!(a.intersect(&b), policy("1.2", "ECDHE-RSA-AES256-GCM-SHA384"));
+    }
+}
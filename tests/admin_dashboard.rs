@@ -0,0 +1,1212 @@
+//! The admin dashboard of the running binary: it listens on `[admin] port`,
+//! its statistics and log count the requests served to the vhosts, which it
+//! lists with what is wrong with them, the PHP-FPM pools are pinged, the log
+//! can be filtered, paged, streamed and exported, and an admin can list and
+//! sign out the other sessions and set the debug tap. "Remember me" sessions
+//! get a cookie that outlives the browser, and end with a sign-out or a
+//! changed password like any other. Viewers can look but not change
+//! anything, and scripts can use API tokens instead of a session; a
+//! session's changes need its CSRF token. The configuration can be exported
+//! and an export previewed, and reloaded, which a broken config leaves as it
+//! was. Repeated failed sign-ins lock out the client that made them, as a
+//! trusted proxy forwards it, and slow down sign-ins as a username guessed
+//! from many clients. Only `wolfserve admin init` creates the credentials
+//! file from the shell, and a corrupt one refuses every sign-in rather than
+//! being reset. Statistics, the log and sign-ins survive a restart. The
+//! default login is refused off localhost, and a disabled dashboard leaves
+//! no trace.
+
+mod common;
+
+use common::{MockFpm, Site, HOST};
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A port nothing is listening on right now
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Kills the server when the test ends, pass or fail
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Write the site's wolfserve.toml, its vhost on `port` and `admin` the body
+/// of `[admin]`
+fn configure(site: &Site, port: u16, admin: &str) {
+    fs::write(
+        site.dir.join("apache/sites-enabled/test.conf"),
+        format!("<VirtualHost *:{}>\n    ServerName {}\n    DocumentRoot {}\n</VirtualHost>\n", port, HOST, site.docroot.display()),
+    )
+    .unwrap();
+    let toml = format!(
+        "[server]\nhost = \"127.0.0.1\"\nport = {}\n\n[php]\n\n[apache]\nconfig_dir = \"{}\"\nwatch = false\n\n[admin]\n{}\n",
+        port,
+        site.dir.join("apache").display(),
+        admin
+    );
+    fs::write(site.dir.join("wolfserve.toml"), toml).unwrap();
+}
+
+/// Run the binary in the site's directory, where the dashboard keeps its
+/// credentials file
+fn spawn(site: &Site, stderr: Stdio) -> Server {
+    Server(Command::new(env!("CARGO_BIN_EXE_wolfserve")).current_dir(&site.dir).stdout(Stdio::null()).stderr(stderr).spawn().unwrap())
+}
+
+fn wait_listening(ports: &[u16]) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    for &port in ports {
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline, "wolfserve did not start listening on {}", port);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// Start the binary with its vhost on `port` and the dashboard on
+/// `admin_port` of localhost, and wait until both accept connections
+fn start(site: &Site, port: u16, admin_port: u16) -> Server {
+    configure(site, port, &format!("port = {}", admin_port));
+    let server = spawn(site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    server
+}
+
+/// Send one request and return the response head and body
+fn exchange(port: u16, request: &str) -> (String, String) {
+    exchange_over(TcpStream::connect(("127.0.0.1", port)).unwrap(), request)
+}
+
+fn exchange_over(mut stream: TcpStream, request: &str) -> (String, String) {
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+fn get(port: u16, path: &str, host: &str, extra: &str) -> (String, String) {
+    exchange(port, &format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: dashboard-test\r\n{}Connection: close\r\n\r\n", path, host, extra))
+}
+
+/// Connect to 127.0.0.1:`port` from `source`, another loopback address, so
+/// the server sees a different client
+fn connect_from(source: &str, port: u16) -> TcpStream {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+    let stream = runtime.block_on(async {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(format!("{}:0", source).parse().unwrap()).unwrap();
+        socket.connect(([127, 0, 0, 1], port).into()).await.unwrap().into_std().unwrap()
+    });
+    stream.set_nonblocking(false).unwrap();
+    stream
+}
+
+fn login_request(username: &str, password: &str) -> String {
+    let form = format!("username={}&password={}", username, password);
+    format!(
+        "POST /login HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        form.len(),
+        form
+    )
+}
+
+/// Post the login form and return the response head
+fn post_login(admin_port: u16, username: &str, password: &str) -> String {
+    exchange(admin_port, &login_request(username, password)).0
+}
+
+/// Post the login form from the loopback address `source`
+fn post_login_from(source: &str, admin_port: u16, username: &str, password: &str) -> String {
+    exchange_over(connect_from(source, admin_port), &login_request(username, password)).0
+}
+
+/// Sign in with the default credentials and return the session cookie
+fn login(admin_port: u16) -> String {
+    login_as(admin_port, "admin", "admin")
+}
+
+/// Sign in as `username` and return the session cookie
+fn login_as(admin_port: u16, username: &str, password: &str) -> String {
+    let head = post_login(admin_port, username, password);
+    let cookie = head
+        .lines()
+        .find_map(|line| line.to_ascii_lowercase().starts_with("set-cookie:").then(|| line[11..].trim().to_string()))
+        .unwrap_or_else(|| panic!("no session cookie: {}", head));
+    cookie.split(';').next().unwrap().to_string()
+}
+
+/// The CSRF token the dashboard page hands the session's scripts; empty
+/// for a session that is no longer signed in
+fn csrf_token(admin_port: u16, cookie: &str) -> String {
+    let (_, page) = get(admin_port, "/", "localhost", &format!("Cookie: {}\r\n", cookie));
+    page.split(r#"<meta name="csrf-token" content=""#).nth(1).and_then(|rest| rest.split('"').next()).unwrap_or_default().to_string()
+}
+
+/// Post JSON as the session `cookie` (if any), with its CSRF token as the
+/// dashboard sends it
+fn post_json(admin_port: u16, path: &str, cookie: &str, body: &str) -> (String, String) {
+    let csrf = if cookie.is_empty() { String::new() } else { format!("X-CSRF-Token: {}\r\n", csrf_token(admin_port, cookie)) };
+    exchange(
+        admin_port,
+        &format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            cookie,
+            csrf,
+            body.len(),
+            body
+        ),
+    )
+}
+
+fn api(admin_port: u16, path: &str, cookie: &str) -> Value {
+    let (head, body) = get(admin_port, path, "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn dashboard_counts_vhost_requests() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    let (head, body) = get(port, "/index.html", HOST, "");
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(body, "hello");
+    let (head, _) = get(port, "/missing.html", HOST, "");
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+
+    let (head, _) = get(admin_port, "/api/stats", "localhost", "");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    let cookie = login(admin_port);
+    let stats = api(admin_port, "/api/stats", &cookie);
+    assert_eq!(stats["total_requests"], 2, "{}", stats);
+    assert_eq!(stats["requests_2xx"], 1, "{}", stats);
+    assert_eq!(stats["requests_4xx"], 1, "{}", stats);
+
+    let logs = api(admin_port, "/api/logs", &cookie);
+    assert_eq!(logs["total"], 2, "{}", logs);
+    let paths: Vec<(&str, u64)> =
+        logs["entries"].as_array().unwrap().iter().map(|log| (log["path"].as_str().unwrap(), log["status"].as_u64().unwrap())).collect();
+    assert!(paths.contains(&("/index.html", 200)) && paths.contains(&("/missing.html", 404)), "{}", logs);
+    let log = &logs["entries"][0];
+    assert_eq!(log["host"], HOST);
+    assert_eq!(log["user_agent"], "dashboard-test");
+    assert_eq!(log["method"], "GET");
+}
+
+#[test]
+fn vhost_status_flags_missing_roots_and_unreachable_php() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("port = {}", admin_port));
+    let toml = fs::read_to_string(site.dir.join("wolfserve.toml")).unwrap();
+    let php = format!("[php]\nfpm_address = \"127.0.0.1:{}\"\n", free_port());
+    fs::write(site.dir.join("wolfserve.toml"), toml.replace("[php]\n", &php)).unwrap();
+    fs::write(
+        site.dir.join("apache/sites-enabled/zz-gone.conf"),
+        format!("<VirtualHost *:{}>\n    ServerName gone.local\n    DocumentRoot {}\n</VirtualHost>\n", port, site.dir.join("gone").display()),
+    )
+    .unwrap();
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    get(port, "/index.html", HOST, "");
+    get(port, "/missing.html", HOST, "");
+
+    let cookie = login(admin_port);
+    let (head, body) = get(admin_port, "/vhosts", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 200") && body.contains("/api/vhosts"), "{}", head);
+
+    let dump = api(admin_port, "/api/vhosts", &cookie);
+    let vhost = |name: &str| dump["vhosts"].as_array().unwrap().iter().find(|v| v["names"][0] == name).unwrap().clone();
+    let test = vhost(HOST);
+    assert_eq!(test["id"], format!("{}:{}", HOST, port));
+    assert_eq!(test["document_root_exists"], true);
+    assert_eq!(test["requests"]["requests"], 2, "{}", test);
+    assert_eq!(test["requests"]["requests_4xx"], 1, "{}", test);
+    assert_eq!(test["php"]["reachable"], false);
+    assert!(test["problems"][0].as_str().unwrap().starts_with("PHP handler unreachable"), "{}", test);
+
+    let gone = vhost("gone.local");
+    assert_eq!(gone["document_root_exists"], false);
+    assert_eq!(gone["requests"]["requests"], 0);
+    assert!(gone["problems"][0].as_str().unwrap().contains("is missing"), "{}", gone);
+}
+
+// The helpers block; the mock pool answers from another worker thread
+#[tokio::test(flavor = "multi_thread")]
+async fn php_pools_are_pinged_and_a_down_one_is_flagged() {
+    let status = r#"{"pool": "www", "listen queue": 2, "idle processes": 5, "active processes": 1}"#;
+    let fpm = MockFpm::start(format!("Content-Type: application/json\r\n\r\n{}", status)).await;
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("port = {}", admin_port));
+    let toml = fs::read_to_string(site.dir.join("wolfserve.toml")).unwrap();
+    let php = format!("[php]\nfpm_address = \"{}\"\n", fpm.addr);
+    fs::write(site.dir.join("wolfserve.toml"), toml.replace("[php]\n", &php)).unwrap();
+    let down = format!("127.0.0.1:{}", free_port());
+    fs::write(
+        site.dir.join("apache/sites-enabled/zz-shop.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName shop.local\n    DocumentRoot {}\n    SetHandler \"proxy:fcgi://{}\"\n</VirtualHost>\n",
+            port,
+            site.docroot.display(),
+            down
+        ),
+    )
+    .unwrap();
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    let cookie = login(admin_port);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let pools = loop {
+        let pools = api(admin_port, "/api/php", &cookie);
+        if pools.as_object().unwrap().len() == 2 {
+            break pools;
+        }
+        assert!(Instant::now() < deadline, "{}", pools);
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    let up = &pools[&fpm.addr];
+    assert_eq!(up["reachable"], true, "{}", up);
+    assert_eq!(up["vhosts"], serde_json::json!([format!("{}:{}", HOST, port)]), "{}", up);
+    assert_eq!(up["workers"], serde_json::json!({ "active": 1, "idle": 5, "listen_queue": 2 }), "{}", up);
+    assert_eq!(fpm.requests()[0].params["QUERY_STRING"], "json");
+    let shop = &pools[&down];
+    assert_eq!(shop["reachable"], false, "{}", shop);
+    assert_eq!(shop["last_ok"], Value::Null);
+    assert!(shop["consecutive_failures"].as_u64().unwrap() >= 1, "{}", shop);
+
+    let stats = api(admin_port, "/api/stats", &cookie);
+    assert_eq!(stats["php_pools_down"], serde_json::json!([format!("{} (shop.local:{})", down, port)]));
+    let (_, page) = get(admin_port, "/", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(page.contains(r#"<div class="alert-banner" id="php-banner">"#), "banner hidden");
+}
+
+#[test]
+fn log_can_be_filtered_and_paged() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    for path in ["/index.html", "/missing-1.html", "/missing-2.html", "/missing-3.html"] {
+        get(port, path, HOST, "");
+    }
+    let cookie = login(admin_port);
+    let paths = |page: &Value| -> Vec<String> {
+        page["entries"].as_array().unwrap().iter().map(|log| log["path"].as_str().unwrap().to_string()).collect()
+    };
+
+    let page = api(admin_port, "/api/logs?status=4xx&limit=2", &cookie);
+    assert_eq!(page["total"], 3, "{}", page);
+    assert_eq!(paths(&page), ["/missing-3.html", "/missing-2.html"]);
+    let page = api(admin_port, "/api/logs?status=4xx&limit=2&offset=2", &cookie);
+    assert_eq!((page["total"].as_u64(), page["offset"].as_u64()), (Some(3), Some(2)));
+    assert_eq!(paths(&page), ["/missing-1.html"]);
+
+    assert_eq!(paths(&api(admin_port, "/api/logs?status=200", &cookie)), ["/index.html"]);
+    assert_eq!(paths(&api(admin_port, "/api/logs?path=index&method=get", &cookie)), ["/index.html"]);
+    assert_eq!(api(admin_port, "/api/logs?path_regex=%5E%2Fmissing-%5B12%5D", &cookie)["total"], 2);
+    assert_eq!(api(admin_port, &format!("/api/logs?host={}&client_ip=127.0.0.1", HOST), &cookie)["total"], 4);
+    assert_eq!(api(admin_port, "/api/logs?host=other.example", &cookie)["total"], 0);
+    assert_eq!(api(admin_port, "/api/logs?since=2999-01-01T00:00:00Z", &cookie)["total"], 0);
+
+    for query in ["status=6xx", "status=abc", "path_regex=%5B"] {
+        let (head, _) = get(admin_port, &format!("/api/logs?{}", query), "localhost", &format!("Cookie: {}\r\n", cookie));
+        assert!(head.starts_with("HTTP/1.1 400"), "{}: {}", query, head);
+    }
+}
+
+#[test]
+fn log_can_be_exported_as_csv_and_ndjson() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    exchange(
+        port,
+        &format!("GET /index.html HTTP/1.1\r\nHost: {}\r\nUser-Agent: Bot, \"quoted\"\r\nConnection: close\r\n\r\n", HOST),
+    );
+    get(port, "/missing.html", HOST, "");
+    let cookie = login(admin_port);
+
+    let (head, _) = get(admin_port, "/api/logs/export?format=csv", "localhost", "");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    let (head, _) = get(admin_port, "/api/logs/export?format=xml", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+
+    let (head, body) = get(admin_port, "/api/logs/export?format=csv&status=200", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("content-type: text/csv"), "{}", head);
+    assert!(head.contains("content-disposition: attachment; filename=\"wolfserve-requests-"), "{}", head);
+    let body = dechunk(&body);
+    let lines: Vec<&str> = body.split("\r\n").collect();
+    assert_eq!(lines[0], "timestamp,method,path,status,duration_ms,client_ip,host,user_agent,protocol,php_duration_ms");
+    assert!(lines[1].contains(",GET,/index.html,200,"), "{}", body);
+    assert!(lines[1].contains(",\"Bot, \"\"quoted\"\"\",HTTP/1.1,"), "{}", body);
+    assert_eq!(lines[2..], [""]);
+
+    let (_, body) = get(admin_port, "/api/logs/export?format=ndjson", "localhost", &format!("Cookie: {}\r\n", cookie));
+    let body = dechunk(&body);
+    let entries: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(entries.len(), 2, "{}", body);
+    assert_eq!(entries[0]["path"], "/missing.html");
+    assert_eq!(entries[1]["user_agent"], "Bot, \"quoted\"");
+}
+
+#[test]
+fn log_pages_back_through_the_log_file() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let log_file = site.dir.join("requests.ndjson");
+    configure(&site, port, &format!("port = {}\nlog_entries = 2\nlog_file = \"{}\"", admin_port, log_file.display()));
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    for i in 0..5 {
+        get(port, &format!("/page-{}.html", i), HOST, "");
+    }
+    // Written in the background
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(&log_file).map_or(0, |data| data.lines().count()) < 5 {
+        assert!(Instant::now() < deadline, "log file not written");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let cookie = login(admin_port);
+    let paths = |page: &Value| -> Vec<String> {
+        page["entries"].as_array().unwrap().iter().map(|log| log["path"].as_str().unwrap().to_string()).collect()
+    };
+    let page = api(admin_port, "/api/logs?limit=2", &cookie);
+    assert_eq!(page["total"], 5, "{}", page);
+    assert_eq!(paths(&page), ["/page-4.html", "/page-3.html"]);
+    let page = api(admin_port, "/api/logs?limit=2&offset=3", &cookie);
+    assert_eq!(paths(&page), ["/page-1.html", "/page-0.html"]);
+
+    // A damaged line is skipped
+    let mut file = fs::OpenOptions::new().append(true).open(&log_file).unwrap();
+    file.write_all(b"{\"timestamp\": \"2024-01-01T00:00\n").unwrap();
+    assert_eq!(api(admin_port, "/api/logs", &cookie)["total"], 5);
+
+    // A file that cannot be read leaves the in-memory log
+    fs::remove_file(&log_file).unwrap();
+    fs::create_dir(&log_file).unwrap();
+    let page = api(admin_port, "/api/logs", &cookie);
+    assert_eq!(paths(&page), ["/page-4.html", "/page-3.html"]);
+}
+
+/// The payload of a chunked body
+fn dechunk(mut body: &str) -> String {
+    let mut payload = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        if size == 0 {
+            return payload;
+        }
+        payload.push_str(&rest[..size]);
+        body = &rest[size + 2..];
+    }
+}
+
+/// Read from `stream` until what was read contains `needle`
+fn read_until(stream: &mut TcpStream, read: &mut String, needle: &str) {
+    let mut buf = [0; 4096];
+    while !read.contains(needle) {
+        let n = stream.read(&mut buf).unwrap_or_else(|e| panic!("no {:?} in {:?}: {}", needle, read, e));
+        assert!(n > 0, "stream closed without {:?}: {}", needle, read);
+        read.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+}
+
+#[test]
+fn log_entries_are_streamed_as_they_are_logged() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    let (head, _) = get(admin_port, "/api/logs/stream", "localhost", "");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    let cookie = login(admin_port);
+    let mut stream = TcpStream::connect(("127.0.0.1", admin_port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+        .write_all(format!("GET /api/logs/stream?status=4xx HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n", cookie).as_bytes())
+        .unwrap();
+    let mut read = String::new();
+    read_until(&mut stream, &mut read, "\r\n\r\n");
+    assert!(read.starts_with("HTTP/1.1 200"), "{}", read);
+    assert!(read.to_ascii_lowercase().contains("content-type: text/event-stream"), "{}", read);
+
+    get(port, "/index.html", HOST, "");
+    get(port, "/missing.html", HOST, "");
+    read_until(&mut stream, &mut read, "/missing.html");
+    assert!(read.contains("data: {"), "{}", read);
+    // Filtered out by status
+    assert!(!read.contains("/index.html"), "{}", read);
+}
+
+#[test]
+fn sessions_can_be_listed_and_signed_out() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    let (first, second, third) = (login(admin_port), login(admin_port), login(admin_port));
+    let sessions = api(admin_port, "/api/sessions", &first);
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 3);
+    assert_eq!(sessions.iter().filter(|s| s["current"] == true).count(), 1);
+    assert!(sessions[0]["current"] == true && sessions[0]["username"] == "admin");
+    // The listing never carries the cookie's token
+    let token = first.split_once('=').unwrap().1;
+    assert!(!sessions.iter().any(|s| s.to_string().contains(token)));
+
+    // One session by id
+    let id = sessions[1]["id"].as_str().unwrap();
+    let (head, body) = post_json(admin_port, "/api/sessions/revoke", &first, &format!("{{\"id\": \"{}\"}}", id));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap().as_array().unwrap().len(), 2);
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &format!("Cookie: {}\r\n", second));
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    let (head, _) = post_json(admin_port, "/api/sessions/revoke", &first, &format!("{{\"id\": \"{}\"}}", id));
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+
+    // Everything but the caller's
+    let (head, _) = post_json(admin_port, "/api/sessions/revoke", &first, "{}");
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &format!("Cookie: {}\r\n", third));
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    assert_eq!(api(admin_port, "/api/sessions", &first).as_array().unwrap().len(), 1);
+
+    let (head, _) = post_json(admin_port, "/api/sessions/revoke", &third, "{}");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+}
+
+/// Post a form to `path` as the session `cookie` (if any)
+fn post_form(admin_port: u16, path: &str, cookie: &str, form: &str) -> (String, String) {
+    exchange(
+        admin_port,
+        &format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            cookie,
+            form.len(),
+            form
+        ),
+    )
+}
+
+/// The Set-Cookie header of a response head
+fn set_cookie(head: &str) -> String {
+    head.lines()
+        .find_map(|line| line.to_ascii_lowercase().starts_with("set-cookie:").then(|| line[11..].trim().to_string()))
+        .unwrap_or_else(|| panic!("no Set-Cookie: {}", head))
+}
+
+/// Sign in as admin/`password` with "remember me" ticked; the session cookie
+fn login_remembered(admin_port: u16, password: &str) -> String {
+    let (head, _) = post_form(admin_port, "/login", "", &format!("username=admin&password={}&remember=on", password));
+    set_cookie(&head).split(';').next().unwrap().to_string()
+}
+
+fn is_signed_in(admin_port: u16, cookie: &str) -> bool {
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &format!("Cookie: {}\r\n", cookie));
+    head.starts_with("HTTP/1.1 200")
+}
+
+#[test]
+fn remembered_sessions_get_a_cookie_for_remember_me_days() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("port = {}\nremember_me_days = 7", admin_port));
+    let server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+
+    let (_, page) = get(admin_port, "/login", "localhost", "");
+    assert!(page.contains("Remember me for 7 days"), "{}", page);
+    let (head, _) = post_form(admin_port, "/login", "", "username=admin&password=admin&remember=on");
+    let remembered = set_cookie(&head);
+    assert!(remembered.ends_with("; Max-Age=604800"), "{}", remembered);
+    // Without the box ticked the cookie ends with the browser
+    assert!(!set_cookie(&post_login(admin_port, "admin", "admin")).contains("Max-Age"));
+
+    let cookie = remembered.split(';').next().unwrap();
+    let sessions = api(admin_port, "/api/sessions", cookie);
+    let current = sessions.as_array().unwrap().iter().find(|s| s["current"] == true).unwrap();
+    assert_eq!(current["remember"], true);
+    let lifetime = chrono::DateTime::parse_from_rfc3339(current["expires_at"].as_str().unwrap()).unwrap()
+        - chrono::DateTime::parse_from_rfc3339(current["created_at"].as_str().unwrap()).unwrap();
+    assert_eq!(lifetime, chrono::Duration::days(7));
+    drop(server);
+
+    // remember_me_days = 0 takes the box away and ignores it when sent
+    configure(&site, port, &format!("port = {}\nremember_me_days = 0", admin_port));
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    let (_, page) = get(admin_port, "/login", "localhost", "");
+    assert!(!page.contains("Remember me"), "{}", page);
+    let (head, _) = post_form(admin_port, "/login", "", "username=admin&password=admin&remember=on");
+    assert!(!set_cookie(&head).contains("Max-Age"), "{}", head);
+}
+
+#[test]
+fn signing_out_ends_a_remembered_session() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let (cookie, other) = (login_remembered(admin_port, "admin"), login(admin_port));
+
+    let (head, _) = get(admin_port, "/logout", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+    assert!(set_cookie(&head).starts_with("wolfserve_session=;"), "{}", head);
+    assert!(set_cookie(&head).contains("Max-Age=0"), "{}", head);
+    // A copy of the cookie kept elsewhere is no use either
+    assert!(!is_signed_in(admin_port, &cookie));
+    assert_eq!(api(admin_port, "/api/sessions", &other).as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn a_changed_password_ends_the_other_sessions() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let (remembered, changing) = (login_remembered(admin_port, "admin"), login(admin_port));
+
+    let form = format!(
+        "current_password=admin&new_password=changed-once&confirm_password=changed-once&csrf_token={}",
+        csrf_token(admin_port, &changing)
+    );
+    let (head, body) = post_form(admin_port, "/change-password", &changing, &form);
+    assert!(head.starts_with("HTTP/1.1 200") && body.contains("Password changed"), "{}{}", head, body);
+    assert!(!is_signed_in(admin_port, &remembered));
+    assert!(is_signed_in(admin_port, &changing));
+
+    // From the shell, while the server runs: every session of the user ends
+    let remembered = login_remembered(admin_port, "changed-once");
+    let output = admin_cli(&site, &["set-password", "admin"], "changed-twice\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!is_signed_in(admin_port, &remembered));
+    assert!(!is_signed_in(admin_port, &changing));
+    assert!(is_signed_in(admin_port, &login_as(admin_port, "admin", "changed-twice")));
+}
+
+#[test]
+fn repeated_failed_sign_ins_are_locked_out() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    for _ in 0..5 {
+        assert!(post_login(admin_port, "admin", "guess").starts_with("HTTP/1.1 200"));
+    }
+    let head = post_login(admin_port, "admin", "guess");
+    assert!(head.starts_with("HTTP/1.1 429"), "{}", head);
+    assert!(head.to_ascii_lowercase().contains("retry-after: 1\r\n"), "{}", head);
+    // Even the right password waits out the lockout, whatever the username
+    assert!(post_login(admin_port, "admin", "admin").starts_with("HTTP/1.1 429"));
+    assert!(post_login(admin_port, "someone", "else").starts_with("HTTP/1.1 429"));
+
+    // The lockout is the client's, not the account's: admin can still sign in
+    // from elsewhere
+    let head = post_login_from("127.0.0.2", admin_port, "admin", "admin");
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+
+    std::thread::sleep(Duration::from_millis(1100));
+    let cookie = login(admin_port);
+    let events = api(admin_port, "/api/audit", &cookie);
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|event| event["event"].as_str().unwrap()).collect();
+    assert_eq!(kinds[..4], ["login", "login", "lockout", "login_failed"]);
+    assert_eq!(kinds.len(), 9);
+    assert_eq!(events[0]["client_ip"], "127.0.0.1");
+    assert_eq!(events[1]["client_ip"], "127.0.0.2");
+
+    // Signing in cleared the count
+    assert!(post_login(admin_port, "admin", "guess").starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn sign_ins_through_a_trusted_proxy_are_counted_per_forwarded_client() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("port = {}", admin_port));
+    let toml = fs::read_to_string(site.dir.join("wolfserve.toml")).unwrap();
+    fs::write(site.dir.join("wolfserve.toml"), toml.replace("[server]\n", "[server]\ntrusted_proxies = [\"127.0.0.1\"]\n")).unwrap();
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    let forwarded_login = |client: &str, username: &str, password: &str| {
+        let request = login_request(username, password).replacen("\r\n", &format!("\r\nX-Forwarded-For: {}\r\n", client), 1);
+        exchange(admin_port, &request).0
+    };
+
+    for _ in 0..5 {
+        assert!(forwarded_login("203.0.113.1", "nobody", "guess").starts_with("HTTP/1.1 200"));
+    }
+    let head = forwarded_login("203.0.113.1", "nobody", "guess");
+    assert!(head.starts_with("HTTP/1.1 429"), "{}", head);
+
+    // Another client behind the same proxy is not locked out
+    let head = forwarded_login("203.0.113.2", "admin", "admin");
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+    let events = api(admin_port, "/api/audit", &login(admin_port));
+    assert_eq!(events[0]["client_ip"], "127.0.0.1");
+    assert_eq!(events[1]["client_ip"], "203.0.113.2");
+    assert_eq!(events[2]["client_ip"], "203.0.113.1");
+}
+
+#[test]
+fn a_username_failing_from_many_clients_is_slowed_not_locked() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    for source in ["127.0.0.2", "127.0.0.3", "127.0.0.4", "127.0.0.5", "127.0.0.6", "127.0.0.7"] {
+        assert!(post_login_from(source, admin_port, "admin", "guess").starts_with("HTTP/1.1 200"));
+    }
+    // The next sign-in as admin waits two seconds, then goes through
+    let started = Instant::now();
+    let head = post_login_from("127.0.0.8", admin_port, "admin", "admin");
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+    let delayed = started.elapsed();
+    assert!(delayed >= Duration::from_secs(2), "{:?}", delayed);
+    // Signing in cleared the count
+    let started = Instant::now();
+    login(admin_port);
+    assert!(started.elapsed() + Duration::from_secs(1) < delayed, "{:?} after {:?}", started.elapsed(), delayed);
+}
+
+#[test]
+fn session_changes_need_the_csrf_token() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+    let csrf = csrf_token(admin_port, &cookie);
+
+    let requests = [
+        ("POST", "/api/certificates/reload", ""),
+        ("POST", "/api/reload", ""),
+        ("POST", "/api/maintenance", r#"{"enabled": true}"#),
+        ("POST", "/api/users", r#"{"username": "ops", "password": "secret", "role": "admin"}"#),
+        ("POST", "/api/tokens", r#"{"name": "forged"}"#),
+        ("POST", "/api/sessions/revoke", "{}"),
+        ("DELETE", "/api/debug-tap", ""),
+    ];
+    for (method, path, body) in requests {
+        for sent in ["", "X-CSRF-Token: forged\r\n"] {
+            let (head, _) = exchange(
+                admin_port,
+                &format!(
+                    "{} {} HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    method,
+                    path,
+                    cookie,
+                    sent,
+                    body.len(),
+                    body
+                ),
+            );
+            assert!(head.starts_with("HTTP/1.1 403"), "{} {} with {:?}: {}", method, path, sent, head);
+        }
+    }
+    let (head, _) = post_json(admin_port, "/api/certificates/reload", &cookie, "");
+    assert!(!head.starts_with("HTTP/1.1 403"), "{}", head);
+
+    // Nothing was changed
+    assert_eq!(api(admin_port, "/api/users", &cookie).as_array().unwrap().len(), 1);
+    assert_eq!(api(admin_port, "/api/tokens", &cookie), serde_json::json!([]));
+    assert_eq!(api(admin_port, "/api/sessions", &cookie).as_array().unwrap().len(), 1);
+
+    // The change-password form posts the token as a field
+    let change_password = |fields: &str| {
+        let form = format!("current_password=admin&new_password=changed&confirm_password=changed{}", fields);
+        exchange(
+            admin_port,
+            &format!(
+                "POST /change-password HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                cookie,
+                form.len(),
+                form
+            ),
+        )
+    };
+    let (_, page) = get(admin_port, "/change-password", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(page.contains(&format!(r#"name="csrf_token" value="{}""#, csrf)), "{}", page);
+    for fields in ["", "&csrf_token=forged"] {
+        let (head, _) = change_password(fields);
+        assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+    }
+    let (head, body) = change_password(&format!("&csrf_token={}", csrf));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(body.contains("Password changed successfully"), "{}", body);
+}
+
+#[test]
+fn debug_tap_can_be_set_and_cleared() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+
+    assert_eq!(api(admin_port, "/api/debug-tap", &cookie), Value::Null);
+    let (head, _) = post_json(admin_port, "/api/debug-tap", "", r#"{"host": "example.com"}"#);
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    let (head, body) = post_json(admin_port, "/api/debug-tap", &cookie, r#"{"host": "example.com"}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), serde_json::json!({ "host": "example.com", "path": "*" }));
+    let (head, _) = post_json(admin_port, "/api/debug-tap", &cookie, r#"{"host": ""}"#);
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+    assert_eq!(api(admin_port, "/api/debug-tap", &cookie)["host"], "example.com");
+
+    let (head, body) = exchange(
+        admin_port,
+        &format!(
+            "DELETE /api/debug-tap HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nX-CSRF-Token: {}\r\nConnection: close\r\n\r\n",
+            cookie,
+            csrf_token(admin_port, &cookie)
+        ),
+    );
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(body, "null");
+    assert_eq!(api(admin_port, "/api/debug-tap", &cookie), Value::Null);
+}
+
+#[test]
+fn viewers_can_read_but_not_change_anything() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+
+    let (head, body) = post_json(admin_port, "/api/users", &cookie, r#"{"username": "ops", "password": "secret", "role": "viewer"}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(
+        serde_json::from_str::<Value>(&body).unwrap(),
+        serde_json::json!([{ "username": "admin", "role": "admin" }, { "username": "ops", "role": "viewer" }])
+    );
+    let (head, _) = post_json(admin_port, "/api/users", &cookie, r#"{"username": "ops", "password": "secret"}"#);
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+
+    let viewer = login_as(admin_port, "ops", "secret");
+    assert_eq!(api(admin_port, "/api/maintenance", &viewer)["global"], false);
+    let (head, body) = get(admin_port, "/", "localhost", &format!("Cookie: {}\r\n", viewer));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(body.contains(r#"<body class="role-viewer">"#));
+    for (path, body) in [
+        ("/api/maintenance", r#"{"enabled": true}"#),
+        ("/api/users", r#"{"username": "eve", "password": "secret", "role": "admin"}"#),
+        ("/api/reload", "{}"),
+    ] {
+        let (head, _) = post_json(admin_port, path, &viewer, body);
+        assert!(head.starts_with("HTTP/1.1 403"), "{}: {}", path, head);
+    }
+    assert_eq!(api(admin_port, "/api/maintenance", &cookie)["global"], false);
+
+    // The last admin stays
+    let (head, body) = post_json(admin_port, "/api/users/remove", &cookie, r#"{"username": "admin"}"#);
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+    assert!(body.contains("last admin"), "{}", body);
+
+    // Resetting a password signs the user out
+    let (head, _) = post_json(admin_port, "/api/users/password", &cookie, r#"{"username": "ops", "password": "changed"}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let (head, _) = get(admin_port, "/api/maintenance", "localhost", &format!("Cookie: {}\r\n", viewer));
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    login_as(admin_port, "ops", "changed");
+
+    // Users can be added from the command line too
+    let mut cli = Command::new(env!("CARGO_BIN_EXE_wolfserve"))
+        .current_dir(&site.dir)
+        .args(["admin", "add-user", "root2", "--role", "admin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    cli.stdin.take().unwrap().write_all(b"hunter2\n").unwrap();
+    assert!(cli.wait().unwrap().success());
+    let cookie = login_as(admin_port, "root2", "hunter2");
+    let (head, _) = post_json(admin_port, "/api/users/remove", &cookie, r#"{"username": "admin"}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let users = api(admin_port, "/api/users", &cookie);
+    assert_eq!(users.as_array().unwrap().iter().map(|user| user["username"].as_str().unwrap()).collect::<Vec<_>>(), ["ops", "root2"]);
+}
+
+/// Run `wolfserve admin <args>` in the site's directory with `stdin` as its input
+fn admin_cli(site: &Site, args: &[&str], stdin: &str) -> std::process::Output {
+    let mut cli = Command::new(env!("CARGO_BIN_EXE_wolfserve"))
+        .current_dir(&site.dir)
+        .arg("admin")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    cli.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    cli.wait_with_output().unwrap()
+}
+
+#[test]
+fn only_admin_init_creates_the_credentials_file() {
+    let site = Site::new("");
+    let credentials = site.dir.join("wolfserve_admin.dat");
+
+    for args in [&["list-users"][..], &["add-user", "ops"], &["set-password", "admin"], &["remove-user", "admin"]] {
+        let output = admin_cli(&site, args, "hunter2\n");
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("wolfserve admin init"), "{:?}", output);
+        assert!(!credentials.exists(), "{:?} created the credentials file", args);
+    }
+
+    assert!(admin_cli(&site, &["init", "root"], "hunter2\n").status.success());
+    let output = admin_cli(&site, &["init"], "other\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"), "{:?}", output);
+    assert!(admin_cli(&site, &["set-password", "root"], "changed\n").status.success());
+    let output = admin_cli(&site, &["list-users"], "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "root\tadmin\n");
+
+    // The server keeps the file it finds: there is no admin/admin
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    assert!(post_login(admin_port, "admin", "admin").starts_with("HTTP/1.1 200"));
+    login_as(admin_port, "root", "changed");
+}
+
+#[test]
+fn api_tokens_stand_in_for_a_session() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+
+    let (head, body) = post_json(admin_port, "/api/tokens", &cookie, r#"{"name": "monitoring", "read_only": true}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let created: Value = serde_json::from_str(&body).unwrap();
+    let token = created["token"].as_str().unwrap();
+    let bearer = format!("Authorization: Bearer {}\r\n", token);
+    let listed = api(admin_port, "/api/tokens", &cookie);
+    assert_eq!(listed[0]["id"], created["id"]);
+    assert_eq!(listed[0]["role"], "viewer");
+    assert!(!listed.to_string().contains(token), "tokens are listed without the token");
+
+    let (head, body) = get(admin_port, "/api/stats", "localhost", &bearer);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(serde_json::from_str::<Value>(&body).unwrap()["total_requests"].is_number());
+    // Read-only, and only for the API
+    let toggle = r#"{"enabled": true}"#;
+    let (head, _) = exchange(
+        admin_port,
+        &format!(
+            "POST /api/maintenance HTTP/1.1\r\nHost: localhost\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            bearer,
+            toggle.len(),
+            toggle
+        ),
+    );
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+    let (head, _) = get(admin_port, "/", "localhost", &bearer);
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+    let (head, _) = get(admin_port, "/api/stats", "localhost", "Authorization: Bearer wst_guess\r\n");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    let events = api(admin_port, "/api/audit", &cookie);
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|event| event["event"].as_str().unwrap()).collect();
+    assert_eq!(kinds[..3], ["token_rejected", "token_used", "token_created"]);
+    assert_eq!(events[1]["username"], "monitoring");
+
+    let credentials = site.dir.join("wolfserve_admin.dat");
+    let with_token = fs::read(&credentials).unwrap();
+    let (head, _) = post_json(admin_port, "/api/tokens/revoke", &cookie, &format!(r#"{{"id": "{}"}}"#, created["id"].as_str().unwrap()));
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &bearer);
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    // Changes made to the file outside the server are picked up too
+    fs::write(&credentials, with_token).unwrap();
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &bearer);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+}
+
+#[test]
+fn corrupt_credentials_file_is_never_replaced() {
+    let site = Site::new("");
+    let credentials = site.dir.join("wolfserve_admin.dat");
+    fs::write(&credentials, "half-written").unwrap();
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+
+    let (head, _) = get(admin_port, "/api/stats", "localhost", "Authorization: Bearer wst_guess\r\n");
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    let head = post_login(admin_port, "admin", "admin");
+    assert!(head.starts_with("HTTP/1.1 500"), "{}", head);
+    assert!(!head.to_ascii_lowercase().contains("set-cookie"), "{}", head);
+    assert_eq!(fs::read_to_string(&credentials).unwrap(), "half-written");
+}
+
+#[test]
+fn configuration_can_be_exported_and_previewed() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("port = {}", admin_port));
+    let mut toml = fs::read_to_string(site.dir.join("wolfserve.toml")).unwrap();
+    toml.push_str("\n[tls]\nkey_passphrase = \"hunter2\"\n");
+    fs::write(site.dir.join("wolfserve.toml"), toml).unwrap();
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+    let cookie = login(admin_port);
+
+    let (head, body) = get(admin_port, "/api/config/export", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 200") && head.contains("attachment"), "{}", head);
+    assert!(!body.contains("hunter2"), "{}", body);
+    let snapshot: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(snapshot["config"]["server"]["port"], port);
+    assert_eq!(snapshot["vhosts"][0]["server_name"], HOST);
+
+    // Importing the export changes nothing
+    let (head, body) = post_json(admin_port, "/api/config/import", &cookie, &snapshot.to_string());
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let preview: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(preview["ready"], true, "{}", preview);
+    assert_eq!(preview["changed_settings"], serde_json::json!([]), "{}", preview);
+    assert_eq!(preview["vhosts"]["changed"], serde_json::json!([]), "{}", preview);
+    assert!(preview["wolfserve_toml"].as_str().unwrap().contains(&format!("port = {}", port)), "{}", preview);
+
+    // One from another host
+    let mut moved = snapshot.clone();
+    moved["config"]["server"]["host"] = "0.0.0.0".into();
+    moved["vhosts"][0]["document_root"] = "/nonexistent/www".into();
+    let (_, body) = post_json(admin_port, "/api/config/import", &cookie, &moved.to_string());
+    let preview: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(preview["ready"], false, "{}", preview);
+    assert_eq!(preview["missing_files"], serde_json::json!(["/nonexistent/www"]), "{}", preview);
+    assert_eq!(preview["changed_settings"], serde_json::json!(["server"]), "{}", preview);
+    assert_eq!(preview["vhosts"]["changed"], serde_json::json!([format!("{}:{}", HOST, port)]), "{}", preview);
+
+    let (head, _) = post_json(admin_port, "/api/config/import", &cookie, "{\"format\": 1}");
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+}
+
+#[test]
+fn configuration_can_be_reloaded_from_the_dashboard() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+    let csrf = csrf_token(admin_port, cookie.as_str());
+    let reload = |csrf: &str| {
+        exchange(
+            admin_port,
+            &format!(
+                "POST /api/reload HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nX-CSRF-Token: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                cookie, csrf
+            ),
+        )
+    };
+
+    let (head, _) = reload("forged");
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+
+    let vhost = |name: &str, root: &str| format!("<VirtualHost *:{}>\n    ServerName {}\n    DocumentRoot {}\n</VirtualHost>\n", port, name, root);
+    fs::write(site.dir.join("apache/sites-enabled/other.conf"), vhost("other.local", &site.docroot.display().to_string())).unwrap();
+    let (head, body) = reload(&csrf);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let status: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status["ok"], true, "{}", status);
+    assert_eq!(status["vhosts"]["added"], serde_json::json!([format!("other.local:{}", port)]), "{}", status);
+
+    // A broken vhost is reported with its file and line, and not loaded
+    let broken = site.dir.join("apache/sites-enabled/broken.conf");
+    fs::write(&broken, vhost("broken.local", "/nonexistent/www")).unwrap();
+    let (_, body) = reload(&csrf);
+    let status: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status["ok"], false, "{}", status);
+    assert!(status["errors"][0].as_str().unwrap().contains(&format!("{}:1", broken.display())), "{}", status);
+    let vhosts = api(admin_port, "/api/vhosts", &cookie).to_string();
+    assert!(vhosts.contains("other.local") && !vhosts.contains("broken.local"), "{}", vhosts);
+    assert_eq!(api(admin_port, "/api/stats", &cookie)["last_reload"]["ok"], false);
+    assert!(api(admin_port, "/api/audit", &cookie).to_string().contains("config_reload"));
+}
+
+/// Stop the server with SIGTERM, as systemd does, and wait for it to exit
+fn terminate(mut server: Server) {
+    let status = Command::new("kill").arg("-TERM").arg(server.0.id().to_string()).status().unwrap();
+    assert!(status.success());
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "wolfserve did not exit on SIGTERM");
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success(), "{}", status);
+}
+
+#[test]
+fn statistics_and_log_survive_a_restart() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    let server = start(&site, port, admin_port);
+    get(port, "/index.html", HOST, "");
+    get(port, "/missing.html", HOST, "");
+    terminate(server);
+
+    let saved: Value = serde_json::from_str(&fs::read_to_string(site.dir.join("wolfserve_stats.json")).unwrap()).unwrap();
+    assert_eq!(saved["stats"]["total_requests"], 2, "{}", saved);
+
+    let _server = start(&site, port, admin_port);
+    let cookie = login(admin_port);
+    let stats = api(admin_port, "/api/stats", &cookie);
+    assert_eq!(stats["total_requests"], 2, "{}", stats);
+    assert_eq!(stats["requests_4xx"], 1, "{}", stats);
+    let logs = api(admin_port, "/api/logs", &cookie);
+    let paths: Vec<&str> = logs["entries"].as_array().unwrap().iter().map(|log| log["path"].as_str().unwrap()).collect();
+    assert!(paths.contains(&"/index.html") && paths.contains(&"/missing.html"), "{}", logs);
+}
+
+#[test]
+fn sessions_survive_a_restart_until_logout() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let sessions_file = site.dir.join("wolfserve_sessions.json");
+    let server = start(&site, port, admin_port);
+    let (cookie, other) = (login(admin_port), login(admin_port));
+    // Killed outright: every change is saved as it happens
+    drop(server);
+
+    let saved = fs::read_to_string(&sessions_file).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&saved).unwrap().as_array().unwrap().len(), 2);
+    for token in [&cookie, &other].map(|c| c.split_once('=').unwrap().1) {
+        assert!(!saved.contains(token), "{}", saved);
+    }
+    use std::os::unix::fs::PermissionsExt;
+    assert_eq!(fs::metadata(&sessions_file).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let server = start(&site, port, admin_port);
+    assert_eq!(api(admin_port, "/api/sessions", &cookie).as_array().unwrap().len(), 2);
+    let (head, _) = get(admin_port, "/logout", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 303"), "{}", head);
+    drop(server);
+
+    let saved: Value = serde_json::from_str(&fs::read_to_string(&sessions_file).unwrap()).unwrap();
+    assert_eq!(saved.as_array().unwrap().len(), 1);
+    let _server = start(&site, port, admin_port);
+    let (head, _) = get(admin_port, "/api/stats", "localhost", &format!("Cookie: {}\r\n", cookie));
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+    assert_eq!(api(admin_port, "/api/sessions", &other).as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn request_rate_leaves_out_downtime() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    // 3600 requests over an hour of serving, saved long ago
+    let saved = serde_json::json!({
+        "saved_at": "2020-01-01T00:00:00Z",
+        "stats": { "total_requests": 3600, "requests_2xx": 3600, "requests_3xx": 0, "requests_4xx": 0, "requests_5xx": 0,
+                   "total_response_time_ms": 0, "start_time": "2019-12-31T23:00:00Z", "bytes_sent": 0, "php_requests": 0,
+                   "total_php_time_ms": 0, "tls_handshake_failures": 0, "previous_uptime_secs": 3600 },
+        "logs": []
+    });
+    fs::write(site.dir.join("wolfserve_stats.json"), saved.to_string()).unwrap();
+    let _server = start(&site, port, admin_port);
+
+    let stats = api(admin_port, "/api/stats", &login(admin_port));
+    let rate = stats["requests_per_second"].as_f64().unwrap();
+    // Not 3600 requests over the seconds since this start, nor over the
+    // years since 2019
+    assert!(rate > 0.9 && rate <= 1.0, "{}", stats);
+    assert!(stats["uptime"].as_str().unwrap().starts_with("0d 0h"), "{}", stats);
+}
+
+#[test]
+fn corrupt_statistics_file_is_ignored() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    let stats_file = site.dir.join("stats/saved.json");
+    fs::create_dir_all(stats_file.parent().unwrap()).unwrap();
+    fs::write(&stats_file, "{\"stats\": ").unwrap();
+    configure(&site, port, &format!("port = {}\nstats_file = \"{}\"\nstats_save_interval = 1", admin_port, stats_file.display()));
+    let mut server = spawn(&site, Stdio::piped());
+    wait_listening(&[port, admin_port]);
+
+    let stats = api(admin_port, "/api/stats", &login(admin_port));
+    assert_eq!(stats["total_requests"], 0, "{}", stats);
+    // The next save replaces it
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while serde_json::from_str::<Value>(&fs::read_to_string(&stats_file).unwrap()).is_err() {
+        assert!(Instant::now() < deadline, "statistics file was not rewritten");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let _ = server.0.kill();
+    let mut stderr = String::new();
+    server.0.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+    assert!(stderr.contains("Warning: ignoring corrupt statistics file"), "{}", stderr);
+}
+
+#[test]
+fn default_login_is_only_served_on_localhost() {
+    let site = Site::new("");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("host = \"0.0.0.0\"\nport = {}", admin_port));
+    let credentials = site.dir.join("wolfserve_admin.dat");
+
+    let mut server = spawn(&site, Stdio::piped());
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "wolfserve started with the default login on 0.0.0.0");
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let mut stderr = String::new();
+    server.0.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+    assert!(!status.success());
+    assert!(stderr.contains("still has the default admin/admin login"), "{}", stderr);
+    assert!(!credentials.exists());
+
+    // Once the password is changed, any address will do
+    let hash = bcrypt::hash("s3cret", 4).unwrap();
+    let json = serde_json::json!({ "username": "admin", "password_hash": hash }).to_string();
+    fs::write(&credentials, base64::Engine::encode(&base64::engine::general_purpose::STANDARD, json)).unwrap();
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port, admin_port]);
+}
+
+#[test]
+fn disabled_dashboard_does_not_listen_or_create_credentials() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let (port, admin_port) = (free_port(), free_port());
+    configure(&site, port, &format!("enabled = false\nport = {}", admin_port));
+    let _server = spawn(&site, Stdio::null());
+    wait_listening(&[port]);
+
+    assert_eq!(get(port, "/index.html", HOST, "").1, "hello");
+    assert!(TcpStream::connect(("127.0.0.1", admin_port)).is_err());
+    assert!(!site.dir.join("wolfserve_admin.dat").exists());
+}
@@ -0,0 +1,67 @@
+//! `canonical_host`: aliases and bare-IP requests get a 301 to the vhost's
+//! canonical name with the port, path and query kept; the canonical name
+//! itself and `canonical_exempt` paths are served as they are.
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::{Site, HOST, PORT};
+use std::sync::Arc;
+use tower::ServiceExt;
+use wolfserve::AppState;
+
+/// The test vhost with alias www.HOST and `[vhosts."test.local"] canonical_host`
+fn site(canonical_host: &str) -> (Site, Arc<AppState>) {
+    let site = Site::new("    ServerAlias www.test.local");
+    site.write("page.html", "page");
+    let state = site.state(&format!("\n[vhosts.\"{}\"]\ncanonical_host = {}", HOST, canonical_host));
+    (site, state)
+}
+
+/// Status and Location of a request for `host` and `target`
+async fn get(state: &Arc<AppState>, host: &str, target: &str) -> (StatusCode, Option<String>) {
+    let request = Request::get(target).header(header::HOST, host).body(Body::empty()).unwrap();
+    let response = wolfserve::site_router(state, PORT, false).oneshot(request).await.unwrap();
+    let location = response.headers().get(header::LOCATION).map(|value| value.to_str().unwrap().to_string());
+    (response.status(), location)
+}
+
+fn moved(location: &str) -> (StatusCode, Option<String>) {
+    (StatusCode::MOVED_PERMANENTLY, Some(location.to_string()))
+}
+
+#[tokio::test]
+async fn aliases_redirect_to_the_server_name() {
+    let (_site, state) = site("true");
+
+    assert_eq!(get(&state, "www.test.local", "/page.html?a=1&b=2").await, moved("http://test.local/page.html?a=1&b=2"));
+    assert_eq!(get(&state, "www.test.local:8080", "/page.html").await, moved("http://test.local:8080/page.html"));
+    // The only vhost answers for a bare IP too
+    assert_eq!(get(&state, "127.0.0.1:8080", "/").await, moved("http://test.local:8080/"));
+}
+
+#[tokio::test]
+async fn the_canonical_name_is_served() {
+    let (_site, state) = site("true");
+
+    assert_eq!(get(&state, HOST, "/page.html").await, (StatusCode::OK, None));
+    assert_eq!(get(&state, "TEST.local:8080", "/page.html?a=1").await, (StatusCode::OK, None));
+}
+
+#[tokio::test]
+async fn an_explicit_name_redirects_the_server_name() {
+    let (_site, state) = site("\"WWW.test.local.\"");
+
+    assert_eq!(get(&state, HOST, "/page.html").await, moved("http://www.test.local/page.html"));
+    assert_eq!(get(&state, "www.test.local", "/page.html").await, (StatusCode::OK, None));
+}
+
+#[tokio::test]
+async fn exempt_paths_are_not_redirected() {
+    let (_site, state) = site("true");
+
+    assert_eq!(get(&state, "www.test.local", "/health").await, (StatusCode::NOT_FOUND, None));
+    assert_eq!(get(&state, "www.test.local", "/.well-known/acme-challenge/token").await, (StatusCode::NOT_FOUND, None));
+    assert_eq!(get(&state, "www.test.local", "/healthcheck").await, moved("http://test.local/healthcheck"));
+}
@@ -0,0 +1,80 @@
+//! Certificates picked up from `[tls] cert_dir`: served for the name in the
+//! file name and their DNS names, after the vhosts' own certificates.
+
+mod common;
+
+use common::pki::*;
+use common::{serve_tls, Site, HOST};
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::fs;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// A server certificate for `names`, issued by `ca`
+fn issue_for(names: &[&str], ca: &Issued, serial: u8) -> Issued {
+    let alt_names: Vec<u8> = names.iter().flat_map(|name| der(0x82, name.as_bytes())).collect();
+    issue(
+        name(names[0], None),
+        vec![extension(OID_SUBJECT_ALT_NAME, der(0x30, &alt_names)), key_usage(OID_SERVER_AUTH)],
+        Some(ca),
+        serial,
+    )
+}
+
+/// The certificate sent for a handshake with SNI `sni`
+async fn server_cert(addr: std::net::SocketAddr, ca: &Issued, sni: &str) -> Vec<u8> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(ca.cert.clone())).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from(sni.to_string()).unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await.unwrap();
+    tls.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+}
+
+#[tokio::test]
+async fn directory_certificates_answer_for_their_names() {
+    let site = Site::new("");
+    let ca = issue(
+        name("Test CA", Some("Example")),
+        vec![extension(OID_BASIC_CONSTRAINTS, sequence(&[der(0x01, &[0xff])]))],
+        None,
+        1,
+    );
+    let vhost = issue_for(&[HOST], &ca, 2);
+    fs::write(site.dir.join("vhost.pem"), pem("CERTIFICATE", &vhost.cert)).unwrap();
+    fs::write(site.dir.join("vhost.key"), pem("PRIVATE KEY", &vhost.pkcs8)).unwrap();
+    site.set_directives(&format!(
+        "    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}",
+        site.dir.join("vhost.pem").display(),
+        site.dir.join("vhost.key").display()
+    ));
+
+    let certs = site.dir.join("certs");
+    fs::create_dir(&certs).unwrap();
+    // A .crt/.key pair with an extra SAN
+    let shop = issue_for(&["shop.example", "www.shop.example"], &ca, 3);
+    fs::write(certs.join("shop.example.crt"), pem("CERTIFICATE", &shop.cert)).unwrap();
+    fs::write(certs.join("shop.example.key"), pem("PRIVATE KEY", &shop.pkcs8)).unwrap();
+    // A combined .pem
+    let api = issue_for(&["api.example"], &ca, 4);
+    fs::write(certs.join("api.example.pem"), pem("CERTIFICATE", &api.cert) + &pem("PRIVATE KEY", &api.pkcs8)).unwrap();
+    // The vhost's name: its own certificate wins
+    let shadowed = issue_for(&[HOST], &ca, 5);
+    fs::write(certs.join("test.local.crt"), pem("CERTIFICATE", &shadowed.cert)).unwrap();
+    fs::write(certs.join("test.local.key"), pem("PRIVATE KEY", &shadowed.pkcs8)).unwrap();
+    // A certificate without its key is skipped
+    fs::write(certs.join("lonely.example.crt"), pem("CERTIFICATE", &api.cert)).unwrap();
+
+    let addr = serve_tls(site.state(&format!("\n[tls]\ncert_dir = \"{}\"", certs.display()))).await;
+
+    assert_eq!(server_cert(addr, &ca, "shop.example").await, shop.cert);
+    assert_eq!(server_cert(addr, &ca, "www.shop.example").await, shop.cert);
+    assert_eq!(server_cert(addr, &ca, "api.example").await, api.cert);
+    assert_eq!(server_cert(addr, &ca, HOST).await, vhost.cert);
+}
@@ -0,0 +1,195 @@
+//! Certificates of the running binary: files that change on disk are picked
+//! up within `[tls] check_interval`, and a certificate that no longer loads
+//! keeps serving its previous version. One that comes within `[tls]
+//! expiry_warning_days` is logged and posted to `[tls] expiry_webhook`.
+
+mod common;
+
+use common::pki::*;
+use common::{Site, HOST};
+use rustls::pki_types::{CertificateDer, ServerName};
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// A port nothing is listening on right now
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Kills the server when the test ends, pass or fail
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// The binary serving the site's vhost over TLS on a free port, its
+/// certificate files checked every second and `tls` added to `[tls]`;
+/// the port, and the file its error output goes to
+fn start(site: &Site, tls: &str) -> (Server, u16, PathBuf) {
+    let (port, tls_port) = (free_port(), free_port());
+    fs::write(
+        site.dir.join("apache/sites-enabled/test.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName {}\n    DocumentRoot {}\n    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n</VirtualHost>\n",
+            tls_port,
+            HOST,
+            site.docroot.display(),
+            site.dir.join("site.pem").display(),
+            site.dir.join("site.key").display()
+        ),
+    )
+    .unwrap();
+    let toml = format!(
+        "[server]\nhost = \"127.0.0.1\"\nport = {}\n\n[php]\n\n[apache]\nconfig_dir = \"{}\"\n\n[admin]\nenabled = false\n\n[tls]\ncheck_interval = 1\n{}\n",
+        port,
+        site.dir.join("apache").display(),
+        tls
+    );
+    fs::write(site.dir.join("wolfserve.toml"), toml).unwrap();
+    let stderr = site.dir.join("stderr.log");
+    let server = Command::new(env!("CARGO_BIN_EXE_wolfserve"))
+        .current_dir(&site.dir)
+        .stdout(Stdio::null())
+        .stderr(fs::File::create(&stderr).unwrap())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while std::net::TcpStream::connect(("127.0.0.1", tls_port)).is_err() {
+        assert!(Instant::now() < deadline, "wolfserve did not start listening on {}", tls_port);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    (Server(server), tls_port, stderr)
+}
+
+fn test_ca() -> Issued {
+    issue(name("Test CA", Some("Example")), vec![extension(OID_BASIC_CONSTRAINTS, sequence(&[der(0x01, &[0xff])]))], None, 1)
+}
+
+/// Write `issued` as the vhost's certificate and key files
+fn install(site: &Site, issued: &Issued) {
+    fs::write(site.dir.join("site.pem"), pem("CERTIFICATE", &issued.cert)).unwrap();
+    fs::write(site.dir.join("site.key"), pem("PRIVATE KEY", &issued.pkcs8)).unwrap();
+}
+
+/// The certificate the server on `port` sends for a handshake
+async fn served_cert(port: u16, ca: &Issued) -> Vec<u8> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(ca.cert.clone())).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.unwrap();
+    tls.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+}
+
+#[tokio::test]
+async fn changed_certificate_files_are_picked_up() {
+    let site = Site::new("");
+    let ca = test_ca();
+    let first = issue(name(HOST, None), server_extensions(HOST), Some(&ca), 2);
+    install(&site, &first);
+    let (_server, port, stderr) = start(&site, "");
+    assert_eq!(served_cert(port, &ca).await, first.cert);
+
+    // A renewal, as certbot writes it
+    let renewed = issue(name(HOST, None), server_extensions(HOST), Some(&ca), 3);
+    install(&site, &renewed);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while served_cert(port, &ca).await != renewed.cert {
+        assert!(Instant::now() < deadline, "the renewed certificate was not picked up");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // A half-written file keeps the renewed certificate in service
+    fs::write(site.dir.join("site.pem"), "-----BEGIN CERTIFICATE-----\nMIIB\n").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !fs::read_to_string(&stderr).unwrap().contains("keeping the previous certificate") {
+        assert!(Instant::now() < deadline, "the broken certificate was not reported");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert_eq!(served_cert(port, &ca).await, renewed.cert);
+}
+
+/// A webhook on a local port; its URL, and the JSON bodies posted to it
+fn webhook() -> (String, mpsc::Receiver<Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/expiry", listener.local_addr().unwrap());
+    let (sender, received) = mpsc::channel();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let body = loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                        .unwrap();
+                    if body.len() >= length || n == 0 {
+                        break body.to_string();
+                    }
+                }
+            };
+            stream.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").unwrap();
+            let _ = sender.send(serde_json::from_str(&body).unwrap());
+        }
+    });
+    (url, received)
+}
+
+/// UTCTime `days` from now
+fn days_from_now(days: i64) -> String {
+    (chrono::Utc::now() + chrono::Duration::days(days)).format("%y%m%d%H%M%SZ").to_string()
+}
+
+#[test]
+fn expiring_certificate_is_posted_to_the_webhook() {
+    let site = Site::new("");
+    let ca = test_ca();
+    let not_after = days_from_now(10);
+    install(&site, &issue_until(name(HOST, None), server_extensions(HOST), Some(&ca), 2, &not_after));
+    let (url, posted) = webhook();
+    let (_server, _, stderr) = start(&site, &format!("expiry_warning_days = 30\nexpiry_webhook = \"{}\"", url));
+
+    let payload = posted.recv_timeout(Duration::from_secs(10)).expect("no webhook call");
+    assert_eq!(payload["certificate"], HOST);
+    assert_eq!(payload["names"], serde_json::json!([HOST]));
+    assert_eq!(payload["state"], "expiring");
+    // Ten days less the seconds since the certificate was made
+    assert_eq!(payload["days_left"], 9);
+    let posted_not_after = chrono::DateTime::parse_from_rfc3339(payload["not_after"].as_str().unwrap()).unwrap();
+    assert_eq!(posted_not_after.format("%y%m%d%H%M%SZ").to_string(), not_after);
+    assert!(fs::read_to_string(&stderr).unwrap().contains(&format!("WARNING: certificate for {} expires in 9 day(s)", HOST)));
+    // Only once: it stays expiring
+    assert!(posted.recv_timeout(Duration::from_millis(1500)).is_err());
+}
+
+#[test]
+fn certificate_outside_the_warning_days_is_not_posted() {
+    let site = Site::new("");
+    let ca = test_ca();
+    install(&site, &issue_until(name(HOST, None), server_extensions(HOST), Some(&ca), 2, &days_from_now(31)));
+    let (url, posted) = webhook();
+    let (_server, _, stderr) = start(&site, &format!("expiry_warning_days = 30\nexpiry_webhook = \"{}\"", url));
+
+    assert!(posted.recv_timeout(Duration::from_secs(2)).is_err());
+    assert!(!fs::read_to_string(&stderr).unwrap().contains("WARNING: certificate"));
+}
@@ -0,0 +1,170 @@
+//! Client certificate authentication (SSLVerifyClient) over real TLS
+//! connections, with a throwaway CA issuing the server and client
+//! certificates.
+
+mod common;
+
+use common::pki::*;
+use common::{serve_tls, MockFpm, Site, HOST, PORT};
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// A throwaway CA, a server certificate for HOST and a client certificate
+/// for alice, with the CA and server files written to `dir`
+struct Pki {
+    ca: Issued,
+    client: Issued,
+    ca_file: PathBuf,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+}
+
+impl Pki {
+    fn new(dir: &std::path::Path) -> Self {
+        let ca = issue(
+            name("Test CA", Some("Example")),
+            vec![extension(OID_BASIC_CONSTRAINTS, sequence(&[der(0x01, &[0xff])]))],
+            None,
+            1,
+        );
+        let server = issue(
+            name(HOST, None),
+            server_extensions(HOST),
+            Some(&ca),
+            2,
+        );
+        let client = issue(name("alice", Some("Example")), vec![key_usage(OID_CLIENT_AUTH)], Some(&ca), 3);
+
+        let pki = Pki {
+            ca_file: dir.join("ca.pem"),
+            cert_file: dir.join("server.pem"),
+            key_file: dir.join("server.key"),
+            ca,
+            client,
+        };
+        fs::write(&pki.ca_file, pem("CERTIFICATE", &pki.ca.cert)).unwrap();
+        fs::write(&pki.cert_file, pem("CERTIFICATE", &server.cert)).unwrap();
+        fs::write(&pki.key_file, pem("PRIVATE KEY", &server.pkcs8)).unwrap();
+        pki
+    }
+
+    /// Vhost directives for TLS with `SSLVerifyClient mode`
+    fn directives(&self, mode: &str) -> String {
+        format!(
+            "    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n    SSLVerifyClient {}\n    SSLCACertificateFile {}",
+            self.cert_file.display(),
+            self.key_file.display(),
+            mode,
+            self.ca_file.display()
+        )
+    }
+}
+
+/// A site whose vhost has `SSLVerifyClient mode`, served over TLS on a local
+/// port with its index.php answered by a mock PHP-FPM
+async fn serve(mode: &str) -> (Site, Pki, MockFpm, std::net::SocketAddr) {
+    let site = Site::new("");
+    let pki = Pki::new(&site.dir);
+    site.set_directives(&pki.directives(mode));
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+
+    let addr = serve_tls(site.state(&format!("fpm_address = \"{}\"", mock.addr))).await;
+    (site, pki, mock, addr)
+}
+
+/// GET /index.php over TLS, presenting `client` if given; the raw response,
+/// or the error that ended the connection
+async fn fetch(addr: std::net::SocketAddr, pki: &Pki, client: Option<&Issued>) -> std::io::Result<String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(pki.ca.cert.clone())).unwrap();
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client {
+        Some(client) => builder.with_client_auth_cert(vec![CertificateDer::from(client.cert.clone())], client.key_der()).unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    let stream = TcpStream::connect(addr).await?;
+    let mut tls = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await?;
+    tls.write_all(format!("GET /index.php HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HOST).as_bytes()).await?;
+    let mut response = Vec::new();
+    tls.read_to_end(&mut response).await?;
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+#[tokio::test]
+async fn required_certificate_is_passed_to_php() {
+    let (_site, pki, mock, addr) = serve("require").await;
+
+    let response = fetch(addr, &pki, Some(&pki.client)).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["SSL_CLIENT_VERIFY"], "SUCCESS");
+    assert_eq!(params["SSL_CLIENT_S_DN"], "CN=alice,O=Example");
+    assert_eq!(params["SSL_CLIENT_CERT"], pem("CERTIFICATE", &pki.client.cert));
+}
+
+#[tokio::test]
+async fn required_certificate_missing_is_refused() {
+    let (_site, pki, mock, addr) = serve("require").await;
+
+    // With TLS 1.3 the refusal arrives after the client's side of the handshake
+    if let Ok(response) = fetch(addr, &pki, None).await {
+        assert!(!response.contains("200"), "{}", response);
+    }
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn certificate_from_another_ca_is_refused() {
+    let (_site, pki, mock, addr) = serve("require").await;
+    let stranger = issue(name("mallory", None), vec![key_usage(OID_CLIENT_AUTH)], None, 4);
+
+    if let Ok(response) = fetch(addr, &pki, Some(&stranger)).await {
+        assert!(!response.contains("200"), "{}", response);
+    }
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn optional_certificate() {
+    let (_site, pki, mock, addr) = serve("optional").await;
+
+    let response = fetch(addr, &pki, None).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let response = fetch(addr, &pki, Some(&pki.client)).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].params["SSL_CLIENT_VERIFY"], "NONE");
+    assert!(!requests[0].params.contains_key("SSL_CLIENT_S_DN"));
+    assert_eq!(requests[1].params["SSL_CLIENT_VERIFY"], "SUCCESS");
+    assert_eq!(requests[1].params["SSL_CLIENT_S_DN"], "CN=alice,O=Example");
+}
+
+#[tokio::test]
+async fn conflicting_vhosts_on_one_port_are_an_error() {
+    let (site, pki, _mock, _addr) = serve("require").await;
+    fs::write(
+        site.dir.join("apache/sites-enabled/other.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName other.local\n    DocumentRoot {}\n{}\n</VirtualHost>\n",
+            PORT,
+            site.docroot.display(),
+            pki.directives("none")
+        ),
+    )
+    .unwrap();
+
+    let error = wolfserve::tls_config_for_port(&site.state(""), PORT).err().unwrap();
+    assert!(error.contains("disagree on SSLVerifyClient"), "{}", error);
+}
@@ -0,0 +1,284 @@
+//! Fixtures shared by the integration tests: a throwaway Apache layout with
+//! one vhost and its document root, and a mock PHP-FPM that speaks FastCGI.
+
+#![allow(dead_code)]
+
+pub mod pki;
+
+use parking_lot::Mutex;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use wolfserve::AppState;
+
+/// Name and port of the test vhost
+pub const HOST: &str = "test.local";
+pub const PORT: u16 = 8080;
+
+/// A temp directory holding `apache/sites-enabled/test.conf` and `www/`
+pub struct Site {
+    pub dir: PathBuf,
+    pub docroot: PathBuf,
+}
+
+impl Site {
+    /// `directives` go inside the `<VirtualHost>` after ServerName and DocumentRoot
+    pub fn new(directives: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("wolfserve-test-{}", uuid::Uuid::new_v4()));
+        let docroot = dir.join("www");
+        fs::create_dir_all(dir.join("apache/sites-enabled")).unwrap();
+        fs::create_dir_all(&docroot).unwrap();
+        let site = Site { dir, docroot };
+        site.set_directives(directives);
+        site
+    }
+
+    /// Replace the directives after ServerName and DocumentRoot, for ones
+    /// that name paths under `dir`
+    pub fn set_directives(&self, directives: &str) {
+        fs::write(
+            self.dir.join("apache/sites-enabled/test.conf"),
+            format!(
+                "<VirtualHost *:{}>\n    ServerName {}\n    DocumentRoot {}\n{}\n</VirtualHost>\n",
+                PORT,
+                HOST,
+                self.docroot.display(),
+                directives
+            ),
+        )
+        .unwrap();
+    }
+
+    /// Write a file under the document root, creating its directories
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let path = self.docroot.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Server state for this site; `php` is the body of the `[php]` table
+    pub fn state(&self, php: &str) -> Arc<AppState> {
+        self.state_with_server("", php)
+    }
+
+    /// Server state for this site with `server` added to the `[server]` table
+    pub fn state_with_server(&self, server: &str, php: &str) -> Arc<AppState> {
+        let toml = format!(
+            "[server]\nhost = \"127.0.0.1\"\nport = {}\n{}\n\n[php]\n{}\n\n[apache]\nconfig_dir = \"{}\"\n",
+            PORT,
+            server,
+            php,
+            self.dir.join("apache").display()
+        );
+        wolfserve::load_state(toml::from_str(&toml).unwrap())
+    }
+}
+
+/// Serve `state`'s vhosts on PORT over TLS from a local port
+pub async fn serve_tls(state: Arc<AppState>) -> std::net::SocketAddr {
+    let tls = wolfserve::tls_config_for_port(&state, PORT).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(wolfserve::serve_https(state, listener, PORT, tls));
+    addr
+}
+
+/// Serve `state`'s vhosts on PORT over plain HTTP from a local port
+pub async fn serve_plain(state: Arc<AppState>) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(wolfserve::serve_http(state, listener, PORT));
+    addr
+}
+
+/// Accepts any certificate
+#[derive(Debug)]
+pub struct AcceptAny;
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ECDSA_NISTP256_SHA256]
+    }
+}
+
+impl Drop for Site {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// One request as PHP-FPM received it
+#[derive(Debug, Default, Clone)]
+pub struct FpmRequest {
+    pub params: HashMap<String, String>,
+    pub stdin: Vec<u8>,
+}
+
+/// A FastCGI responder on a local port answering every request with the same
+/// CGI output (headers, blank line, body)
+pub struct MockFpm {
+    pub addr: String,
+    requests: Arc<Mutex<Vec<FpmRequest>>>,
+}
+
+const BEGIN_REQUEST: u8 = 1;
+pub const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+pub const STDOUT: u8 = 6;
+
+type Reply = Arc<dyn Fn(u16) -> Vec<u8> + Send + Sync>;
+
+impl MockFpm {
+    pub async fn start(output: impl Into<Vec<u8>>) -> Self {
+        Self::serve(cgi_reply(output.into()), true).await
+    }
+
+    /// Like `start`, without keeping the requests (for benchmarks)
+    pub async fn start_unrecorded(output: impl Into<Vec<u8>>) -> Self {
+        Self::serve(cgi_reply(output.into()), false).await
+    }
+
+    /// Answer every request with the bytes `reply` makes for its request id,
+    /// as they are, then close the connection
+    pub async fn start_raw(reply: impl Fn(u16) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        Self::serve(Arc::new(reply), true).await
+    }
+
+    async fn serve(reply: Reply, record: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let reply = reply.clone();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    if let Ok(request) = respond(stream, &*reply).await {
+                        if record {
+                            received.lock().push(request);
+                        }
+                    }
+                });
+            }
+        });
+        MockFpm { addr, requests }
+    }
+
+    /// Requests answered so far
+    pub fn requests(&self) -> Vec<FpmRequest> {
+        self.requests.lock().clone()
+    }
+}
+
+async fn read_record(stream: &mut TcpStream) -> std::io::Result<(u8, u16, Vec<u8>)> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await?;
+    let request_id = u16::from_be_bytes([header[2], header[3]]);
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0u8; length + header[6] as usize];
+    stream.read_exact(&mut content).await?;
+    content.truncate(length);
+    Ok((header[1], request_id, content))
+}
+
+pub fn record(kind: u8, request_id: u16, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![1, kind];
+    out.extend_from_slice(&request_id.to_be_bytes());
+    out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(content);
+    out
+}
+
+/// Name-value pairs: lengths are one byte, or four with the top bit set
+fn parse_params(mut data: &[u8], params: &mut HashMap<String, String>) {
+    fn length(data: &mut &[u8]) -> usize {
+        if data[0] & 0x80 == 0 {
+            let len = data[0] as usize;
+            *data = &data[1..];
+            len
+        } else {
+            let len = u32::from_be_bytes([data[0] & 0x7f, data[1], data[2], data[3]]) as usize;
+            *data = &data[4..];
+            len
+        }
+    }
+    while !data.is_empty() {
+        let name_len = length(&mut data);
+        let value_len = length(&mut data);
+        let name = String::from_utf8_lossy(&data[..name_len]).into_owned();
+        let value = String::from_utf8_lossy(&data[name_len..name_len + value_len]).into_owned();
+        params.insert(name, value);
+        data = &data[name_len + value_len..];
+    }
+}
+
+/// STDOUT records carrying `output`, then a successful END_REQUEST
+fn cgi_reply(output: Vec<u8>) -> Reply {
+    Arc::new(move |request_id| {
+        let mut reply = Vec::new();
+        for chunk in output.chunks(u16::MAX as usize) {
+            reply.extend(record(STDOUT, request_id, chunk));
+        }
+        reply.extend(record(STDOUT, request_id, &[]));
+        reply.extend(record(END_REQUEST, request_id, &[0; 8]));
+        reply
+    })
+}
+
+async fn respond(mut stream: TcpStream, reply: &(dyn Fn(u16) -> Vec<u8> + Send + Sync)) -> std::io::Result<FpmRequest> {
+    let mut request = FpmRequest::default();
+    let mut params = Vec::new();
+    let request_id = loop {
+        let (kind, request_id, content) = read_record(&mut stream).await?;
+        match kind {
+            BEGIN_REQUEST => {}
+            PARAMS if content.is_empty() => parse_params(&params, &mut request.params),
+            PARAMS => params.extend_from_slice(&content),
+            STDIN if content.is_empty() => break request_id,
+            STDIN => request.stdin.extend_from_slice(&content),
+            _ => {}
+        }
+    };
+
+    stream.write_all(&reply(request_id)).await?;
+    stream.shutdown().await?;
+    Ok(request)
+}
@@ -0,0 +1,144 @@
+//! A minimal DER writer for issuing throwaway CA, server and client
+//! certificates in tests.
+
+use aws_lc_rs::encoding::{AsDer, Pkcs8V1Der};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::rsa::{KeyPair as RsaKeyPair, KeySize};
+use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+pub const OID_ECDSA_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+pub const OID_RSA_ENCRYPTION: &[u8] = &[0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub const OID_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+pub const OID_ORGANIZATION: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x0a];
+pub const OID_BASIC_CONSTRAINTS: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x13];
+pub const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+pub const OID_EXT_KEY_USAGE: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x25];
+pub const OID_SERVER_AUTH: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+pub const OID_CLIENT_AUTH: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+
+pub fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else if content.len() < 0x100 {
+        out.extend_from_slice(&[0x81, content.len() as u8]);
+    } else {
+        out.extend_from_slice(&[0x82, (content.len() >> 8) as u8, content.len() as u8]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub fn sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    der(0x30, &parts.concat())
+}
+
+pub fn pem(label: &str, der: &[u8]) -> String {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let lines: Vec<&str> = encoded.as_bytes().chunks(64).map(|line| std::str::from_utf8(line).unwrap()).collect();
+    format!("-----BEGIN {}-----\n{}\n-----END {}-----\n", label, lines.join("\n"), label)
+}
+
+/// Name of CN and, if given, O; encoded O first so it reads `CN=..,O=..`
+pub fn name(common_name: &str, organization: Option<&str>) -> Vec<u8> {
+    let rdn = |oid: &[u8], value: &str| der(0x31, &sequence(&[oid.to_vec(), der(0x0c, value.as_bytes())]));
+    let mut rdns = Vec::new();
+    if let Some(organization) = organization {
+        rdns.push(rdn(OID_ORGANIZATION, organization));
+    }
+    rdns.push(rdn(OID_COMMON_NAME, common_name));
+    sequence(&rdns)
+}
+
+/// A P-256 key and its certificate
+pub struct Issued {
+    pub key: EcdsaKeyPair,
+    pub pkcs8: Vec<u8>,
+    pub cert: Vec<u8>,
+    pub subject: Vec<u8>,
+}
+
+impl Issued {
+    pub fn key_der(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.pkcs8.clone()))
+    }
+}
+
+/// Issue a certificate for `subject` with `extensions`, signed by `issuer`
+/// or self-signed
+pub fn issue(subject: Vec<u8>, extensions: Vec<Vec<u8>>, issuer: Option<&Issued>, serial: u8) -> Issued {
+    issue_until(subject, extensions, issuer, serial, "491231235959Z")
+}
+
+/// Like `issue`, valid until `not_after`, a UTCTime such as `491231235959Z`
+pub fn issue_until(subject: Vec<u8>, extensions: Vec<Vec<u8>>, issuer: Option<&Issued>, serial: u8, not_after: &str) -> Issued {
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new()).unwrap();
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+    let mut public_key = vec![0];
+    public_key.extend_from_slice(key.public_key().as_ref());
+    let spki = sequence(&[sequence(&[OID_EC_PUBLIC_KEY.to_vec(), OID_P256.to_vec()]), der(0x03, &public_key)]);
+
+    let signer = issuer.map_or((&subject, &key), |issuer| (&issuer.subject, &issuer.key));
+    let cert = certificate(&subject, spki, extensions, signer, serial, not_after);
+    Issued { key, pkcs8: pkcs8.as_ref().to_vec(), cert, subject }
+}
+
+/// An RSA key and its certificate
+pub struct RsaIssued {
+    pub pkcs8: Vec<u8>,
+    pub cert: Vec<u8>,
+}
+
+/// Issue a certificate for an RSA-2048 key, signed by `issuer`
+pub fn issue_rsa(subject: Vec<u8>, extensions: Vec<Vec<u8>>, issuer: &Issued, serial: u8) -> RsaIssued {
+    let key = RsaKeyPair::generate(KeySize::Rsa2048).unwrap();
+    let pkcs8 = AsDer::<Pkcs8V1Der>::as_der(&key).unwrap();
+    let mut public_key = vec![0];
+    public_key.extend_from_slice(key.public_key().as_ref());
+    let spki = sequence(&[sequence(&[OID_RSA_ENCRYPTION.to_vec(), vec![0x05, 0x00]]), der(0x03, &public_key)]);
+
+    let cert = certificate(&subject, spki, extensions, (&issuer.subject, &issuer.key), serial, "491231235959Z");
+    RsaIssued { pkcs8: pkcs8.as_ref().to_vec(), cert }
+}
+
+/// A DER certificate for `subject` and its `spki`, signed by the issuer's
+/// name and key
+fn certificate(
+    subject: &[u8],
+    spki: Vec<u8>,
+    extensions: Vec<Vec<u8>>,
+    (issuer, signer): (&Vec<u8>, &EcdsaKeyPair),
+    serial: u8,
+    not_after: &str,
+) -> Vec<u8> {
+    let tbs = sequence(&[
+        der(0xa0, &der(0x02, &[2])),
+        der(0x02, &[serial]),
+        sequence(&[OID_ECDSA_SHA256.to_vec()]),
+        issuer.clone(),
+        sequence(&[der(0x17, b"250101000000Z"), der(0x17, not_after.as_bytes())]),
+        subject.to_vec(),
+        spki,
+        der(0xa3, &sequence(&extensions)),
+    ]);
+    let mut signature = vec![0];
+    signature.extend_from_slice(signer.sign(&SystemRandom::new(), &tbs).unwrap().as_ref());
+    sequence(&[tbs, sequence(&[OID_ECDSA_SHA256.to_vec()]), der(0x03, &signature)])
+}
+
+pub fn extension(oid: &[u8], value: Vec<u8>) -> Vec<u8> {
+    sequence(&[oid.to_vec(), der(0x04, &value)])
+}
+
+pub fn key_usage(purpose: &[u8]) -> Vec<u8> {
+    extension(OID_EXT_KEY_USAGE, sequence(&[purpose.to_vec()]))
+}
+
+/// Extensions of a TLS server certificate for `host`
+pub fn server_extensions(host: &str) -> Vec<Vec<u8>> {
+    vec![extension(OID_SUBJECT_ALT_NAME, sequence(&[der(0x82, host.as_bytes())])), key_usage(OID_SERVER_AUTH)]
+}
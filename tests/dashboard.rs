@@ -0,0 +1,37 @@
+//! The admin dashboard's request log: request fields are escaped, so a
+//! request cannot plant markup or script in the page.
+
+use chrono::Utc;
+use wolfserve::{generate_dashboard_html, AdminState, RequestLogEntry};
+
+#[test]
+fn logged_requests_are_html_escaped() {
+    let state = AdminState::new();
+    state.log_request(RequestLogEntry {
+        timestamp: Utc::now(),
+        method: "GET<img src=x onerror=alert(1)>".to_string(),
+        path: "/<script>alert('xss')</script>".to_string(),
+        status: 404,
+        duration_ms: 1,
+        client_ip: "203.0.113.9\"><b>".to_string(),
+        host: "<svg onload=alert(2)>.example".to_string(),
+        user_agent: "Mozilla/5.0 <iframe src=//evil>".to_string(),
+        protocol: "HTTP/1.1".to_string(),
+        php_duration_ms: None,
+    });
+
+    let html = generate_dashboard_html("admin", &state);
+    assert!(html.contains("/&lt;script&gt;alert(&#39;xss&#39;)&lt;/script&gt;"), "{}", html);
+    assert!(html.contains("GET&lt;img src=x onerror=alert(1)&gt;"));
+    assert!(html.contains("203.0.113.9&quot;&gt;&lt;b&gt;"));
+    assert!(html.contains("&lt;svg onload=alert(2)&gt;.example"));
+    assert!(html.contains(
+        "<td class=\"user-agent\" title=\"Mozilla/5.0 &lt;iframe src=//evil&gt;\">Mozilla/5.0 &lt;iframe src=//evil&gt;</td>"
+    ));
+    for raw in ["<script>alert", "<img src=x", "<svg onload", "\"><b>", "<iframe"] {
+        assert!(!html.contains(raw), "{} left unescaped", raw);
+    }
+    // Rows refreshed from /api/logs are escaped in the browser
+    assert!(html.contains("${escapeHtml(log.path)}"));
+    assert!(html.contains("${escapeHtml(log.user_agent)}"));
+}
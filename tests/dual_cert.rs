@@ -0,0 +1,185 @@
+//! A vhost with both an ECDSA and an RSA certificate: each handshake gets the
+//! one its ClientHello can use, ECDSA first.
+
+mod common;
+
+use common::pki::*;
+use common::{serve_tls, Site, HOST, PORT};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{aws_lc_rs, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::fs;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// The two server certificates, both issued by `ca`
+struct Certs {
+    ca: Issued,
+    ecdsa: Issued,
+    rsa: RsaIssued,
+}
+
+/// Serve a vhost listing `pairs` of (certificate, key) file names, written
+/// from a fresh ECDSA and RSA certificate
+async fn serve(pairs: &[(&str, &str)]) -> (Site, Certs, std::net::SocketAddr) {
+    let site = Site::new("");
+    let ca = issue(
+        name("Test CA", Some("Example")),
+        vec![extension(OID_BASIC_CONSTRAINTS, sequence(&[der(0x01, &[0xff])]))],
+        None,
+        1,
+    );
+    let ecdsa = issue(name(HOST, None), server_extensions(HOST), Some(&ca), 2);
+    let rsa = issue_rsa(name(HOST, None), server_extensions(HOST), &ca, 3);
+    fs::write(site.dir.join("ecdsa.pem"), pem("CERTIFICATE", &ecdsa.cert)).unwrap();
+    fs::write(site.dir.join("ecdsa.key"), pem("PRIVATE KEY", &ecdsa.pkcs8)).unwrap();
+    fs::write(site.dir.join("rsa.pem"), pem("CERTIFICATE", &rsa.cert)).unwrap();
+    fs::write(site.dir.join("rsa.key"), pem("PRIVATE KEY", &rsa.pkcs8)).unwrap();
+    fs::write(site.dir.join("ca.key"), pem("PRIVATE KEY", &ca.pkcs8)).unwrap();
+
+    let directives: String = pairs
+        .iter()
+        .map(|(cert, key)| {
+            format!(
+                "    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n",
+                site.dir.join(cert).display(),
+                site.dir.join(key).display()
+            )
+        })
+        .collect();
+    fs::write(
+        site.dir.join("apache/sites-enabled/test.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName {}\n    DocumentRoot {}\n    SSLEngine on\n{}</VirtualHost>\n",
+            PORT,
+            HOST,
+            site.docroot.display(),
+            directives
+        ),
+    )
+    .unwrap();
+
+    let addr = serve_tls(site.state("")).await;
+    (site, Certs { ca, ecdsa, rsa }, addr)
+}
+
+/// Accepts any certificate, offering only `schemes` in signature_algorithms
+#[derive(Debug)]
+struct OfferOnly {
+    schemes: Vec<SignatureScheme>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for OfferOnly {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.schemes.clone()
+    }
+}
+
+/// Handshake with `config` and return the certificate the server sent
+async fn server_cert(addr: std::net::SocketAddr, config: rustls::ClientConfig) -> Vec<u8> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.unwrap();
+    tls.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+}
+
+/// A client that trusts `certs.ca` and offers everything
+fn default_client(certs: &Certs) -> rustls::ClientConfig {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(certs.ca.cert.clone())).unwrap();
+    rustls::ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// A client offering `schemes` and, if given, only the `suites` of TLS 1.2
+fn restricted_client(schemes: &[SignatureScheme], suites: Option<&[rustls::SupportedCipherSuite]>) -> rustls::ClientConfig {
+    let mut provider = aws_lc_rs::default_provider();
+    let versions: &[&rustls::SupportedProtocolVersion] = match suites {
+        Some(suites) => {
+            provider.cipher_suites = suites.to_vec();
+            &[&rustls::version::TLS12]
+        }
+        None => rustls::DEFAULT_VERSIONS,
+    };
+    let provider = Arc::new(provider);
+    rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(OfferOnly { schemes: schemes.to_vec(), provider }))
+        .with_no_client_auth()
+}
+
+const RSA_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+#[tokio::test]
+async fn ecdsa_is_preferred() {
+    let (_site, certs, addr) = serve(&[("rsa.pem", "rsa.key"), ("ecdsa.pem", "ecdsa.key")]).await;
+
+    assert_eq!(server_cert(addr, default_client(&certs)).await, certs.ecdsa.cert);
+}
+
+#[tokio::test]
+async fn rsa_only_signature_schemes_get_rsa() {
+    let (_site, certs, addr) = serve(&[("ecdsa.pem", "ecdsa.key"), ("rsa.pem", "rsa.key")]).await;
+
+    assert_eq!(server_cert(addr, restricted_client(RSA_SCHEMES, None)).await, certs.rsa.cert);
+}
+
+#[tokio::test]
+async fn rsa_only_cipher_suites_get_rsa() {
+    let (_site, certs, addr) = serve(&[("ecdsa.pem", "ecdsa.key"), ("rsa.pem", "rsa.key")]).await;
+    let schemes = [SignatureScheme::ECDSA_NISTP256_SHA256, SignatureScheme::RSA_PSS_SHA256];
+    let suites = [aws_lc_rs::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256];
+
+    assert_eq!(server_cert(addr, restricted_client(&schemes, Some(&suites))).await, certs.rsa.cert);
+}
+
+#[tokio::test]
+async fn mismatched_key_is_not_served() {
+    // The RSA certificate is given the CA's key, so only the ECDSA pair loads
+    let (_site, certs, addr) = serve(&[("ecdsa.pem", "ecdsa.key"), ("rsa.pem", "ca.key")]).await;
+
+    assert_eq!(server_cert(addr, default_client(&certs)).await, certs.ecdsa.cert);
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let connector = TlsConnector::from(Arc::new(restricted_client(RSA_SCHEMES, None)));
+    assert!(connector.connect(ServerName::try_from(HOST).unwrap(), stream).await.is_err());
+}
@@ -0,0 +1,114 @@
+//! Private keys encrypted with a passphrase. The fixtures in
+//! tests/fixtures/keys are one P-256 key (ec.key, certified by ec.pem)
+//! written by `openssl pkcs8 -topk8` and `openssl ec` with several ciphers,
+//! all with the passphrase "correct-horse".
+
+mod common;
+
+use common::{serve_tls, AcceptAny, Site, HOST};
+use rustls::pki_types::{PrivateKeyDer, ServerName};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use wolfserve::{load_private_key, Passphrase};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+fn passphrase(value: &str) -> Passphrase {
+    Passphrase { value: Some(value.to_string()), ..Default::default() }
+}
+
+/// The DER of the unencrypted key
+fn plain_key() -> Vec<u8> {
+    match load_private_key(&fixture("ec.key"), &Passphrase::default()).unwrap() {
+        PrivateKeyDer::Pkcs8(key) => key.secret_pkcs8_der().to_vec(),
+        other => panic!("{:?}", other),
+    }
+}
+
+fn secret_der(key: PrivateKeyDer) -> Vec<u8> {
+    key.secret_der().to_vec()
+}
+
+#[test]
+fn aes_keys_are_decrypted() {
+    for name in ["ec-aes256.key", "ec-aes128-sha1.key"] {
+        let key = load_private_key(&fixture(name), &passphrase("correct-horse")).unwrap();
+        assert_eq!(secret_der(key), plain_key(), "{}", name);
+    }
+}
+
+#[test]
+fn passphrase_from_environment_and_command() {
+    let var = format!("WOLFSERVE_TEST_PASSPHRASE_{}", std::process::id());
+    std::env::set_var(&var, "correct-horse");
+    let from_env = Passphrase { env: Some(var.clone()), ..Default::default() };
+    assert_eq!(secret_der(load_private_key(&fixture("ec-aes256.key"), &from_env).unwrap()), plain_key());
+
+    // The command gets the key file as $1
+    let command = "case \"$1\" in */ec-aes256.key) echo correct-horse;; *) exit 1;; esac";
+    let from_command = Passphrase { command: Some(command.to_string()), ..Default::default() };
+    assert_eq!(secret_der(load_private_key(&fixture("ec-aes256.key"), &from_command).unwrap()), plain_key());
+
+    let failing = Passphrase { command: Some("exit 3".to_string()), ..Default::default() };
+    let error = load_private_key(&fixture("ec-aes256.key"), &failing).unwrap_err().to_string();
+    assert!(error.contains("key_passphrase_command `exit 3` failed"), "{}", error);
+
+    std::env::remove_var(&var);
+    let error = load_private_key(&fixture("ec-aes256.key"), &from_env).unwrap_err().to_string();
+    assert!(error.contains(&format!("${} is not set", var)), "{}", error);
+}
+
+#[test]
+fn encrypted_key_without_passphrase_source() {
+    let error = load_private_key(&fixture("ec-aes256.key"), &Passphrase::default()).unwrap_err().to_string();
+    assert!(error.contains("is encrypted but no passphrase is configured"), "{}", error);
+    assert!(error.contains("key_passphrase_command"), "{}", error);
+}
+
+#[test]
+fn wrong_passphrase() {
+    for name in ["ec-aes256.key", "ec-aes128-sha1.key"] {
+        let error = load_private_key(&fixture(name), &passphrase("battery-staple")).unwrap_err().to_string();
+        assert!(error.ends_with("wrong passphrase"), "{}: {}", name, error);
+    }
+}
+
+#[test]
+fn unsupported_ciphers() {
+    for (name, cipher) in [
+        ("ec-des3.key", "unsupported key encryption des-ede3-cbc"),
+        ("ec-scrypt.key", "unsupported key encryption scrypt"),
+        ("ec-traditional.key", "legacy PEM encryption"),
+    ] {
+        let error = load_private_key(&fixture(name), &passphrase("correct-horse")).unwrap_err().to_string();
+        assert!(error.contains(cipher), "{}: {}", name, error);
+        assert!(error.contains("openssl pkcs8 -topk8 -v2 aes-256-cbc"), "{}: {}", name, error);
+    }
+}
+
+#[tokio::test]
+async fn encrypted_default_certificate_is_served() {
+    let site = Site::new("    SSLEngine on");
+    let tls = format!(
+        "\n[tls]\ncertificate_file = \"{}\"\ncertificate_key_file = \"{}\"\nkey_passphrase = \"correct-horse\"",
+        fixture("ec.pem").display(),
+        fixture("ec-aes256.key").display()
+    );
+    let addr = serve_tls(site.state(&tls)).await;
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.unwrap();
+    let pem = std::fs::read(fixture("ec.pem")).unwrap();
+    let cert = rustls_pemfile::certs(&mut pem.as_slice()).next().unwrap().unwrap();
+    assert_eq!(tls.get_ref().1.peer_certificates().unwrap()[0], cert);
+}
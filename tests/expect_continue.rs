@@ -0,0 +1,74 @@
+//! `Expect: 100-continue`: an upload the server takes gets 100 Continue
+//! before the client sends its body; one it refuses gets the final status
+//! straight away, and other expectations get 417.
+
+mod common;
+
+use common::{serve_plain, MockFpm, Site, HOST};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Send the head of a POST of `length` bytes to /upload.php
+async fn post_head(addr: SocketAddr, length: usize, expect: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let head = format!(
+        "POST /upload.php HTTP/1.1\r\nHost: {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nExpect: {}\r\nConnection: close\r\n\r\n",
+        HOST, length, expect
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream
+}
+
+/// Read one response head; the client sent no body, so this must not wait
+/// on one
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        let read = timeout(Duration::from_secs(5), stream.read(&mut byte)).await.expect("no response without the body").unwrap();
+        assert_eq!(read, 1, "connection closed after {:?}", String::from_utf8_lossy(&head));
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+async fn site() -> (Site, MockFpm, SocketAddr) {
+    let site = Site::new("    LimitRequestBody 1000");
+    site.write("upload.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nstored").await;
+    let addr = serve_plain(site.state(&format!("fpm_address = \"{}\"", mock.addr))).await;
+    (site, mock, addr)
+}
+
+#[tokio::test]
+async fn upload_waits_for_100_continue() {
+    let (_site, mock, addr) = site().await;
+    let body = vec![b'x'; 800];
+
+    let mut stream = post_head(addr, body.len(), "100-continue").await;
+    assert_eq!(read_head(&mut stream).await, "HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(&body).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("stored"), "{}", response);
+    assert_eq!(mock.requests()[0].stdin, body);
+}
+
+#[tokio::test]
+async fn refused_upload_gets_no_100_continue() {
+    let (_site, mock, addr) = site().await;
+
+    // Over LimitRequestBody: the client learns before sending anything
+    let mut stream = post_head(addr, 5000, "100-continue").await;
+    let head = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", head);
+
+    let mut stream = post_head(addr, 10, "something-else").await;
+    let head = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 417 Expectation Failed\r\n"), "{}", head);
+    assert!(mock.requests().is_empty());
+}
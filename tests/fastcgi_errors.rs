@@ -0,0 +1,62 @@
+//! How FastCGI failures are answered: 502 for a backend that breaks the
+//! protocol or hangs up mid-response, 503 for one that says it is overloaded,
+//! with the reason on the page only under `[php] error_detail`.
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use common::{record, MockFpm, Site, END_REQUEST, HOST, PORT, STDOUT};
+use tower::ServiceExt;
+
+async fn send(fpm: &MockFpm, php: &str) -> (StatusCode, String) {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let php = format!("fpm_address = \"{}\"\n{}", fpm.addr, php);
+    let request = Request::get("/index.php").header(header::HOST, HOST).body(Body::empty()).unwrap();
+    let response = wolfserve::site_router(&site.state(&php), PORT, false).oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+/// A record of type 99, which FastCGI doesn't have
+async fn unknown_record_fpm() -> MockFpm {
+    MockFpm::start_raw(|id| record(99, id, b"what")).await
+}
+
+#[tokio::test]
+async fn unknown_record_type_is_502() {
+    let (status, body) = send(&unknown_record_fpm().await, "").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert!(body.contains("failed to answer"), "{}", body);
+    assert!(!body.contains("FastCGI"), "{}", body);
+}
+
+#[tokio::test]
+async fn truncated_response_is_502() {
+    let fpm = MockFpm::start_raw(|id| {
+        let mut reply = record(STDOUT, id, b"Content-Type: text/plain\r\n\r\nthe whole body");
+        reply.truncate(reply.len() - 5);
+        reply
+    })
+    .await;
+    let (status, _) = send(&fpm, "").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn overloaded_backend_is_503() {
+    // protocolStatus 2 is FCGI_OVERLOADED
+    let fpm = MockFpm::start_raw(|id| record(END_REQUEST, id, &[0, 0, 0, 0, 2, 0, 0, 0])).await;
+    let (status, body) = send(&fpm, "").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("overloaded"), "{}", body);
+}
+
+#[tokio::test]
+async fn error_detail_puts_the_reason_on_the_page() {
+    let (status, body) = send(&unknown_record_fpm().await, "error_detail = true").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert!(body.contains("broke the FastCGI protocol"), "{}", body);
+}
@@ -0,0 +1,88 @@
+//! Which host PHP sees and which vhost a request goes to: `php_server_name`
+//! and `php_http_host` replace SERVER_NAME and HTTP_HOST for one vhost, and
+//! with `[server] trust_forwarded_host` a trusted proxy's X-Forwarded-Host
+//! picks the vhost.
+
+mod common;
+
+use common::{serve_plain, MockFpm, Site, HOST, PORT};
+use std::fs;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn get(addr: SocketAddr, path: &str, headers: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\n{}Connection: close\r\n\r\n", path, headers);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn vhost_overrides_the_host_php_sees() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    // Site::state puts this inside [php]; the [vhosts] table starts after it
+    let php = format!("fpm_address = \"{}\"\n\n[vhosts.\"{}\"]\nphp_http_host = \"www.example.com:8443\"", mock.addr, HOST);
+    let addr = serve_plain(site.state(&php)).await;
+
+    get(addr, "/index.php", &format!("Host: {}\r\n", HOST)).await;
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["HTTP_HOST"], "www.example.com:8443");
+    assert_eq!(params["SERVER_NAME"], "www.example.com");
+}
+
+#[tokio::test]
+async fn php_server_name_is_set_on_its_own() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let php = format!("fpm_address = \"{}\"\n\n[vhosts.\"{}\"]\nphp_server_name = \"public.example.com\"", mock.addr, HOST);
+    let addr = serve_plain(site.state(&php)).await;
+
+    get(addr, "/index.php", &format!("Host: {}:{}\r\n", HOST, PORT)).await;
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["HTTP_HOST"], format!("{}:{}", HOST, PORT));
+    assert_eq!(params["SERVER_NAME"], "public.example.com");
+}
+
+/// The test vhost plus `public.local`, whose index says which it is
+fn two_vhosts() -> Site {
+    let site = Site::new("");
+    site.write("index.html", "internal");
+    let public_root = site.dir.join("public");
+    fs::create_dir_all(&public_root).unwrap();
+    fs::write(public_root.join("index.html"), "public").unwrap();
+    fs::write(
+        site.dir.join("apache/sites-enabled/zz-public.conf"),
+        format!("<VirtualHost *:{}>\n    ServerName public.local\n    DocumentRoot {}\n</VirtualHost>\n", PORT, public_root.display()),
+    )
+    .unwrap();
+    site
+}
+
+#[tokio::test]
+async fn trusted_forwarded_host_picks_the_vhost() {
+    let site = two_vhosts();
+    let addr = serve_plain(site.state_with_server("trusted_proxies = [\"127.0.0.1\"]\ntrust_forwarded_host = true", "")).await;
+
+    let response = get(addr, "/", &format!("Host: {}\r\nX-Forwarded-Host: public.local, {}\r\n", HOST, HOST)).await;
+    assert!(response.ends_with("public"), "{}", response);
+    let response = get(addr, "/", &format!("Host: {}\r\n", HOST)).await;
+    assert!(response.ends_with("internal"), "{}", response);
+}
+
+#[tokio::test]
+async fn forwarded_host_is_ignored_unless_trusted() {
+    let site = two_vhosts();
+    let forwarded = format!("Host: {}\r\nX-Forwarded-Host: public.local\r\n", HOST);
+
+    let untrusted = serve_plain(site.state_with_server("trusted_proxies = [\"192.0.2.1\"]\ntrust_forwarded_host = true", "")).await;
+    assert!(get(untrusted, "/", &forwarded).await.ends_with("internal"));
+    // Trusting the proxy's X-Forwarded-Proto doesn't mean trusting its host
+    let off = serve_plain(site.state_with_server("trusted_proxies = [\"127.0.0.1\"]", "")).await;
+    assert!(get(off, "/", &forwarded).await.ends_with("internal"));
+}
@@ -0,0 +1,64 @@
+//! X-Forwarded-Proto and X-Forwarded-Port from a `[server] trusted_proxies`
+//! address decide what PHP sees as the scheme and port and where canonical
+//! redirects point; from anyone else they are ignored.
+
+mod common;
+
+use common::{serve_plain, MockFpm, Site, HOST};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// GET `path` as a TLS-terminating proxy would forward it
+async fn forwarded_get(addr: SocketAddr, host: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Port: 8443\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+async fn php_site(server: &str) -> (Site, MockFpm, SocketAddr) {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let addr = serve_plain(site.state_with_server(server, &format!("fpm_address = \"{}\"", mock.addr))).await;
+    (site, mock, addr)
+}
+
+#[tokio::test]
+async fn trusted_proxy_sets_https_for_php() {
+    let (_site, mock, addr) = php_site("trusted_proxies = [\"127.0.0.1\"]").await;
+
+    forwarded_get(addr, HOST, "/index.php").await;
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["HTTPS"], "on");
+    assert_eq!(params["REQUEST_SCHEME"], "https");
+    assert_eq!(params["SERVER_PORT"], "8443");
+}
+
+#[tokio::test]
+async fn forwarded_headers_from_other_peers_are_ignored() {
+    let (_site, mock, addr) = php_site("trusted_proxies = [\"192.0.2.1\"]").await;
+
+    forwarded_get(addr, HOST, "/index.php").await;
+    let params = &mock.requests()[0].params;
+    assert!(!params.contains_key("HTTPS"));
+    assert_eq!(params["REQUEST_SCHEME"], "http");
+    assert_eq!(params["SERVER_PORT"], "8080");
+}
+
+#[tokio::test]
+async fn canonical_redirect_keeps_the_forwarded_scheme() {
+    let site = Site::new("    UseCanonicalName On");
+    let server = "canonical_redirect = true\ntrusted_proxies = [\"127.0.0.1\"]";
+    let addr = serve_plain(site.state_with_server(server, "")).await;
+
+    let response = forwarded_get(addr, "alias.local", "/page?x=1").await;
+    assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+    assert!(response.to_ascii_lowercase().contains(&format!("location: https://{}/page?x=1\r\n", HOST)), "{}", response);
+}
@@ -0,0 +1,35 @@
+//! The annotated config written by --generate-config.
+
+use std::fs;
+
+#[test]
+fn annotated_config_parses_with_every_option_set() {
+    toml::from_str::<wolfserve::Config>(wolfserve::ANNOTATED_CONFIG).unwrap();
+
+    // Uncomment the `# key = value` and `# [table]` lines
+    let uncommented: String = wolfserve::ANNOTATED_CONFIG
+        .lines()
+        .map(|line| {
+            let setting = line.strip_prefix("# ").filter(|rest| {
+                rest.starts_with('[') || rest.split_once(" = ").is_some_and(|(key, _)| key.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+            });
+            format!("{}\n", setting.unwrap_or(line))
+        })
+        .collect();
+    assert!(uncommented.contains("\n[vhosts.\"example.com\"]\n"));
+    toml::from_str::<wolfserve::Config>(&uncommented).unwrap();
+}
+
+#[test]
+fn existing_file_is_kept_without_force() {
+    let path = std::env::temp_dir().join(format!("wolfserve-test-{}.toml", uuid::Uuid::new_v4()));
+    fs::write(&path, "# mine\n").unwrap();
+
+    let error = wolfserve::generate_config(&path, false).unwrap_err();
+    assert!(error.contains("--force"), "{}", error);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# mine\n");
+
+    wolfserve::generate_config(&path, true).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), wolfserve::ANNOTATED_CONFIG);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,57 @@
+//! Connections that never complete the TLS handshake are dropped after
+//! `[tls] handshake_timeout`.
+
+mod common;
+
+use common::pki::*;
+use common::{serve_tls, Site, HOST};
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// A TLS site using the `[tls]` default certificate, with a one second
+/// handshake timeout
+async fn serve() -> (Site, std::net::SocketAddr) {
+    let site = Site::new("    SSLEngine on");
+    let server = issue(name(HOST, None), server_extensions(HOST), None, 1);
+    fs::write(site.dir.join("server.pem"), pem("CERTIFICATE", &server.cert)).unwrap();
+    fs::write(site.dir.join("server.key"), pem("PRIVATE KEY", &server.pkcs8)).unwrap();
+    let tls = format!(
+        "\n[tls]\ncertificate_file = \"{}\"\ncertificate_key_file = \"{}\"\nhandshake_timeout = 1",
+        site.dir.join("server.pem").display(),
+        site.dir.join("server.key").display()
+    );
+    let addr = serve_tls(site.state(&tls)).await;
+    (site, addr)
+}
+
+/// How long until the server closes `stream`
+async fn time_to_close(mut stream: TcpStream) -> Duration {
+    let start = Instant::now();
+    let mut buf = [0; 64];
+    let read = timeout(Duration::from_secs(5), stream.read(&mut buf)).await.expect("connection was not closed");
+    assert!(matches!(read, Ok(0) | Err(_)), "{:?}", read);
+    start.elapsed()
+}
+
+#[tokio::test]
+async fn silent_client_is_dropped() {
+    let (_site, addr) = serve().await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let elapsed = time_to_close(stream).await;
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn stalled_client_hello_is_dropped() {
+    let (_site, addr) = serve().await;
+
+    // A record header promising a ClientHello that never arrives
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01]).await.unwrap();
+    let elapsed = time_to_close(stream).await;
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+}
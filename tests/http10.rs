@@ -0,0 +1,70 @@
+//! HTTP/1.0 clients without a Host header, as old monitoring tools send: they
+//! get the port's default vhost, and every response says whether the
+//! connection stays open.
+
+mod common;
+
+use common::{serve_plain, Site, HOST};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Send raw requests on one connection and read until the server closes it
+async fn exchange(addr: SocketAddr, requests: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).to_ascii_lowercase()
+}
+
+fn site() -> Site {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    site
+}
+
+#[tokio::test]
+async fn request_without_host_is_served_and_closed() {
+    let site = site();
+    let addr = serve_plain(site.state("")).await;
+
+    let response = exchange(addr, "GET /index.html HTTP/1.0\r\n\r\n").await;
+    assert!(response.starts_with("http/1.0 200 ") || response.starts_with("http/1.1 200 "), "{}", response);
+    assert!(response.contains("\r\nconnection: close\r\n"), "{}", response);
+    assert!(response.ends_with("hello"), "{}", response);
+}
+
+#[tokio::test]
+async fn keep_alive_is_kept_when_asked_for() {
+    let site = site();
+    let addr = serve_plain(site.state("")).await;
+
+    let response = exchange(
+        addr,
+        "GET /index.html HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /index.html HTTP/1.0\r\n\r\n",
+    )
+    .await;
+    let (first, second) = response.split_once("hello").unwrap();
+    assert!(first.contains("\r\nconnection: keep-alive\r\n"), "{}", response);
+    assert!(second.contains(" 200 ") && second.contains("\r\nconnection: close\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn keep_alive_can_be_refused() {
+    let site = site();
+    let addr = serve_plain(site.state_with_server("http10_keep_alive = false", "")).await;
+
+    let response = exchange(
+        addr,
+        "GET /index.html HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /index.html HTTP/1.0\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.matches(" 200 ").count(), 1, "{}", response);
+    assert!(response.contains("\r\nconnection: close\r\n"), "{}", response);
+
+    // HTTP/1.1 keeps its keep-alive
+    let requests = format!("GET /index.html HTTP/1.1\r\nHost: {0}\r\n\r\nGET /index.html HTTP/1.1\r\nHost: {0}\r\nConnection: close\r\n\r\n", HOST);
+    let response = exchange(addr, &requests).await;
+    assert_eq!(response.matches(" 200 ").count(), 2, "{}", response);
+}
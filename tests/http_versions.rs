@@ -0,0 +1,156 @@
+//! `[ports] http_versions`: the ALPN list of HTTPS listeners, h2c on plain
+//! ones, and a readable refusal for HTTP/1 clients of HTTP/2-only ports.
+//! PHP is told which version a request came in over.
+
+mod common;
+
+use bytes::Bytes;
+use common::{serve_plain, serve_tls, AcceptAny, MockFpm, Site, HOST, PORT};
+use http_body_util::{BodyExt, Empty};
+use hyper::Version;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+/// An HTTPS site with `ports` as the `[ports]` entry of the test port
+fn tls_site(ports: &str) -> (Site, String) {
+    let site = Site::new("    SSLEngine on");
+    site.write("index.html", "hello");
+    let toml = format!(
+        "\n[tls]\ncertificate_file = \"{}\"\ncertificate_key_file = \"{}\"\n\n[ports]\n{}",
+        fixture("ec.pem").display(),
+        fixture("ec.key").display(),
+        ports
+    );
+    (site, toml)
+}
+
+/// The protocol ALPN settled on, or None if the handshake failed
+async fn negotiate(addr: SocketAddr, alpn: &[&[u8]]) -> Option<Option<Vec<u8>>> {
+    let tls = connect_tls(addr, alpn).await?;
+    Some(tls.get_ref().1.alpn_protocol().map(<[u8]>::to_vec))
+}
+
+async fn connect_tls(addr: SocketAddr, alpn: &[&[u8]]) -> Option<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.ok()
+}
+
+/// GET /index.html over HTTP/2 with prior knowledge
+async fn h2c_get(addr: SocketAddr) -> Option<Version> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await.ok()?;
+    tokio::spawn(connection);
+    let request = hyper::Request::get(format!("http://{}/index.html", HOST)).body(Empty::<Bytes>::new()).unwrap();
+    let response = sender.send_request(request).await.ok()?;
+    let version = response.version();
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "hello");
+    Some(version)
+}
+
+/// Send a raw HTTP/1.1 request and read until the server closes
+async fn http1_exchange<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(mut stream: S) -> String {
+    let request = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", HOST);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn https_offers_h2_then_http11_by_default() {
+    let (site, toml) = tls_site("");
+    let addr = serve_tls(site.state(&toml)).await;
+
+    assert_eq!(negotiate(addr, &[b"h2", b"http/1.1"]).await, Some(Some(b"h2".to_vec())));
+    assert_eq!(negotiate(addr, &[b"http/1.1"]).await, Some(Some(b"http/1.1".to_vec())));
+}
+
+#[tokio::test]
+async fn php_over_negotiated_h2_sees_http2() {
+    let (site, toml) = tls_site("");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nfrom php").await;
+    let addr = serve_tls(site.state(&format!("fpm_address = \"{}\"\n{}", mock.addr, toml))).await;
+
+    let tls = connect_tls(addr, &[b"h2", b"http/1.1"]).await.unwrap();
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tls)).await.unwrap();
+    tokio::spawn(connection);
+    let request = hyper::Request::get(format!("https://{}/index.php?id=7", HOST)).body(Empty::<Bytes>::new()).unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.version(), Version::HTTP_2);
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "from php");
+
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["SERVER_PROTOCOL"], "HTTP/2.0");
+    assert_eq!(params["REQUEST_URI"], "/index.php?id=7");
+    assert_eq!(params["HTTPS"], "on");
+}
+
+#[tokio::test]
+async fn https_port_forced_to_http11() {
+    let (site, toml) = tls_site(&format!("{} = {{ http_versions = [\"http/1.1\"] }}\n", PORT));
+    let addr = serve_tls(site.state(&toml)).await;
+
+    assert_eq!(negotiate(addr, &[b"h2", b"http/1.1"]).await, Some(Some(b"http/1.1".to_vec())));
+    // A client that insists on h2 gets a no_application_protocol alert
+    assert_eq!(negotiate(addr, &[b"h2"]).await, None);
+}
+
+#[tokio::test]
+async fn h2_only_https_refuses_clients_without_alpn() {
+    let (site, toml) = tls_site(&format!("{} = {{ http_versions = [\"h2\"] }}\n", PORT));
+    let addr = serve_tls(site.state(&toml)).await;
+
+    assert_eq!(negotiate(addr, &[b"h2", b"http/1.1"]).await, Some(Some(b"h2".to_vec())));
+    let response = http1_exchange(connect_tls(addr, &[]).await.unwrap()).await;
+    assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{}", response);
+    assert!(response.contains("speaks HTTP/2 only"), "{}", response);
+}
+
+#[tokio::test]
+async fn plain_port_takes_h2c_only_when_enabled() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+
+    let addr = serve_plain(site.state("")).await;
+    assert_eq!(h2c_get(addr).await, None);
+
+    let ports = format!("\n[ports]\n{} = {{ http_versions = [\"http/1.1\", \"h2\"] }}\n", PORT);
+    let addr = serve_plain(site.state(&ports)).await;
+    assert_eq!(h2c_get(addr).await, Some(Version::HTTP_2));
+    let response = http1_exchange(TcpStream::connect(addr).await.unwrap()).await;
+    assert!(response.starts_with("HTTP/1.1 "), "{}", response);
+}
+
+#[tokio::test]
+async fn h2_only_plain_port_answers_http1_with_505() {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    let ports = format!("\n[ports]\n{} = {{ http_versions = [\"h2\"] }}\n", PORT);
+    let addr = serve_plain(site.state(&ports)).await;
+
+    assert_eq!(h2c_get(addr).await, Some(Version::HTTP_2));
+    let response = http1_exchange(TcpStream::connect(addr).await.unwrap()).await;
+    assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{}", response);
+    assert!(response.contains(&format!("[ports] {} http_versions", PORT)), "{}", response);
+}
@@ -0,0 +1,107 @@
+//! `redirect_to_https`: plain HTTP requests for a name with a TLS vhost get a
+//! 301 to the same path and query on that vhost's port, except ACME
+//! challenges and requests a trusted proxy says came over HTTPS.
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::{serve_plain, Site, HOST, PORT};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tower::ServiceExt;
+use wolfserve::AppState;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+/// The plain HTTP test vhost, and a TLS vhost for HOST and www.HOST on `tls_port`
+fn site(tls_port: u16) -> Site {
+    let site = Site::new("");
+    site.write("page.html", "plain");
+    site.write(".well-known/acme-challenge/token", "key-authorization");
+    std::fs::write(
+        site.dir.join("apache/sites-enabled/tls.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName {}\n    ServerAlias www.{}\n    DocumentRoot {}\n    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n</VirtualHost>\n",
+            tls_port,
+            HOST,
+            HOST,
+            site.docroot.display(),
+            fixture("ec.pem").display(),
+            fixture("ec.key").display()
+        ),
+    )
+    .unwrap();
+    site
+}
+
+/// Status and Location of a plain HTTP request for `host` and `target`
+async fn get(state: &Arc<AppState>, host: &str, target: &str) -> (StatusCode, Option<String>) {
+    let request = Request::get(target).header(header::HOST, host).body(Body::empty()).unwrap();
+    let response = wolfserve::site_router(state, PORT, false).oneshot(request).await.unwrap();
+    let location = response.headers().get(header::LOCATION).map(|value| value.to_str().unwrap().to_string());
+    (response.status(), location)
+}
+
+#[tokio::test]
+async fn redirects_to_the_tls_vhost_port_keeping_path_and_query() {
+    let site = site(8443);
+    let state = site.state_with_server("redirect_to_https = true", "");
+
+    let expected = (StatusCode::MOVED_PERMANENTLY, Some("https://test.local:8443/page.html?a=1&b=2".to_string()));
+    assert_eq!(get(&state, HOST, "/page.html?a=1&b=2").await, expected);
+    // The port the client used for HTTP is not carried over
+    assert_eq!(get(&state, &format!("{}:{}", HOST, PORT), "/page.html?a=1&b=2").await, expected);
+    assert_eq!(
+        get(&state, "www.test.local", "/").await,
+        (StatusCode::MOVED_PERMANENTLY, Some("https://www.test.local:8443/".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn port_443_is_left_out() {
+    let site = site(443);
+    let state = site.state_with_server("redirect_to_https = true", "");
+
+    assert_eq!(
+        get(&state, HOST, "/page.html").await,
+        (StatusCode::MOVED_PERMANENTLY, Some("https://test.local/page.html".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn acme_challenges_stay_on_http() {
+    let site = site(8443);
+    let state = site.state_with_server("redirect_to_https = true", "");
+
+    assert_eq!(get(&state, HOST, "/.well-known/acme-challenge/token").await, (StatusCode::OK, None));
+}
+
+#[tokio::test]
+async fn names_without_a_tls_vhost_and_disabled_redirects_are_served() {
+    let site = site(8443);
+    let state = site.state_with_server("redirect_to_https = true", "");
+    assert_eq!(get(&state, "other.local", "/page.html").await, (StatusCode::OK, None));
+
+    assert_eq!(get(&site.state(""), HOST, "/page.html").await, (StatusCode::OK, None));
+    let state = site.state_with_server("redirect_to_https = true", "\n[vhosts.\"test.local\"]\nredirect_to_https = false");
+    assert_eq!(get(&state, HOST, "/page.html").await, (StatusCode::OK, None));
+}
+
+#[tokio::test]
+async fn requests_a_trusted_proxy_received_over_https_are_served() {
+    let site = site(8443);
+    let addr = serve_plain(site.state_with_server("redirect_to_https = true\ntrusted_proxies = [\"127.0.0.1\"]", "")).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET /page.html HTTP/1.1\r\nHost: {}\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n", HOST);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("plain"), "{}", response);
+}
@@ -0,0 +1,75 @@
+//! KeepAlive and MaxKeepAliveRequests from Apache's main config: connections
+//! close after one request with KeepAlive Off and after the cap otherwise,
+//! unless wolfserve.toml says differently.
+
+mod common;
+
+use common::{serve_plain, Site, HOST};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A site whose apache2.conf holds `directives` and includes the vhost
+fn site(directives: &str) -> Site {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    std::fs::write(
+        site.dir.join("apache/apache2.conf"),
+        format!("{}\nIncludeOptional sites-enabled/*.conf\n", directives),
+    )
+    .unwrap();
+    site
+}
+
+/// Send `count` pipelined requests on one connection, the last asking to
+/// close it, and read until the server closes it; the responses' status lines
+/// and Connection headers
+async fn pipeline(addr: SocketAddr, count: usize) -> Vec<(String, Option<String>)> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut requests = format!("GET /index.html HTTP/1.1\r\nHost: {}\r\n\r\n", HOST).repeat(count - 1);
+    requests += &format!("GET /index.html HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HOST);
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+        .split("HTTP/1.1 ")
+        .skip(1)
+        .map(|response| {
+            let status = response.lines().next().unwrap().to_string();
+            let connection = response
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .find_map(|line| line.to_ascii_lowercase().strip_prefix("connection: ").map(String::from));
+            (status, connection)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn keep_alive_off_closes_after_one_request() {
+    let site = site("KeepAlive Off");
+    let addr = serve_plain(site.state("")).await;
+
+    assert_eq!(pipeline(addr, 3).await, [("200 OK".to_string(), Some("close".to_string()))]);
+}
+
+#[tokio::test]
+async fn max_keep_alive_requests_closes_at_the_cap() {
+    let site = site("KeepAlive On\nMaxKeepAliveRequests 2");
+    let addr = serve_plain(site.state("")).await;
+
+    assert_eq!(
+        pipeline(addr, 4).await,
+        [("200 OK".to_string(), None), ("200 OK".to_string(), Some("close".to_string()))]
+    );
+}
+
+#[tokio::test]
+async fn wolfserve_toml_overrides_apache() {
+    let site = site("KeepAlive Off\nMaxKeepAliveRequests 2");
+    let addr = serve_plain(site.state_with_server("keep_alive = true\nmax_keep_alive_requests = 0", "")).await;
+
+    let responses = pipeline(addr, 4).await;
+    assert_eq!(responses.len(), 4);
+    assert!(responses[..3].iter().all(|response| *response == ("200 OK".to_string(), None)), "{:?}", responses);
+}
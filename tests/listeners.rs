@@ -0,0 +1,103 @@
+//! What each port serves: a port with both SSL and non-SSL vhosts is an
+//! error naming them, `[ports]` settles it, and `--dump-vhosts` shows the
+//! listener of each port.
+
+mod common;
+
+use common::{Site, HOST, PORT};
+use serde_json::Value;
+use std::fs;
+use std::process::{Command, Output};
+
+/// A second vhost on the test port, with SSLEngine on
+fn add_ssl_vhost(site: &Site) {
+    fs::write(
+        site.dir.join("apache/sites-enabled/secure.conf"),
+        format!(
+            "<VirtualHost *:{}>\n    ServerName secure.local\n    DocumentRoot {}\n    SSLEngine on\n</VirtualHost>\n",
+            PORT,
+            site.docroot.display()
+        ),
+    )
+    .unwrap();
+}
+
+/// Run the binary in the site's directory with `extra` appended to its
+/// wolfserve.toml
+fn wolfserve(site: &Site, extra: &str, arg: &str) -> Output {
+    let toml = format!(
+        "[server]\nhost = \"127.0.0.1\"\nport = {}\n\n[php]\n\n[apache]\nconfig_dir = \"{}\"\nwatch = false\n{}",
+        PORT,
+        site.dir.join("apache").display(),
+        extra
+    );
+    fs::write(site.dir.join("wolfserve.toml"), toml).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_wolfserve")).arg(arg).current_dir(&site.dir).output().unwrap()
+}
+
+fn port_listener(site: &Site, extra: &str) -> Value {
+    let output = wolfserve(site, extra, "--dump-vhosts");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let dump: Value = serde_json::from_slice(&output.stdout).unwrap();
+    dump["ports"].as_array().unwrap().iter().find(|p| p["port"] == PORT).unwrap().clone()
+}
+
+#[test]
+fn mixed_port_is_an_error_naming_the_vhosts() {
+    let site = Site::new("");
+    add_ssl_vhost(&site);
+
+    let output = wolfserve(&site, "", "--check-config");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains(&format!("port {} has both SSL and non-SSL vhosts: secure.local (SSL), {}", PORT, HOST)),
+        "{}",
+        stdout
+    );
+
+    let listener = port_listener(&site, "");
+    assert_eq!(listener["protocol"], Value::Null);
+    // Site files are read in name order
+    assert_eq!(listener["vhosts"], serde_json::json!(["secure.local", HOST]));
+}
+
+#[test]
+fn ports_table_picks_the_protocol() {
+    let site = Site::new("");
+    add_ssl_vhost(&site);
+    let ports = format!("\n[ports]\n{} = \"http\"\n", PORT);
+
+    let stdout = String::from_utf8_lossy(&wolfserve(&site, &ports, "--check-config").stdout).into_owned();
+    assert!(!stdout.contains("has both SSL and non-SSL vhosts"), "{}", stdout);
+    assert!(stdout.contains("secure.local served over HTTP too"), "{}", stdout);
+
+    let listener = port_listener(&site, &ports);
+    assert_eq!(listener["protocol"], "http");
+    assert_eq!(listener["source"], "ports");
+    assert_eq!(listener["tls"], false);
+    assert_eq!(listener["mismatched"], serde_json::json!(["secure.local"]));
+}
+
+#[test]
+fn listen_protocol_applies_to_ports_without_vhosts() {
+    let site = Site::new("");
+    fs::write(site.dir.join("apache/apache2.conf"), "Listen 8443 https\nListen 127.0.0.1:9443 https\nListen [::]:8080 https\n")
+        .unwrap();
+    let ports = "\n[ports]\n8443 = \"https\"\n9000 = \"http\"\n";
+
+    let output = wolfserve(&site, ports, "--dump-vhosts");
+    let dump: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let ports: Vec<(u64, &str, &str)> = dump["ports"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["port"].as_u64().unwrap(), p["protocol"].as_str().unwrap(), p["source"].as_str().unwrap()))
+        .collect();
+    // 9443 has neither a vhost nor a [ports] entry, so nothing listens there;
+    // the vhosts of 8080 outrank its Listen
+    assert_eq!(ports, vec![(8080, "http", "vhosts"), (8443, "https", "ports"), (9000, "http", "ports")]);
+
+    let stdout = String::from_utf8_lossy(&wolfserve(&site, "", "--check-config").stdout).into_owned();
+    assert!(stdout.contains("Listen 8080 https ignored: the port's vhosts are HTTP"), "{}", stdout);
+}
@@ -0,0 +1,54 @@
+//! Maintenance mode's `allow_ips`: matched against the connecting address,
+//! and against X-Forwarded-For only when a `[server] trusted_proxies` entry
+//! sent it.
+
+mod common;
+
+use common::{serve_plain, Site, HOST};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn get(addr: SocketAddr, headers: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET /index.html HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n", HOST, headers);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// The test site in maintenance, letting `allowed` through, with `server`
+/// added to the `[server]` table
+async fn in_maintenance(server: &str, allowed: &str) -> (Site, SocketAddr) {
+    let site = Site::new("");
+    site.write("index.html", "live");
+    let server = format!("{}\n\n[maintenance]\nenabled = true\nallow_ips = [\"{}\"]", server, allowed);
+    let addr = serve_plain(site.state_with_server(&server, "")).await;
+    (site, addr)
+}
+
+#[tokio::test]
+async fn spoofed_forwarded_for_from_an_untrusted_peer_gets_503() {
+    let (_site, addr) = in_maintenance("", "203.0.113.10").await;
+    let response = get(addr, "X-Forwarded-For: 203.0.113.10\r\nX-Real-IP: 203.0.113.10\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+}
+
+#[tokio::test]
+async fn trusted_proxy_forwards_an_allowed_client() {
+    let (_site, addr) = in_maintenance("trusted_proxies = [\"127.0.0.1\"]", "203.0.113.10").await;
+    let response = get(addr, "X-Forwarded-For: 203.0.113.10, 10.0.0.5\r\n").await;
+    assert!(response.ends_with("live"), "{}", response);
+    let response = get(addr, "X-Forwarded-For: 198.51.100.7\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+}
+
+#[tokio::test]
+async fn allowed_peer_sees_the_live_site() {
+    let (_site, addr) = in_maintenance("", "127.0.0.1").await;
+    assert!(get(addr, "").await.ends_with("live"));
+    // Any other peer gets the maintenance page
+    let (_site, addr) = in_maintenance("", "192.0.2.1").await;
+    assert!(get(addr, "").await.starts_with("HTTP/1.1 503"));
+}
@@ -0,0 +1,109 @@
+//! A request whose Host belongs to another vhost than the connection's SNI
+//! name gets 421 Misdirected Request, unless `[tls] sni_mismatch = "warn"`.
+
+mod common;
+
+use common::pki::*;
+use common::{serve_tls, Site, HOST, PORT};
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::fs;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// test.local (alias www.test.local) and other.local (alias *.other.local)
+/// on one TLS port, sharing a certificate for all their names
+async fn serve(tls: &str) -> (Site, Issued, std::net::SocketAddr) {
+    let site = Site::new("");
+    let ca = issue(
+        name("Test CA", Some("Example")),
+        vec![extension(OID_BASIC_CONSTRAINTS, sequence(&[der(0x01, &[0xff])]))],
+        None,
+        1,
+    );
+    let names: Vec<u8> = ["test.local", "www.test.local", "other.local", "*.other.local"]
+        .iter()
+        .flat_map(|name| der(0x82, name.as_bytes()))
+        .collect();
+    let server = issue(
+        name(HOST, None),
+        vec![extension(OID_SUBJECT_ALT_NAME, der(0x30, &names)), key_usage(OID_SERVER_AUTH)],
+        Some(&ca),
+        2,
+    );
+    fs::write(site.dir.join("server.pem"), pem("CERTIFICATE", &server.cert)).unwrap();
+    fs::write(site.dir.join("server.key"), pem("PRIVATE KEY", &server.pkcs8)).unwrap();
+
+    let vhost = |name: &str, alias: &str, root: &str| {
+        format!(
+            "<VirtualHost *:{}>\n    ServerName {}\n    ServerAlias {}\n    DocumentRoot {}\n    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n</VirtualHost>\n",
+            PORT,
+            name,
+            alias,
+            site.docroot.join(root).display(),
+            site.dir.join("server.pem").display(),
+            site.dir.join("server.key").display()
+        )
+    };
+    fs::write(
+        site.dir.join("apache/sites-enabled/test.conf"),
+        vhost(HOST, "www.test.local", "test") + &vhost("other.local", "*.other.local", "other"),
+    )
+    .unwrap();
+    site.write("test/index.html", "test site");
+    site.write("other/index.html", "other site");
+
+    let addr = serve_tls(site.state(&format!("\n[tls]\n{}", tls))).await;
+    (site, ca, addr)
+}
+
+/// GET / with `host` over a TLS connection whose SNI is `sni`
+async fn fetch(addr: std::net::SocketAddr, ca: &Issued, sni: &str, host: &str) -> String {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from(ca.cert.clone())).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from(sni.to_string()).unwrap();
+    let mut tls = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await.unwrap();
+    tls.write_all(format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host).as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    tls.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn names_of_the_same_vhost_are_served() {
+    let (_site, ca, addr) = serve("").await;
+
+    let response = fetch(addr, &ca, "test.local", "www.test.local").await;
+    assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("test site"), "{}", response);
+    let response = fetch(addr, &ca, "a.other.local", "other.local:8080").await;
+    assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("other site"), "{}", response);
+    let response = fetch(addr, &ca, "TEST.local", "test.local").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[tokio::test]
+async fn host_of_another_vhost_is_misdirected() {
+    let (_site, ca, addr) = serve("").await;
+
+    let response = fetch(addr, &ca, "test.local", "other.local").await;
+    assert!(response.starts_with("HTTP/1.1 421"), "{}", response);
+    assert!(!response.contains("other site"), "{}", response);
+    let response = fetch(addr, &ca, "b.other.local", "www.test.local").await;
+    assert!(response.starts_with("HTTP/1.1 421"), "{}", response);
+}
+
+#[tokio::test]
+async fn warn_mode_serves_the_host() {
+    let (_site, ca, addr) = serve("sni_mismatch = \"warn\"").await;
+
+    let response = fetch(addr, &ca, "test.local", "other.local").await;
+    assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("other site"), "{}", response);
+}
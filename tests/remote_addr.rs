@@ -0,0 +1,49 @@
+//! The client address PHP sees as REMOTE_ADDR and the logs record: the
+//! connection's peer, or the address a `[server] trusted_proxies` peer
+//! forwards in X-Forwarded-For. Anyone else's X-Forwarded-For is ignored.
+
+mod common;
+
+use common::{serve_plain, MockFpm, Site, HOST};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn get_forwarded_for(addr: SocketAddr, path: &str, client: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nX-Forwarded-For: {}, 10.0.0.1\r\nConnection: close\r\n\r\n", path, HOST, client);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// A PHP site logging the client address of each request to `access.log`
+async fn php_site(server: &str) -> (Site, MockFpm, SocketAddr) {
+    let site = Site::new("");
+    site.set_directives(&format!("    CustomLog {} \"%h\"", site.dir.join("access.log").display()));
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let addr = serve_plain(site.state_with_server(server, &format!("fpm_address = \"{}\"", mock.addr))).await;
+    (site, mock, addr)
+}
+
+#[tokio::test]
+async fn remote_addr_is_the_peer() {
+    let (site, mock, addr) = php_site("").await;
+
+    let response = get_forwarded_for(addr, "/index.php", "203.0.113.9").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(mock.requests()[0].params["REMOTE_ADDR"], "127.0.0.1");
+    assert_eq!(std::fs::read_to_string(site.dir.join("access.log")).unwrap(), "127.0.0.1\n");
+}
+
+#[tokio::test]
+async fn remote_addr_is_forwarded_by_a_trusted_proxy() {
+    let (site, mock, addr) = php_site("trusted_proxies = [\"127.0.0.1\"]").await;
+
+    let response = get_forwarded_for(addr, "/index.php", "203.0.113.9").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(mock.requests()[0].params["REMOTE_ADDR"], "203.0.113.9");
+    assert_eq!(std::fs::read_to_string(site.dir.join("access.log")).unwrap(), "203.0.113.9\n");
+}
@@ -0,0 +1,981 @@
+//! The full request path through the site router: vhost lookup, static
+//! files, directory indexes and PHP via a mock PHP-FPM.
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::response::Response;
+use bytes::Bytes;
+use common::{serve_plain, MockFpm, Site, HOST, PORT};
+use std::fs;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tower::ServiceExt;
+
+async fn send(site: &Site, php: &str, request: Request<Body>) -> Response {
+    wolfserve::site_router(&site.state(php), PORT, false).oneshot(request).await.unwrap()
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).header(header::HOST, HOST).body(Body::empty()).unwrap()
+}
+
+async fn body_of(response: Response) -> Bytes {
+    to_bytes(response.into_body(), usize::MAX).await.unwrap()
+}
+
+fn fpm(mock: &MockFpm) -> String {
+    format!("fpm_address = \"{}\"", mock.addr)
+}
+
+#[tokio::test]
+async fn static_file() {
+    let site = Site::new("");
+    site.write("hello.txt", "hello world");
+
+    let response = send(&site, "", get("/hello.txt")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+    assert!(response.headers()[header::SERVER].to_str().unwrap().starts_with("wolfserve/"));
+    assert_eq!(body_of(response).await, "hello world");
+}
+
+#[tokio::test]
+async fn directory_index() {
+    let site = Site::new("");
+    site.write("docs/index.html", "<h1>docs</h1>");
+
+    let response = send(&site, "", get("/docs/")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+    assert_eq!(body_of(response).await, "<h1>docs</h1>");
+
+    let response = send(&site, "", get("/docs?page=2")).await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()[header::LOCATION], "/docs/?page=2");
+}
+
+/// Status and Location of `request` with `[server] strip_index` on
+async fn send_stripping_index(site: &Site, php: &str, request: Request<Body>) -> (StatusCode, Option<String>) {
+    let state = site.state_with_server("strip_index = true", php);
+    let response = wolfserve::site_router(&state, PORT, false).oneshot(request).await.unwrap();
+    let location = response.headers().get(header::LOCATION).map(|value| value.to_str().unwrap().to_string());
+    (response.status(), location)
+}
+
+#[tokio::test]
+async fn strip_index_redirects_to_the_directory() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    site.write("dir/index.html", "<h1>dir</h1>");
+    let moved = |location: &str| (StatusCode::MOVED_PERMANENTLY, Some(location.to_string()));
+
+    assert_eq!(send_stripping_index(&site, "", get("/index.php")).await, moved("/"));
+    assert_eq!(send_stripping_index(&site, "", get("/dir/index.html")).await, moved("/dir/"));
+    assert_eq!(send_stripping_index(&site, "", get("/dir/index.html?a=1&b=%20")).await, moved("/dir/?a=1&b=%20"));
+    assert_eq!(send_stripping_index(&site, "", get("/dir/")).await, (StatusCode::OK, None));
+    // Off by default
+    assert_eq!(send(&site, "", get("/dir/index.html")).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn strip_index_leaves_other_files_and_posts_alone() {
+    let site = Site::new("");
+    site.write("dir/index.php", "<?php");
+    site.write("dir/index.html", "<h1>dir</h1>");
+    site.write("dir/about.html", "<h1>about</h1>");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nposted").await;
+
+    // index.php is what /dir/ serves, so index.html is a file of its own
+    assert_eq!(send_stripping_index(&site, "", get("/dir/index.html")).await, (StatusCode::OK, None));
+    assert_eq!(send_stripping_index(&site, "", get("/dir/about.html")).await, (StatusCode::OK, None));
+    let post = Request::post("/dir/index.php").header(header::HOST, HOST).body(Body::from("a=1")).unwrap();
+    assert_eq!(send_stripping_index(&site, &fpm(&mock), post).await, (StatusCode::OK, None));
+}
+
+#[tokio::test]
+async fn missing_file_is_404() {
+    let site = Site::new("");
+    let response = send(&site, "", get("/nope.html")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn fallback_resource_runs_for_missing_paths() {
+    let site = Site::new("    FallbackResource /index.php");
+    site.write("index.php", "<?php");
+    site.write("assets/.htaccess", "FallbackResource disabled");
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nrouted").await;
+
+    let response = send(&site, &fpm(&mock), get("/blog/hello-world?page=2")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "routed");
+    let params = &mock.requests()[0].params;
+    assert_eq!(params["REQUEST_URI"], "/blog/hello-world?page=2");
+    assert_eq!(params["QUERY_STRING"], "page=2");
+    assert_eq!(params["SCRIPT_NAME"], "/index.php");
+    assert!(params["SCRIPT_FILENAME"].ends_with("/www/index.php"));
+
+    let response = send(&site, &fpm(&mock), get("/assets/missing.css")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn php_response_via_fpm() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start(
+        "Status: 201 Created\r\nContent-Type: application/json\r\nX-App: test\r\n\
+         Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n{\"ok\":true}",
+    )
+    .await;
+
+    let response = send(&site, &fpm(&mock), get("/index.php?id=7")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(response.headers()["x-app"], "test");
+    let cookies: Vec<_> = response.headers().get_all(header::SET_COOKIE).iter().collect();
+    assert_eq!(cookies, ["a=1", "b=2"]);
+    assert_eq!(body_of(response).await, "{\"ok\":true}");
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    let params = &requests[0].params;
+    assert_eq!(params["REQUEST_METHOD"], "GET");
+    assert_eq!(params["QUERY_STRING"], "id=7");
+    assert_eq!(params["REQUEST_URI"], "/index.php?id=7");
+    assert_eq!(params["HTTP_HOST"], HOST);
+    assert!(params["SCRIPT_FILENAME"].ends_with("/www/index.php"));
+}
+
+#[tokio::test]
+async fn directory_index_runs_php() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    site.write("index.html", "static");
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    let response = send(&site, &fpm(&mock), get("/")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "from php");
+}
+
+#[tokio::test]
+async fn uppercase_php_extension_runs_php() {
+    let site = Site::new("");
+    site.write("test.PHP", "<?php echo 'secret';");
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    let response = send(&site, &fpm(&mock), get("/test.PHP")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "from php");
+    assert!(mock.requests()[0].params["SCRIPT_FILENAME"].ends_with("test.PHP"));
+}
+
+#[tokio::test]
+async fn phtml_runs_php() {
+    let site = Site::new("");
+    site.write("page.phtml", "<?php echo 'secret';");
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    let response = send(&site, &fpm(&mock), get("/page.phtml")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "from php");
+}
+
+#[tokio::test]
+async fn add_handler_maps_extension_to_php() {
+    let site = Site::new("    AddHandler application/x-httpd-php .tpl");
+    site.write("view.tpl", "<?php echo 'secret';");
+    site.write("other.txt", "<?php echo 'secret';");
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    let response = send(&site, &fpm(&mock), get("/view.tpl")).await;
+    assert_eq!(body_of(response).await, "from php");
+    let response = send(&site, &fpm(&mock), get("/other.txt")).await;
+    assert_eq!(body_of(response).await, "<?php echo 'secret';");
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn fastcgi_mapping_routes_other_extensions() {
+    let site = Site::new("");
+    site.write("app.py", "print('secret')");
+    site.write("index.php", "<?php");
+    let php = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+    let python = MockFpm::start("Content-Type: text/plain\r\n\r\nfrom python").await;
+    let toml = format!("{}\n\n[fastcgi]\npy = \"{}\"", fpm(&php), python.addr);
+
+    let response = send(&site, &toml, get("/app.py?x=1")).await;
+    assert_eq!(body_of(response).await, "from python");
+    let response = send(&site, &toml, get("/index.php")).await;
+    assert_eq!(body_of(response).await, "from php");
+
+    let requests = python.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].params["SCRIPT_FILENAME"].ends_with("/app.py"));
+    assert_eq!(requests[0].params["QUERY_STRING"], "x=1");
+    assert_eq!(php.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn add_handler_maps_extension_to_fastcgi_upstream() {
+    let upstream = MockFpm::start("Content-Type: text/plain\r\n\r\nfrom upstream").await;
+    let site = Site::new(&format!("    AddHandler \"proxy:fcgi://{}\" .fcgi", upstream.addr));
+    site.write("cgi-bin/run.fcgi", "binary");
+
+    let response = send(&site, "", get("/cgi-bin/run.fcgi")).await;
+    assert_eq!(body_of(response).await, "from upstream");
+    assert!(upstream.requests()[0].params["SCRIPT_FILENAME"].ends_with("/cgi-bin/run.fcgi"));
+}
+
+#[tokio::test]
+async fn unreachable_fastcgi_upstream_is_502() {
+    let site = Site::new("");
+    site.write("app.py", "print('secret')");
+
+    let response = send(&site, "[fastcgi]\npy = \"127.0.0.1:1\"", get("/app.py")).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn denied_extensions_are_forbidden() {
+    let site = Site::new("");
+    site.write("dump.sql", "CREATE TABLE users");
+    site.write("old/index.php.BAK", "<?php");
+    site.write("notes.txt", "notes");
+
+    assert_eq!(send(&site, "", get("/dump.sql")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send(&site, "", get("/old/index.php.BAK")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send(&site, "", get("/notes.txt")).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn vhost_replaces_deny_list() {
+    let site = Site::new("");
+    site.write("dump.sql", "CREATE TABLE users");
+    site.write("notes.txt", "notes");
+    // Site::state puts this inside [php]; the [vhosts] table starts after it
+    let toml = format!("\n[vhosts.\"{}\"]\ndeny_extensions = [\"txt\"]", HOST);
+
+    assert_eq!(send(&site, &toml, get("/dump.sql")).await.status(), StatusCode::OK);
+    assert_eq!(send(&site, &toml, get("/notes.txt")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn denied_extensions_are_forbidden_as_a_fallback_resource() {
+    let site = Site::new("    FallbackResource /site.bak");
+    site.write("site.bak", "backup");
+
+    let response = send(&site, "", get("/missing")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_ne!(body_of(response).await, "backup");
+}
+
+#[tokio::test]
+async fn denied_extensions_are_forbidden_under_well_known() {
+    let site = Site::new("");
+    site.write(".well-known/dump.sql", "CREATE TABLE users");
+    assert_eq!(send(&site, "", get("/.well-known/dump.sql")).await.status(), StatusCode::FORBIDDEN);
+
+    let shared = site.dir.join("well-known");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("dump.sql"), "CREATE TABLE users").unwrap();
+    fs::write(shared.join("security.txt"), "Contact: mailto:security@test.local").unwrap();
+    let state = site.state_with_server(&format!("well_known_dir = \"{}\"", shared.display()), "");
+    let response = wolfserve::site_router(&state, PORT, false).oneshot(get("/.well-known/dump.sql")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = wolfserve::site_router(&state, PORT, false).oneshot(get("/.well-known/security.txt")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn php_outside_document_root_is_refused() {
+    let site = Site::new("");
+    let outside = site.dir.join("outside/evil.php");
+    fs::create_dir_all(outside.parent().unwrap()).unwrap();
+    fs::write(&outside, "<?php system($_GET['c']);").unwrap();
+    std::os::unix::fs::symlink(&outside, site.docroot.join("link.php")).unwrap();
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    assert_eq!(send(&site, &fpm(&mock), get("/link.php")).await.status(), StatusCode::FORBIDDEN);
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn php_under_an_alias_runs() {
+    let site = Site::new("");
+    let tools = site.dir.join("tools");
+    fs::create_dir_all(&tools).unwrap();
+    fs::write(tools.join("info.php"), "<?php phpinfo();").unwrap();
+    site.set_directives(&format!("    Alias /tools {}", tools.display()));
+    let mock = MockFpm::start("Content-Type: text/html\r\n\r\nfrom php").await;
+
+    let response = send(&site, &fpm(&mock), get("/tools/info.php")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(mock.requests()[0].params["SCRIPT_FILENAME"].ends_with("/tools/info.php"));
+}
+
+#[tokio::test]
+async fn post_body_reaches_fpm() {
+    let site = Site::new("");
+    site.write("form.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+
+    let request = Request::post("/form.php")
+        .header(header::HOST, HOST)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(header::CONTENT_LENGTH, "7")
+        .body(Body::from("a=1&b=2"))
+        .unwrap();
+    let response = send(&site, &fpm(&mock), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = &mock.requests()[0];
+    assert_eq!(request.stdin, b"a=1&b=2");
+    assert_eq!(request.params["CONTENT_LENGTH"], "7");
+    assert_eq!(request.params["CONTENT_TYPE"], "application/x-www-form-urlencoded");
+}
+
+#[tokio::test]
+async fn chunked_body_gets_content_length() {
+    let site = Site::new("");
+    site.write("form.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+
+    let chunks = ["a=1", "&b=", "22"].map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk)));
+    let request = Request::post("/form.php")
+        .header(header::HOST, HOST)
+        .header(header::TRANSFER_ENCODING, "chunked")
+        .body(Body::from_stream(futures_util::stream::iter(chunks)))
+        .unwrap();
+    let response = send(&site, &fpm(&mock), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = &mock.requests()[0];
+    assert_eq!(request.stdin, b"a=1&b=22");
+    assert_eq!(request.params["CONTENT_LENGTH"], "8");
+}
+
+#[tokio::test]
+async fn php_headers_and_body_are_byte_exact() {
+    let site = Site::new("");
+    site.write("download.php", "<?php");
+    let mut output = b"Content-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"caf\xe9.bin\"\r\n\r\n".to_vec();
+    output.extend_from_slice(&[0, 159, 146, 150, 13, 10, 13, 10, 255]);
+    let mock = MockFpm::start(output).await;
+
+    let response = send(&site, &fpm(&mock), get("/download.php")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION].as_bytes(),
+        b"attachment; filename=\"caf\xe9.bin\""
+    );
+    assert_eq!(body_of(response).await.as_ref(), [0, 159, 146, 150, 13, 10, 13, 10, 255]);
+}
+
+#[tokio::test]
+async fn unreachable_fpm_is_502() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    // Bind and drop a listener so nothing is listening on the port
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let response = send(&site, &format!("fpm_address = \"{}\"", addr), get("/index.php")).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn unknown_host_uses_first_vhost() {
+    let site = Site::new("");
+    site.write("hello.txt", "hello");
+
+    let request = Request::get("/hello.txt").header(header::HOST, "other.example").body(Body::empty()).unwrap();
+    let response = send(&site, "", request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "hello");
+}
+
+#[tokio::test]
+async fn each_port_has_its_own_catch_all_vhost() {
+    let site = Site::new("");
+    for (port, text) in [(8081, "first"), (8082, "second")] {
+        let root = site.dir.join(text);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), text).unwrap();
+        fs::write(
+            site.dir.join(format!("apache/sites-enabled/{}.conf", text)),
+            format!("<VirtualHost *:{}>\n    DocumentRoot {}\n</VirtualHost>\n", port, root.display()),
+        )
+        .unwrap();
+    }
+    let state = site.state("");
+
+    for (port, text) in [(8081, "first"), (8082, "second")] {
+        let request = Request::get("/").header(header::HOST, "unnamed.example").body(Body::empty()).unwrap();
+        let response = wolfserve::site_router(&state, port, false).oneshot(request).await.unwrap();
+        assert_eq!(body_of(response).await, text);
+    }
+}
+
+#[tokio::test]
+async fn listening_port_picks_among_vhosts_of_one_name() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let other_root = site.dir.join("other");
+    fs::create_dir_all(&other_root).unwrap();
+    fs::write(other_root.join("index.php"), "<?php").unwrap();
+    fs::write(
+        site.dir.join("apache/sites-enabled/other.conf"),
+        format!("<VirtualHost *:8081>\n    ServerName {}\n    DocumentRoot {}\n</VirtualHost>\n", HOST, other_root.display()),
+    )
+    .unwrap();
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let state = site.state(&fpm(&mock));
+
+    for port in [PORT, 8081] {
+        wolfserve::site_router(&state, port, false).oneshot(get("/index.php")).await.unwrap();
+    }
+    let requests = mock.requests();
+    assert_eq!(requests[0].params["SERVER_PORT"], PORT.to_string());
+    assert!(requests[0].params["SCRIPT_FILENAME"].starts_with(&*site.docroot.canonicalize().unwrap().to_string_lossy()));
+    assert_eq!(requests[1].params["SERVER_PORT"], "8081");
+    assert!(requests[1].params["SCRIPT_FILENAME"].starts_with(&*other_root.canonicalize().unwrap().to_string_lossy()));
+}
+
+#[tokio::test]
+async fn error_page_names_the_server_admin() {
+    let site = Site::new("    ServerAdmin web<master>@example.com");
+    let response = send(&site, "", get("/nope.html")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    let body = String::from_utf8(body_of(response).await.to_vec()).unwrap();
+    assert!(body.contains("<title>404 Not Found</title>"), "{}", body);
+    assert!(
+        body.contains(r#"<a href="mailto:web&lt;master&gt;@example.com">web&lt;master&gt;@example.com</a>"#),
+        "{}",
+        body
+    );
+
+    // Inherited from the main server, else a generic contact
+    fs::write(site.dir.join("apache/apache2.conf"), "ServerAdmin https://example.com/support\n").unwrap();
+    site.set_directives("");
+    let body = body_of(send(&site, "", get("/nope.html")).await).await;
+    assert!(String::from_utf8_lossy(&body).contains(r#"<a href="https://example.com/support">"#));
+    fs::remove_file(site.dir.join("apache/apache2.conf")).unwrap();
+    let body = body_of(send(&site, "", get("/nope.html")).await).await;
+    assert!(String::from_utf8_lossy(&body).contains("please contact the server administrator.</p>"));
+}
+
+#[tokio::test]
+async fn php_error_responses_are_left_alone() {
+    let site = Site::new("    ServerAdmin webmaster@example.com");
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nno such user").await;
+
+    let response = send(&site, &fpm(&mock), get("/index.php")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(body_of(response).await, "no such user");
+}
+
+/// A PHP-FPM that takes connections and never answers
+async fn stuck_fpm() -> std::net::SocketAddr {
+    let stuck = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = stuck.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = stuck.accept().await {
+            held.push(stream);
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn php_over_max_concurrent_is_503() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    site.write("hello.txt", "hello");
+    let php = format!("fpm_address = \"{}\"\nmax_concurrent = 1\nqueue_timeout = 0", stuck_fpm().await);
+    let router = wolfserve::site_router(&site.state(&php), PORT, false);
+
+    let first = tokio::spawn(router.clone().oneshot(get("/index.php")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = router.clone().oneshot(get("/index.php")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    // Static files don't need a slot
+    let response = router.clone().oneshot(get("/hello.txt")).await.unwrap();
+    assert_eq!(body_of(response).await, "hello");
+
+    // A finished (here: abandoned) request frees its slot
+    first.abort();
+    let _ = first.await;
+    assert!(tokio::time::timeout(Duration::from_millis(300), router.oneshot(get("/index.php"))).await.is_err());
+}
+
+fn get_with_accept(uri: &str, accept: &str) -> Request<Body> {
+    Request::get(uri).header(header::HOST, HOST).header(header::ACCEPT, accept).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn shed_responses_follow_accept() {
+    let site = Site::new("");
+    site.write("index.php", "<?php");
+    let fpm = stuck_fpm().await;
+    let busy_page = site.dir.join("busy.html");
+    fs::write(&busy_page, "<h1>Busy, back soon</h1>").unwrap();
+    let php = format!(
+        "fpm_address = \"{}\"\nmax_concurrent = 1\nqueue_timeout = 0\n\n[shed.503]\nhtml_page = \"{}\"\nretry_after = 30",
+        fpm,
+        busy_page.display()
+    );
+    let router = wolfserve::site_router(&site.state(&php), PORT, false);
+    let first = tokio::spawn(router.clone().oneshot(get("/index.php")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = router.clone().oneshot(get_with_accept("/index.php", "text/html,application/xhtml+xml,*/*;q=0.8")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(body_of(response).await, "<h1>Busy, back soon</h1>");
+
+    // No json_page: the built-in JSON error
+    let response = router.clone().oneshot(get_with_accept("/index.php", "application/json")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(response.headers()[header::VARY], "Accept");
+    let error: serde_json::Value = serde_json::from_slice(&body_of(response).await).unwrap();
+    assert_eq!(error["status"], 503);
+    assert_eq!(error["error"], "Service Unavailable");
+    assert_eq!(error["retry_after"], 30);
+    first.abort();
+}
+
+#[tokio::test]
+async fn shed_json_page_is_served_to_json_clients() {
+    let site = Site::new("    LimitRequestBody 4");
+    site.write("upload.php", "<?php");
+    let json_page = site.dir.join("too-large.json");
+    fs::write(&json_page, r#"{"error": "upload too large"}"#).unwrap();
+    let php = format!("fpm_address = \"127.0.0.1:1\"\n\n[shed.413]\njson_page = \"{}\"", json_page.display());
+
+    let post = |accept: &str| {
+        Request::post("/upload.php").header(header::HOST, HOST).header(header::ACCEPT, accept).body(Body::from("too long")).unwrap()
+    };
+    let response = send(&site, &php, post("application/json, text/html;q=0.5")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(body_of(response).await, r#"{"error": "upload too large"}"#);
+
+    // Browsers get the usual error page
+    let response = send(&site, &php, post("text/html")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(String::from_utf8_lossy(&body_of(response).await).contains("<h1>Payload Too Large</h1>"));
+}
+
+#[tokio::test]
+async fn bodies_past_the_memory_limit_reach_php_whole() {
+    let site = Site::new("");
+    site.write("upload.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nstored").await;
+    let upload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+    let php = format!("{}\nbody_memory_limit = 1000", fpm(&mock));
+    let post = Request::post("/upload.php").header(header::HOST, HOST).body(Body::from(upload.clone())).unwrap();
+    let response = send(&site, &php, post).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let requests = mock.requests();
+    assert_eq!(requests[0].params["CONTENT_LENGTH"], "100000");
+    assert_eq!(requests[0].stdin, upload);
+}
+
+#[tokio::test]
+async fn max_body_size_applies_where_no_limit_request_body_does() {
+    let site = Site::new("");
+    site.write("upload.php", "<?php");
+    site.write("big/upload.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nstored").await;
+    let post = |path: &str| Request::post(path).header(header::HOST, HOST).body(Body::from("x".repeat(20))).unwrap();
+
+    let php = format!("{}\nmax_body_size = 10", fpm(&mock));
+    assert_eq!(send(&site, &php, post("/upload.php")).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let php = format!("{}\nmax_body_size = 0", fpm(&mock));
+    assert_eq!(send(&site, &php, post("/upload.php")).await.status(), StatusCode::OK);
+
+    // LimitRequestBody wins, 0 included
+    site.set_directives(&format!(
+        "    LimitRequestBody 100\n    <Directory {}/big>\n        LimitRequestBody 0\n    </Directory>",
+        site.docroot.display()
+    ));
+    let php = format!("{}\nmax_body_size = 10", fpm(&mock));
+    assert_eq!(send(&site, &php, post("/upload.php")).await.status(), StatusCode::OK);
+    assert_eq!(send(&site, &php, post("/big/upload.php")).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn bodies_over_a_gibibyte_are_refused_by_default() {
+    let site = Site::new("");
+    site.write("upload.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nstored").await;
+    let addr = serve_plain(site.state(&fpm(&mock))).await;
+
+    // Refused from the Content-Length alone, without sending the body
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("POST /upload.php HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n", HOST, (1u64 << 30) + 1);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"HTTP/1.1 413");
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn wasm_is_served_as_application_wasm() {
+    let site = Site::new("");
+    site.write("app.wasm", b"\0asm\x01\0\0\0");
+
+    let response = send(&site, "", get("/app.wasm")).await;
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/wasm");
+}
+
+fn get_accepting(uri: &str, accept_encoding: &str) -> Request<Body> {
+    Request::get(uri).header(header::HOST, HOST).header(header::ACCEPT_ENCODING, accept_encoding).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn brotli_sibling_is_preferred_when_accepted() {
+    let site = Site::new("");
+    site.write("app.js", "console.log(1)");
+    site.write("app.js.br", b"\x0b\x06\x80console.log(1)\x03");
+
+    let response = send(&site, "", get_accepting("/app.js", "gzip, br")).await;
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/javascript");
+    assert!(response.headers()[header::ETAG].to_str().unwrap().ends_with("-br\""));
+    assert_eq!(body_of(response).await.as_ref(), b"\x0b\x06\x80console.log(1)\x03");
+
+    for accept_encoding in ["gzip", "br;q=0, gzip"] {
+        let response = send(&site, "", get_accepting("/app.js", accept_encoding)).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none(), "{}", accept_encoding);
+        assert_eq!(body_of(response).await, "console.log(1)");
+    }
+
+    // A sibling older than the file is stale
+    let old = std::time::SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options().write(true).open(site.docroot.join("app.js.br")).unwrap().set_modified(old).unwrap();
+    let response = send(&site, "", get_accepting("/app.js", "br")).await;
+    assert_eq!(body_of(response).await, "console.log(1)");
+
+    // And `[compression] precompressed = false` never looks for one
+    fs::File::options().write(true).open(site.docroot.join("app.js.br")).unwrap().set_modified(std::time::SystemTime::now()).unwrap();
+    let response = send(&site, "\n[compression]\nprecompressed = false", get_accepting("/app.js", "br")).await;
+    assert_eq!(body_of(response).await, "console.log(1)");
+}
+
+#[tokio::test]
+async fn well_known_is_served_while_dotfiles_are_forbidden() {
+    let site = Site::new("");
+    site.write(".well-known/acme-challenge/x", "token-x");
+    site.write(".well-known/security.txt", "Contact: mailto:security@test.local");
+    for file in [".htaccess", ".env", ".git/config", "sub/.htpasswd", ".well-known/.secret"] {
+        site.write(file, "secret");
+    }
+
+    let response = send(&site, "", get("/.well-known/acme-challenge/x")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "token-x");
+    let response = send(&site, "", get("/.well-known/security.txt")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for uri in ["/.htaccess", "/.env", "/.git/config", "/sub/.htpasswd", "/%2Ehtaccess", "/.well-known/.secret"] {
+        let response = send(&site, "", get(uri)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+    }
+
+    // With `well_known_dir` the files come from there instead
+    let shared = site.dir.join("well-known");
+    fs::create_dir_all(shared.join("acme-challenge")).unwrap();
+    fs::write(shared.join("acme-challenge/x"), "shared-x").unwrap();
+    let state = site.state_with_server(&format!("well_known_dir = \"{}\"", shared.display()), "");
+    let response = wolfserve::site_router(&state, PORT, false).oneshot(get("/.well-known/acme-challenge/x")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "shared-x");
+    let response = wolfserve::site_router(&state, PORT, false).oneshot(get("/.htaccess")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn vhost_redirects_answer_before_files() {
+    let site = Site::new(
+        "    Redirect permanent /old /new\n    \
+         RedirectMatch 302 ^/blog/(\\d+)/(.*)$ /posts/$2?year=$1\n    \
+         Redirect seeother /form /thanks\n    \
+         Redirect gone /retired",
+    );
+    site.write("old/page.html", "stale copy");
+    site.write("retired/index.html", "stale copy");
+
+    let cases = [
+        ("/old", StatusCode::MOVED_PERMANENTLY, Some("/new")),
+        ("/old/page.html?a=1", StatusCode::MOVED_PERMANENTLY, Some("/new/page.html?a=1")),
+        ("/blog/2024/hello", StatusCode::FOUND, Some("/posts/hello?year=2024")),
+        ("/form?id=3", StatusCode::SEE_OTHER, Some("/thanks?id=3")),
+        ("/retired/", StatusCode::GONE, None),
+    ];
+    for (uri, status, location) in cases {
+        let response = send(&site, "", get(uri)).await;
+        assert_eq!(response.status(), status, "{}", uri);
+        assert_eq!(response.headers().get(header::LOCATION).map(|v| v.to_str().unwrap()), location, "{}", uri);
+    }
+
+    // Paths that only share a prefix are not redirected
+    site.write("older.txt", "kept");
+    let response = send(&site, "", get("/older.txt")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "kept");
+}
+
+#[tokio::test]
+async fn server_level_redirects_are_inherited_by_every_vhost() {
+    let site = Site::new("    Redirect 301 /promo/spring /spring-sale");
+    fs::write(
+        site.dir.join("apache/apache2.conf"),
+        "Redirect permanent /legacy /modern\n\
+         RedirectMatch 302 ^/promo/(.*)$ /offers/$1\n\
+         RewriteEngine On\n\
+         RewriteRule ^global-only$ /rewritten.txt [L]\n",
+    )
+    .unwrap();
+    fs::write(
+        site.dir.join("apache/sites-enabled/other.conf"),
+        format!("<VirtualHost *:{}>\n    ServerName other.local\n    DocumentRoot {}\n</VirtualHost>\n", PORT, site.docroot.display()),
+    )
+    .unwrap();
+    site.write("rewritten.txt", "rewritten");
+
+    let on_host = |host: &'static str, uri: &'static str| {
+        let site = &site;
+        async move {
+            let request = Request::get(uri).header(header::HOST, host).body(Body::empty()).unwrap();
+            let response = send(site, "", request).await;
+            let location = response.headers().get(header::LOCATION).map(|v| v.to_str().unwrap().to_string());
+            (response.status(), location)
+        }
+    };
+    for host in [HOST, "other.local"] {
+        let (status, location) = on_host(host, "/legacy/page?a=1").await;
+        assert_eq!((status, location.as_deref()), (StatusCode::MOVED_PERMANENTLY, Some("/modern/page?a=1")), "{}", host);
+        let (status, location) = on_host(host, "/promo/summer").await;
+        assert_eq!((status, location.as_deref()), (StatusCode::FOUND, Some("/offers/summer")), "{}", host);
+    }
+
+    // A vhost's own redirect is tried before the inherited ones
+    let (status, location) = on_host(HOST, "/promo/spring").await;
+    assert_eq!((status, location.as_deref()), (StatusCode::MOVED_PERMANENTLY, Some("/spring-sale")));
+    let (status, location) = on_host("other.local", "/promo/spring").await;
+    assert_eq!((status, location.as_deref()), (StatusCode::FOUND, Some("/offers/spring")));
+
+    // As in Apache without `RewriteOptions Inherit`, the main server's
+    // rewrite rules don't reach the vhosts; a document root .htaccess does
+    assert_eq!(on_host(HOST, "/global-only").await.0, StatusCode::NOT_FOUND);
+    site.write(".htaccess", "RewriteEngine On\nRewriteRule ^local-only$ /rewritten.txt [L]\n");
+    for host in [HOST, "other.local"] {
+        let request = Request::get("/local-only").header(header::HOST, host).body(Body::empty()).unwrap();
+        let response = send(&site, "", request).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", host);
+        assert_eq!(body_of(response).await, "rewritten");
+    }
+    // Server-level redirects still come first
+    assert_eq!(on_host(HOST, "/legacy").await.0, StatusCode::MOVED_PERMANENTLY);
+}
+
+#[tokio::test]
+async fn htaccess_sends_plain_http_to_https() {
+    let site = Site::new("");
+    site.write("blog/index.html", "blog");
+    site.write(
+        ".htaccess",
+        "RewriteEngine On\nRewriteCond %{HTTPS} off\nRewriteRule ^(.*)$ https://%{HTTP_HOST}/$1 [R=301,L]\n",
+    );
+
+    let response = send(&site, "", get("/blog/?page=2")).await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()[header::LOCATION], format!("https://{}/blog/?page=2", HOST));
+
+    // Over TLS the condition fails and the page is served
+    let router = wolfserve::site_router(&site.state(""), PORT, true);
+    let response = router.oneshot(get("/blog/?page=2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "blog");
+}
+
+#[tokio::test]
+async fn each_encoding_gets_its_own_etag() {
+    let site = Site::new("");
+    site.write("site.css", "body { color: #333; }\n".repeat(64));
+
+    let mut etags = Vec::new();
+    for (accept_encoding, encoding) in [("gzip", Some("gzip")), ("br", Some("br")), ("identity", None)] {
+        let response = send(&site, "", get_accepting("/site.css", accept_encoding)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_ENCODING).map(|v| v.to_str().unwrap()), encoding);
+        let vary: Vec<_> = response.headers().get_all(header::VARY).iter().map(|v| v.to_str().unwrap().to_ascii_lowercase()).collect();
+        assert!(vary.iter().any(|v| v.contains("accept-encoding")), "{:?}", vary);
+        etags.push(response.headers()[header::ETAG].to_str().unwrap().to_string());
+    }
+    let (gzip, br, identity) = (&etags[0], &etags[1], &etags[2]);
+    assert!(gzip.ends_with("-gzip\""), "{}", gzip);
+    assert!(br.ends_with("-br\""), "{}", br);
+    assert!(gzip != br && gzip != identity && br != identity);
+
+    // If-None-Match only matches the ETag of the encoding that would be sent
+    for (accept_encoding, etag) in [("gzip", gzip), ("br", br), ("identity", identity)] {
+        for candidate in [gzip, br, identity] {
+            let request = Request::get("/site.css")
+                .header(header::HOST, HOST)
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .header(header::IF_NONE_MATCH, candidate.as_str())
+                .body(Body::empty())
+                .unwrap();
+            let response = send(&site, "", request).await;
+            let expected = if candidate == etag { StatusCode::NOT_MODIFIED } else { StatusCode::OK };
+            assert_eq!(response.status(), expected, "{} with If-None-Match {}", accept_encoding, candidate);
+        }
+    }
+}
+
+fn get_range(uri: &str, range: &str, if_range: Option<&str>) -> Request<Body> {
+    let mut request = Request::get(uri).header(header::HOST, HOST).header(header::RANGE, range);
+    if let Some(validator) = if_range {
+        request = request.header(header::IF_RANGE, validator);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn range_is_served_in_part() {
+    let site = Site::new("");
+    site.write("file.txt", "0123456789");
+
+    let response = send(&site, "", get_range("/file.txt", "bytes=2-5", None)).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(body_of(response).await, "2345");
+    for range in ["bytes=-3", "bytes=7-", "bytes=7-99"] {
+        let response = send(&site, "", get_range("/file.txt", range, None)).await;
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 7-9/10", "{}", range);
+        assert_eq!(body_of(response).await, "789", "{}", range);
+    }
+
+    let response = send(&site, "", get_range("/file.txt", "bytes=10-", None)).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+    // Several ranges are answered with the whole file
+    let response = send(&site, "", get_range("/file.txt", "bytes=0-1,4-5", None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_of(response).await, "0123456789");
+}
+
+#[tokio::test]
+async fn if_range_with_the_current_validator_gets_the_part() {
+    let site = Site::new("");
+    site.write("file.txt", "0123456789");
+    let response = send(&site, "", get("/file.txt")).await;
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+    let last_modified = response.headers()[header::LAST_MODIFIED].to_str().unwrap().to_string();
+
+    for validator in [&etag, &last_modified] {
+        let response = send(&site, "", get_range("/file.txt", "bytes=5-", Some(validator))).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", validator);
+        assert_eq!(body_of(response).await, "56789", "{}", validator);
+    }
+}
+
+#[tokio::test]
+async fn stale_if_range_gets_the_whole_file() {
+    let site = Site::new("");
+    site.write("file.txt", "0123456789");
+    let etag = send(&site, "", get("/file.txt")).await.headers()[header::ETAG].to_str().unwrap().to_string();
+    site.write("file.txt", "the new version");
+
+    let weak = format!("W/{}", etag);
+    for validator in [etag.as_str(), weak.as_str(), "Wed, 21 Oct 2015 07:28:00 GMT"] {
+        let response = send(&site, "", get_range("/file.txt", "bytes=5-", Some(validator))).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", validator);
+        assert!(response.headers().get(header::CONTENT_RANGE).is_none(), "{}", validator);
+        assert_eq!(body_of(response).await, "the new version", "{}", validator);
+    }
+}
+
+#[tokio::test]
+async fn vhost_sends_cross_origin_isolation_headers() {
+    let site = Site::new("");
+    site.write("index.html", "<h1>app</h1>");
+    site.write("index.php", "<?php");
+    let vhost = format!(
+        "\n[vhosts.\"{}\"]\ncross_origin_opener_policy = \"same-origin\"\ncross_origin_embedder_policy = \"require-corp\"",
+        HOST
+    );
+
+    let response = send(&site, &vhost, get("/index.html")).await;
+    assert_eq!(response.headers()["cross-origin-opener-policy"], "same-origin");
+    assert_eq!(response.headers()["cross-origin-embedder-policy"], "require-corp");
+
+    // PHP's own header wins
+    let mock = MockFpm::start("Content-Type: text/html\r\nCross-Origin-Embedder-Policy: credentialless\r\n\r\nok").await;
+    let response = send(&site, &format!("{}{}", fpm(&mock), vhost), get("/index.php")).await;
+    assert_eq!(response.headers()["cross-origin-embedder-policy"], "credentialless");
+    assert_eq!(response.headers()["cross-origin-opener-policy"], "same-origin");
+
+    let response = send(&site, "", get("/index.html")).await;
+    assert!(response.headers().get("cross-origin-opener-policy").is_none());
+}
+
+#[tokio::test]
+async fn location_blocks_match_prefix_wildcard_and_regex() {
+    let site = Site::new(
+        "    <Location /private>\n        Require all denied\n    </Location>\n    \
+         <Location /files/*.txt>\n        Require all denied\n    </Location>\n    \
+         <LocationMatch \"^/old/.*\\.html$\">\n        Require all denied\n    </LocationMatch>",
+    );
+    for file in ["private/a.txt", "privately.txt", "files/a.txt", "files/a.md", "files/sub/b.txt", "old/x.html", "old/x.md"] {
+        site.write(file, "content");
+    }
+
+    let status = |uri: &'static str| {
+        let site = &site;
+        async move { send(site, "", get(uri)).await.status() }
+    };
+    assert_eq!(status("/private/a.txt").await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/privately.txt").await, StatusCode::OK);
+    assert_eq!(status("/files/a.txt").await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/files/a.md").await, StatusCode::OK);
+    // `*` stops at a slash
+    assert_eq!(status("/files/sub/b.txt").await, StatusCode::OK);
+    assert_eq!(status("/old/x.html").await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/old/x.md").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn location_set_handler_runs_files_through_fastcgi() {
+    let upstream = MockFpm::start("Content-Type: text/plain\r\n\r\nfrom upstream").await;
+    let site = Site::new(&format!(
+        "    <Location /api/*>\n        SetHandler \"proxy:fcgi://{}\"\n    </Location>\n    \
+         <Location /api/static.json>\n        SetHandler none\n    </Location>",
+        upstream.addr
+    ));
+    site.write("api/users", "source");
+    site.write("api/static.json", "{}");
+    site.write("api/v2/users", "source");
+
+    assert_eq!(body_of(send(&site, "", get("/api/users")).await).await, "from upstream");
+    assert!(upstream.requests()[0].params["SCRIPT_FILENAME"].ends_with("/api/users"));
+    assert_eq!(body_of(send(&site, "", get("/api/static.json")).await).await, "{}");
+    assert_eq!(body_of(send(&site, "", get("/api/v2/users")).await).await, "source");
+    assert_eq!(upstream.requests().len(), 1);
+}
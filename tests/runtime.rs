@@ -0,0 +1,18 @@
+//! `[server] worker_threads` sizes the runtime main() builds; a missing or
+//! broken wolfserve.toml leaves tokio's defaults for run() to report on.
+
+#[test]
+fn worker_threads_size_the_runtime() {
+    let config = "[server]\nhost = \"127.0.0.1\"\nport = 3000\nworker_threads = 3\nmax_blocking_threads = 8\n\n[php]\n";
+    let runtime = wolfserve::build_runtime(Some(config)).unwrap();
+    assert_eq!(runtime.metrics().num_workers(), 3);
+}
+
+#[test]
+fn unreadable_config_uses_the_defaults() {
+    for config in [None, Some("[server]\nworker_threads = \"many\"\n")] {
+        let runtime = wolfserve::build_runtime(config).unwrap();
+        assert!(runtime.metrics().num_workers() > 0);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+}
@@ -0,0 +1,76 @@
+//! The Server header and PHP's SERVER_SOFTWARE for each ServerTokens level,
+//! from `[server] server_tokens` or Apache's ServerTokens.
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request};
+use common::{MockFpm, Site, HOST, PORT};
+use std::sync::Arc;
+use tower::ServiceExt;
+use wolfserve::AppState;
+
+const FULL: &str = concat!("wolfserve/", env!("CARGO_PKG_VERSION"));
+
+fn site(apache: Option<&str>) -> Site {
+    let site = Site::new("");
+    site.write("index.html", "hello");
+    if let Some(directive) = apache {
+        std::fs::write(
+            site.dir.join("apache/apache2.conf"),
+            format!("{}\nIncludeOptional sites-enabled/*.conf\n", directive),
+        )
+        .unwrap();
+    }
+    site
+}
+
+async fn server_header(state: &Arc<AppState>, path: &str) -> Option<String> {
+    let request = Request::get(path).header(header::HOST, HOST).body(Body::empty()).unwrap();
+    let response = wolfserve::site_router(state, PORT, false).oneshot(request).await.unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    response.headers().get(header::SERVER).map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn full_is_the_default() {
+    let site = site(None);
+    assert_eq!(server_header(&site.state(""), "/index.html").await.as_deref(), Some(FULL));
+}
+
+#[tokio::test]
+async fn server_tokens_setting_picks_the_level() {
+    let site = site(None);
+    for (level, expected) in [("full", Some(FULL)), ("prod", Some("wolfserve")), ("name-only", Some("wolfserve")), ("off", None)] {
+        let state = site.state_with_server(&format!("server_tokens = \"{}\"", level), "");
+        assert_eq!(server_header(&state, "/index.html").await.as_deref(), expected, "{}", level);
+    }
+}
+
+#[tokio::test]
+async fn apache_server_tokens_is_honoured() {
+    for (directive, expected) in [("ServerTokens Prod", "wolfserve"), ("ServerTokens ProductOnly", "wolfserve"), ("ServerTokens OS", FULL), ("ServerTokens Bogus", FULL)] {
+        let site = site(Some(directive));
+        assert_eq!(server_header(&site.state(""), "/index.html").await.as_deref(), Some(expected), "{}", directive);
+    }
+}
+
+#[tokio::test]
+async fn the_setting_wins_over_apache() {
+    let site = site(Some("ServerTokens Prod"));
+    let state = site.state_with_server("server_tokens = \"off\"", "");
+    assert_eq!(server_header(&state, "/index.html").await, None);
+}
+
+#[tokio::test]
+async fn php_always_gets_the_name() {
+    let site = site(None);
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let php = format!("fpm_address = \"{}\"", mock.addr);
+    for (level, expected) in [("full", FULL), ("prod", "wolfserve"), ("off", "wolfserve")] {
+        let state = site.state_with_server(&format!("server_tokens = \"{}\"", level), &php);
+        server_header(&state, "/index.php").await;
+        assert_eq!(mock.requests().last().unwrap().params["SERVER_SOFTWARE"], expected, "{}", level);
+    }
+}
@@ -0,0 +1,82 @@
+//! Files reached through symlinks are served or refused as `Options`
+//! FollowSymLinks and SymLinksIfOwnerMatch say.
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::{Site, HOST, PORT};
+use std::fs;
+use std::os::unix::fs::symlink;
+use tower::ServiceExt;
+
+async fn status(site: &Site, uri: &str) -> StatusCode {
+    let request = Request::get(uri).header(header::HOST, HOST).body(Body::empty()).unwrap();
+    wolfserve::site_router(&site.state(""), PORT, false).oneshot(request).await.unwrap().status()
+}
+
+/// A site whose document root has `plain.txt`, `escape.txt` linking to a
+/// file outside the root and `shared/` holding `link.txt` to `plain.txt`
+fn site() -> Site {
+    let site = Site::new("");
+    site.write("plain.txt", "plain");
+    let secret = site.dir.join("secret.txt");
+    fs::write(&secret, "secret").unwrap();
+    symlink(&secret, site.docroot.join("escape.txt")).unwrap();
+    fs::create_dir(site.docroot.join("shared")).unwrap();
+    symlink(site.docroot.join("plain.txt"), site.docroot.join("shared/link.txt")).unwrap();
+    site
+}
+
+#[tokio::test]
+async fn symlinks_are_followed_by_default() {
+    let site = site();
+
+    assert_eq!(status(&site, "/escape.txt").await, StatusCode::OK);
+    assert_eq!(status(&site, "/shared/link.txt").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn symlink_escaping_the_root_is_refused() {
+    let site = site();
+    site.set_directives("    Options -FollowSymLinks");
+
+    assert_eq!(status(&site, "/escape.txt").await, StatusCode::FORBIDDEN);
+    assert_eq!(status(&site, "/shared/link.txt").await, StatusCode::FORBIDDEN);
+    assert_eq!(status(&site, "/plain.txt").await, StatusCode::OK);
+
+    // Options without + or - replace the set, leaving FollowSymLinks off
+    site.set_directives("    Options Indexes");
+    assert_eq!(status(&site, "/escape.txt").await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn symlinked_directory_is_refused() {
+    let site = site();
+    symlink(site.dir.join("apache"), site.docroot.join("conf")).unwrap();
+    site.set_directives("    Options None");
+
+    assert_eq!(status(&site, "/conf/sites-enabled/test.conf").await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn directory_options_override_the_vhost() {
+    let site = site();
+    site.set_directives(&format!(
+        "    Options None\n    <Directory {}/shared>\n        Options +FollowSymLinks\n    </Directory>",
+        site.docroot.display()
+    ));
+
+    assert_eq!(status(&site, "/shared/link.txt").await, StatusCode::OK);
+    assert_eq!(status(&site, "/escape.txt").await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn owner_match_allows_links_owned_by_the_target_owner() {
+    let site = site();
+    site.set_directives("    Options -FollowSymLinks +SymLinksIfOwnerMatch");
+
+    // The test created both the links and their targets
+    assert_eq!(status(&site, "/escape.txt").await, StatusCode::OK);
+    assert_eq!(status(&site, "/shared/link.txt").await, StatusCode::OK);
+}
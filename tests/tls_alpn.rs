@@ -0,0 +1,100 @@
+//! ACME TLS-ALPN-01: handshakes offering only `acme-tls/1` get the challenge
+//! certificate while one is published, and nothing otherwise; every other
+//! handshake keeps getting the real certificate.
+
+mod common;
+
+use common::{serve_tls, AcceptAny, Site, HOST};
+use rustls::pki_types::ServerName;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use wolfserve::{publish_tls_alpn_challenge, withdraw_tls_alpn_challenge};
+
+const KEY_AUTHORIZATION: &str = "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA.9jg46WB3rR_AHD-EBXdN7cBkH1WOu0tA3M9fm21mqTI";
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+fn real_cert() -> Vec<u8> {
+    let pem = std::fs::read(fixture("ec.pem")).unwrap();
+    let cert = rustls_pemfile::certs(&mut pem.as_slice()).next().unwrap().unwrap();
+    cert.to_vec()
+}
+
+/// Handshake with SNI `sni` offering `alpn`: the server's certificate and
+/// the connection, or None if the handshake failed
+async fn handshake(
+    addr: SocketAddr,
+    sni: &str,
+    alpn: &[&[u8]],
+) -> Option<(Vec<u8>, tokio_rustls::client::TlsStream<TcpStream>)> {
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from(sni.to_string()).unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await.ok()?;
+    let cert = tls.get_ref().1.peer_certificates()?[0].to_vec();
+    Some((cert, tls))
+}
+
+async fn serve() -> (Site, Arc<wolfserve::AppState>, SocketAddr) {
+    let site = Site::new("    SSLEngine on");
+    let tls = format!(
+        "\n[tls]\ncertificate_file = \"{}\"\ncertificate_key_file = \"{}\"",
+        fixture("ec.pem").display(),
+        fixture("ec.key").display()
+    );
+    let state = site.state(&tls);
+    let addr = serve_tls(state.clone()).await;
+    (site, state, addr)
+}
+
+#[tokio::test]
+async fn challenge_certificate_answers_acme_handshakes_only() {
+    let (_site, state, addr) = serve().await;
+    publish_tls_alpn_challenge(&state, HOST, KEY_AUTHORIZATION).unwrap();
+
+    let (cert, mut tls) = handshake(addr, HOST, &[b"acme-tls/1"]).await.unwrap();
+    assert_ne!(cert, real_cert());
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"acme-tls/1"[..]));
+    // critical acmeIdentifier holding OCTET STRING(SHA-256(key authorization))
+    let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, KEY_AUTHORIZATION.as_bytes());
+    let extension = [
+        &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f, 0x01, 0x01, 0xff, 0x04, 0x22, 0x04, 0x20][..],
+        digest.as_ref(),
+    ]
+    .concat();
+    assert!(cert.windows(extension.len()).any(|w| w == extension));
+    assert!(cert.windows(HOST.len()).any(|w| w == HOST.as_bytes()));
+    // No HTTP is served on a validation connection
+    let mut buf = Vec::new();
+    assert_eq!(tls.read_to_end(&mut buf).await.unwrap_or(0), 0);
+
+    // Browsers, and clients offering HTTP alongside acme-tls/1, get the real one
+    assert_eq!(handshake(addr, HOST, &[b"h2", b"http/1.1"]).await.unwrap().0, real_cert());
+    assert_eq!(handshake(addr, HOST, &[]).await.unwrap().0, real_cert());
+    assert_eq!(handshake(addr, HOST, &[b"http/1.1", b"acme-tls/1"]).await.unwrap().0, real_cert());
+    // Another name has no challenge
+    assert!(handshake(addr, "other.test", &[b"acme-tls/1"]).await.is_none());
+
+    withdraw_tls_alpn_challenge(&state, HOST);
+    assert!(handshake(addr, HOST, &[b"acme-tls/1"]).await.is_none());
+}
+
+#[tokio::test]
+async fn acme_handshake_without_challenge_fails() {
+    let (_site, _state, addr) = serve().await;
+
+    assert!(handshake(addr, HOST, &[b"acme-tls/1"]).await.is_none());
+    assert_eq!(handshake(addr, HOST, &[b"http/1.1"]).await.unwrap().0, real_cert());
+}
@@ -0,0 +1,62 @@
+//! SSLProtocol and SSLCipherSuite limit what an HTTPS listener negotiates.
+
+mod common;
+
+use common::{serve_tls, AcceptAny, Site, HOST};
+use rustls::pki_types::ServerName;
+use rustls::{CipherSuite, ProtocolVersion, SupportedProtocolVersion};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+/// Handshake offering `versions`: the negotiated version and cipher suite,
+/// or None if the handshake failed
+async fn handshake(addr: SocketAddr, versions: &[&'static SupportedProtocolVersion]) -> Option<(ProtocolVersion, CipherSuite)> {
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.ok()?;
+    let connection = tls.get_ref().1;
+    Some((connection.protocol_version()?, connection.negotiated_cipher_suite()?.suite()))
+}
+
+#[tokio::test]
+async fn directives_limit_the_negotiated_protocol_and_cipher() {
+    let site = Site::new(&format!(
+        "    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n    SSLProtocol -all +TLSv1.2\n    SSLCipherSuite ECDHE-ECDSA-AES256-GCM-SHA384:DES-CBC3-SHA",
+        fixture("ec.pem").display(),
+        fixture("ec.key").display()
+    ));
+    let addr = serve_tls(site.state("")).await;
+
+    assert_eq!(
+        handshake(addr, rustls::ALL_VERSIONS).await,
+        Some((ProtocolVersion::TLSv1_2, CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384))
+    );
+    assert_eq!(handshake(addr, &[&rustls::version::TLS13]).await, None);
+}
+
+#[tokio::test]
+async fn tls13_cipher_suites_are_limited() {
+    let site = Site::new(&format!(
+        "    SSLEngine on\n    SSLCertificateFile {}\n    SSLCertificateKeyFile {}\n    SSLCipherSuite TLSv1.3 TLS_CHACHA20_POLY1305_SHA256",
+        fixture("ec.pem").display(),
+        fixture("ec.key").display()
+    ));
+    let addr = serve_tls(site.state("")).await;
+
+    assert_eq!(
+        handshake(addr, &[&rustls::version::TLS13]).await,
+        Some((ProtocolVersion::TLSv1_3, CipherSuite::TLS13_CHACHA20_POLY1305_SHA256))
+    );
+}
@@ -0,0 +1,64 @@
+//! TLS handshake statistics: completed handshakes by protocol and cipher
+//! suite, and failed ones.
+
+mod common;
+
+use common::{serve_tls, AcceptAny, Site, HOST};
+use rustls::pki_types::ServerName;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys").join(name)
+}
+
+async fn handshake(addr: std::net::SocketAddr, version: &'static rustls::SupportedProtocolVersion) -> bool {
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_protocol_versions(&[version])
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    TlsConnector::from(Arc::new(config)).connect(ServerName::try_from(HOST).unwrap(), stream).await.is_ok()
+}
+
+#[tokio::test]
+async fn handshakes_are_counted_by_protocol_and_cipher() {
+    let site = Site::new("    SSLEngine on");
+    let tls = format!(
+        "\n[tls]\ncertificate_file = \"{}\"\ncertificate_key_file = \"{}\"",
+        fixture("ec.pem").display(),
+        fixture("ec.key").display()
+    );
+    let state = site.state(&tls);
+    let addr = serve_tls(state.clone()).await;
+
+    assert!(handshake(addr, &rustls::version::TLS13).await);
+    assert!(handshake(addr, &rustls::version::TLS13).await);
+    assert!(handshake(addr, &rustls::version::TLS12).await);
+    // Not TLS at all, as a scanner might send
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: test.local\r\n\r\n").await.unwrap();
+    let mut buf = Vec::new();
+    let _ = stream.read_to_end(&mut buf).await;
+
+    // The server counts after its side of the handshake, maybe after the client's
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while state.stats().tls_handshake_failures == 0 || state.stats().tls_handshakes < 3 {
+        assert!(tokio::time::Instant::now() < deadline, "{:?}", state.stats());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let stats = state.stats();
+    assert_eq!(stats.tls_handshakes, 3);
+    assert_eq!(stats.tls_handshake_failures, 1);
+    assert_eq!(stats.tls_protocols.get("TLSv1.3"), Some(&2), "{:?}", stats.tls_protocols);
+    assert_eq!(stats.tls_protocols.get("TLSv1.2"), Some(&1), "{:?}", stats.tls_protocols);
+    assert_eq!(stats.tls_ciphers.values().sum::<u64>(), 3);
+    assert!(stats.tls_ciphers.keys().any(|cipher| cipher.starts_with("TLS13_")), "{:?}", stats.tls_ciphers);
+    assert!(stats.tls_ciphers.keys().any(|cipher| cipher.starts_with("TLS_ECDHE_ECDSA_")), "{:?}", stats.tls_ciphers);
+}
@@ -0,0 +1,101 @@
+//! ErrorLog and CustomLog: each vhost's requests go to its own files, in the
+//! format it names, and the errors of its requests to its ErrorLog.
+
+mod common;
+
+use common::{serve_plain, MockFpm, Site, HOST};
+use std::fs;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const OTHER: &str = "other.local";
+
+async fn send(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+async fn get(addr: SocketAddr, host: &str, target: &str) -> String {
+    send(addr, &format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: probe/1.0\r\nConnection: close\r\n\r\n", target, host)).await
+}
+
+#[tokio::test]
+async fn each_vhost_logs_to_its_own_file() {
+    let site = Site::new("");
+    site.set_directives(&format!("    CustomLog {} \"%v %U\"", site.dir.join("test.log").display()));
+    fs::write(
+        site.dir.join("apache/sites-enabled/other.conf"),
+        format!(
+            "<VirtualHost *:8080>\n    ServerName {}\n    DocumentRoot {}\n    CustomLog {} \"%v %U\"\n</VirtualHost>\n",
+            OTHER,
+            site.docroot.display(),
+            site.dir.join("other.log").display()
+        ),
+    )
+    .unwrap();
+    site.write("index.html", "hello");
+    let addr = serve_plain(site.state("")).await;
+
+    get(addr, HOST, "/index.html").await;
+    get(addr, OTHER, "/index.html").await;
+    get(addr, OTHER, "/missing").await;
+
+    assert_eq!(fs::read_to_string(site.dir.join("test.log")).unwrap(), "test.local /index.html\n");
+    assert_eq!(fs::read_to_string(site.dir.join("other.log")).unwrap(), "other.local /index.html\nother.local /missing\n");
+}
+
+#[tokio::test]
+async fn formats_are_expanded() {
+    let site = Site::new("");
+    site.set_directives(&format!(
+        "    CustomLog {} combined\n    CustomLog {} \"%v:%p %h %m %U%q %H %>s %b %{{User-Agent}}i %{{SSL_PROTOCOL}}x %% %Z\"",
+        site.dir.join("combined.log").display(),
+        site.dir.join("custom.log").display()
+    ));
+    site.write("index.html", "hello");
+    let addr = serve_plain(site.state("")).await;
+
+    get(addr, HOST, "/index.html?a=1").await;
+
+    let combined = fs::read_to_string(site.dir.join("combined.log")).unwrap();
+    let (start, rest) = combined.split_once(" [").unwrap();
+    assert_eq!(start, "127.0.0.1 - -");
+    let (_time, rest) = rest.split_once("] ").unwrap();
+    assert_eq!(rest, "\"GET /index.html?a=1 HTTP/1.1\" 200 5 \"-\" \"probe/1.0\"\n");
+    // Codes wolfserve has nothing for log "-", as Apache does
+    assert_eq!(
+        fs::read_to_string(site.dir.join("custom.log")).unwrap(),
+        "test.local:8080 127.0.0.1 GET /index.html?a=1 HTTP/1.1 200 5 probe/1.0 - % -\n"
+    );
+}
+
+#[tokio::test]
+async fn request_errors_go_to_the_vhost_error_log() {
+    let site = Site::new("");
+    site.set_directives(&format!(
+        "    LimitRequestBody 10\n    ErrorLog {}\n    CustomLog {} \"%>s\"",
+        site.dir.join("logs/error.log").display(),
+        site.dir.join("logs/access.log").display()
+    ));
+    site.write("index.php", "<?php");
+    let mock = MockFpm::start("Content-Type: text/plain\r\n\r\nok").await;
+    let addr = serve_plain(site.state(&format!("fpm_address = \"{}\"", mock.addr))).await;
+
+    let body = "x".repeat(20);
+    let response = send(
+        addr,
+        &format!("POST /index.php HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", HOST, body.len(), body),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+    let error_log = fs::read_to_string(site.dir.join("logs/error.log")).unwrap();
+    assert_eq!(error_log.lines().count(), 1, "{}", error_log);
+    assert!(error_log.starts_with('['), "{}", error_log);
+    assert!(error_log.contains(&format!("] [wolfserve:error] [pid {}] [client 127.0.0.1] Request body exceeds LimitRequestBody 10", std::process::id())), "{}", error_log);
+    assert_eq!(fs::read_to_string(site.dir.join("logs/access.log")).unwrap(), "413\n");
+}